@@ -0,0 +1,379 @@
+//! Counting repeater acks (`--acks-secs <s>`).
+//!
+//! A repeater with `ack_every_cycles` set airs a signed `AckBeacon` for each
+//! notification it relays, now and then. With `--acks-secs` the broadcaster
+//! scans for them while it broadcasts and for `<s>` seconds after, then
+//! reports how many distinct repeaters relayed each notification. Only acks
+//! that verify against our keyring and name a notification as we issued it
+//! (its `seq` included) count, so neither a forged ack nor one left over
+//! from an earlier run inflates the tally.
+//!
+//! Only repeaters within radio range of the broadcaster are heard; a count
+//! of 0 means none of those relayed it, not that nobody did.
+//!
+//! Acks are looked for under one company ID: `--manufacturer-id`, or
+//! `--filter-company-id` when the repeaters re-air under another. Where
+//! BlueZ offers advertisement monitors, the company ID is handed to one as a
+//! manufacturer-data pattern, which BlueZ offloads to the controller if the
+//! controller can match it, and only devices it reports are read. Otherwise
+//! every device discovered is read and filtered here. Either way a payload
+//! under another company ID is never counted.
+//!
+//! Each ack counted is also a sample of how strongly this spot hears the
+//! repeater that aired it. With `--rssi-json` the broadcaster prints, after
+//! the report, the min/max/average RSSI and number of acks heard per
+//! `source_id` of the notifications acked, and per repeater under each: a
+//! point of a coverage heatmap, taken by running it at a fixed location.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use ble_protocol_core::{AckBeacon, InfraKey, TransportNotification};
+use bluer::monitor::{Monitor, MonitorEvent, MonitorHandle, MonitorManager, Pattern, Type, data_type};
+use bluer::{Address, AdapterEvent, DiscoveryFilter, DiscoveryTransport};
+use futures::StreamExt;
+
+/// The signal strength a run of acks was heard at, in dBm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RssiStats {
+    pub min: i16,
+    pub max: i16,
+    sum: i64,
+    pub count: u32,
+}
+
+impl RssiStats {
+    fn new(rssi: i16) -> Self {
+        Self {
+            min: rssi,
+            max: rssi,
+            sum: rssi.into(),
+            count: 1,
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+
+    pub fn avg(&self) -> f64 {
+        self.sum as f64 / f64::from(self.count)
+    }
+
+    /// The fields of a JSON object, without the braces.
+    fn json_fields(&self) -> String {
+        format!(
+            "\"min\":{},\"max\":{},\"avg\":{:.1},\"count\":{}",
+            self.min,
+            self.max,
+            self.avg(),
+            self.count
+        )
+    }
+}
+
+/// Which repeaters have acked each notification of a batch.
+#[derive(Debug)]
+pub struct AckTally {
+    sent: Vec<TransportNotification>,
+    /// Repeater ids per entry of `sent`.
+    relayed_by: Vec<BTreeSet<[u8; 4]>>,
+    /// RSSI of every ack counted, by `source_id` of the notification acked,
+    /// then by repeater.
+    rssi: BTreeMap<[u8; 4], BTreeMap<[u8; 4], RssiStats>>,
+}
+
+impl AckTally {
+    pub fn new(sent: &[TransportNotification]) -> Self {
+        Self {
+            sent: sent.to_vec(),
+            relayed_by: vec![BTreeSet::new(); sent.len()],
+            rssi: BTreeMap::new(),
+        }
+    }
+
+    /// Count `payload`, manufacturer data heard under our company ID, if it
+    /// is an ack verifying against `keyring` for one of our notifications,
+    /// and the `rssi` it was heard at if the adapter reported one. The first
+    /// time each repeater is heard for a notification, returns the
+    /// notification, the repeater and how many repeaters it now has; `None`
+    /// otherwise. Every ack counted adds to the RSSI figures, repeats too.
+    pub fn record(
+        &mut self,
+        payload: &[u8],
+        keyring: &[InfraKey],
+        rssi: Option<i16>,
+    ) -> Option<(&TransportNotification, [u8; 4], usize)> {
+        if !AckBeacon::is_ack(payload) {
+            return None;
+        }
+        let ack = AckBeacon::from_payload_with(payload, keyring).ok()?;
+        let i = self.sent.iter().position(|notif| ack.acknowledges(notif))?;
+        if let Some(rssi) = rssi {
+            let sample = RssiStats::new(rssi);
+            self.rssi
+                .entry(self.sent[i].source_id)
+                .or_default()
+                .entry(ack.repeater_id)
+                .and_modify(|stats| stats.add(&sample))
+                .or_insert(sample);
+        }
+        let repeaters = &mut self.relayed_by[i];
+        repeaters
+            .insert(ack.repeater_id)
+            .then(|| (&self.sent[i], ack.repeater_id, repeaters.len()))
+    }
+
+    /// `record` the payload `data`, a device's manufacturer data, carries
+    /// under `company_id`. Data under any other company ID is ignored.
+    pub fn record_heard(
+        &mut self,
+        data: &HashMap<u16, Vec<u8>>,
+        company_id: u16,
+        keyring: &[InfraKey],
+        rssi: Option<i16>,
+    ) -> Option<(&TransportNotification, [u8; 4], usize)> {
+        self.record(data.get(&company_id)?, keyring, rssi)
+    }
+
+    /// One line per notification: how many repeaters relayed it.
+    pub fn report(&self) -> Vec<String> {
+        self.sent
+            .iter()
+            .zip(&self.relayed_by)
+            .map(|(notif, repeaters)| {
+                format!("notification {} relayed by {} repeater(s)", notif.id_hex(), repeaters.len())
+            })
+            .collect()
+    }
+
+    /// The RSSI figures as one JSON object: per `source_id` acks were heard
+    /// for, in hex, the figures over all of its acks and per repeater. A
+    /// source none of whose notifications were acked is left out.
+    pub fn rssi_json(&self) -> String {
+        let sources: Vec<String> = self
+            .rssi
+            .iter()
+            .map(|(source, repeaters)| {
+                let mut stats = repeaters.values().copied();
+                let mut total = stats.next().expect("a source is only added with a sample");
+                stats.for_each(|s| total.add(&s));
+                let repeaters: Vec<String> = repeaters
+                    .iter()
+                    .map(|(repeater, s)| format!("{{\"repeater_id\":\"{}\",{}}}", hex(repeater), s.json_fields()))
+                    .collect();
+                format!(
+                    "{{\"source_id\":\"{}\",{},\"repeaters\":[{}]}}",
+                    hex(source),
+                    total.json_fields(),
+                    repeaters.join(",")
+                )
+            })
+            .collect();
+        format!("{{\"sources\":[{}]}}", sources.join(","))
+    }
+}
+
+fn hex(id: &[u8; 4]) -> String {
+    id.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Scan on `adapter` until the task is dropped, counting into `tally` every
+/// ack heard under `company_id`. A failure to scan is reported and ends it.
+pub async fn listen(
+    adapter: bluer::Adapter,
+    company_id: u16,
+    keyring: &'static [InfraKey],
+    tally: Arc<Mutex<AckTally>>,
+) {
+    if let Err(e) = scan(&adapter, company_id, keyring, &tally).await {
+        eprintln!("warning: scanning for repeater acks failed: {e}");
+    }
+}
+
+async fn scan(
+    adapter: &bluer::Adapter,
+    company_id: u16,
+    keyring: &'static [InfraKey],
+    tally: &Mutex<AckTally>,
+) -> bluer::Result<()> {
+    match monitor(adapter, company_id).await {
+        Ok((_manager, mut monitor)) => {
+            while let Some(event) = monitor.next().await {
+                if let MonitorEvent::DeviceFound(id) = event {
+                    heard(adapter, id.device, company_id, keyring, tally).await?;
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("note: no advertisement monitor ({e}); filtering company IDs on the host");
+            // Duplicate data: a repeater re-airs its acks from the same
+            // address, and each airing may be for another notification.
+            let filter = DiscoveryFilter {
+                transport: DiscoveryTransport::Le,
+                duplicate_data: true,
+                ..Default::default()
+            };
+            adapter.set_discovery_filter(filter).await?;
+            let mut events = std::pin::pin!(adapter.discover_devices_with_changes().await?);
+            while let Some(event) = events.next().await {
+                if let AdapterEvent::DeviceAdded(addr) = event {
+                    heard(adapter, addr, company_id, keyring, tally).await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// An advertisement monitor for manufacturer data under `company_id`. The
+/// manager has to be held as long as the monitor.
+async fn monitor(adapter: &bluer::Adapter, company_id: u16) -> bluer::Result<(MonitorManager, MonitorHandle)> {
+    let manager = adapter.monitor().await?;
+    let monitor = manager
+        .register(Monitor {
+            monitor_type: Type::OrPatterns,
+            patterns: Some(vec![Pattern::new(
+                data_type::MANUFACTURER_SPECIFIC_DATA,
+                0,
+                &company_id.to_le_bytes(),
+            )]),
+            ..Default::default()
+        })
+        .await?;
+    Ok((manager, monitor))
+}
+
+/// Read the manufacturer data of the device at `addr` and count it.
+async fn heard(
+    adapter: &bluer::Adapter,
+    addr: Address,
+    company_id: u16,
+    keyring: &'static [InfraKey],
+    tally: &Mutex<AckTally>,
+) -> bluer::Result<()> {
+    let device = adapter.device(addr)?;
+    let Ok(Some(data)) = device.manufacturer_data().await else {
+        return Ok(());
+    };
+    let rssi = device.rssi().await.ok().flatten();
+    if let Some((notif, repeater, count)) = tally.lock().unwrap().record_heard(&data, company_id, keyring, rssi) {
+        println!(
+            "  ← ack from repeater {repeater:02x?} for {} ({count} repeater(s) so far)",
+            notif.id_hex()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_protocol_core::{
+        INFRA_KEY_CURRENT, INFRA_KEYRING, TransportNotificationBuilder, TransportStatus, TransportType,
+    };
+
+    fn notification(id: u8, seq: u32) -> TransportNotification {
+        TransportNotificationBuilder::new()
+            .notification_id([id; 4])
+            .transport(TransportType::Train)
+            .status(TransportStatus::Coming)
+            .duration_secs(30)
+            .seq(seq)
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap()
+    }
+
+    #[test]
+    fn distinct_repeaters_are_counted_per_notification() {
+        let sent = [notification(1, 10), notification(2, 11)];
+        let mut tally = AckTally::new(&sent);
+        let ack = |notif: &TransportNotification, repeater: u8| {
+            AckBeacon::new(notif, [repeater; 4], INFRA_KEY_CURRENT)
+        };
+
+        assert_eq!(tally.record(ack(&sent[0], 0xA1).as_bytes(), INFRA_KEYRING, None).unwrap().2, 1);
+        // The same repeater again, then a second one.
+        assert!(tally.record(ack(&sent[0], 0xA1).as_bytes(), INFRA_KEYRING, None).is_none());
+        assert_eq!(tally.record(ack(&sent[0], 0xB2).as_bytes(), INFRA_KEYRING, None).unwrap().2, 2);
+
+        // Not ours: an earlier issue of the same notification, a forgery, a
+        // notification rather than an ack.
+        assert!(tally.record(ack(&notification(2, 5), 0xA1).as_bytes(), INFRA_KEYRING, None).is_none());
+        let mut forged = ack(&sent[1], 0xC3);
+        forged.repeater_id = [0xD4; 4];
+        assert!(tally.record(forged.as_bytes(), INFRA_KEYRING, None).is_none());
+        assert!(tally.record(sent[1].as_bytes(), INFRA_KEYRING, None).is_none());
+
+        assert_eq!(
+            tally.report(),
+            [
+                format!("notification {} relayed by 2 repeater(s)", sent[0].id_hex()),
+                format!("notification {} relayed by 0 repeater(s)", sent[1].id_hex()),
+            ]
+        );
+    }
+
+    #[test]
+    fn acks_under_other_company_ids_are_not_counted() {
+        let sent = [notification(1, 10)];
+        let mut tally = AckTally::new(&sent);
+        let ack = AckBeacon::new(&sent[0], [0xA1; 4], INFRA_KEY_CURRENT).as_bytes().to_vec();
+
+        let elsewhere = HashMap::from([(0x1234, ack.clone())]);
+        assert!(tally.record_heard(&elsewhere, 0x05F1, INFRA_KEYRING, None).is_none());
+        let both = HashMap::from([(0x1234, ack.clone()), (0x05F1, ack)]);
+        assert_eq!(tally.record_heard(&both, 0x05F1, INFRA_KEYRING, None).unwrap().2, 1);
+        assert_eq!(tally.report(), [format!("notification {} relayed by 1 repeater(s)", sent[0].id_hex())]);
+    }
+
+    #[test]
+    fn rssi_is_aggregated_per_source_and_repeater() {
+        let from = |id: u8, seq: u32, source: u8| {
+            TransportNotificationBuilder::new()
+                .notification_id([id; 4])
+                .source_id([source; 4])
+                .transport(TransportType::Train)
+                .status(TransportStatus::Coming)
+                .duration_secs(30)
+                .seq(seq)
+                .build_signed(INFRA_KEY_CURRENT)
+                .unwrap()
+        };
+        let sent = [from(1, 10, 0x5A), from(2, 11, 0x5B), from(3, 12, 0x5C)];
+        let mut tally = AckTally::new(&sent);
+        let ack = |i: usize, repeater: u8| AckBeacon::new(&sent[i], [repeater; 4], INFRA_KEY_CURRENT);
+
+        for (i, repeater, rssi) in [
+            (0, 0xA1, Some(-60)),
+            (0, 0xA1, Some(-70)),
+            (1, 0xA1, Some(-80)),
+            (0, 0xB2, Some(-90)),
+            // Counted, but the adapter reported no RSSI to add.
+            (1, 0xB2, None),
+        ] {
+            tally.record(ack(i, repeater).as_bytes(), INFRA_KEYRING, rssi);
+        }
+        // A forgery adds nothing.
+        let mut forged = ack(0, 0xC3);
+        forged.repeater_id = [0xD4; 4];
+        tally.record(forged.as_bytes(), INFRA_KEYRING, Some(-10));
+
+        let a1 = &tally.rssi[&[0x5A; 4]][&[0xA1; 4]];
+        assert_eq!((a1.min, a1.max, a1.count, a1.avg()), (-70, -60, 2, -65.0));
+        assert_eq!(
+            tally.rssi_json(),
+            concat!(
+                r#"{"sources":["#,
+                r#"{"source_id":"5a5a5a5a","min":-90,"max":-60,"avg":-73.3,"count":3,"repeaters":["#,
+                r#"{"repeater_id":"a1a1a1a1","min":-70,"max":-60,"avg":-65.0,"count":2},"#,
+                r#"{"repeater_id":"b2b2b2b2","min":-90,"max":-90,"avg":-90.0,"count":1}]},"#,
+                r#"{"source_id":"5b5b5b5b","min":-80,"max":-80,"avg":-80.0,"count":1,"repeaters":["#,
+                r#"{"repeater_id":"a1a1a1a1","min":-80,"max":-80,"avg":-80.0,"count":1}]}]}"#,
+            )
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Burst advertising (`--burst <count>,<gap-ms>`).
+//!
+//! By default a notification is advertised continuously at the advertising
+//! interval (`--interval-ms`) for its whole broadcast window. A burst pattern
+//! instead airs `count` advertisements back to back, then goes quiet for
+//! `gap`, and repeats.
+//!
+//! Tradeoff: a client that scans for a short window every so often catches a
+//! notification if at least one packet lands inside its window. Continuous
+//! advertising guarantees that but occupies the channel the whole time. A
+//! burst keeps most of the catch probability as long as the period
+//! (`count × interval + gap`) stays shorter than the client's scan window,
+//! while leaving the gaps free for other stations. A gap longer than the
+//! client's scan window means some windows see nothing at all.
+
+use std::time::Duration;
+
+/// A repeating burst of `count` advertisements at `interval`, followed by
+/// `gap` of silence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BurstPattern {
+    pub count: u32,
+    pub interval: Duration,
+    pub gap: Duration,
+}
+
+impl BurstPattern {
+    /// Parse `count,gap_ms`, e.g. `5,400`, using the given advertising
+    /// interval.
+    pub fn parse(value: &str, interval: Duration) -> Result<Self, String> {
+        let Some((count, gap)) = value.split_once(',') else {
+            return Err(format!("expected <count>,<gap-ms>, got '{value}'"));
+        };
+        let count = match count.trim().parse::<u32>() {
+            Ok(c) if c > 0 => c,
+            _ => {
+                return Err(format!(
+                    "burst count must be a positive number, got '{count}'"
+                ));
+            }
+        };
+        let gap = gap
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("burst gap must be milliseconds, got '{gap}'"))?;
+        Ok(Self {
+            count,
+            interval,
+            gap: Duration::from_millis(gap),
+        })
+    }
+
+    /// How long the advertisement stays on for one burst.
+    pub fn on_time(&self) -> Duration {
+        self.interval * self.count
+    }
+
+    /// Length of one burst plus its gap.
+    pub fn period(&self) -> Duration {
+        self.on_time() + self.gap
+    }
+}
+
+/// Advertisement times within a broadcast window of length `window`, relative
+/// to its start.
+pub fn schedule(pattern: &BurstPattern, window: Duration) -> Vec<Duration> {
+    let mut times = Vec::new();
+    let mut burst_start = Duration::ZERO;
+    while burst_start < window {
+        for k in 0..pattern.count {
+            let t = burst_start + pattern.interval * k;
+            if t >= window {
+                break;
+            }
+            times.push(t);
+        }
+        burst_start += pattern.period();
+    }
+    times
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn pattern(count: u32, gap_ms: u32) -> BurstPattern {
+        BurstPattern {
+            count,
+            interval: 20 * MS,
+            gap: gap_ms * MS,
+        }
+    }
+
+    #[test]
+    fn parses_count_and_gap() {
+        assert_eq!(BurstPattern::parse("5,400", 20 * MS), Ok(pattern(5, 400)));
+        assert_eq!(BurstPattern::parse(" 3 , 0 ", 20 * MS), Ok(pattern(3, 0)));
+        for bad in ["5", "0,400", "x,400", "5,-1", "5,abc"] {
+            assert!(
+                BurstPattern::parse(bad, 20 * MS).is_err(),
+                "accepted: {bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn schedule_follows_burst_and_gap() {
+        let times = schedule(&pattern(3, 100), 400 * MS);
+        let ms: Vec<u128> = times.iter().map(Duration::as_millis).collect();
+        // Period = 3 × 20 + 100 = 160 ms.
+        assert_eq!(ms, vec![0, 20, 40, 160, 180, 200, 320, 340, 360]);
+    }
+
+    #[test]
+    fn schedule_is_cut_off_at_the_window_end() {
+        let times = schedule(&pattern(4, 50), 150 * MS);
+        let ms: Vec<u128> = times.iter().map(Duration::as_millis).collect();
+        // Period = 130 ms; the second burst only fits one slot.
+        assert_eq!(ms, vec![0, 20, 40, 60, 130]);
+    }
+
+    #[test]
+    fn zero_gap_is_continuous_advertising() {
+        let times = schedule(&pattern(5, 0), 200 * MS);
+        assert_eq!(times.len(), 10);
+        assert!(times.windows(2).all(|w| w[1] - w[0] == 20 * MS));
+    }
+
+    #[test]
+    fn every_bursts_spacing_matches_the_pattern() {
+        let p = pattern(5, 400);
+        let times = schedule(&p, Duration::from_secs(5));
+        for burst in times.chunks(p.count as usize) {
+            assert_eq!(burst[0].as_nanos() % p.period().as_nanos(), 0);
+            assert!(burst.windows(2).all(|w| w[1] - w[0] == p.interval));
+        }
+        assert_eq!(times.len() as u32, p.count * 10); // 5 s / 500 ms period
+    }
+}
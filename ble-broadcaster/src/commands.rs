@@ -0,0 +1,225 @@
+//! Line protocol for driving the broadcaster from stdin (`--stdin`).
+//!
+//! One command per line:
+//!
+//! ```text
+//! ADD type=<bus|train> status=<passing|coming|late> dest=<0-15> [event=<0-15>] [dur=<0-3600>] [valid=<secs>] [prio=<0-255>] [canary]
+//! REMOVE id=<8 hex digits>
+//! ```
+//!
+//! Blank lines and lines starting with `#` are ignored.
+
+use crate::{FLAG_CANARY, MAX_DURATION_SECS, NotificationSpec, TransportStatus, TransportType};
+
+/// A parsed stdin command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Sign and start broadcasting a new notification.
+    Add(NotificationSpec),
+    /// Stop broadcasting the notification with this id.
+    Remove([u8; 4]),
+}
+
+/// Parse one line. `Ok(None)` means the line was blank or a comment.
+pub fn parse_command(line: &str) -> Result<Option<Command>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut words = line.split_whitespace();
+    let verb = words.next().unwrap_or_default();
+    match verb.to_ascii_uppercase().as_str() {
+        "ADD" => parse_add(words).map(|spec| Some(Command::Add(spec))),
+        "REMOVE" => parse_remove(words).map(|id| Some(Command::Remove(id))),
+        _ => Err(format!("unknown command '{verb}' (expected ADD or REMOVE)")),
+    }
+}
+
+fn parse_add<'a>(words: impl Iterator<Item = &'a str>) -> Result<NotificationSpec, String> {
+    let mut transport_type = None;
+    let mut status = None;
+    let mut destination_id = None;
+    let mut spec = NotificationSpec {
+        transport_type: TransportType::Bus,
+        status: TransportStatus::Passing,
+        event_id: 0,
+        destination_id: 0,
+        duration_secs: 30,
+        validity_secs: 600,
+        flags: 0,
+        priority: 0,
+    };
+
+    for word in words {
+        if word.eq_ignore_ascii_case("canary") {
+            spec.flags |= FLAG_CANARY;
+            continue;
+        }
+        let (key, value) = word
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, got '{word}'"))?;
+        match key {
+            "type" => {
+                transport_type = Some(match value.to_ascii_lowercase().as_str() {
+                    "bus" => TransportType::Bus,
+                    "train" => TransportType::Train,
+                    _ => return Err(format!("invalid type '{value}' (expected bus or train)")),
+                })
+            }
+            "status" => {
+                status = Some(match value.to_ascii_lowercase().as_str() {
+                    "passing" => TransportStatus::Passing,
+                    "coming" => TransportStatus::Coming,
+                    "late" => TransportStatus::Late,
+                    _ => {
+                        return Err(format!(
+                            "invalid status '{value}' (expected passing, coming or late)"
+                        ));
+                    }
+                })
+            }
+            "dest" => destination_id = Some(parse_nibble(key, value)?),
+            "event" => spec.event_id = parse_nibble(key, value)?,
+            "dur" => spec.duration_secs = parse_duration(key, value)?,
+            "valid" => spec.validity_secs = parse_secs(key, value)?,
+            "prio" => {
+                spec.priority = value
+                    .parse()
+                    .map_err(|_| format!("prio must be 0-255, got '{value}'"))?
+            }
+            _ => return Err(format!("unknown key '{key}'")),
+        }
+    }
+
+    spec.transport_type = transport_type.ok_or("missing type=")?;
+    spec.status = status.ok_or("missing status=")?;
+    spec.destination_id = destination_id.ok_or("missing dest=")?;
+    Ok(spec)
+}
+
+fn parse_remove<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<[u8; 4], String> {
+    let word = words.next().ok_or("missing id=")?;
+    let hex = word
+        .strip_prefix("id=")
+        .ok_or_else(|| format!("expected id=<hex>, got '{word}'"))?;
+    if let Some(extra) = words.next() {
+        return Err(format!("unexpected '{extra}' after id"));
+    }
+    if hex.len() != 8 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("id must be 8 hex digits, got '{hex}'"));
+    }
+    let mut id = [0u8; 4];
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|_| format!("id must be 8 hex digits, got '{hex}'"))?;
+    }
+    Ok(id)
+}
+
+fn parse_nibble(key: &str, value: &str) -> Result<u8, String> {
+    match value.parse::<u8>() {
+        Ok(v) if v <= 15 => Ok(v),
+        _ => Err(format!("{key} must be 0-15, got '{value}'")),
+    }
+}
+
+fn parse_duration(key: &str, value: &str) -> Result<u16, String> {
+    match value.parse::<u16>() {
+        Ok(v) if v <= MAX_DURATION_SECS => Ok(v),
+        _ => Err(format!("{key} must be 0-{MAX_DURATION_SECS} seconds, got '{value}'")),
+    }
+}
+
+fn parse_secs(key: &str, value: &str) -> Result<u16, String> {
+    value
+        .parse::<u16>()
+        .map_err(|_| format!("{key} must be 0-65535 seconds, got '{value}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_with_defaults() {
+        let cmd = parse_command("ADD type=train status=late dest=5").unwrap();
+        assert_eq!(
+            cmd,
+            Some(Command::Add(NotificationSpec {
+                transport_type: TransportType::Train,
+                status: TransportStatus::Late,
+                event_id: 0,
+                destination_id: 5,
+                duration_secs: 30,
+                validity_secs: 600,
+                flags: 0,
+                priority: 0,
+            }))
+        );
+    }
+
+    #[test]
+    fn add_with_every_key() {
+        let Some(Command::Add(spec)) =
+            parse_command("add type=bus status=coming dest=15 event=7 dur=45 valid=900 prio=9 canary")
+                .unwrap()
+        else {
+            panic!("expected ADD");
+        };
+        assert_eq!(spec.transport_type, TransportType::Bus);
+        assert_eq!(spec.status, TransportStatus::Coming);
+        assert_eq!((spec.destination_id, spec.event_id), (15, 7));
+        assert_eq!((spec.duration_secs, spec.validity_secs), (45, 900));
+        assert_eq!(spec.flags, FLAG_CANARY);
+        assert_eq!(spec.priority, 9);
+    }
+
+    #[test]
+    fn duration_is_accepted_up_to_the_maximum() {
+        let Some(Command::Add(spec)) =
+            parse_command("ADD type=bus status=late dest=5 dur=3600").unwrap()
+        else {
+            panic!("expected ADD");
+        };
+        assert_eq!(spec.duration_secs, MAX_DURATION_SECS);
+    }
+
+    #[test]
+    fn remove_parses_hex_id() {
+        assert_eq!(
+            parse_command("REMOVE id=a1B2c3D4").unwrap(),
+            Some(Command::Remove([0xA1, 0xB2, 0xC3, 0xD4]))
+        );
+    }
+
+    #[test]
+    fn blank_and_comment_lines_are_skipped() {
+        assert_eq!(parse_command("   ").unwrap(), None);
+        assert_eq!(parse_command("# nightly timetable").unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_commands_are_rejected() {
+        for line in [
+            "LAUNCH type=bus",
+            "ADD status=late dest=5",
+            "ADD type=plane status=late dest=5",
+            "ADD type=bus status=early dest=5",
+            "ADD type=bus status=late dest=16",
+            "ADD type=bus status=late dest=5 dur=-1",
+            "ADD type=bus status=late dest=5 dur=3601",
+            "ADD type=bus status=late dest=5 prio=256",
+            "ADD type=bus status=late dest=5 colour=red",
+            "ADD type=bus status=late dest",
+            "REMOVE",
+            "REMOVE id=a1b2",
+            "REMOVE id=zzzzzzzz",
+            "REMOVE id=aéééb",
+            "REMOVE id=+a+b+c+d",
+            "REMOVE id=a1b2c3d4 extra",
+        ] {
+            assert!(parse_command(line).is_err(), "accepted: {line}");
+        }
+    }
+}
@@ -1,277 +1,1166 @@
-use bluer::adv::Advertisement;
-use hmac::{Hmac, Mac};
-use rand::Rng;
-use sha2::Sha256;
-use std::collections::BTreeMap;
+use ble_protocol_core::conf::{CONF_KEY, SealedNotification};
+use ble_protocol_core::crypto::infra_key;
+use ble_protocol_core::transport::Relay;
+use ble_protocol_core::{
+    EventId, FLAG_CANARY, LEGACY_ADV_DATA_LEN, InfraKey, KeyProvider, StaticKeys,
+    MANUFACTURER_ID, MAX_DURATION_SECS, MFG_AD_OVERHEAD, PROTOCOL_VERSION, PROTOCOL_VERSION_V1, TransportNotification,
+    TransportNotificationBuilder, TransportNotificationV1, TransportStatus, TransportType,
+};
+use bluer::adv::{Advertisement, AdvertisementHandle, PlatformFeature, SecondaryChannel};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use uuid::Uuid;
+use tokio::io::AsyncBufReadExt;
 
+mod acks;
+mod burst;
+mod commands;
+mod transport;
+
+use acks::AckTally;
+use burst::BurstPattern;
+use commands::Command;
+use transport::BleTransport;
+
+/// Where signing keys come from. A Linux host has no eFuse to burn keys
+/// into, so this is the built-in development keyring; everything else asks
+/// `KEYS`, so another provider only needs swapping in here.
+static KEYS: StaticKeys = StaticKeys;
+
+/// Default number of random notifications to generate (`--count`).
 const NOTIFICATION_COUNT: usize = 5;
 
-// ── Protocol definitions ────────────────────────────────────────────────
+/// Default time each group of notifications is advertised before moving to
+/// the next (`--broadcast-secs`).
+const BROADCAST_WINDOW: Duration = Duration::from_secs(5);
 
-/// Custom manufacturer ID used by our protocol.
-const MANUFACTURER_ID: u16 = 0xFFFF;
+/// Default advertising interval while a notification is on air
+/// (`--interval-ms`).
+const ADV_INTERVAL: Duration = Duration::from_millis(20);
 
-/// Current protocol version.
-const PROTOCOL_VERSION: u8 = 1;
+/// How long to wait after advertisement handles are dropped before exiting,
+/// or before registering the next group. bluer unregisters an advertisement
+/// from a spawned task: exiting straight away can cut that short and leave a
+/// stale advertisement registered in BlueZ, and registering straight away
+/// can find every advertising slot still taken.
+const UNREGISTER_GRACE: Duration = Duration::from_millis(200);
 
-/// Infrastructure key: shared between broadcaster and repeater.
-/// Used by the broadcaster to sign, and by the repeater to verify.
-/// In production, store in eFuse — assumed impossible to extract.
-const HMAC_KEY_INFRA: &[u8] = b"infra-secret-key-efuse!!";
+/// Wait before the first attempt to re-acquire a lost adapter; doubled
+/// after each failed attempt, up to `REACQUIRE_BACKOFF_MAX`.
+const REACQUIRE_BACKOFF_MIN: Duration = Duration::from_secs(1);
 
+/// Longest wait between attempts to re-acquire a lost adapter.
+const REACQUIRE_BACKOFF_MAX: Duration = Duration::from_secs(30);
 
-/// Number of bytes of the truncated HMAC-SHA256 infrastructure tag.
-/// 8 bytes = 64-bit tag (strong enough, fits BLE payload).
-const HMAC_TAG_INFRA_LEN: usize = 8;
+/// Local name included in every advertisement.
+const LOCAL_NAME: &str = "TransportNotifier";
 
-/// Number of bytes of the truncated HMAC-SHA256 client tag.
-/// 4 bytes = 32-bit tag (compact, set by first repeater).
-const HMAC_TAG_CLIENT_LEN: usize = 4;
+/// Advertising data of one advertisement carrying a `payload_len`-byte
+/// payload: the manufacturer data plus the local name's AD structure.
+const fn adv_data_len(payload_len: usize) -> usize {
+    payload_len + MFG_AD_OVERHEAD + 2 + LOCAL_NAME.len()
+}
 
-type HmacSha256 = Hmac<Sha256>;
+/// Legal BLE advertising interval range in whole milliseconds
+/// (0x0020..=0x4000 in 0.625 ms units).
+const ADV_INTERVAL_RANGE_MS: std::ops::RangeInclusive<u64> = 20..=10_240;
 
-#[repr(u8)]
+/// The caller-chosen content of a notification; ids are filled in by `pack`,
+/// `seq`, timestamp and tags by `Signer::sign`.
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum TransportType {
-    Bus = 1,
-    Train = 2,
+struct NotificationSpec {
+    transport_type: TransportType,
+    status: TransportStatus,
+    event_id: u8,
+    destination_id: u8,
+    duration_secs: u16,
+    validity_secs: u16,
+    flags: u8,
+    priority: u8,
+}
+
+impl NotificationSpec {
+    /// Pack the spec into an unsigned notification. Both nibbles and the
+    /// duration are in range by construction: from `commands::parse_nibble`
+    /// and `parse_duration`, `random_notification` or a literal.
+    fn pack(&self, source_id: [u8; 4], notification_id: [u8; 4]) -> TransportNotification {
+        TransportNotificationBuilder::new()
+            .source_id(source_id)
+            .notification_id(notification_id)
+            .event(EventId::from_u4(self.event_id))
+            .destination(self.destination_id)
+            .transport(self.transport_type)
+            .status(self.status)
+            .duration_secs(self.duration_secs)
+            .validity_secs(self.validity_secs)
+            .flags(self.flags)
+            .priority(self.priority)
+            .build_unsigned()
+            .expect("spec nibbles and duration are in range")
+    }
+}
+
+/// Unix-epoch milliseconds, for `timestamp_ms`.
+fn unix_now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// `timestamp_ms` of every notification in deterministic mode
+/// (2026-01-01T00:00:00Z).
+const DETERMINISTIC_TIME_MS: u64 = 1_767_225_600_000;
+
+/// Stamps notifications with what they need besides their content: the next
+/// `seq`, an emission time, and the infrastructure tag.
+struct Signer {
+    seq: SeqCounter,
+    key: InfraKey,
+    /// Fixed `timestamp_ms` for reproducible output; `None` reads the clock.
+    fixed_time_ms: Option<u64>,
 }
 
-impl TransportType {
-    fn from_u8(v: u8) -> Option<Self> {
-        match v {
-            1 => Some(Self::Bus),
-            2 => Some(Self::Train),
-            _ => None,
+impl Signer {
+    /// `seq` seeded from the clock and real emission times.
+    fn live(key: InfraKey) -> Self {
+        Self {
+            seq: SeqCounter::from_clock(),
+            key,
+            fixed_time_ms: None,
+        }
+    }
+
+    /// `seq` from 0 and a fixed emission time, so the same notifications
+    /// always sign to the same bytes. Repeaters that already accepted a run
+    /// reject a repeat of it as a replay, and ones with a clock as stale.
+    fn deterministic(key: InfraKey) -> Self {
+        Self {
+            seq: SeqCounter(0),
+            key,
+            fixed_time_ms: Some(DETERMINISTIC_TIME_MS),
         }
     }
+
+    /// Pack `spec` and sign it.
+    fn sign(
+        &mut self,
+        spec: &NotificationSpec,
+        source_id: [u8; 4],
+        notification_id: [u8; 4],
+    ) -> TransportNotification {
+        let mut notif = spec.pack(source_id, notification_id);
+        self.restamp(&mut notif);
+        notif
+    }
+
+    /// Give `notif` the next `seq` and a current `timestamp_ms`, and re-sign
+    /// it. Also used before each airing of a long-lived notification, which
+    /// would otherwise be rejected as stale by repeaters with a clock once it
+    /// passes `MAX_AGE_MS`, and its unchanged `seq` as a replay.
+    fn restamp(&mut self, notif: &mut TransportNotification) {
+        let now_ms = self.fixed_time_ms.unwrap_or_else(unix_now_ms);
+        notif.seq = self.seq.next().to_le_bytes();
+        notif.timestamp_ms = TransportNotification::timestamp_bytes(now_ms);
+        notif.sign_infra_with(self.key);
+    }
+}
+
+/// Hands out `seq` values, one per notification (or airing, see
+/// `Signer::restamp`).
+///
+/// Seeded from the wall clock in seconds, so a restarted broadcaster resumes
+/// ahead of the last `seq` repeaters accepted from it, as long as it averaged
+/// under one notification per second. Wraps at `u32::MAX`, which repeaters
+/// handle (see `ble_protocol_core::seq`).
+struct SeqCounter(u32);
+
+impl SeqCounter {
+    fn from_clock() -> Self {
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Self(secs as u32)
+    }
+
+    fn next(&mut self) -> u32 {
+        let seq = self.0;
+        self.0 = self.0.wrapping_add(1);
+        seq
+    }
+}
+
+/// A random 32-bit short id.
+fn short_id(rng: &mut impl Rng) -> [u8; 4] {
+    let mut id = [0u8; 4];
+    rng.fill(&mut id);
+    id
 }
 
-#[repr(u8)]
+/// Relative weights for picking a random `TransportStatus`.
+///
+/// The default (45 passing / 45 coming / 10 late) keeps alerts rare, roughly
+/// like real traffic; `StatusWeights::UNIFORM` restores an even split, and a
+/// tester can raise `late` to stress alert handling.
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum TransportStatus {
-    Passing = 1,
-    Coming = 2,
-    Late = 3,
-}
-
-impl TransportStatus {
-    fn from_u8(v: u8) -> Option<Self> {
-        match v {
-            1 => Some(Self::Passing),
-            2 => Some(Self::Coming),
-            3 => Some(Self::Late),
-            _ => None,
-        }
-    }
-}
-
-#[repr(C, packed)]
-#[derive(Debug, Clone, Copy)]
-struct TransportNotification {
-    version: u8,
-    source_id: [u8; 4],
-    notification_id: [u8; 4],
-    /// High nibble = event_id (0–15), low nibble = destination_id (0–15).
-    event_dest: u8,
-    /// High nibble = transport_type, low nibble = transport_status.
-    type_status: u8,
-    /// How long (in seconds) this notification should be re-broadcast.
-    duration_secs: u16,
-    /// HMAC tag signed by the broadcaster (infrastructure key).
-    /// Verified by every repeater in the chain — never modified.
-    hmac_tag_infra: [u8; HMAC_TAG_INFRA_LEN],
-    /// HMAC tag signed by the first repeater (client key).
-    /// Verified by the client app. Set to zeroes by the broadcaster.
-    hmac_tag_client: [u8; HMAC_TAG_CLIENT_LEN],
+struct StatusWeights {
+    passing: u32,
+    coming: u32,
+    late: u32,
 }
 
-impl TransportNotification {
-    /// Size of the full struct in bytes (including both HMAC tags).
-    const SIZE: usize = core::mem::size_of::<Self>();
+impl StatusWeights {
+    const UNIFORM: Self = Self {
+        passing: 1,
+        coming: 1,
+        late: 1,
+    };
 
-    /// Byte size of the base payload (everything before the two HMAC tags).
-    /// This is what both HMAC tags authenticate.
-    const BASE_PAYLOAD_SIZE: usize = Self::SIZE - HMAC_TAG_INFRA_LEN - HMAC_TAG_CLIENT_LEN;
+    /// Parse `passing,coming,late`, e.g. `70,20,10`.
+    fn parse(value: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = value.split(',').collect();
+        let [passing, coming, late] = parts[..] else {
+            return Err(format!("expected three comma-separated weights, got '{value}'"));
+        };
+        let weight = |w: &str| {
+            w.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("invalid weight '{w}' in '{value}'"))
+        };
+        let weights = Self {
+            passing: weight(passing)?,
+            coming: weight(coming)?,
+            late: weight(late)?,
+        };
+        if weights.passing as u64 + weights.coming as u64 + weights.late as u64 == 0 {
+            return Err("at least one status weight must be non-zero".to_string());
+        }
+        Ok(weights)
+    }
 
-    // ── Nibble accessors ────────────────────────────────────────────
+    /// Pick a status with probability proportional to its weight.
+    fn pick(&self, rng: &mut impl Rng) -> TransportStatus {
+        let choices = [
+            (TransportStatus::Passing, self.passing),
+            (TransportStatus::Coming, self.coming),
+            (TransportStatus::Late, self.late),
+        ];
+        let dist = WeightedIndex::new(choices.iter().map(|(_, w)| *w))
+            .expect("weights validated non-zero");
+        choices[dist.sample(rng)].0
+    }
+}
 
-    fn event_id(&self) -> u8 {
-        ({ self.event_dest } >> 4) & 0x0F
+impl Default for StatusWeights {
+    fn default() -> Self {
+        Self {
+            passing: 45,
+            coming: 45,
+            late: 10,
+        }
     }
+}
+
+/// Build a random TransportNotification with the given `flags`, signed by
+/// `signer`, drawing everything random from `rng` and picking its status
+/// according to `weights`.
+fn random_notification(
+    rng: &mut impl Rng,
+    flags: u8,
+    weights: &StatusWeights,
+    signer: &mut Signer,
+) -> TransportNotification {
+    let transport_type = if rng.gen_bool(0.5) {
+        TransportType::Bus
+    } else {
+        TransportType::Train
+    };
+
+    let status = weights.pick(rng);
+
+    let spec = NotificationSpec {
+        transport_type,
+        status,
+        event_id: EventId::ALL[rng.gen_range(0..EventId::ALL.len())].to_u4(),
+        destination_id: rng.gen_range(0..=15),
+        duration_secs: 30,
+        validity_secs: 600,
+        flags,
+        priority: 0,
+    };
+
+    // Each random notification comes from its own random station.
+    signer.sign(&spec, short_id(rng), short_id(rng))
+}
+
+/// `source_id` and `notification_id` of the `--fixed` notification.
+const FIXED_SOURCE_ID: [u8; 4] = [0x01, 0x02, 0x03, 0x04];
+const FIXED_NOTIFICATION_ID: [u8; 4] = [0x05, 0x06, 0x07, 0x08];
+
+/// The `--fixed` notification: known ids and content, for scripting a test
+/// against an expected packet.
+fn fixed_notification(flags: u8, signer: &mut Signer) -> TransportNotification {
+    let spec = NotificationSpec {
+        transport_type: TransportType::Bus,
+        status: TransportStatus::Coming,
+        event_id: EventId::Departure.to_u4(),
+        destination_id: 2,
+        duration_secs: 30,
+        validity_secs: 600,
+        flags,
+        priority: 0,
+    };
+    signer.sign(&spec, FIXED_SOURCE_ID, FIXED_NOTIFICATION_ID)
+}
 
-    fn destination_id(&self) -> u8 {
-        ({ self.event_dest }) & 0x0Fu8
+/// The notifications a batch run advertises: `--fixed`, or `--count` random
+/// ones (reproducible with `--seed`).
+fn batch(args: &Args) -> Vec<TransportNotification> {
+    let flags = if args.canary { FLAG_CANARY } else { 0 };
+    let mut signer = args.signer();
+    if args.fixed {
+        return vec![fixed_notification(flags, &mut signer)];
     }
+    let mut rng = args.rng();
+    (0..args.count)
+        .map(|_| random_notification(&mut rng, flags, &args.status_weights, &mut signer))
+        .collect()
+}
+
+/// Secondary channel to request for `adv_data_len` bytes of advertising data.
+/// Setting one makes BlueZ use extended advertising; `None` keeps legacy
+/// advertising, which every scanner hears, whenever the data fits it.
+fn secondary_channel(extended: bool, adv_data_len: usize) -> Option<SecondaryChannel> {
+    (extended && adv_data_len > LEGACY_ADV_DATA_LEN).then_some(SecondaryChannel::OneM)
+}
 
-    fn transport_type(&self) -> Option<TransportType> {
-        TransportType::from_u8(({ self.type_status } >> 4) & 0x0F)
+/// The bytes to put on air for `notif`: as is, re-signed in the v1 layout
+/// for `--protocol-version 1`, or with `--encrypt` sealed under a fresh
+/// random nonce. Called for every airing, so a nonce is never used twice
+/// (see `ble_protocol_core::conf`); it comes from the OS-seeded thread RNG,
+/// never from the `--seed` one, whose draws repeat across runs.
+fn wire_payload(notif: &TransportNotification, args: &Args) -> Vec<u8> {
+    if args.protocol_version == PROTOCOL_VERSION_V1 {
+        return TransportNotificationV1::downgrade(notif, args.infra_key()).as_bytes().to_vec();
+    }
+    if !args.encrypt {
+        return notif.as_bytes().to_vec();
     }
+    SealedNotification::seal(notif, args.infra_key(), CONF_KEY, rand::random())
+        .as_bytes()
+        .to_vec()
+}
 
-    fn transport_status(&self) -> Option<TransportStatus> {
-        TransportStatus::from_u8({ self.type_status } & 0x0F)
+/// Build the non-connectable advertisement carrying `payload` under
+/// `company_id`, advertised every `interval`; with `extended`, as a BLE 5
+/// extended advertisement if it doesn't fit a legacy one.
+fn advertisement(
+    payload: Vec<u8>,
+    company_id: u16,
+    interval: Duration,
+    extended: bool,
+) -> Advertisement {
+    let adv_data_len = adv_data_len(payload.len());
+    let mut manufacturer_data = BTreeMap::new();
+    manufacturer_data.insert(company_id, payload);
+
+    // Type::Broadcast produces ADV_NONCONN_IND — the advertisement is
+    // non-connectable by definition.  Scanners will still see it in
+    // their discovery results.
+    Advertisement {
+        advertisement_type: bluer::adv::Type::Broadcast,
+        manufacturer_data,
+        min_interval: Some(interval),
+        max_interval: Some(interval),
+        local_name: Some(LOCAL_NAME.to_string()),
+        secondary_channel: secondary_channel(extended, adv_data_len),
+        ..Default::default()
     }
+}
+
+// ── Adapter startup errors ──────────────────────────────────────────────
+
+/// The startup step that produced an error, for `startup_error`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StartupStep {
+    /// Connecting to BlueZ over the system D-Bus.
+    Session,
+    /// Finding the adapter and reading its power state.
+    Adapter,
+    /// Powering on an adapter that was off.
+    PowerOn,
+}
+
+/// Turn a startup failure into a message that says what to fix. Errors we
+/// don't recognise keep bluer's own text, prefixed with the failed step.
+fn startup_error(step: StartupStep, err: &bluer::Error) -> String {
+    use bluer::{ErrorKind, InternalErrorKind};
 
-    /// Return the full struct as a byte slice (for re-broadcast).
-    fn as_bytes(&self) -> &[u8] {
-        unsafe {
-            core::slice::from_raw_parts(
-                (self as *const Self) as *const u8,
-                Self::SIZE,
-            )
+    let dbus_name = match &err.kind {
+        ErrorKind::Internal(InternalErrorKind::DBus(name)) => name.as_str(),
+        _ => "",
+    };
+    let hint = match (&err.kind, step) {
+        _ if matches!(
+            dbus_name,
+            "org.freedesktop.DBus.Error.ServiceUnknown" | "org.freedesktop.DBus.Error.NameHasNoOwner"
+        ) =>
+        {
+            "bluetoothd is not running — start it with `systemctl start bluetooth`"
+        }
+        (ErrorKind::Internal(InternalErrorKind::Io(_) | InternalErrorKind::DBusConnectionLost), _)
+        | (ErrorKind::Internal(InternalErrorKind::DBus(_)), StartupStep::Session) => {
+            "cannot connect to the system D-Bus — is dbus running?"
+        }
+        (ErrorKind::NotFound, StartupStep::Adapter) => {
+            "no Bluetooth adapter found — is Bluetooth enabled and are you in the `bluetooth` group?"
+        }
+        (ErrorKind::NotAuthorized | ErrorKind::NotPermitted, StartupStep::PowerOn) => {
+            "not allowed to power on the adapter — run as root or as a member of the `bluetooth` group"
         }
+        _ if step == StartupStep::PowerOn && dbus_name == "org.freedesktop.DBus.Error.AccessDenied" => {
+            "D-Bus policy denies powering on the adapter — run as root or as a member of the `bluetooth` group"
+        }
+        (ErrorKind::Failed, StartupStep::PowerOn) if err.message.contains("rfkill") => {
+            "the adapter is blocked by rfkill — run `rfkill unblock bluetooth`"
+        }
+        _ => {
+            let what = match step {
+                StartupStep::Session => "connecting to BlueZ",
+                StartupStep::Adapter => "opening the Bluetooth adapter",
+                StartupStep::PowerOn => "powering on the Bluetooth adapter",
+            };
+            return format!("{what} failed: {err}");
+        }
+    };
+    format!("{hint} ({err})")
+}
+
+/// Connect to BlueZ and return the adapter called `name` (the default one
+/// if `None`), powering it on if it is off. The session is returned too,
+/// since the adapter is only usable while it is alive.
+async fn open_adapter(name: Option<&str>) -> Result<(bluer::Session, bluer::Adapter), String> {
+    let fail = |step| move |e: bluer::Error| startup_error(step, &e);
+
+    let session = bluer::Session::new().await.map_err(fail(StartupStep::Session))?;
+    let adapter = match name {
+        Some(name) => session.adapter(name),
+        None => session.default_adapter().await,
+    }
+    .map_err(fail(StartupStep::Adapter))?;
+    if !adapter.is_powered().await.map_err(fail(StartupStep::Adapter))? {
+        println!("Adapter {} is powered off — powering it on.", adapter.name());
+        adapter.set_powered(true).await.map_err(fail(StartupStep::PowerOn))?;
     }
+    Ok((session, adapter))
+}
 
-    /// Return only the base payload (everything before both HMAC tags).
-    fn base_payload(&self) -> &[u8] {
-        unsafe {
-            core::slice::from_raw_parts(
-                (self as *const Self) as *const u8,
-                Self::BASE_PAYLOAD_SIZE,
-            )
-        }
+// ── Adapter capabilities ────────────────────────────────────────────────
+
+/// What an adapter reports about its advertising support.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct AdvertisingCaps {
+    /// Advertising instances still free. Other advertisers on the same
+    /// controller count against it.
+    instances: u8,
+    /// BlueZ's `HardwareOffload` platform feature: advertising parameters,
+    /// the interval among them, are handed to the controller. Without it
+    /// BlueZ advertises at its own default interval.
+    offload: bool,
+    /// Secondary channels for extended advertising; empty if the controller
+    /// has none.
+    secondary_channels: BTreeSet<SecondaryChannel>,
+}
+
+impl AdvertisingCaps {
+    async fn probe(adapter: &bluer::Adapter) -> bluer::Result<Self> {
+        let features = adapter.supported_advertising_features().await?.unwrap_or_default();
+        Ok(Self {
+            instances: adapter.supported_advertising_instances().await?,
+            offload: features.contains(&PlatformFeature::HardwareOffload),
+            secondary_channels: adapter.supported_advertising_secondary_channels().await?.unwrap_or_default(),
+        })
     }
 
-    /// Compute a truncated HMAC-SHA256 tag of `N` bytes over the given data.
-    fn compute_tag<const N: usize>(key: &[u8], data: &[u8]) -> [u8; N] {
-        let mut mac =
-            HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
-        mac.update(data);
-        let result = mac.finalize().into_bytes();
-        let mut tag = [0u8; N];
-        tag.copy_from_slice(&result[..N]);
-        tag
+    /// Whether the requested advertising interval takes effect.
+    fn sets_interval(&self) -> bool {
+        self.offload
     }
 
-    /// Verify the infrastructure HMAC tag (broadcaster → repeater chain).
-    fn verify_infra(&self) -> bool {
-        let expected: [u8; HMAC_TAG_INFRA_LEN] =
-            Self::compute_tag(HMAC_KEY_INFRA, self.base_payload());
-        expected == ({ self.hmac_tag_infra })
+    /// Whether more than one notification can be on air at once.
+    fn multiple_instances(&self) -> bool {
+        self.instances > 1
     }
 
-    /// Returns true if the client tag has been set (non-zero).
-    fn has_client_tag(&self) -> bool {
-        ({ self.hmac_tag_client }) != [0u8; HMAC_TAG_CLIENT_LEN]
+    /// Warnings for advertising as `args` asks on this adapter.
+    fn warnings(&self, args: &Args) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.sets_interval() {
+            warnings.push(format!(
+                "adapter can't set the advertising interval; BlueZ will use its default instead of {} ms",
+                args.adv_interval.as_millis()
+            ));
+        }
+        if !self.multiple_instances() && !args.fixed {
+            warnings.push(format!(
+                "adapter has {} advertising instance(s) free; notifications go on air one at a time",
+                self.instances
+            ));
+        }
+        if args.extended && self.secondary_channels.is_empty() {
+            warnings.push("--extended given, but the adapter reports no extended advertising".to_string());
+        }
+        warnings
     }
+}
 
-    /// Parse and verify a notification from the manufacturer-data payload.
-    /// Verifies the infrastructure HMAC tag. Returns `None` if invalid.
-    fn from_payload(payload: &[u8]) -> Option<Self> {
-        println!("  ▶ Parsing notification from payload ({} B)", payload.len());
-        if payload.len() < Self::SIZE {
-            return None;
+impl std::fmt::Display for AdvertisingCaps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} advertising instance(s) free, interval {}settable",
+            self.instances,
+            if self.sets_interval() { "" } else { "not " }
+        )?;
+        if self.secondary_channels.is_empty() {
+            write!(f, ", no extended advertising")
+        } else {
+            let channels: Vec<String> = self.secondary_channels.iter().map(ToString::to_string).collect();
+            write!(f, ", extended advertising on {}", channels.join("/"))
         }
+    }
+}
 
-        let notif: Self = unsafe {
-            let ptr = payload.as_ptr() as *const Self;
-            core::ptr::read_unaligned(ptr)
+/// `--list-adapters`: print every BlueZ adapter with its address, power
+/// state and advertising capabilities.
+async fn list_adapters() -> Result<(), String> {
+    let session = bluer::Session::new()
+        .await
+        .map_err(|e| startup_error(StartupStep::Session, &e))?;
+    let names = session
+        .adapter_names()
+        .await
+        .map_err(|e| startup_error(StartupStep::Adapter, &e))?;
+    if names.is_empty() {
+        println!("No Bluetooth adapters found.");
+        return Ok(());
+    }
+    let default = session.default_adapter().await.ok().map(|a| a.name().to_string());
+    for name in names {
+        let marker = if default.as_deref() == Some(name.as_str()) { " (default)" } else { "" };
+        let describe = async {
+            let adapter = session.adapter(&name)?;
+            let address = adapter.address().await?;
+            let powered = if adapter.is_powered().await? { "powered" } else { "powered off" };
+            let caps = AdvertisingCaps::probe(&adapter).await?;
+            bluer::Result::Ok(format!("{address}, {powered}, {caps}"))
         };
+        match describe.await {
+            Ok(description) => println!("{name}{marker}: {description}"),
+            Err(e) => println!("{name}{marker}: {e}"),
+        }
+    }
+    Ok(())
+}
+
+// ── Adapter recovery ────────────────────────────────────────────────────
+
+/// Whether `err` means the adapter itself went away (a USB dongle pulled,
+/// the controller reset) rather than that one request failed.
+fn adapter_lost(err: &bluer::Error) -> bool {
+    use bluer::ErrorKind;
+
+    matches!(err.kind, ErrorKind::NotReady | ErrorKind::NotFound | ErrorKind::DoesNotExist)
+}
+
+/// How long to wait before re-acquire attempt `attempt` (counted from 0).
+fn reacquire_delay(attempt: u32) -> Duration {
+    REACQUIRE_BACKOFF_MIN
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(REACQUIRE_BACKOFF_MAX)
+}
 
-        // Validate protocol version
-        if { notif.version } != PROTOCOL_VERSION {
-            return None;
+/// The adapter everything is advertised on. When it goes away mid-run, the
+/// next advertisement waits for it to come back instead of failing the run,
+/// so re-plugging a dongle resumes broadcasting where it left off.
+struct Radio {
+    /// `--adapter`, or `None` for the default adapter.
+    name: Option<String>,
+    /// Keeps `adapter` usable.
+    _session: bluer::Session,
+    adapter: bluer::Adapter,
+    /// What the adapter reported when it was opened.
+    caps: AdvertisingCaps,
+    /// How many advertisements we can keep registered at once: the
+    /// instances the controller had free when the adapter was opened, at
+    /// least one.
+    slots: usize,
+}
+
+impl Radio {
+    async fn open(name: Option<String>) -> Result<Self, String> {
+        let (session, adapter) = open_adapter(name.as_deref()).await?;
+        // BlueZ reports the instances still free, so this is read before we
+        // register anything.
+        let caps = AdvertisingCaps::probe(&adapter)
+            .await
+            .map_err(|e| startup_error(StartupStep::Adapter, &e))?;
+        let slots = usize::from(caps.instances).max(1);
+        Ok(Self { name, _session: session, adapter, caps, slots })
+    }
+
+    /// Register `adv`. If the adapter is gone, re-acquire it first, for as
+    /// long as that takes.
+    async fn advertise(&mut self, adv: Advertisement) -> bluer::Result<AdvertisementHandle> {
+        loop {
+            match self.adapter.advertise(adv.clone()).await {
+                Err(e) if adapter_lost(&e) => {
+                    eprintln!("Adapter {} lost: {e}", self.adapter.name());
+                    self.reacquire().await;
+                }
+                result => return result,
+            }
         }
-        // Validate packed enum nibbles
-        notif.transport_type()?;
-        notif.transport_status()?;
+    }
 
-        // Verify infrastructure HMAC tag (set by broadcaster, never changes)
-        if !notif.verify_infra() {
-            println!("    ✗ infra HMAC mismatch — rejecting forged notification");
-            return None;
+    /// Re-open the adapter, backing off between attempts, until it is back.
+    async fn reacquire(&mut self) {
+        for attempt in 0.. {
+            let delay = reacquire_delay(attempt);
+            eprintln!("  re-acquiring adapter in {}s (attempt {})", delay.as_secs(), attempt + 1);
+            tokio::time::sleep(delay).await;
+            match Self::open(self.name.clone()).await {
+                Ok(radio) => {
+                    println!("Adapter {} is back, resuming.", radio.adapter.name());
+                    *self = radio;
+                    return;
+                }
+                Err(e) => eprintln!("  {e}"),
+            }
         }
+    }
+}
+
+/// Register an advertisement for each of `notifs`, all on air at once.
+async fn advertise_all(
+    radio: &mut Radio,
+    notifs: &[TransportNotification],
+    args: &Args,
+) -> bluer::Result<Vec<AdvertisementHandle>> {
+    let mut handles = Vec::with_capacity(notifs.len());
+    for notif in notifs {
+        let adv = advertisement(wire_payload(notif, args), args.manufacturer_id, args.adv_interval, args.extended);
+        handles.push(radio.advertise(adv).await?);
+    }
+    Ok(handles)
+}
+
+/// Indices of the notifications to put on air together out of `len`, with
+/// `slots` advertisements available, starting at `next` and wrapping.
+fn rotation(len: usize, next: usize, slots: usize) -> Vec<usize> {
+    (0..len.min(slots)).map(|i| (next + i) % len).collect()
+}
+
+// ── Adapter power on exit ───────────────────────────────────────────────
 
-        Some(notif)
+/// What to do with the adapter's power state when the broadcaster exits.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum InterfacePower {
+    /// Leave the adapter powered as it is (default).
+    #[default]
+    Keep,
+    /// Power the adapter off on every exit path, so an unattended kiosk
+    /// doesn't keep a stale advertisement or an idle radio running.
+    OffOnExit,
+}
+
+// ── Command line ────────────────────────────────────────────────────────
+
+/// Broadcaster options.
+#[derive(Debug, Clone, PartialEq)]
+struct Args {
+    /// `--interface-power <keep|off-on-exit>`
+    interface_power: InterfacePower,
+    /// `--adapter <name>`: BlueZ adapter to advertise on, e.g. `hci1`.
+    /// Defaults to BlueZ's default adapter.
+    adapter: Option<String>,
+    /// `--canary`: mark every generated notification as a test/canary.
+    canary: bool,
+    /// `--status-weights <passing,coming,late>` / `--uniform-status`:
+    /// relative status weights for generated notifications.
+    status_weights: StatusWeights,
+    /// `--stdin`: take ADD/REMOVE commands from stdin instead of generating
+    /// a random batch.
+    stdin: bool,
+    /// `--burst <count>,<gap-ms>`: advertise in bursts instead of
+    /// continuously (see `burst`).
+    burst: Option<BurstPattern>,
+    /// `--key-id <id>`: infrastructure key to sign with, by its id in
+    /// `KEYS`' keyring. Defaults to `KEYS`' current key.
+    infra_key: Option<InfraKey>,
+    /// `--count <n>`: number of random notifications to generate.
+    count: usize,
+    /// `--broadcast-secs <s>`: how long each notification stays on air.
+    broadcast_window: Duration,
+    /// `--interval-ms <ms>`: advertising interval, within
+    /// `ADV_INTERVAL_RANGE_MS`.
+    adv_interval: Duration,
+    /// `--seed <u64>`: draw random notifications from a seeded RNG, with
+    /// `seq` and timestamps fixed, so runs are byte-for-byte reproducible.
+    seed: Option<u64>,
+    /// `--fixed`: advertise only the hardcoded `fixed_notification`.
+    fixed: bool,
+    /// `--extended`: use BLE 5 extended advertising when a notification
+    /// doesn't fit a legacy advertisement. Needs controller support.
+    extended: bool,
+    /// `--manufacturer-id <id>`: Bluetooth SIG company ID to advertise under,
+    /// decimal or `0x` hex. The 0xFFFF testing ID is the default, and is
+    /// rejected in release builds.
+    manufacturer_id: u16,
+    /// `--encrypt`: seal each notification with AES-CCM before it goes on
+    /// air (see `ble_protocol_core::conf`). Sealed payloads need
+    /// `--extended`, and are not reproducible under `--seed` or `--fixed`.
+    encrypt: bool,
+    /// `--protocol-version <1|8>`: the wire version to emit. 1, the layout
+    /// the first repeaters and clients shipped with, is for areas where they
+    /// aren't upgraded yet; it keeps only the id, event, destination, type,
+    /// status and duration, and can't be sealed.
+    protocol_version: u8,
+    /// `--list-adapters`: list the BlueZ adapters and what they support,
+    /// then exit.
+    list_adapters: bool,
+    /// `--acks-secs <s>`: scan for repeater acks while broadcasting and for
+    /// this long after, then report how many repeaters relayed each
+    /// notification (see `acks`).
+    ack_listen: Option<Duration>,
+    /// `--filter-company-id <hex>`: the company ID to scan for acks under,
+    /// when it differs from `--manufacturer-id`, e.g. on a bench where the
+    /// repeaters re-air under another one. Needs `--acks-secs`.
+    filter_company_id: Option<u16>,
+    /// `--rssi-json`: after the ack report, print the RSSI acks were heard
+    /// at per source and repeater as JSON (see `acks`). Needs `--acks-secs`.
+    rssi_json: bool,
+    /// `--relay`: relay what is heard under `--manufacturer-id` as a
+    /// repeater would, instead of broadcasting, each relayed notification
+    /// on air for `--broadcast-secs` (see `transport`).
+    relay: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            interface_power: InterfacePower::default(),
+            adapter: None,
+            canary: false,
+            status_weights: StatusWeights::default(),
+            stdin: false,
+            burst: None,
+            infra_key: None,
+            count: NOTIFICATION_COUNT,
+            broadcast_window: BROADCAST_WINDOW,
+            adv_interval: ADV_INTERVAL,
+            seed: None,
+            fixed: false,
+            extended: false,
+            manufacturer_id: MANUFACTURER_ID,
+            encrypt: false,
+            protocol_version: PROTOCOL_VERSION,
+            list_adapters: false,
+            ack_listen: None,
+            filter_company_id: None,
+            rssi_json: false,
+            relay: false,
+        }
     }
 }
 
-/// Build a random TransportNotification with a valid HMAC tag.
-fn random_notification() -> TransportNotification {
-    let mut rng = rand::thread_rng();
+impl Args {
+    /// The infrastructure key notifications are signed with.
+    fn infra_key(&self) -> InfraKey {
+        self.infra_key.unwrap_or_else(|| KEYS.current_infra_key())
+    }
 
-    let transport_type = if rng.gen_bool(0.5) {
-        TransportType::Bus
-    } else {
-        TransportType::Train
-    };
+    /// Whether output must be reproducible (`--seed` or `--fixed`).
+    fn deterministic(&self) -> bool {
+        self.seed.is_some() || self.fixed
+    }
 
-    let status = match rng.gen_range(0u8..3) {
-        0 => TransportStatus::Passing,
-        1 => TransportStatus::Coming,
-        _ => TransportStatus::Late,
-    };
+    fn signer(&self) -> Signer {
+        if self.deterministic() {
+            Signer::deterministic(self.infra_key())
+        } else {
+            Signer::live(self.infra_key())
+        }
+    }
 
-    // Generate a UUID v4 and take the first 4 bytes as a 32-bit short UUID.
-    let notif_uuid = Uuid::new_v4();
-    let mut notification_id = [0u8; 4];
-    notification_id.copy_from_slice(&notif_uuid.as_bytes()[..4]);
-
-    // Generate a UUID v4 and take the first 4 bytes as the station source_id.
-    let source_uuid = Uuid::new_v4();
-    let mut source_id = [0u8; 4];
-    source_id.copy_from_slice(&source_uuid.as_bytes()[..4]);
-
-    // Pack event_id (high nibble) and destination_id (low nibble) into one byte.
-    let event_id: u8 = rng.gen_range(0..=15);
-    let destination_id: u8 = rng.gen_range(0..=15);
-    let event_dest = (event_id << 4) | (destination_id & 0x0F);
-
-    // Pack transport_type (high nibble) and transport_status (low nibble).
-    let type_status = ((transport_type as u8) << 4) | (status as u8);
-
-    let mut notif = TransportNotification {
-        version: PROTOCOL_VERSION,
-        source_id,
-        notification_id,
-        event_dest,
-        type_status,
-        duration_secs: 30,
-        hmac_tag_infra: [0u8; HMAC_TAG_INFRA_LEN],
-        hmac_tag_client: [0u8; HMAC_TAG_CLIENT_LEN],
+    /// The company ID acks are scanned for under.
+    fn ack_company_id(&self) -> u16 {
+        self.filter_company_id.unwrap_or(self.manufacturer_id)
+    }
+
+    /// Bytes of each payload on air.
+    fn payload_len(&self) -> usize {
+        if self.protocol_version == PROTOCOL_VERSION_V1 {
+            TransportNotificationV1::SIZE
+        } else if self.encrypt {
+            SealedNotification::SIZE
+        } else {
+            TransportNotification::SIZE
+        }
+    }
+
+    fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+}
+
+/// Parse a positive integer option value.
+fn positive<T: std::str::FromStr + PartialOrd + Default>(
+    flag: &str,
+    value: &str,
+) -> Result<T, String> {
+    match value.parse::<T>() {
+        Ok(n) if n > T::default() => Ok(n),
+        _ => Err(format!("{flag} must be a positive number, got '{value}'")),
+    }
+}
+
+/// Parse a `--manufacturer-id` value: decimal, or hex with a `0x` prefix.
+fn parse_manufacturer_id(value: &str) -> Result<u16, String> {
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
     };
+    parsed.map_err(|_| format!("invalid --manufacturer-id '{value}' (expected 0-65535 or 0x0000-0xFFFF)"))
+}
+
+/// Parse a `--filter-company-id` value: 1 to 4 hex digits, `0x` prefix
+/// optional.
+fn parse_company_id_hex(value: &str) -> Result<u16, String> {
+    let hex = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")).unwrap_or(value);
+    if hex.is_empty() || hex.len() > 4 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("invalid --filter-company-id '{value}' (expected hex 0000-FFFF)"));
+    }
+    u16::from_str_radix(hex, 16).map_err(|e| e.to_string())
+}
+
+/// Only debug builds may advertise under `MANUFACTURER_ID`, the company ID
+/// reserved for testing.
+fn check_manufacturer_id(id: u16, debug_build: bool) -> Result<(), String> {
+    if id == MANUFACTURER_ID && !debug_build {
+        return Err(format!(
+            "manufacturer ID 0x{MANUFACTURER_ID:04X} is reserved for testing; \
+             pass --manufacturer-id with this deployment's company ID"
+        ));
+    }
+    Ok(())
+}
+
+/// Parse the broadcaster's command line (without the program name).
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    // Parsed last, so it uses the final `--interval-ms` wherever that appears.
+    let mut burst = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--canary" => parsed.canary = true,
+            "--stdin" => parsed.stdin = true,
+            "--fixed" => parsed.fixed = true,
+            "--extended" => parsed.extended = true,
+            "--encrypt" => parsed.encrypt = true,
+            "--list-adapters" => parsed.list_adapters = true,
+            "--relay" => parsed.relay = true,
+            "--rssi-json" => parsed.rssi_json = true,
+            "--adapter" => {
+                parsed.adapter = Some(args.next().ok_or("--adapter requires a value")?);
+            }
+            "--manufacturer-id" => {
+                let value = args.next().ok_or("--manufacturer-id requires a value")?;
+                parsed.manufacturer_id = parse_manufacturer_id(&value)?;
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed requires a value")?;
+                let seed = value
+                    .parse()
+                    .map_err(|_| format!("invalid --seed '{value}' (expected a u64)"))?;
+                parsed.seed = Some(seed);
+            }
+            "--uniform-status" => parsed.status_weights = StatusWeights::UNIFORM,
+            "--burst" => {
+                burst = Some(args.next().ok_or("--burst requires a value")?);
+            }
+            "--count" => {
+                let value = args.next().ok_or("--count requires a value")?;
+                parsed.count = positive("--count", &value)?;
+            }
+            "--broadcast-secs" => {
+                let value = args.next().ok_or("--broadcast-secs requires a value")?;
+                parsed.broadcast_window = Duration::from_secs(positive("--broadcast-secs", &value)?);
+            }
+            "--acks-secs" => {
+                let value = args.next().ok_or("--acks-secs requires a value")?;
+                parsed.ack_listen = Some(Duration::from_secs(positive("--acks-secs", &value)?));
+            }
+            "--filter-company-id" => {
+                let value = args.next().ok_or("--filter-company-id requires a value")?;
+                parsed.filter_company_id = Some(parse_company_id_hex(&value)?);
+            }
+            "--interval-ms" => {
+                let value = args.next().ok_or("--interval-ms requires a value")?;
+                let ms = value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|ms| ADV_INTERVAL_RANGE_MS.contains(ms))
+                    .ok_or_else(|| {
+                        format!(
+                            "--interval-ms must be a legal BLE advertising interval ({}..={} ms), got '{value}'",
+                            ADV_INTERVAL_RANGE_MS.start(),
+                            ADV_INTERVAL_RANGE_MS.end()
+                        )
+                    })?;
+                parsed.adv_interval = Duration::from_millis(ms);
+            }
+            "--key-id" => {
+                let value = args.next().ok_or("--key-id requires a value")?;
+                let id: u8 = value
+                    .parse()
+                    .map_err(|_| format!("invalid --key-id '{value}' (expected 0-255)"))?;
+                let key = infra_key(KEYS.infra_keyring(), id)
+                    .ok_or_else(|| format!("--key-id {id} is not in the infrastructure keyring"))?;
+                parsed.infra_key = Some((id, key));
+            }
+            "--status-weights" => {
+                let value = args.next().ok_or("--status-weights requires a value")?;
+                parsed.status_weights = StatusWeights::parse(&value)?;
+            }
+            "--protocol-version" => {
+                let value = args.next().ok_or("--protocol-version requires a value")?;
+                parsed.protocol_version = value
+                    .parse()
+                    .ok()
+                    .filter(|v| [PROTOCOL_VERSION_V1, PROTOCOL_VERSION].contains(v))
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid --protocol-version '{value}' (expected {PROTOCOL_VERSION_V1} or {PROTOCOL_VERSION})"
+                        )
+                    })?;
+            }
+            "--interface-power" => {
+                parsed.interface_power = match args.next().as_deref() {
+                    Some("keep") => InterfacePower::Keep,
+                    Some("off-on-exit") => InterfacePower::OffOnExit,
+                    Some(other) => {
+                        return Err(format!(
+                            "invalid --interface-power value '{other}' (expected keep or off-on-exit)"
+                        ));
+                    }
+                    None => return Err("--interface-power requires a value".to_string()),
+                };
+            }
+            other => return Err(format!("unknown argument '{other}'")),
+        }
+    }
+    if let Some(value) = burst {
+        parsed.burst = Some(BurstPattern::parse(&value, parsed.adv_interval)?);
+    }
+    if parsed.fixed && parsed.stdin {
+        return Err("--fixed cannot be combined with --stdin".to_string());
+    }
+    if parsed.relay && (parsed.stdin || parsed.fixed || parsed.ack_listen.is_some()) {
+        return Err("--relay cannot be combined with --stdin, --fixed or --acks-secs".to_string());
+    }
+    if parsed.filter_company_id.is_some() && parsed.ack_listen.is_none() {
+        return Err("--filter-company-id needs --acks-secs".to_string());
+    }
+    if parsed.rssi_json && parsed.ack_listen.is_none() {
+        return Err("--rssi-json needs --acks-secs".to_string());
+    }
+    if parsed.ack_listen.is_some() && parsed.stdin {
+        return Err("--acks-secs cannot be combined with --stdin".to_string());
+    }
+    // Repeaters don't ack sealed notifications.
+    if parsed.ack_listen.is_some() && parsed.encrypt {
+        return Err("--acks-secs cannot be combined with --encrypt".to_string());
+    }
+    if parsed.encrypt && parsed.protocol_version == PROTOCOL_VERSION_V1 {
+        return Err(format!("--encrypt needs --protocol-version {PROTOCOL_VERSION}"));
+    }
+    check_manufacturer_id(parsed.manufacturer_id, cfg!(debug_assertions))?;
+    Ok(parsed)
+}
+
+/// The part of the adapter the exit path needs; mocked in tests.
+trait AdapterPower {
+    async fn set_powered(&self, powered: bool) -> bluer::Result<()>;
+}
+
+impl AdapterPower for bluer::Adapter {
+    async fn set_powered(&self, powered: bool) -> bluer::Result<()> {
+        bluer::Adapter::set_powered(self, powered).await
+    }
+}
 
-    // Sign with infrastructure key.
-    notif.hmac_tag_infra = TransportNotification::compute_tag(HMAC_KEY_INFRA, notif.base_payload());
-    notif
+/// Apply the exit power policy, then hand back the run's result unchanged.
+/// A failure to power off is logged but never masks the original outcome.
+async fn finish(
+    adapter: &impl AdapterPower,
+    power: InterfacePower,
+    result: bluer::Result<()>,
+) -> bluer::Result<()> {
+    if power == InterfacePower::OffOnExit {
+        match adapter.set_powered(false).await {
+            Ok(()) => println!("Adapter powered off."),
+            Err(e) => eprintln!("Failed to power off adapter: {e}"),
+        }
+    }
+    result
 }
 
 #[tokio::main]
 async fn main() -> bluer::Result<()> {
     env_logger::init();
 
-    let session = bluer::Session::new().await?;
-    let adapter = session.default_adapter().await?;
-    adapter.set_powered(true).await?;
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    if args.list_adapters {
+        if let Err(e) = list_adapters().await {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let adv_data_len = adv_data_len(args.payload_len());
+    if !args.extended && adv_data_len > LEGACY_ADV_DATA_LEN {
+        eprintln!(
+            "warning: advertisements are {adv_data_len} bytes, over the {LEGACY_ADV_DATA_LEN}-byte legacy limit; \
+             pass --extended if the adapter supports BLE 5"
+        );
+    }
+    if args.manufacturer_id == MANUFACTURER_ID {
+        eprintln!(
+            "warning: advertising under manufacturer ID 0x{MANUFACTURER_ID:04X}, reserved for testing; \
+             pass --manufacturer-id before deploying"
+        );
+    }
+    if !cfg!(debug_assertions) {
+        eprintln!("warning: signing with the built-in development keys, which every build shares");
+    }
+
+    // Nothing is advertised yet, so an interrupt here needs no cleanup.
+    let opened = tokio::select! {
+        opened = Radio::open(args.adapter.clone()) => opened,
+        Ok(()) = tokio::signal::ctrl_c() => {
+            println!("\nInterrupted during adapter setup, shutting down.");
+            return Ok(());
+        }
+    };
+    let mut radio = match opened {
+        Ok(radio) => radio,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    println!("Adapter {}: {}", radio.adapter.name(), radio.caps);
+    for warning in radio.caps.warnings(&args) {
+        eprintln!("warning: {warning}");
+    }
+
+    let run = async {
+        if args.relay {
+            relay(&radio, &args).await
+        } else if args.stdin {
+            broadcast_from_stdin(&mut radio, &args).await
+        } else {
+            broadcast(&mut radio, &args).await
+        }
+    };
+    // On Ctrl-C the run is dropped, and with it the handle of whatever is on
+    // air.
+    let (result, interrupted) = tokio::select! {
+        result = run => (result, false),
+        Ok(()) = tokio::signal::ctrl_c() => {
+            println!("\nInterrupted, shutting down.");
+            (Ok(()), true)
+        }
+    };
+    tokio::time::sleep(UNREGISTER_GRACE).await;
+    let result = finish(&radio.adapter, args.interface_power, result).await;
+    if interrupted {
+        // A pending stdin read, or `--relay`'s loop, holds a blocking thread
+        // that runtime shutdown would wait on.
+        std::process::exit(0);
+    }
+    result
+}
+
+/// Relay what is heard under `--manufacturer-id` until interrupted: a
+/// `Relay` over the radio, on a blocking thread since `Transport` is
+/// synchronous.
+async fn relay(radio: &Radio, args: &Args) -> bluer::Result<()> {
+    println!(
+        "Relaying on Bluetooth adapter {} [{}] under company ID 0x{:04X}",
+        radio.adapter.name(),
+        radio.adapter.address().await?,
+        args.manufacturer_id
+    );
+    let mut transport = BleTransport::new(
+        tokio::runtime::Handle::current(),
+        radio.adapter.clone(),
+        args.broadcast_window,
+        args.adv_interval,
+        args.extended,
+    );
+    let company_id = args.manufacturer_id;
+    let relaying = tokio::task::spawn_blocking(move || {
+        let mut relay = Relay::new(KEYS.infra_keyring(), KEYS.client_key(), company_id);
+        loop {
+            for notif in relay.step(&mut transport, Some(unix_now_ms())) {
+                println!("  relayed {} seq={} hops={}", notif.id_hex(), notif.seq(), { notif.hops_remaining });
+            }
+        }
+    });
+    match relaying.await {
+        Err(e) => panic!("relay thread failed: {e}"),
+    }
+}
 
+/// Generate a batch of notifications and advertise them, as many at once as
+/// the adapter allows.
+async fn broadcast(radio: &mut Radio, args: &Args) -> bluer::Result<()> {
     println!(
-        "Advertising on Bluetooth adapter {} [{}]",
-        adapter.name(),
-        adapter.address().await?
+        "Advertising on Bluetooth adapter {} [{}], up to {} notification(s) at once",
+        radio.adapter.name(),
+        radio.adapter.address().await?,
+        radio.slots
     );
 
-    // Generate a batch of random signed notifications.
-    let notifications: Vec<TransportNotification> =
-        (0..NOTIFICATION_COUNT).map(|_| random_notification()).collect();
+    let notifications = batch(args);
 
     for (i, notif) in notifications.iter().enumerate() {
         let payload = notif.as_bytes();
-        let nid = { notif.notification_id };
-        let sid = { notif.source_id };
         println!(
             "\n── Notification {} ──\n  \
-            id={:02x}{:02x}{:02x}{:02x} source={:02x}{:02x}{:02x}{:02x} event={} dest={} type={:?} status={:?} dur={}s\n  \
-            infra-HMAC-valid={} client-tag-set={} payload({} B)={:02x?}",
+            {} seq={} key={} ts={} event={:?} valid={}s\n  \
+            canary={} infra-HMAC-valid={} client-tag-set={} payload({} B)={:02x?}",
             i,
-            nid[0], nid[1], nid[2], nid[3],
-            sid[0], sid[1], sid[2], sid[3],
-            notif.event_id(),
-            notif.destination_id(),
-            notif.transport_type(),
-            notif.transport_status(),
-            { notif.duration_secs },
+            notif,
+            notif.seq(),
+            { notif.key_id },
+            notif.timestamp_ms(),
+            notif.event(),
+            notif.validity_secs(),
+            notif.is_canary(),
             notif.verify_infra(),
             notif.has_client_tag(),
             payload.len(),
@@ -279,44 +1168,689 @@ async fn main() -> bluer::Result<()> {
         );
 
         // Verify round-trip parsing.
-        if let Some(parsed) = TransportNotification::from_payload(payload) {
-            let pid = { parsed.notification_id };
-            println!("    ✓ round-trip parse OK (id={:02x}{:02x}{:02x}{:02x})", pid[0], pid[1], pid[2], pid[3]);
+        match TransportNotification::from_payload(payload) {
+            Ok(parsed) => println!("    ✓ round-trip parse OK (id={})", parsed.id_hex()),
+            Err(e) => println!("    ✗ round-trip parse failed: {}", e),
+        }
+        if args.protocol_version == PROTOCOL_VERSION_V1 {
+            let v1 = wire_payload(notif, args);
+            match TransportNotification::from_payload(&v1) {
+                Ok(_) => println!("    ✓ v1 ({} B) parses OK", v1.len()),
+                Err(e) => println!("    ✗ v1 payload fails to parse: {}", e),
+            }
+        }
+        if args.encrypt {
+            let sealed = wire_payload(notif, args);
+            match SealedNotification::open_with(&sealed, KEYS.infra_keyring(), CONF_KEY, None) {
+                Ok(_) => println!("    ✓ sealed ({} B) opens OK", sealed.len()),
+                Err(e) => println!("    ✗ sealed payload fails to open: {}", e),
+            }
         }
     }
 
-    // Broadcast each notification one by one, 5 seconds apart.
-    for (i, notif) in notifications.iter().enumerate() {
-        let mut manufacturer_data = BTreeMap::new();
-        manufacturer_data.insert(MANUFACTURER_ID, notif.as_bytes().to_vec());
-
-        // Type::Broadcast produces ADV_NONCONN_IND — the advertisement is
-        // non-connectable by definition.  Scanners will still see it in
-        // their discovery results.
-        let adv = Advertisement {
-            advertisement_type: bluer::adv::Type::Broadcast,
-            manufacturer_data,
-            min_interval: Some(Duration::from_millis(20)),
-            max_interval: Some(Duration::from_millis(20)),
-            local_name: Some("TransportNotifier".to_string()),
-            ..Default::default()
-        };
+    let tally = args.ack_listen.map(|_| Arc::new(Mutex::new(AckTally::new(&notifications))));
+    let listener = tally.as_ref().map(|tally| {
+        println!("\nScanning for repeater acks under company ID 0x{:04X}.", args.ack_company_id());
+        tokio::spawn(acks::listen(
+            radio.adapter.clone(),
+            args.ack_company_id(),
+            KEYS.infra_keyring(),
+            Arc::clone(tally),
+        ))
+    });
 
-        let nid = { notif.notification_id };
+    // Broadcast as many notifications side by side as there are slots, one
+    // window per group. Slots are re-read on each group, since a re-acquired
+    // adapter may have a different number.
+    let mut aired = 0;
+    while aired < notifications.len() {
+        if aired > 0 {
+            tokio::time::sleep(UNREGISTER_GRACE).await;
+        }
+        let group = &notifications[aired..notifications.len().min(aired + radio.slots)];
+        let ids: Vec<String> = group.iter().map(TransportNotification::id_hex).collect();
         println!(
-            "\n[{}/{}] Broadcasting notification {:02x}{:02x}{:02x}{:02x} for 5s...",
-            i + 1,
+            "\n[{}-{}/{}] Broadcasting {} for {}s...",
+            aired + 1,
+            aired + group.len(),
             notifications.len(),
-            nid[0], nid[1], nid[2], nid[3],
+            ids.join(", "),
+            args.broadcast_window.as_secs(),
         );
 
-        let handle = adapter.advertise(adv).await?;
-        tokio::time::sleep(Duration::from_secs(5)).await;
-        drop(handle);
+        air(radio, group, args).await?;
+        aired += group.len();
 
         println!("  ✓ done");
     }
 
+    if let (Some(tally), Some(listener), Some(linger)) = (tally, listener, args.ack_listen) {
+        println!("\nAll notifications broadcast. Listening {}s more for repeater acks...", linger.as_secs());
+        tokio::time::sleep(linger).await;
+        listener.abort();
+        println!();
+        let tally = tally.lock().unwrap();
+        for line in tally.report() {
+            println!("{line}");
+        }
+        if args.rssi_json {
+            println!("{}", tally.rssi_json());
+        }
+        println!("Exiting.");
+        return Ok(());
+    }
+
     println!("\nAll notifications broadcast. Exiting.");
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Advertise `notifs` side by side for one broadcast window, continuously or
+/// in bursts.
+async fn air(radio: &mut Radio, notifs: &[TransportNotification], args: &Args) -> bluer::Result<()> {
+    let window = args.broadcast_window;
+    let Some(burst) = args.burst else {
+        let _handles = advertise_all(radio, notifs, args).await?;
+        tokio::time::sleep(window).await;
+        return Ok(());
+    };
+
+    let start = tokio::time::Instant::now();
+    let end = start + window;
+    for times in burst::schedule(&burst, window).chunks(burst.count as usize) {
+        let (first, last) = (times[0], times[times.len() - 1]);
+        tokio::time::sleep_until(start + first).await;
+        let handles = advertise_all(radio, notifs, args).await?;
+        tokio::time::sleep_until(end.min(start + last + burst.interval)).await;
+        drop(handles);
+    }
+    tokio::time::sleep_until(end).await;
+    Ok(())
+}
+
+/// Broadcast a live set of notifications driven by stdin commands.
+///
+/// As much of the set as the adapter has slots for is on air at once; a
+/// larger set is rotated through round-robin, one group per broadcast
+/// window. Commands are applied as they arrive. Accepted commands are
+/// acknowledged on stdout, rejected ones reported on stderr with their line
+/// number. Exits when stdin closes. With a burst pattern the current group
+/// is switched on and off within its window.
+async fn broadcast_from_stdin(radio: &mut Radio, args: &Args) -> bluer::Result<()> {
+    let burst = args.burst;
+    println!(
+        "Advertising on Bluetooth adapter {} [{}], up to {} notification(s) at once, reading commands from stdin",
+        radio.adapter.name(),
+        radio.adapter.address().await?,
+        radio.slots
+    );
+
+    // All notifications added over stdin come from this one station.
+    let mut rng = args.rng();
+    let source_id = short_id(&mut rng);
+    let mut signer = args.signer();
+    let mut notifications: Vec<TransportNotification> = Vec::new();
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut line_no = 0usize;
+    let mut next = 0usize;
+    let mut handles = Vec::new();
+
+    loop {
+        let current: Vec<TransportNotification> = rotation(notifications.len(), next, radio.slots)
+            .into_iter()
+            .map(|i| {
+                signer.restamp(&mut notifications[i]);
+                notifications[i]
+            })
+            .collect();
+        if !current.is_empty() {
+            next = (next + current.len()) % notifications.len();
+        }
+        if !handles.is_empty() {
+            handles.clear();
+            tokio::time::sleep(UNREGISTER_GRACE).await;
+        }
+        handles = advertise_all(radio, &current, args).await?;
+
+        let window = tokio::time::sleep(args.broadcast_window);
+        tokio::pin!(window);
+        // Ends the current burst or gap; only polled with a burst pattern.
+        let toggle = tokio::time::sleep(burst.map_or(args.broadcast_window, |b| b.on_time()));
+        tokio::pin!(toggle);
+        loop {
+            tokio::select! {
+                _ = &mut window => break,
+                _ = &mut toggle, if burst.is_some() && !current.is_empty() => {
+                    let burst = burst.expect("guarded by the select condition");
+                    let now = tokio::time::Instant::now();
+                    if !handles.is_empty() {
+                        handles.clear();
+                        toggle.as_mut().reset(now + burst.gap);
+                    } else {
+                        handles = advertise_all(radio, &current, args).await?;
+                        toggle.as_mut().reset(now + burst.on_time());
+                    }
+                }
+                line = lines.next_line() => {
+                    let Some(line) = line? else {
+                        println!("stdin closed. Exiting.");
+                        return Ok(());
+                    };
+                    line_no += 1;
+                    match commands::parse_command(&line) {
+                        Ok(None) => {}
+                        Ok(Some(Command::Add(spec))) => {
+                            let notif = signer.sign(&spec, source_id, short_id(&mut rng));
+                            println!("OK ADD id={}", notif.id_hex());
+                            notifications.push(notif);
+                            // Start airing right away if a slot is free.
+                            if current.len() < radio.slots {
+                                break;
+                            }
+                        }
+                        Ok(Some(Command::Remove(id))) => {
+                            let before = notifications.len();
+                            notifications.retain(|n| { n.notification_id } != id);
+                            if notifications.len() < before {
+                                println!("OK REMOVE id={:02x}{:02x}{:02x}{:02x}", id[0], id[1], id[2], id[3]);
+                            } else {
+                                eprintln!("line {line_no}: no active notification with that id: {line}");
+                            }
+                        }
+                        Err(e) => eprintln!("line {line_no}: {e}: {line}"),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_protocol_core::{INFRA_KEY_CURRENT, INFRA_KEYRING};
+
+    use std::cell::RefCell;
+
+    #[test]
+    fn nibble_packing_is_lossless_for_every_variant() {
+        for transport_type in TransportType::ALL {
+            for status in TransportStatus::ALL {
+                let spec = NotificationSpec {
+                    transport_type,
+                    status,
+                    event_id: 15,
+                    destination_id: 0,
+                    duration_secs: 30,
+                    validity_secs: 600,
+                    flags: 0,
+                    priority: 0,
+                };
+                let mut signer = Signer {
+                    seq: SeqCounter(u32::MAX),
+                    ..Signer::live(INFRA_KEY_CURRENT)
+                };
+                let notif = signer.sign(&spec, [1, 2, 3, 4], [5, 6, 7, 8]);
+                let parsed = TransportNotification::from_payload(notif.as_bytes()).unwrap();
+                assert_eq!(parsed.transport_type(), Some(transport_type));
+                assert_eq!(parsed.transport_status(), Some(status));
+                assert_eq!(parsed.event_id(), 15);
+                assert_eq!(parsed.destination_id(), 0);
+                assert_eq!(parsed.seq(), u32::MAX);
+            }
+        }
+    }
+
+    #[test]
+    fn seq_counter_increments_and_wraps() {
+        let mut seq = SeqCounter(u32::MAX - 1);
+        assert_eq!([seq.next(), seq.next(), seq.next()], [u32::MAX - 1, u32::MAX, 0]);
+    }
+
+    #[test]
+    fn status_weights_parse_and_validate() {
+        assert_eq!(StatusWeights::parse("1,1,1"), Ok(StatusWeights::UNIFORM));
+        assert!(StatusWeights::parse("0,0,0").is_err());
+        assert!(StatusWeights::parse("1,2").is_err());
+        assert!(StatusWeights::parse("1,2,x").is_err());
+    }
+
+    /// Observed share of each status over a large seeded sample.
+    fn status_shares(weights: &StatusWeights) -> [f64; 3] {
+        use rand::SeedableRng;
+
+        const SAMPLES: usize = 100_000;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x5EED);
+        let mut counts = [0usize; 3];
+        for _ in 0..SAMPLES {
+            counts[weights.pick(&mut rng) as usize - 1] += 1;
+        }
+        counts.map(|c| c as f64 / SAMPLES as f64)
+    }
+
+    #[test]
+    fn weighted_status_matches_configured_distribution() {
+        let shares = status_shares(&StatusWeights::default());
+        for (observed, expected) in shares.iter().zip([0.45, 0.45, 0.10]) {
+            assert!((observed - expected).abs() < 0.01, "{shares:?}");
+        }
+
+        let shares = status_shares(&StatusWeights::UNIFORM);
+        for observed in shares {
+            assert!((observed - 1.0 / 3.0).abs() < 0.01, "{shares:?}");
+        }
+
+        let alerts_only = StatusWeights { passing: 0, coming: 0, late: 1 };
+        assert_eq!(status_shares(&alerts_only), [0.0, 0.0, 1.0]);
+    }
+
+    /// Records every `set_powered` call instead of touching hardware.
+    #[derive(Default)]
+    struct MockAdapter {
+        calls: RefCell<Vec<bool>>,
+    }
+
+    impl AdapterPower for MockAdapter {
+        async fn set_powered(&self, powered: bool) -> bluer::Result<()> {
+            self.calls.borrow_mut().push(powered);
+            Ok(())
+        }
+    }
+
+    fn args(list: &[&str]) -> impl Iterator<Item = String> {
+        list.iter().map(|s| s.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn interface_power_defaults_to_keep() {
+        assert_eq!(parse_args(args(&[])).unwrap().interface_power, InterfacePower::Keep);
+        assert_eq!(
+            parse_args(args(&["--interface-power", "off-on-exit"])).unwrap().interface_power,
+            InterfacePower::OffOnExit
+        );
+        assert!(parse_args(args(&["--interface-power", "sometimes"])).is_err());
+        assert!(parse_args(args(&["--interface-power"])).is_err());
+    }
+
+    #[test]
+    fn burst_defaults_to_continuous() {
+        assert_eq!(parse_args(args(&[])).unwrap().burst, None);
+        let burst = parse_args(args(&["--burst", "5,400"])).unwrap().burst.unwrap();
+        assert_eq!((burst.count, burst.interval), (5, ADV_INTERVAL));
+        assert_eq!(burst.gap, Duration::from_millis(400));
+        assert!(parse_args(args(&["--burst"])).is_err());
+        assert!(parse_args(args(&["--burst", "0,400"])).is_err());
+    }
+
+    #[test]
+    fn key_id_must_name_a_keyring_entry() {
+        assert_eq!(parse_args(args(&[])).unwrap().infra_key(), INFRA_KEY_CURRENT);
+        let id = INFRA_KEY_CURRENT.0.to_string();
+        assert_eq!(
+            parse_args(args(&["--key-id", &id])).unwrap().infra_key(),
+            INFRA_KEY_CURRENT
+        );
+        let unknown = (0..=u8::MAX)
+            .find(|id| infra_key(INFRA_KEYRING, *id).is_none())
+            .unwrap();
+        assert!(parse_args(args(&["--key-id", &unknown.to_string()])).is_err());
+        assert!(parse_args(args(&["--key-id", "256"])).is_err());
+        assert!(parse_args(args(&["--key-id"])).is_err());
+    }
+
+    #[test]
+    fn restamp_moves_seq_and_timestamp_forward_and_re_signs() {
+        let mut signer = Signer {
+            seq: SeqCounter(1),
+            ..Signer::live(INFRA_KEY_CURRENT)
+        };
+        let mut notif = fixed_notification(0, &mut signer);
+        notif.timestamp_ms = TransportNotification::timestamp_bytes(0);
+        signer.restamp(&mut notif);
+        assert_eq!(notif.seq(), 2);
+        assert!(notif.timestamp_ms() > 0);
+        let parsed = TransportNotification::from_payload_with(
+            notif.as_bytes(),
+            INFRA_KEYRING,
+            Some(unix_now_ms()),
+        );
+        assert!(parsed.is_ok(), "{parsed:?}");
+    }
+
+    fn batch_bytes(list: &[&str]) -> Vec<Vec<u8>> {
+        batch(&parse_args(args(list)).unwrap())
+            .iter()
+            .map(|n| n.as_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_byte_identical_batches() {
+        let first = batch_bytes(&["--seed", "42"]);
+        assert_eq!(first.len(), NOTIFICATION_COUNT);
+        assert_eq!(first, batch_bytes(&["--seed", "42"]));
+        assert_ne!(first, batch_bytes(&["--seed", "43"]));
+        assert!(parse_args(args(&["--seed", "-1"])).is_err());
+    }
+
+    #[test]
+    fn random_notifications_use_named_events() {
+        let batch = batch(&parse_args(args(&["--seed", "7", "--count", "200"])).unwrap());
+        assert!(batch.iter().all(|n| EventId::ALL.contains(&n.event())));
+    }
+
+    #[test]
+    fn fixed_mode_emits_one_known_notification() {
+        let fixed = batch(&parse_args(args(&["--fixed", "--count", "9"])).unwrap());
+        assert_eq!(fixed.len(), 1);
+        let notif = TransportNotification::from_payload(fixed[0].as_bytes()).unwrap();
+        assert_eq!({ notif.source_id }, FIXED_SOURCE_ID);
+        assert_eq!({ notif.notification_id }, FIXED_NOTIFICATION_ID);
+        assert_eq!((notif.seq(), notif.timestamp_ms()), (0, DETERMINISTIC_TIME_MS));
+        assert_eq!(batch_bytes(&["--fixed"]), [fixed[0].as_bytes().to_vec()]);
+        assert!(parse_args(args(&["--fixed", "--stdin"])).is_err());
+    }
+
+    #[test]
+    fn timing_options_default_to_the_constants() {
+        let parsed = parse_args(args(&[])).unwrap();
+        assert_eq!(parsed.count, NOTIFICATION_COUNT);
+        assert_eq!(parsed.broadcast_window, BROADCAST_WINDOW);
+        assert_eq!(parsed.adv_interval, ADV_INTERVAL);
+
+        let parsed = parse_args(args(&[
+            "--count", "10", "--broadcast-secs", "3", "--interval-ms", "50",
+        ]))
+        .unwrap();
+        assert_eq!(parsed.count, 10);
+        assert_eq!(parsed.broadcast_window, Duration::from_secs(3));
+        assert_eq!(parsed.adv_interval, Duration::from_millis(50));
+
+        assert!(parse_args(args(&["--count", "0"])).is_err());
+        assert!(parse_args(args(&["--broadcast-secs", "0"])).is_err());
+        assert!(parse_args(args(&["--broadcast-secs", "-1"])).is_err());
+        assert!(parse_args(args(&["--count"])).is_err());
+    }
+
+    #[test]
+    fn interval_must_be_a_legal_ble_interval() {
+        for ok in ["20", "10240"] {
+            assert!(parse_args(args(&["--interval-ms", ok])).is_ok(), "{ok}");
+        }
+        for bad in ["19", "10241", "0", "fast"] {
+            let err = parse_args(args(&["--interval-ms", bad])).unwrap_err();
+            assert!(err.contains("20..=10240"), "{err}");
+        }
+    }
+
+    #[test]
+    fn burst_uses_the_configured_interval_in_any_order() {
+        for list in [
+            ["--burst", "5,400", "--interval-ms", "100"],
+            ["--interval-ms", "100", "--burst", "5,400"],
+        ] {
+            let burst = parse_args(args(&list)).unwrap().burst.unwrap();
+            assert_eq!(burst.interval, Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn canary_flag_survives_parsing() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut signer = Signer::live(INFRA_KEY_CURRENT);
+        let weights = StatusWeights::default();
+        let notif = random_notification(&mut rng, FLAG_CANARY, &weights, &mut signer);
+        let parsed = TransportNotification::from_payload(notif.as_bytes()).unwrap();
+        assert!(parsed.is_canary());
+
+        let plain = random_notification(&mut rng, 0, &weights, &mut signer);
+        let parsed = TransportNotification::from_payload(plain.as_bytes()).unwrap();
+        assert!(!parsed.is_canary());
+    }
+
+    fn bluer_error(kind: bluer::ErrorKind, message: &str) -> bluer::Error {
+        bluer::Error {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    fn dbus_error(name: &str) -> bluer::Error {
+        bluer_error(
+            bluer::ErrorKind::Internal(bluer::InternalErrorKind::DBus(name.to_string())),
+            "",
+        )
+    }
+
+    #[test]
+    fn missing_adapter_is_explained() {
+        let msg = startup_error(StartupStep::Adapter, &bluer_error(bluer::ErrorKind::NotFound, ""));
+        assert!(msg.starts_with("no Bluetooth adapter found"), "{msg}");
+        assert!(msg.contains("`bluetooth` group"), "{msg}");
+    }
+
+    #[test]
+    fn bluetoothd_not_running_is_explained_at_any_step() {
+        for step in [StartupStep::Session, StartupStep::Adapter] {
+            let msg = startup_error(step, &dbus_error("org.freedesktop.DBus.Error.ServiceUnknown"));
+            assert!(msg.starts_with("bluetoothd is not running"), "{msg}");
+        }
+        let msg = startup_error(
+            StartupStep::Session,
+            &dbus_error("org.freedesktop.DBus.Error.FileNotFound"),
+        );
+        assert!(msg.starts_with("cannot connect to the system D-Bus"), "{msg}");
+    }
+
+    #[test]
+    fn power_on_failures_are_told_apart() {
+        let denied = [
+            bluer_error(bluer::ErrorKind::NotAuthorized, ""),
+            bluer_error(bluer::ErrorKind::NotPermitted, ""),
+            dbus_error("org.freedesktop.DBus.Error.AccessDenied"),
+        ];
+        for err in denied {
+            let msg = startup_error(StartupStep::PowerOn, &err);
+            assert!(msg.contains("`bluetooth` group"), "{msg}");
+        }
+
+        let rfkill = bluer_error(bluer::ErrorKind::Failed, "Blocked through rfkill");
+        let msg = startup_error(StartupStep::PowerOn, &rfkill);
+        assert!(msg.starts_with("the adapter is blocked by rfkill"), "{msg}");
+    }
+
+    #[test]
+    fn unrecognised_errors_keep_bluer_text() {
+        let err = bluer_error(bluer::ErrorKind::Failed, "something else");
+        assert_eq!(
+            startup_error(StartupStep::PowerOn, &err),
+            format!("powering on the Bluetooth adapter failed: {err}")
+        );
+        // NotFound only means "no adapter" when looking for one.
+        let err = bluer_error(bluer::ErrorKind::NotFound, "");
+        assert!(startup_error(StartupStep::PowerOn, &err).starts_with("powering on"));
+    }
+
+    #[test]
+    fn only_a_vanished_adapter_triggers_reacquiring() {
+        for kind in [bluer::ErrorKind::NotReady, bluer::ErrorKind::NotFound, bluer::ErrorKind::DoesNotExist] {
+            assert!(adapter_lost(&bluer_error(kind, "")));
+        }
+        assert!(!adapter_lost(&bluer_error(bluer::ErrorKind::Failed, "Maximum advertisements reached")));
+        assert!(!adapter_lost(&bluer_error(bluer::ErrorKind::InvalidArguments, "")));
+    }
+
+    #[test]
+    fn reacquire_backoff_doubles_up_to_the_cap() {
+        let delays: Vec<u64> = (0..7).map(|n| reacquire_delay(n).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(reacquire_delay(u32::MAX), REACQUIRE_BACKOFF_MAX);
+    }
+
+    #[test]
+    fn rotation_fills_the_slots_and_wraps() {
+        assert_eq!(rotation(3, 0, 4), [0, 1, 2]);
+        assert_eq!(rotation(5, 0, 2), [0, 1]);
+        assert_eq!(rotation(5, 4, 2), [4, 0]);
+        assert_eq!(rotation(5, 3, 5), [3, 4, 0, 1, 2]);
+        assert!(rotation(0, 0, 4).is_empty());
+    }
+
+    #[test]
+    fn adapter_flag_names_the_adapter() {
+        assert_eq!(parse_args(args(&[])).unwrap().adapter, None);
+        assert_eq!(parse_args(args(&["--adapter", "hci1"])).unwrap().adapter.as_deref(), Some("hci1"));
+        assert!(parse_args(args(&["--adapter"])).is_err());
+    }
+
+    #[test]
+    fn extended_advertising_only_when_legacy_does_not_fit() {
+        assert_eq!(secondary_channel(true, LEGACY_ADV_DATA_LEN), None);
+        assert_eq!(secondary_channel(true, LEGACY_ADV_DATA_LEN + 1), Some(SecondaryChannel::OneM));
+        assert_eq!(secondary_channel(false, LEGACY_ADV_DATA_LEN + 1), None);
+
+        let notif = fixed_notification(0, &mut Signer::deterministic(INFRA_KEY_CURRENT));
+        let adv = advertisement(notif.as_bytes().to_vec(), MANUFACTURER_ID, ADV_INTERVAL, true);
+        assert_eq!(adv.manufacturer_data[&MANUFACTURER_ID], notif.as_bytes());
+        assert_eq!(
+            adv.secondary_channel.is_some(),
+            adv_data_len(TransportNotification::SIZE) > LEGACY_ADV_DATA_LEN
+        );
+        assert!(advertisement(notif.as_bytes().to_vec(), MANUFACTURER_ID, ADV_INTERVAL, false).secondary_channel.is_none());
+        assert!(parse_args(args(&["--extended"])).unwrap().extended);
+    }
+
+    #[test]
+    fn manufacturer_id_flag_and_release_check() {
+        assert_eq!(parse_args(args(&["--manufacturer-id", "0x1234"])).unwrap().manufacturer_id, 0x1234);
+        assert_eq!(parse_args(args(&["--manufacturer-id", "4660"])).unwrap().manufacturer_id, 0x1234);
+        assert!(parse_args(args(&["--manufacturer-id", "0x10000"])).is_err());
+        assert!(parse_args(args(&["--manufacturer-id", "abc"])).is_err());
+        assert!(parse_args(args(&["--manufacturer-id"])).is_err());
+
+        let notif = fixed_notification(0, &mut Signer::deterministic(INFRA_KEY_CURRENT));
+        let adv = advertisement(notif.as_bytes().to_vec(), 0x1234, ADV_INTERVAL, false);
+        assert_eq!(adv.manufacturer_data.keys().collect::<Vec<_>>(), [&0x1234]);
+
+        assert!(check_manufacturer_id(MANUFACTURER_ID, true).is_ok());
+        assert!(check_manufacturer_id(MANUFACTURER_ID, false).is_err());
+        assert!(check_manufacturer_id(0x1234, false).is_ok());
+    }
+
+    #[test]
+    fn encrypt_seals_every_airing_under_a_fresh_nonce() {
+        let notif = fixed_notification(0, &mut Signer::deterministic(INFRA_KEY_CURRENT));
+        assert_eq!(wire_payload(&notif, &Args::default()), notif.as_bytes());
+
+        let args = parse_args(args(&["--encrypt", "--extended"])).unwrap();
+        assert_eq!(args.payload_len(), SealedNotification::SIZE);
+        let (first, second) = (wire_payload(&notif, &args), wire_payload(&notif, &args));
+        assert_eq!(first.len(), SealedNotification::SIZE);
+        assert_ne!(first, second);
+        for payload in [first, second] {
+            let (_, opened) = SealedNotification::open_with(&payload, INFRA_KEYRING, CONF_KEY, None).unwrap();
+            assert_eq!(opened.base_payload(), notif.base_payload());
+        }
+
+        let adv = advertisement(wire_payload(&notif, &args), MANUFACTURER_ID, ADV_INTERVAL, true);
+        assert_eq!(adv.secondary_channel, Some(SecondaryChannel::OneM));
+    }
+
+    #[test]
+    fn protocol_version_1_airs_the_v1_layout() {
+        let notif = fixed_notification(0, &mut Signer::deterministic(INFRA_KEY_CURRENT));
+        let v1 = parse_args(args(&["--protocol-version", "1"])).unwrap();
+        assert_eq!(v1.payload_len(), TransportNotificationV1::SIZE);
+        let payload = wire_payload(&notif, &v1);
+        assert_eq!(payload.len(), TransportNotificationV1::SIZE);
+        let parsed = TransportNotification::from_payload(&payload).unwrap();
+        assert_eq!(parsed.version, PROTOCOL_VERSION_V1);
+        assert_eq!(parsed.notification_id, notif.notification_id);
+
+        assert_eq!(parse_args(args(&["--protocol-version", "8"])).unwrap(), Args::default());
+        assert!(parse_args(args(&["--protocol-version", "7"])).is_err());
+        assert!(parse_args(args(&["--protocol-version"])).is_err());
+        assert!(parse_args(args(&["--protocol-version", "1", "--encrypt", "--extended"])).is_err());
+    }
+
+    #[test]
+    fn adapter_capabilities_are_checked_against_the_args() {
+        assert!(parse_args(args(&["--list-adapters"])).unwrap().list_adapters);
+
+        let capable = AdvertisingCaps {
+            instances: 4,
+            offload: true,
+            secondary_channels: BTreeSet::from([SecondaryChannel::OneM, SecondaryChannel::TwoM]),
+        };
+        let extended = parse_args(args(&["--extended"])).unwrap();
+        assert!(capable.warnings(&extended).is_empty());
+        assert_eq!(
+            capable.to_string(),
+            "4 advertising instance(s) free, interval settable, extended advertising on 1M/2M"
+        );
+
+        let basic = AdvertisingCaps { instances: 1, ..AdvertisingCaps::default() };
+        assert!(!basic.sets_interval() && !basic.multiple_instances());
+        assert_eq!(basic.warnings(&extended).len(), 3);
+        // A single fixed notification doesn't need a second instance.
+        let fixed = parse_args(args(&["--fixed"])).unwrap();
+        assert_eq!(basic.warnings(&fixed).len(), 1);
+    }
+
+    #[test]
+    fn filter_company_id_is_hex_and_defaults_to_the_manufacturer_id() {
+        let parsed = parse_args(args(&["--acks-secs", "20", "--manufacturer-id", "0x1234"])).unwrap();
+        assert_eq!(parsed.ack_company_id(), 0x1234);
+        for value in ["0x05F1", "05f1", "5F1"] {
+            let parsed = parse_args(args(&["--acks-secs", "20", "--filter-company-id", value])).unwrap();
+            assert_eq!(parsed.ack_company_id(), 0x05F1, "{value}");
+        }
+
+        for value in ["", "0x", "12345", "0x1G", "-1", "+12"] {
+            assert!(parse_company_id_hex(value).is_err(), "{value:?}");
+        }
+        assert!(parse_args(args(&["--acks-secs", "20", "--filter-company-id"])).is_err());
+        assert!(parse_args(args(&["--filter-company-id", "05F1"])).is_err());
+    }
+
+    #[test]
+    fn relay_stands_alone() {
+        assert!(parse_args(args(&["--relay"])).unwrap().relay);
+        assert!(parse_args(args(&["--relay", "--broadcast-secs", "2", "--extended"])).is_ok());
+        assert!(parse_args(args(&["--relay", "--stdin"])).is_err());
+        assert!(parse_args(args(&["--relay", "--fixed"])).is_err());
+        assert!(parse_args(args(&["--relay", "--acks-secs", "20"])).is_err());
+    }
+
+    #[test]
+    fn acks_secs_needs_a_plain_batch() {
+        let parsed = parse_args(args(&["--acks-secs", "20"])).unwrap();
+        assert_eq!(parsed.ack_listen, Some(Duration::from_secs(20)));
+        assert!(parse_args(args(&["--acks-secs", "0"])).is_err());
+        assert!(parse_args(args(&["--acks-secs"])).is_err());
+        assert!(parse_args(args(&["--acks-secs", "20", "--stdin"])).is_err());
+        assert!(parse_args(args(&["--acks-secs", "20", "--encrypt", "--extended"])).is_err());
+        assert!(parse_args(args(&["--acks-secs", "20", "--rssi-json"])).unwrap().rssi_json);
+        assert!(parse_args(args(&["--rssi-json"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn off_on_exit_powers_down_after_success_and_failure() {
+        let adapter = MockAdapter::default();
+        assert!(finish(&adapter, InterfacePower::OffOnExit, Ok(())).await.is_ok());
+
+        let failed = Err(bluer::Error {
+            kind: bluer::ErrorKind::Failed,
+            message: "advertising failed".to_string(),
+        });
+        assert!(finish(&adapter, InterfacePower::OffOnExit, failed).await.is_err());
+
+        assert_eq!(*adapter.calls.borrow(), vec![false, false]);
+    }
+
+    #[tokio::test]
+    async fn keep_leaves_power_untouched() {
+        let adapter = MockAdapter::default();
+        finish(&adapter, InterfacePower::Keep, Ok(())).await.unwrap();
+        assert!(adapter.calls.borrow().is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+//! The radio as a `ble_protocol_core::transport::Transport`, for `--relay`.
+//!
+//! `Transport` is synchronous, so `BleTransport` runs on a blocking thread
+//! and drives bluer through the runtime's handle. Each `receive` scans for
+//! one window; each `broadcast` registers an advertisement and leaves it on
+//! air for the dwell, through the scan windows that follow, instead of
+//! blocking the relay while it airs.
+
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use ble_protocol_core::transport::{ScanRecord, Transport};
+use bluer::adv::AdvertisementHandle;
+use bluer::{AdapterEvent, DiscoveryFilter, DiscoveryTransport};
+use futures::{Stream, StreamExt};
+use tokio::runtime::Handle;
+
+use crate::advertisement;
+
+/// How long each `receive` scans for.
+const SCAN_WINDOW: Duration = Duration::from_secs(1);
+
+type Discovery = Pin<Box<dyn Stream<Item = AdapterEvent> + Send>>;
+
+pub struct BleTransport {
+    runtime: Handle,
+    adapter: bluer::Adapter,
+    /// Started by the first `receive` and kept running, so discovery isn't
+    /// restarted every window.
+    discovery: Option<Discovery>,
+    /// Relayed advertisements on air, with when each goes off.
+    on_air: Vec<(AdvertisementHandle, Instant)>,
+    /// How long each relayed advertisement stays on air.
+    dwell: Duration,
+    interval: Duration,
+    extended: bool,
+}
+
+impl BleTransport {
+    /// Scan and advertise on `adapter`. Relayed advertisements go out every
+    /// `interval`, as extended ones if `extended` and they need it, and
+    /// stay on air for `dwell`. To be used off the runtime `runtime` is a
+    /// handle to, on a blocking thread.
+    pub fn new(
+        runtime: Handle,
+        adapter: bluer::Adapter,
+        dwell: Duration,
+        interval: Duration,
+        extended: bool,
+    ) -> Self {
+        Self { runtime, adapter, discovery: None, on_air: Vec::new(), dwell, interval, extended }
+    }
+
+    async fn discovery(&mut self) -> bluer::Result<&mut Discovery> {
+        if self.discovery.is_none() {
+            // Duplicate data: a repeater re-airs from the same address, and
+            // each airing may carry another notification.
+            let filter = DiscoveryFilter {
+                transport: DiscoveryTransport::Le,
+                duplicate_data: true,
+                ..Default::default()
+            };
+            self.adapter.set_discovery_filter(filter).await?;
+            self.discovery = Some(Box::pin(self.adapter.discover_devices_with_changes().await?));
+        }
+        Ok(self.discovery.as_mut().unwrap())
+    }
+
+    async fn scan(&mut self) -> bluer::Result<Vec<ScanRecord>> {
+        let adapter = self.adapter.clone();
+        let events = self.discovery().await?;
+        let mut heard = Vec::new();
+        let window = tokio::time::sleep(SCAN_WINDOW);
+        tokio::pin!(window);
+        loop {
+            let addr = tokio::select! {
+                Some(AdapterEvent::DeviceAdded(addr)) = events.next() => addr,
+                () = &mut window => return Ok(heard),
+            };
+            let device = adapter.device(addr)?;
+            let Ok(Some(data)) = device.manufacturer_data().await else {
+                continue;
+            };
+            let rssi = device.rssi().await.ok().flatten().unwrap_or(i16::MIN);
+            let rssi = rssi.clamp(i8::MIN.into(), i8::MAX.into()) as i8;
+            heard.extend(data.into_iter().map(|(company_id, payload)| {
+                let mut data = company_id.to_le_bytes().to_vec();
+                data.extend(payload);
+                ScanRecord { addr: addr.0, rssi, data }
+            }));
+        }
+    }
+}
+
+impl Transport for BleTransport {
+    fn receive(&mut self) -> Vec<ScanRecord> {
+        let now = Instant::now();
+        self.on_air.retain(|(_, off)| *off > now);
+        let runtime = self.runtime.clone();
+        match runtime.block_on(self.scan()) {
+            Ok(heard) => heard,
+            Err(e) => {
+                eprintln!("warning: scanning failed: {e}");
+                // Start discovery afresh next time.
+                self.discovery = None;
+                Vec::new()
+            }
+        }
+    }
+
+    fn broadcast(&mut self, data: &[u8]) {
+        let Some((company_id, payload)) = data.split_first_chunk::<2>() else {
+            return;
+        };
+        let adv = advertisement(payload.to_vec(), u16::from_le_bytes(*company_id), self.interval, self.extended);
+        match self.runtime.block_on(self.adapter.advertise(adv)) {
+            Ok(handle) => self.on_air.push((handle, Instant::now() + self.dwell)),
+            Err(e) => eprintln!("warning: relaying failed: {e}"),
+        }
+    }
+}
@@ -75,6 +75,10 @@ export function useBluetooth() {
     () => notifications.value.filter((n) => !n.clientVerified).length,
   );
 
+  // Canary notifications are never shown; their IDs are kept for diagnostics.
+  const canaryIds = ref(new Set<string>());
+  const canaryCount = computed(() => canaryIds.value.size);
+
   // ══════════════════════════════════════════════════════════════════
   //  MODE 1 — requestLEScan  (Scanning spec)
   // ══════════════════════════════════════════════════════════════════
@@ -245,6 +249,7 @@ export function useBluetooth() {
 
   function clearNotifications() {
     notifications.value = [];
+    canaryIds.value.clear();
   }
 
   // ── Shared advertisement handler ──────────────────────────────────
@@ -266,8 +271,19 @@ export function useBluetooth() {
 
     if (!notif) return;
 
+    // Drop notifications whose validity window has passed
+    const now = Date.now();
+    notifications.value = notifications.value.filter(
+      (n) => Date.parse(n.validUntil) > now,
+    );
+
     const notifId = formatNotificationId(notif.notificationId);
 
+    if (notif.isCanary) {
+      canaryIds.value.add(notifId);
+      return;
+    }
+
     const existingIdx = notifications.value.findIndex(
       (n) => formatNotificationId(n.notificationId) === notifId,
     );
@@ -302,6 +318,7 @@ export function useBluetooth() {
     supportsWatch,
     verifiedCount,
     unverifiedCount,
+    canaryCount,
 
     requestPermission,
     startScan,
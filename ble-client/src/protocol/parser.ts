@@ -1,12 +1,16 @@
 import {
   PROTOCOL_VERSION,
+  ACK_KIND,
   NOTIFICATION_SIZE,
   BASE_PAYLOAD_SIZE,
   HMAC_TAG_INFRA_LEN,
   HMAC_TAG_CLIENT_LEN,
   HMAC_KEY_CLIENT,
+  FLAG_CANARY,
   TransportType,
   TransportStatus,
+  ClientTrust,
+  classifyClientTrust,
   type TransportNotification,
 } from './types';
 
@@ -32,47 +36,70 @@ async function computeHmacTag(
   return new Uint8Array(signature).slice(0, length);
 }
 
+/**
+ * Check whether the client tag has been set, i.e. is not all zeroes.
+ * An all-zero tag means no repeater signed the notification; it is never
+ * a valid tag that happens to be zero.
+ */
+export function hasClientTag(tag: Uint8Array): boolean {
+  return tag.some((b) => b !== 0);
+}
+
 /**
  * Verify the client HMAC tag of a notification.
+ *
+ * Returns `UnverifiedNoTag` for an all-zero (unsigned) tag without computing
+ * an HMAC, `TamperedTag` for a present but wrong tag, and `Verified` only
+ * when the tag matches.
  */
-async function verifyClientTag(
+export async function verifyClientTag(
   basePayload: Uint8Array,
   clientTag: Uint8Array,
-): Promise<boolean> {
+): Promise<ClientTrust> {
+  if (!hasClientTag(clientTag)) {
+    return classifyClientTrust(false, false);
+  }
   const expected = await computeHmacTag(
     HMAC_KEY_CLIENT,
     basePayload,
     HMAC_TAG_CLIENT_LEN,
   );
-  if (expected.length !== clientTag.length) return false;
-  return expected.every((b, i) => b === clientTag[i]);
-}
-
-/**
- * Check whether the client tag bytes are all zeroes (not yet signed).
- */
-function hasClientTag(tag: Uint8Array): boolean {
-  return tag.some((b) => b !== 0);
+  const valid =
+    expected.length === clientTag.length &&
+    expected.every((b, i) => b === clientTag[i]);
+  return classifyClientTrust(true, valid);
 }
 
 /**
  * Parse a manufacturer-data payload into a TransportNotification.
  * Returns `null` if the payload is invalid or HMAC verification fails.
  *
- * Layout (24 bytes, packed, little-endian):
+ * Layout (43 bytes, packed, little-endian):
  *   [0]       version          u8
  *   [1..5]    source_id        [u8; 4]
  *   [5..9]    notification_id  [u8; 4]
  *   [9]       event_dest       u8   (high nibble = event_id, low = dest_id)
  *   [10]      type_status      u8   (high nibble = transport_type, low = status)
- *   [11..13]  duration_secs    u16 LE
- *   [13..21]  hmac_tag_infra   [u8; 8]
- *   [21..25]  hmac_tag_client  [u8; 4]
+ *   [11..13]  duration_secs    u16 LE (repeater re-broadcast window)
+ *   [13..15]  validity_secs    u16 LE (how long riders should see it)
+ *   [15]      flags            u8   (FLAG_CANARY, ...)
+ *   [16..20]  seq              u32 LE (per-source sequence number)
+ *   [20]      key_id           u8   (infrastructure key that signed it)
+ *   [21..27]  timestamp_ms     u48 LE (unix-epoch milliseconds at emission)
+ *   [27]      priority         u8   (higher wins a full repeater's active list)
+ *   [28..36]  hmac_tag_infra   [u8; 8]
+ *   [36..40]  hmac_tag_client  [u8; 4]
+ *   [40]      hops_remaining   u8   (unsigned; each repeater decrements it)
+ *   [41..43]  crc16            u16 LE (CRC-16/CCITT of [0..28]; pre-filter only)
  */
 export async function parseNotification(
   payload: Uint8Array,
   rssi?: number,
 ): Promise<TransportNotification | null> {
+  // A repeater's ack beacon: not for us, and not worth a warning.
+  if (payload[0] === ACK_KIND) {
+    return null;
+  }
   if (payload.length < NOTIFICATION_SIZE) {
     console.warn(
       `[BLE] Payload too short: ${payload.length} < ${NOTIFICATION_SIZE}`,
@@ -118,6 +145,14 @@ export async function parseNotification(
   const transportStatus = transportStatusVal as TransportStatus;
 
   const durationSecs = view.getUint16(11, true); // little-endian
+  const validitySecs = view.getUint16(13, true); // little-endian
+  const flags = view.getUint8(15);
+  const seq = view.getUint32(16, true); // little-endian
+  const keyId = view.getUint8(20);
+  // 48-bit little-endian; fits losslessly in a JS number.
+  const timestampMs = view.getUint32(21, true) + view.getUint16(25, true) * 2 ** 32;
+  const priority = view.getUint8(27);
+  const hopsRemaining = view.getUint8(40);
 
   const hmacTagInfra = payload.slice(
     BASE_PAYLOAD_SIZE,
@@ -128,16 +163,16 @@ export async function parseNotification(
     BASE_PAYLOAD_SIZE + HMAC_TAG_INFRA_LEN + HMAC_TAG_CLIENT_LEN,
   );
 
+  const receivedAt = new Date();
+
   // ── Verify client HMAC tag ────────────────────────────────────────
   const basePayload = payload.slice(0, BASE_PAYLOAD_SIZE);
-  let clientVerified = false;
-
-  if (hasClientTag(hmacTagClient)) {
-    clientVerified = await verifyClientTag(basePayload, hmacTagClient);
-    if (!clientVerified) {
-      console.warn('[BLE] Client HMAC tag mismatch — notification may be forged');
-    }
-  } else {
+  const clientTrust = await verifyClientTag(basePayload, hmacTagClient);
+  const clientVerified = clientTrust === ClientTrust.Verified;
+
+  if (clientTrust === ClientTrust.TamperedTag) {
+    console.warn('[BLE] Client HMAC tag mismatch — notification may be forged');
+  } else if (clientTrust === ClientTrust.UnverifiedNoTag) {
     console.warn('[BLE] Client tag not set (no repeater in chain)');
   }
 
@@ -150,11 +185,21 @@ export async function parseNotification(
     transportType,
     transportStatus,
     durationSecs,
+    validitySecs,
+    flags,
+    seq,
+    keyId,
+    timestampMs,
+    priority,
+    hopsRemaining,
+    isCanary: (flags & FLAG_CANARY) !== 0,
     hmacTagInfra,
     hmacTagClient,
     clientVerified,
+    clientTrust,
     raw: payload.slice(0, NOTIFICATION_SIZE),
-    receivedAt: new Date().toISOString(),
+    receivedAt: receivedAt.toISOString(),
+    validUntil: new Date(receivedAt.getTime() + validitySecs * 1000).toISOString(),
     rssi,
   };
 }
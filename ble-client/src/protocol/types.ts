@@ -1,10 +1,20 @@
 // ── Protocol definitions matching ble-broadcaster/ble-repeater ──────────
 
-/** Custom manufacturer ID used by our protocol. */
-export const MANUFACTURER_ID = 0xffff;
+/**
+ * Manufacturer (company) ID the deployment's broadcasters and repeaters use,
+ * from `VITE_MANUFACTURER_ID` at build time (e.g. `0x1234`). Defaults to
+ * 0xFFFF, the Bluetooth SIG ID reserved for testing.
+ */
+export const MANUFACTURER_ID = Number(import.meta.env.VITE_MANUFACTURER_ID ?? 0xffff);
 
 /** Current protocol version. */
-export const PROTOCOL_VERSION = 1;
+export const PROTOCOL_VERSION = 8;
+
+/**
+ * First byte of a repeater's ack beacon, aired for broadcasters checking a
+ * deployment. Never a protocol version; the app ignores these.
+ */
+export const ACK_KIND = 0xac;
 
 /**
  * Client-facing HMAC key (shared with repeater).
@@ -19,12 +29,24 @@ export const HMAC_TAG_INFRA_LEN = 8;
 export const HMAC_TAG_CLIENT_LEN = 4;
 
 /** Total notification struct size in bytes.
- *  1 + 4 + 4 + 1 + 1 + 2 + 8 + 4 = 25 (packed, no padding). */
-export const NOTIFICATION_SIZE = 25;
+ *  1 + 4 + 4 + 1 + 1 + 2 + 2 + 1 + 4 + 1 + 6 + 1 + 8 + 4 + 1 + 2 = 43 (packed, no padding). */
+export const NOTIFICATION_SIZE = 43;
+
+/**
+ * Flag bit: test/canary notification. Repeaters relay it normally, but the
+ * app hides it from riders and only counts it for diagnostics.
+ */
+export const FLAG_CANARY = 0x01;
+
+/** Size of `hops_remaining`, after the HMAC tags. */
+export const HOPS_REMAINING_LEN = 1;
+
+/** Size of `crc16`, the last field. */
+export const CRC16_LEN = 2;
 
 /** Base payload size (everything before both HMAC tags). */
 export const BASE_PAYLOAD_SIZE =
-  NOTIFICATION_SIZE - HMAC_TAG_INFRA_LEN - HMAC_TAG_CLIENT_LEN;
+  NOTIFICATION_SIZE - HMAC_TAG_INFRA_LEN - HMAC_TAG_CLIENT_LEN - HOPS_REMAINING_LEN - CRC16_LEN;
 
 // ── Enums ───────────────────────────────────────────────────────────────
 
@@ -61,6 +83,39 @@ export const transportStatusColor: Record<TransportStatus, string> = {
   [TransportStatus.Late]: '#ef4444',
 };
 
+// ── Client trust model ──────────────────────────────────────────────────
+
+/**
+ * How far the app may trust a notification, from its client tag alone.
+ *
+ * - `Verified`        — client tag present and valid: a signing repeater
+ *                       vouched for every field.
+ * - `UnverifiedNoTag` — all-zero client tag: the packet never passed a
+ *                       signing repeater, so its authenticity is unknown.
+ * - `TamperedTag`     — client tag present but wrong: the payload was
+ *                       altered or forged. Never shown to riders.
+ */
+export enum ClientTrust {
+  Verified = 'verified',
+  UnverifiedNoTag = 'unverified-no-tag',
+  TamperedTag = 'tampered-tag',
+}
+
+/** Classify a notification from its client-tag presence and validity. */
+export function classifyClientTrust(hasTag: boolean, tagValid: boolean): ClientTrust {
+  if (!hasTag) return ClientTrust.UnverifiedNoTag;
+  return tagValid ? ClientTrust.Verified : ClientTrust.TamperedTag;
+}
+
+/**
+ * What to do with `UnverifiedNoTag` notifications:
+ * `'hide'` drops them, `'badge'` shows them marked as unverified.
+ */
+export type UnverifiedPolicy = 'hide' | 'badge';
+
+/** Acceptance policy for notifications that arrive without a client tag. */
+export const UNVERIFIED_POLICY: UnverifiedPolicy = 'hide';
+
 // ── Parsed notification interface ───────────────────────────────────────
 
 export interface TransportNotification {
@@ -71,15 +126,36 @@ export interface TransportNotification {
   destinationId: number; // 0–15
   transportType: TransportType;
   transportStatus: TransportStatus;
+  /** How long repeaters keep re-broadcasting this notification. */
   durationSecs: number;
+  /** How long the notification stays relevant to riders after reception. */
+  validitySecs: number;
+  /** Raw flag bits (see `FLAG_*`). */
+  flags: number;
+  /** Per-source sequence number (u32, wraps). Replays are rejected by repeaters. */
+  seq: number;
+  /** Id of the infrastructure key that signed the notification. */
+  keyId: number;
+  /** When the broadcaster emitted it, in unix-epoch milliseconds. */
+  timestampMs: number;
+  /** Priority repeaters rank by when their active list is full; higher wins. */
+  priority: number;
+  /** Further repeater hops allowed; not covered by either HMAC tag. */
+  hopsRemaining: number;
+  /** Test/canary notification — never shown to riders. */
+  isCanary: boolean;
   hmacTagInfra: Uint8Array; // 8 bytes
   hmacTagClient: Uint8Array; // 4 bytes
-  /** Whether the client HMAC tag was successfully verified. */
+  /** Whether the client HMAC tag was verified (`clientTrust === Verified`). */
   clientVerified: boolean;
+  /** Trust level derived from the client tag. */
+  clientTrust: ClientTrust;
   /** Raw payload bytes. */
   raw: Uint8Array;
   /** ISO timestamp when received. */
   receivedAt: string;
+  /** ISO timestamp after which the notification is no longer relevant. */
+  validUntil: string;
   /** RSSI of the advertisement (if available). */
   rssi?: number;
 }
@@ -97,3 +173,18 @@ export function bytesToHex(bytes: Uint8Array): string {
 export function formatNotificationId(id: Uint8Array): string {
   return bytesToHex(id).toUpperCase();
 }
+
+/** Whether a notification may be shown to riders under `policy`. */
+export function isDisplayable(
+  notif: TransportNotification,
+  policy: UnverifiedPolicy = UNVERIFIED_POLICY,
+): boolean {
+  switch (notif.clientTrust) {
+    case ClientTrust.Verified:
+      return true;
+    case ClientTrust.UnverifiedNoTag:
+      return policy === 'badge';
+    case ClientTrust.TamperedTag:
+      return false;
+  }
+}
@@ -0,0 +1,263 @@
+//! Client-side verification of relayed notifications: what a rider's app
+//! does with a payload it hears.
+//!
+//! A client holds only the client key, never the infrastructure key, so it
+//! trusts a notification through `hmac_tag_client`, which the first repeater
+//! signs. A notification heard straight from a broadcaster carries an
+//! all-zero client tag and is rejected here. This is the reference the
+//! mobile app's own verification should agree with, and the entry point an
+//! app would call through FFI.
+//!
+//! An app released after a client key roll checks the tag under its own
+//! `client_key_id` with `verify_notification_as`; see
+//! `ble_protocol_core::client_tags`.
+//!
+//! `no_std` unless the default `std` feature is enabled, like
+//! `ble-protocol-core`. The `encrypt` feature adds
+//! `verify_sealed_notification` for sealed (encrypted) notifications.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use ble_protocol_core::{
+    ClientKey, ParseError, TransportNotification, TransportStatus, TransportType,
+};
+
+use ble_protocol_core::client_tags::{self, ClientTags};
+
+#[cfg(feature = "encrypt")]
+use ble_protocol_core::conf::SealedNotification;
+
+/// Parse a manufacturer-data payload and verify its client tag with
+/// `client_key`. The infrastructure tag is not checked; a client can't.
+///
+/// Returns the decoded notification, or why it was rejected:
+/// `MissingClientTag` when no repeater signed it, `ClientHmacMismatch` when
+/// the tag is wrong, or the header or CRC error from parsing.
+pub fn verify_notification(
+    payload: &[u8],
+    client_key: &[u8],
+) -> Result<TransportNotification, ParseError> {
+    let notif = TransportNotification::parse_unverified(payload)?;
+    if !notif.verify_crc() {
+        return Err(ParseError::CrcMismatch);
+    }
+    if !notif.has_client_tag() {
+        return Err(ParseError::MissingClientTag);
+    }
+    if !notif.verify_client_with(client_key) {
+        return Err(ParseError::ClientHmacMismatch);
+    }
+    Ok(notif)
+}
+
+/// Like `verify_notification`, with a client key known by its
+/// `client_key_id`: the tag under that id is checked, the notification's own
+/// or one in the client-tag record after it. `client_key_id` 0 with no
+/// record present is `verify_notification`. Fails as that does, and with
+/// the record's parse error if it is malformed.
+pub fn verify_notification_as(
+    payload: &[u8],
+    client_key: ClientKey,
+) -> Result<TransportNotification, ParseError> {
+    let notif = TransportNotification::parse_unverified(payload)?;
+    if !notif.verify_crc() {
+        return Err(ParseError::CrcMismatch);
+    }
+    let record = ClientTags::find(payload)?;
+    client_tags::verify(&notif, record.as_ref(), client_key)?;
+    Ok(notif)
+}
+
+/// Like `verify_notification`, for a sealed payload: verify the envelope's
+/// client tag with `client_key`, and only then decrypt it with `conf_key`.
+///
+/// The returned notification is the decrypted content with zero HMAC tags;
+/// the envelope's tags are what authenticated it. Besides the errors of
+/// `verify_notification`, fails with `DecryptFailed`.
+#[cfg(feature = "encrypt")]
+pub fn verify_sealed_notification(
+    payload: &[u8],
+    client_key: &[u8],
+    conf_key: &[u8; 16],
+) -> Result<TransportNotification, ParseError> {
+    let sealed = SealedNotification::parse_unverified(payload)?;
+    if !sealed.verify_crc() {
+        return Err(ParseError::CrcMismatch);
+    }
+    if !sealed.has_client_tag() {
+        return Err(ParseError::MissingClientTag);
+    }
+    if !sealed.verify_client_with(client_key) {
+        return Err(ParseError::ClientHmacMismatch);
+    }
+    sealed.decrypt(conf_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_protocol_core::crc::crc16_ccitt;
+    use ble_protocol_core::{
+        DEFAULT_HOPS, HMAC_KEY_CLIENT, HMAC_TAG_CLIENT_LEN, HMAC_TAG_INFRA_LEN, PROTOCOL_VERSION,
+    };
+
+    /// A notification as the broadcaster airs it: infra tag only.
+    fn broadcast() -> TransportNotification {
+        let mut notif = TransportNotification {
+            version: PROTOCOL_VERSION,
+            source_id: [1, 2, 3, 4],
+            notification_id: [5, 6, 7, 8],
+            event_dest: 0x12,
+            type_status: ((TransportType::Train as u8) << 4) | TransportStatus::Coming as u8,
+            duration_secs: 30u16.to_le_bytes(),
+            validity_secs: 600u16.to_le_bytes(),
+            flags: 0,
+            seq: 7u32.to_le_bytes(),
+            key_id: 0,
+            timestamp_ms: [0; 6],
+            priority: 0,
+            hmac_tag_infra: [0; HMAC_TAG_INFRA_LEN],
+            hmac_tag_client: [0; HMAC_TAG_CLIENT_LEN],
+            hops_remaining: DEFAULT_HOPS,
+            crc16: [0; 2],
+        };
+        notif.sign_infra();
+        notif
+    }
+
+    /// The same notification after the first repeater relayed it.
+    fn relayed() -> TransportNotification {
+        let mut notif = broadcast().next_hop().unwrap();
+        notif.sign_client();
+        notif
+    }
+
+    #[test]
+    fn repeater_signed_payload_verifies() {
+        let notif = relayed();
+        let got = verify_notification(notif.as_bytes(), HMAC_KEY_CLIENT).unwrap();
+        assert_eq!(got.as_bytes(), notif.as_bytes());
+        assert_eq!(got.transport_type(), Some(TransportType::Train));
+        assert_eq!(got.transport_status(), Some(TransportStatus::Coming));
+        assert_eq!(got.destination_id(), 2);
+    }
+
+    #[test]
+    fn further_hops_still_verify() {
+        let notif = relayed().next_hop().unwrap();
+        assert!(verify_notification(notif.as_bytes(), HMAC_KEY_CLIENT).is_ok());
+    }
+
+    #[test]
+    fn unsigned_client_tag_is_rejected() {
+        let notif = broadcast();
+        assert_eq!(
+            verify_notification(notif.as_bytes(), HMAC_KEY_CLIENT).unwrap_err(),
+            ParseError::MissingClientTag
+        );
+    }
+
+    #[test]
+    fn wrong_key_or_tampered_payload_is_rejected() {
+        let notif = relayed();
+        assert_eq!(
+            verify_notification(notif.as_bytes(), b"some-other-client-key!!!").unwrap_err(),
+            ParseError::ClientHmacMismatch
+        );
+
+        let mut tampered = notif;
+        tampered.duration_secs[0] ^= 0x01;
+        assert_eq!(
+            verify_notification(tampered.as_bytes(), HMAC_KEY_CLIENT).unwrap_err(),
+            ParseError::CrcMismatch
+        );
+        // With the CRC fixed up to match, the client tag still catches it.
+        tampered.crc16 = crc16_ccitt(tampered.base_payload()).to_le_bytes();
+        assert_eq!(
+            verify_notification(tampered.as_bytes(), HMAC_KEY_CLIENT).unwrap_err(),
+            ParseError::ClientHmacMismatch
+        );
+    }
+
+    /// `relayed`, signed by a repeater rolling the client key: the own tag
+    /// under id 0, and a record with a tag under id 1.
+    fn relayed_during_a_roll() -> Vec<u8> {
+        let mut notif = broadcast().next_hop().unwrap();
+        let record =
+            ClientTags::sign(&mut notif, &[(0, HMAC_KEY_CLIENT), (1, NEXT_CLIENT_KEY)]).unwrap();
+        [notif.as_bytes(), record.as_bytes()].concat()
+    }
+
+    const NEXT_CLIENT_KEY: &[u8] = b"next-generation-client!!";
+
+    #[test]
+    fn each_generation_verifies_its_own_tag() {
+        let payload = relayed_during_a_roll();
+        // An app that predates records reads the notification and stops.
+        assert!(verify_notification(&payload, HMAC_KEY_CLIENT).is_ok());
+        assert!(verify_notification_as(&payload, (0, HMAC_KEY_CLIENT)).is_ok());
+        assert!(verify_notification_as(&payload, (1, NEXT_CLIENT_KEY)).is_ok());
+
+        // Under the other generation's id, neither key verifies.
+        assert_eq!(
+            verify_notification_as(&payload, (1, HMAC_KEY_CLIENT)).unwrap_err(),
+            ParseError::ClientHmacMismatch
+        );
+        assert_eq!(
+            verify_notification_as(&payload, (0, NEXT_CLIENT_KEY)).unwrap_err(),
+            ParseError::ClientHmacMismatch
+        );
+        // No tag under an id nobody signed with.
+        assert_eq!(
+            verify_notification_as(&payload, (2, NEXT_CLIENT_KEY)).unwrap_err(),
+            ParseError::MissingClientTag
+        );
+    }
+
+    #[test]
+    fn a_stripped_record_leaves_only_the_own_tag() {
+        let payload = relayed_during_a_roll();
+        let stripped = &payload[..TransportNotification::SIZE];
+        assert!(verify_notification_as(stripped, (0, HMAC_KEY_CLIENT)).is_ok());
+        assert_eq!(
+            verify_notification_as(stripped, (1, NEXT_CLIENT_KEY)).unwrap_err(),
+            ParseError::MissingClientTag
+        );
+    }
+
+    #[test]
+    fn malformed_payload_reports_the_parse_error() {
+        let notif = relayed();
+        let short = &notif.as_bytes()[..10];
+        assert_eq!(
+            verify_notification(short, HMAC_KEY_CLIENT).unwrap_err(),
+            ParseError::TooShort {
+                got: 10,
+                need: TransportNotification::SIZE
+            }
+        );
+    }
+
+    #[cfg(feature = "encrypt")]
+    #[test]
+    fn sealed_payload_verifies_then_decrypts() {
+        use ble_protocol_core::conf::CONF_KEY;
+        use ble_protocol_core::INFRA_KEY_CURRENT;
+
+        let mut sealed =
+            SealedNotification::seal(&broadcast(), INFRA_KEY_CURRENT, CONF_KEY, [3; 12]);
+        assert_eq!(
+            verify_sealed_notification(sealed.as_bytes(), HMAC_KEY_CLIENT, CONF_KEY).unwrap_err(),
+            ParseError::MissingClientTag
+        );
+
+        sealed.sign_client();
+        let got = verify_sealed_notification(sealed.as_bytes(), HMAC_KEY_CLIENT, CONF_KEY).unwrap();
+        assert_eq!(got.base_payload(), broadcast().base_payload());
+        assert_eq!(
+            verify_sealed_notification(sealed.as_bytes(), HMAC_KEY_CLIENT, b"another-key-16b!")
+                .unwrap_err(),
+            ParseError::DecryptFailed
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! Acknowledgement beacons: a repeater's signed "I am relaying this".
+//!
+//! To check a deployment, a repeater can now and then advertise one small
+//! beacon per notification it relays, naming the notification and itself.
+//! A broadcaster scanning nearby counts the distinct repeaters per
+//! notification. Beacons are signed with the infrastructure key the
+//! notification itself was, so only infrastructure can claim a relay, and
+//! carry the notification's `seq`, so an ack captured on an earlier run
+//! doesn't count for a re-issued notification.
+//!
+//!   [0]       kind             u8  ACK_KIND
+//!   [1..5]    notification_id
+//!   [5..9]    seq              as in the notification
+//!   [9..13]   repeater_id      the acknowledging repeater
+//!   [13]      key_id
+//!   [14..]    hmac_tag_infra   over [0..14]
+//!
+//! With the default tag length a beacon fits a legacy advertisement.
+//! `ACK_KIND` is never a protocol version, so receivers that don't know
+//! acks reject them as `UnsupportedVersion`. Sealed notifications are never
+//! acked: the beacon would show their `notification_id` in the clear.
+
+use zerocopy::{FromBytes, Immutable, IntoBytes};
+
+use crate::consts::{InfraKey, HMAC_TAG_INFRA_LEN};
+use crate::crypto::{compute_infra_tag, infra_key, verify_tag};
+use crate::notification::{ParseError, TransportNotification};
+
+/// First byte of every ack beacon.
+pub const ACK_KIND: u8 = 0xAC;
+
+/// An acknowledgement beacon as it goes on air; see the module docs.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, PartialEq, FromBytes, IntoBytes, Immutable)]
+pub struct AckBeacon {
+    pub kind: u8,
+    pub notification_id: [u8; 4],
+    pub seq: [u8; 4],
+    pub repeater_id: [u8; 4],
+    pub key_id: u8,
+    pub hmac_tag_infra: [u8; HMAC_TAG_INFRA_LEN],
+}
+
+impl AckBeacon {
+    /// Size of an ack beacon on the wire.
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    /// Bytes the infrastructure tag covers.
+    pub const SIGNED_SIZE: usize = core::mem::offset_of!(Self, hmac_tag_infra);
+
+    /// `repeater_id`'s ack for `notif`, signed with `infra_key`.
+    pub fn new(
+        notif: &TransportNotification,
+        repeater_id: [u8; 4],
+        (key_id, key): InfraKey,
+    ) -> Self {
+        let mut ack = Self {
+            kind: ACK_KIND,
+            notification_id: notif.notification_id,
+            seq: notif.seq,
+            repeater_id,
+            key_id,
+            hmac_tag_infra: [0; HMAC_TAG_INFRA_LEN],
+        };
+        ack.hmac_tag_infra = compute_infra_tag(key, ack.signed_payload());
+        ack
+    }
+
+    /// Whether `payload` is an ack beacon rather than a notification, by its
+    /// first byte. Says nothing about whether it verifies.
+    pub fn is_ack(payload: &[u8]) -> bool {
+        payload.first() == Some(&ACK_KIND)
+    }
+
+    /// Parse an ack beacon and verify its tag against `keyring`. Trailing
+    /// bytes are ignored.
+    pub fn from_payload_with(payload: &[u8], keyring: &[InfraKey]) -> Result<Self, ParseError> {
+        let (ack, _) = Self::read_from_prefix(payload).map_err(|_| ParseError::TooShort {
+            got: payload.len(),
+            need: Self::SIZE,
+        })?;
+        if ack.kind != ACK_KIND {
+            return Err(ParseError::UnsupportedVersion(ack.kind));
+        }
+        let Some(key) = infra_key(keyring, ack.key_id) else {
+            return Err(ParseError::UnknownKeyId(ack.key_id));
+        };
+        let tag = ack.hmac_tag_infra;
+        if !verify_tag(key, ack.signed_payload(), &tag) {
+            return Err(ParseError::InfraHmacMismatch);
+        }
+        Ok(ack)
+    }
+
+    /// Whether this acknowledges `notif`, as issued: same `notification_id`
+    /// and `seq`.
+    pub fn acknowledges(&self, notif: &TransportNotification) -> bool {
+        self.notification_id == notif.notification_id && self.seq == notif.seq
+    }
+
+    /// The bytes the infrastructure tag covers.
+    pub fn signed_payload(&self) -> &[u8] {
+        &self.as_bytes()[..Self::SIGNED_SIZE]
+    }
+
+    /// The beacon as a byte slice, for broadcast.
+    pub fn as_bytes(&self) -> &[u8] {
+        IntoBytes::as_bytes(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::{INFRA_KEYRING, INFRA_KEY_CURRENT};
+    use crate::{TransportNotificationBuilder, TransportStatus, TransportType};
+
+    fn notification(seq: u32) -> TransportNotification {
+        TransportNotificationBuilder::new()
+            .notification_id([5, 6, 7, 8])
+            .transport(TransportType::Bus)
+            .status(TransportStatus::Coming)
+            .duration_secs(30)
+            .seq(seq)
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap()
+    }
+
+    #[test]
+    fn ack_round_trips_and_names_the_issue() {
+        let sent = AckBeacon::new(&notification(7), [1, 2, 3, 4], INFRA_KEY_CURRENT);
+        assert!(AckBeacon::is_ack(sent.as_bytes()));
+        assert!(!AckBeacon::is_ack(notification(7).as_bytes()));
+
+        let got = AckBeacon::from_payload_with(sent.as_bytes(), INFRA_KEYRING).unwrap();
+        assert_eq!(got, sent);
+        assert_eq!(got.repeater_id, [1, 2, 3, 4]);
+        assert!(got.acknowledges(&notification(7)));
+        // The same notification_id re-issued with a newer seq.
+        assert!(!got.acknowledges(&notification(8)));
+    }
+
+    #[test]
+    fn ack_is_verified() {
+        let sent = AckBeacon::new(&notification(7), [1, 2, 3, 4], INFRA_KEY_CURRENT);
+        let mut claimed = sent;
+        claimed.repeater_id = [9, 9, 9, 9];
+        assert_eq!(
+            AckBeacon::from_payload_with(claimed.as_bytes(), INFRA_KEYRING).unwrap_err(),
+            ParseError::InfraHmacMismatch
+        );
+        assert_eq!(
+            AckBeacon::from_payload_with(&sent.as_bytes()[..AckBeacon::SIZE - 1], INFRA_KEYRING)
+                .unwrap_err(),
+            ParseError::TooShort {
+                got: AckBeacon::SIZE - 1,
+                need: AckBeacon::SIZE
+            }
+        );
+        assert_eq!(
+            AckBeacon::from_payload_with(sent.as_bytes(), &[]).unwrap_err(),
+            ParseError::UnknownKeyId(sent.key_id)
+        );
+        assert_eq!(
+            AckBeacon::from_payload_with(notification(7).as_bytes(), INFRA_KEYRING).unwrap_err(),
+            ParseError::UnsupportedVersion(notification(7).version)
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! Building a `TransportNotification` without packing nibbles by hand.
+//!
+//! `event_dest` and `type_status` each hold two 4-bit values. Writing the
+//! shifts and masks at every call site is how a destination of 16 ends up
+//! silently changing the event. `TransportNotificationBuilder` takes each
+//! value on its own, checks the ranges, packs the bytes and signs.
+
+use core::fmt;
+
+use crate::consts::{InfraKey, MAX_DURATION_SECS, PROTOCOL_VERSION};
+use crate::consts::{DEFAULT_HOPS, HMAC_TAG_CLIENT_LEN, HMAC_TAG_INFRA_LEN};
+use crate::notification::{EventId, TransportNotification, TransportStatus, TransportType};
+
+/// Why a builder could not produce a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildError {
+    /// `destination` doesn't fit in a nibble.
+    DestinationOutOfRange(u8),
+    /// An `EventId::Unknown` value doesn't fit in a nibble.
+    EventOutOfRange(u8),
+    /// `transport` was never set.
+    MissingTransport,
+    /// `status` was never set.
+    MissingStatus,
+    /// `duration_secs` is over `MAX_DURATION_SECS`.
+    DurationTooLong(u16),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DestinationOutOfRange(v) => write!(f, "destination {} is over 15", v),
+            Self::EventOutOfRange(v) => write!(f, "event {} is over 15", v),
+            Self::MissingTransport => write!(f, "transport type not set"),
+            Self::MissingStatus => write!(f, "transport status not set"),
+            Self::DurationTooLong(secs) => {
+                write!(f, "duration {} s is over {} s", secs, MAX_DURATION_SECS)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildError {}
+
+/// Chained construction of a `TransportNotification`.
+///
+/// `transport` and `status` are required. Everything else defaults to zero,
+/// except `hops_remaining`, which starts at `DEFAULT_HOPS`. `key_id`, the
+/// infra tag and `crc16` are set by `build_signed`.
+#[derive(Debug, Clone, Copy)]
+pub struct TransportNotificationBuilder {
+    source_id: [u8; 4],
+    notification_id: [u8; 4],
+    event: EventId,
+    destination: u8,
+    transport: Option<TransportType>,
+    status: Option<TransportStatus>,
+    duration_secs: u16,
+    validity_secs: u16,
+    flags: u8,
+    seq: u32,
+    timestamp_ms: u64,
+    priority: u8,
+    hops_remaining: u8,
+}
+
+impl Default for TransportNotificationBuilder {
+    fn default() -> Self {
+        Self {
+            source_id: [0; 4],
+            notification_id: [0; 4],
+            event: EventId::Unknown(0),
+            destination: 0,
+            transport: None,
+            status: None,
+            duration_secs: 0,
+            validity_secs: 0,
+            flags: 0,
+            seq: 0,
+            timestamp_ms: 0,
+            priority: 0,
+            hops_remaining: DEFAULT_HOPS,
+        }
+    }
+}
+
+impl TransportNotificationBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn source_id(mut self, id: [u8; 4]) -> Self {
+        self.source_id = id;
+        self
+    }
+
+    pub fn notification_id(mut self, id: [u8; 4]) -> Self {
+        self.notification_id = id;
+        self
+    }
+
+    pub fn event(mut self, event: EventId) -> Self {
+        self.event = event;
+        self
+    }
+
+    /// Destination id, 0–15.
+    pub fn destination(mut self, id: u8) -> Self {
+        self.destination = id;
+        self
+    }
+
+    pub fn transport(mut self, transport: TransportType) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    pub fn status(mut self, status: TransportStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn duration_secs(mut self, secs: u16) -> Self {
+        self.duration_secs = secs;
+        self
+    }
+
+    pub fn validity_secs(mut self, secs: u16) -> Self {
+        self.validity_secs = secs;
+        self
+    }
+
+    pub fn flags(mut self, flags: u8) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn seq(mut self, seq: u32) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    /// Emission time in unix-epoch milliseconds.
+    pub fn timestamp_ms(mut self, ms: u64) -> Self {
+        self.timestamp_ms = ms;
+        self
+    }
+
+    /// Priority on a full active list; higher evicts lower.
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn hops_remaining(mut self, hops: u8) -> Self {
+        self.hops_remaining = hops;
+        self
+    }
+
+    /// Pack the fields into a notification with both tags and `crc16` still
+    /// zero, for a caller that signs it later.
+    pub fn build_unsigned(&self) -> Result<TransportNotification, BuildError> {
+        if self.destination > 0x0F {
+            return Err(BuildError::DestinationOutOfRange(self.destination));
+        }
+        if let EventId::Unknown(v @ 0x10..) = self.event {
+            return Err(BuildError::EventOutOfRange(v));
+        }
+        if self.duration_secs > MAX_DURATION_SECS {
+            return Err(BuildError::DurationTooLong(self.duration_secs));
+        }
+        let transport = self.transport.ok_or(BuildError::MissingTransport)?;
+        let status = self.status.ok_or(BuildError::MissingStatus)?;
+
+        Ok(TransportNotification {
+            version: PROTOCOL_VERSION,
+            source_id: self.source_id,
+            notification_id: self.notification_id,
+            event_dest: (self.event.to_u4() << 4) | self.destination,
+            type_status: ((transport as u8) << 4) | status as u8,
+            duration_secs: self.duration_secs.to_le_bytes(),
+            validity_secs: self.validity_secs.to_le_bytes(),
+            flags: self.flags,
+            seq: self.seq.to_le_bytes(),
+            key_id: 0,
+            timestamp_ms: TransportNotification::timestamp_bytes(self.timestamp_ms),
+            priority: self.priority,
+            hmac_tag_infra: [0; HMAC_TAG_INFRA_LEN],
+            hmac_tag_client: [0; HMAC_TAG_CLIENT_LEN],
+            hops_remaining: self.hops_remaining,
+            crc16: [0; 2],
+        })
+    }
+
+    /// Pack the fields and sign the infra tag with `infra_key`, whose id goes
+    /// into `key_id`.
+    pub fn build_signed(&self, infra_key: InfraKey) -> Result<TransportNotification, BuildError> {
+        let mut notif = self.build_unsigned()?;
+        notif.sign_infra_with(infra_key);
+        Ok(notif)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::{INFRA_KEYRING, INFRA_KEY_CURRENT};
+
+    fn train_delay() -> TransportNotificationBuilder {
+        TransportNotificationBuilder::new()
+            .source_id([1, 2, 3, 4])
+            .notification_id([5, 6, 7, 8])
+            .event(EventId::Delay)
+            .destination(15)
+            .transport(TransportType::Train)
+            .status(TransportStatus::Late)
+            .duration_secs(30)
+            .validity_secs(600)
+            .seq(9)
+            .priority(3)
+    }
+
+    #[test]
+    fn built_notification_verifies_and_reads_back() {
+        let notif = train_delay().build_signed(INFRA_KEY_CURRENT).unwrap();
+        let parsed =
+            TransportNotification::from_payload_with(notif.as_bytes(), INFRA_KEYRING, None)
+                .unwrap();
+        assert_eq!(parsed.event(), EventId::Delay);
+        assert_eq!(parsed.destination_id(), 15);
+        assert_eq!(parsed.transport_type(), Some(TransportType::Train));
+        assert_eq!(parsed.transport_status(), Some(TransportStatus::Late));
+        assert_eq!(parsed.duration_secs(), 30);
+        assert_eq!(parsed.seq(), 9);
+        assert_eq!(parsed.priority, 3);
+        assert_eq!(parsed.hops_remaining, DEFAULT_HOPS);
+        assert_eq!(parsed.key_id, INFRA_KEY_CURRENT.0);
+    }
+
+    #[test]
+    fn out_of_range_nibbles_are_rejected() {
+        assert_eq!(
+            train_delay().destination(16).build_unsigned().unwrap_err(),
+            BuildError::DestinationOutOfRange(16)
+        );
+        assert_eq!(
+            train_delay()
+                .event(EventId::Unknown(0x10))
+                .build_unsigned()
+                .unwrap_err(),
+            BuildError::EventOutOfRange(0x10)
+        );
+        assert!(train_delay()
+            .event(EventId::Unknown(15))
+            .build_unsigned()
+            .is_ok());
+    }
+
+    #[test]
+    fn duration_is_capped_at_the_maximum() {
+        assert!(train_delay()
+            .duration_secs(MAX_DURATION_SECS)
+            .build_unsigned()
+            .is_ok());
+        assert_eq!(
+            train_delay()
+                .duration_secs(MAX_DURATION_SECS + 1)
+                .build_unsigned()
+                .unwrap_err(),
+            BuildError::DurationTooLong(MAX_DURATION_SECS + 1)
+        );
+    }
+
+    #[test]
+    fn transport_and_status_are_required() {
+        assert_eq!(
+            TransportNotificationBuilder::new()
+                .status(TransportStatus::Coming)
+                .build_unsigned()
+                .unwrap_err(),
+            BuildError::MissingTransport
+        );
+        assert_eq!(
+            TransportNotificationBuilder::new()
+                .transport(TransportType::Bus)
+                .build_unsigned()
+                .unwrap_err(),
+            BuildError::MissingStatus
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Capability beacons: what a repeater can relay, for the repeaters around
+//! it.
+//!
+//! In a mesh of mixed firmware, a repeater can now and then advertise which
+//! protocol versions it relays and which optional features it has. Its
+//! neighbours keep what they hear in a `NeighborTable` and consult it before
+//! relaying: a notification none of them would relay further is not worth
+//! the airtime. Beacons are signed with an infrastructure key, so only
+//! infrastructure can claim capabilities.
+//!
+//!   [0]       kind             u8  CAPABILITY_KIND
+//!   [1..5]    repeater_id
+//!   [5..7]    versions         u16 LE, bit v set if version v is relayed
+//!   [7]       features         FEATURE_* bits
+//!   [8]       key_id
+//!   [9..]     hmac_tag_infra   over [0..9]
+//!
+//! A beacon fits a legacy advertisement with any tag length.
+//! `CAPABILITY_KIND` is never a protocol version, so receivers that don't
+//! know beacons reject them as `UnsupportedVersion`.
+//!
+//! A beacon carries no time, so a captured one can be replayed. That only
+//! restates capabilities its repeater really had, and keeps its entry fresh
+//! while the replay goes on; see `NeighborTable` for how stale entries age
+//! out otherwise.
+
+use zerocopy::{FromBytes, Immutable, IntoBytes};
+
+use crate::consts::{
+    InfraKey, HMAC_TAG_INFRA_LEN, LEGACY_ADV_DATA_LEN, MFG_AD_OVERHEAD, PROTOCOL_VERSION,
+    PROTOCOL_VERSION_V1,
+};
+use crate::crypto::{compute_infra_tag, infra_key, verify_tag};
+use crate::notification::ParseError;
+
+/// First byte of every capability beacon.
+pub const CAPABILITY_KIND: u8 = 0xCB;
+
+/// The repeater opens and relays sealed notifications (`conf`).
+pub const FEATURE_SEALED: u8 = 0x01;
+/// The repeater airs ack beacons (`ack`).
+pub const FEATURE_ACKS: u8 = 0x02;
+
+/// The versions this crate parses, as a `CapabilityBeacon::versions` bitmap.
+pub const SUPPORTED_VERSIONS: u16 = 1 << PROTOCOL_VERSION_V1 | 1 << PROTOCOL_VERSION;
+
+// Versions are bits of a u16.
+const _: () = assert!(PROTOCOL_VERSION < 16 && PROTOCOL_VERSION_V1 < 16);
+
+/// A capability beacon as it goes on air; see the module docs.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, PartialEq, FromBytes, IntoBytes, Immutable)]
+pub struct CapabilityBeacon {
+    pub kind: u8,
+    pub repeater_id: [u8; 4],
+    pub versions: [u8; 2],
+    pub features: u8,
+    pub key_id: u8,
+    pub hmac_tag_infra: [u8; HMAC_TAG_INFRA_LEN],
+}
+
+const _: () = assert!(CapabilityBeacon::SIZE + MFG_AD_OVERHEAD <= LEGACY_ADV_DATA_LEN);
+
+impl CapabilityBeacon {
+    /// Size of a capability beacon on the wire.
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    /// Bytes the infrastructure tag covers.
+    pub const SIGNED_SIZE: usize = core::mem::offset_of!(Self, hmac_tag_infra);
+
+    /// `repeater_id`'s beacon claiming `versions` (a bitmap, as
+    /// `SUPPORTED_VERSIONS`) and `features`, signed with `infra_key`.
+    pub fn new(repeater_id: [u8; 4], versions: u16, features: u8, (key_id, key): InfraKey) -> Self {
+        let mut beacon = Self {
+            kind: CAPABILITY_KIND,
+            repeater_id,
+            versions: versions.to_le_bytes(),
+            features,
+            key_id,
+            hmac_tag_infra: [0; HMAC_TAG_INFRA_LEN],
+        };
+        beacon.hmac_tag_infra = compute_infra_tag(key, beacon.signed_payload());
+        beacon
+    }
+
+    /// Whether `payload` is a capability beacon rather than a notification,
+    /// by its first byte. Says nothing about whether it verifies.
+    pub fn is_capability(payload: &[u8]) -> bool {
+        payload.first() == Some(&CAPABILITY_KIND)
+    }
+
+    /// Parse a capability beacon and verify its tag against `keyring`.
+    /// Trailing bytes are ignored.
+    pub fn from_payload_with(payload: &[u8], keyring: &[InfraKey]) -> Result<Self, ParseError> {
+        let (beacon, _) = Self::read_from_prefix(payload).map_err(|_| ParseError::TooShort {
+            got: payload.len(),
+            need: Self::SIZE,
+        })?;
+        if beacon.kind != CAPABILITY_KIND {
+            return Err(ParseError::UnsupportedVersion(beacon.kind));
+        }
+        let Some(key) = infra_key(keyring, beacon.key_id) else {
+            return Err(ParseError::UnknownKeyId(beacon.key_id));
+        };
+        let tag = beacon.hmac_tag_infra;
+        if !verify_tag(key, beacon.signed_payload(), &tag) {
+            return Err(ParseError::InfraHmacMismatch);
+        }
+        Ok(beacon)
+    }
+
+    /// Whether the repeater relays protocol `version`.
+    pub fn speaks(&self, version: u8) -> bool {
+        version < 16 && u16::from_le_bytes(self.versions) & (1 << version) != 0
+    }
+
+    /// Whether the repeater has every `FEATURE_*` bit in `features`.
+    pub fn has(&self, features: u8) -> bool {
+        self.features & features == features
+    }
+
+    /// The bytes the infrastructure tag covers.
+    pub fn signed_payload(&self) -> &[u8] {
+        &self.as_bytes()[..Self::SIGNED_SIZE]
+    }
+
+    /// The beacon as a byte slice, for broadcast.
+    pub fn as_bytes(&self) -> &[u8] {
+        IntoBytes::as_bytes(self)
+    }
+}
+
+/// A neighbour's latest beacon and when it was heard.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Neighbor {
+    pub beacon: CapabilityBeacon,
+    /// Monotonic milliseconds, on the table owner's clock.
+    pub heard_at_ms: u64,
+}
+
+/// The capabilities of up to `N` neighbours, from the beacons heard.
+///
+/// Times are monotonic milliseconds on the owner's clock (e.g. since boot);
+/// no real time is needed. An entry not refreshed by a beacon for
+/// `stale_after_ms` is stale: it no longer counts in `worth_relaying`, and
+/// is the first replaced when the table is full. A neighbour that goes
+/// quiet, powered off or re-flashed with firmware that doesn't beacon, so
+/// drops out on its own. Set `stale_after_ms` to a few beacon periods, so
+/// one lost beacon doesn't age a neighbour out. A full table with no stale
+/// entry replaces the one heard longest ago.
+///
+/// The table knows only neighbours that beacon. Clients don't, and neither
+/// do repeaters on older firmware, so a repeater whose relays also reach
+/// those should not skip relays on the table's word.
+#[derive(Debug, Clone)]
+pub struct NeighborTable<const N: usize> {
+    slots: [Option<Neighbor>; N],
+    stale_after_ms: u64,
+}
+
+impl<const N: usize> NeighborTable<N> {
+    pub const fn new(stale_after_ms: u64) -> Self {
+        Self {
+            slots: [None; N],
+            stale_after_ms,
+        }
+    }
+
+    /// Record `beacon`, a verified one heard at `now_ms`, replacing what
+    /// its repeater claimed before.
+    pub fn record(&mut self, beacon: CapabilityBeacon, now_ms: u64) {
+        let new = Some(Neighbor {
+            beacon,
+            heard_at_ms: now_ms,
+        });
+        let id = beacon.repeater_id;
+        if let Some(slot) = self
+            .slots
+            .iter_mut()
+            .find(|s| s.is_some_and(|n| n.beacon.repeater_id == id))
+        {
+            *slot = new;
+        } else if let Some(free) = self.slots.iter_mut().find(|s| s.is_none()) {
+            *free = new;
+        } else if let Some(oldest) = self
+            .slots
+            .iter_mut()
+            .max_by_key(|s| s.map_or(0, |n| now_ms.saturating_sub(n.heard_at_ms)))
+        {
+            *oldest = new;
+        }
+    }
+
+    /// The neighbours heard from within `stale_after_ms` of `now_ms`.
+    pub fn fresh(&self, now_ms: u64) -> impl Iterator<Item = &Neighbor> {
+        let stale_after_ms = self.stale_after_ms;
+        self.slots
+            .iter()
+            .flatten()
+            .filter(move |n| now_ms.saturating_sub(n.heard_at_ms) <= stale_after_ms)
+    }
+
+    /// Whether a notification of protocol `version`, sealed or not, is
+    /// worth relaying: some fresh neighbour relays it, or there is no fresh
+    /// neighbour to go by. Only a table of fresh neighbours that all lack
+    /// the version, or `FEATURE_SEALED` for a sealed one, says no.
+    pub fn worth_relaying(&self, version: u8, sealed: bool, now_ms: u64) -> bool {
+        let feature = if sealed { FEATURE_SEALED } else { 0 };
+        let mut fresh = self.fresh(now_ms).peekable();
+        fresh.peek().is_none() || fresh.any(|n| n.beacon.speaks(version) && n.beacon.has(feature))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::{INFRA_KEYRING, INFRA_KEY_CURRENT};
+
+    const V1_ONLY: u16 = 1 << PROTOCOL_VERSION_V1;
+
+    fn beacon(id: u8, versions: u16, features: u8) -> CapabilityBeacon {
+        CapabilityBeacon::new([id; 4], versions, features, INFRA_KEY_CURRENT)
+    }
+
+    #[test]
+    fn capability_beacon_round_trips() {
+        let sent = beacon(1, SUPPORTED_VERSIONS, FEATURE_SEALED);
+        assert!(CapabilityBeacon::is_capability(sent.as_bytes()));
+        let got = CapabilityBeacon::from_payload_with(sent.as_bytes(), INFRA_KEYRING).unwrap();
+        assert_eq!(got, sent);
+        assert_eq!(got.repeater_id, [1; 4]);
+        assert!(got.speaks(PROTOCOL_VERSION) && got.speaks(PROTOCOL_VERSION_V1));
+        assert!(!got.speaks(2) && !got.speaks(200));
+        assert!(got.has(FEATURE_SEALED) && got.has(0));
+        assert!(!got.has(FEATURE_ACKS) && !got.has(FEATURE_SEALED | FEATURE_ACKS));
+    }
+
+    #[test]
+    fn capability_beacon_is_verified() {
+        let mut claimed = beacon(1, V1_ONLY, 0);
+        claimed.versions = SUPPORTED_VERSIONS.to_le_bytes();
+        assert_eq!(
+            CapabilityBeacon::from_payload_with(claimed.as_bytes(), INFRA_KEYRING).unwrap_err(),
+            ParseError::InfraHmacMismatch
+        );
+        let sent = beacon(1, V1_ONLY, 0);
+        assert!(matches!(
+            CapabilityBeacon::from_payload_with(&sent.as_bytes()[..5], INFRA_KEYRING),
+            Err(ParseError::TooShort { .. })
+        ));
+        let mut other = *sent
+            .as_bytes()
+            .first_chunk::<{ CapabilityBeacon::SIZE }>()
+            .unwrap();
+        other[0] = PROTOCOL_VERSION;
+        assert_eq!(
+            CapabilityBeacon::from_payload_with(&other, INFRA_KEYRING).unwrap_err(),
+            ParseError::UnsupportedVersion(PROTOCOL_VERSION)
+        );
+    }
+
+    #[test]
+    fn relays_only_what_a_fresh_neighbor_relays() {
+        let mut table = NeighborTable::<4>::new(1_000);
+        // Nobody known: relay.
+        assert!(table.worth_relaying(PROTOCOL_VERSION, false, 0));
+
+        table.record(beacon(1, V1_ONLY, 0), 0);
+        assert!(table.worth_relaying(PROTOCOL_VERSION_V1, false, 500));
+        assert!(!table.worth_relaying(PROTOCOL_VERSION, false, 500));
+
+        table.record(beacon(2, SUPPORTED_VERSIONS, 0), 500);
+        assert!(table.worth_relaying(PROTOCOL_VERSION, false, 600));
+        assert!(
+            !table.worth_relaying(PROTOCOL_VERSION, true, 600),
+            "nobody opens sealed"
+        );
+
+        // Neighbour 2 upgrades: its new beacon replaces the old one.
+        table.record(beacon(2, SUPPORTED_VERSIONS, FEATURE_SEALED), 700);
+        assert!(table.worth_relaying(PROTOCOL_VERSION, true, 800));
+        assert_eq!(table.fresh(800).count(), 2);
+    }
+
+    #[test]
+    fn stale_neighbors_stop_counting() {
+        let mut table = NeighborTable::<2>::new(1_000);
+        table.record(beacon(1, V1_ONLY, 0), 0);
+        table.record(beacon(2, SUPPORTED_VERSIONS, 0), 0);
+        table.record(beacon(1, V1_ONLY, 0), 900);
+
+        // Neighbour 2 went quiet: only neighbour 1, v1-only, is left.
+        assert!(!table.worth_relaying(PROTOCOL_VERSION, false, 1_500));
+        // And once it is stale too, nothing is known: relay again.
+        assert!(table.worth_relaying(PROTOCOL_VERSION, false, 2_000));
+
+        // Full: the entry heard longest ago makes room.
+        table.record(beacon(3, SUPPORTED_VERSIONS, 0), 2_000);
+        let ids: Vec<[u8; 4]> = table.fresh(2_000).map(|n| n.beacon.repeater_id).collect();
+        assert_eq!(ids, [[3; 4]]);
+        assert!(table
+            .slots
+            .iter()
+            .flatten()
+            .any(|n| n.beacon.repeater_id == [1; 4]));
+    }
+}
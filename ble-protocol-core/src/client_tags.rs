@@ -0,0 +1,371 @@
+//! Client-tag records: client tags beyond a notification's own.
+//!
+//! A notification carries one client tag, which an app checks with the
+//! client key it holds. Two cases need more: rolling the client key, while
+//! the old app and the new one are both in riders' hands, and a mesh shared
+//! by independent apps (say two agencies'), each with its own key. So a
+//! repeater holding more than one client key follows a notification it
+//! signs, in the same manufacturer data, with a client-tag record saying
+//! which key each tag is under:
+//!
+//!   [0]     CLIENT_TAGS_RECORD (0xC1), where a version byte would be
+//!   [1]     flags: bits 0–1 how many tags follow, at most
+//!           `MAX_EXTRA_CLIENT_TAGS`; the other bits are 0
+//!   [2]     client_key_id of the notification's own `hmac_tag_client`
+//!   then per tag:
+//!     client_key_id  u8
+//!     tag            [u8; HMAC_TAG_CLIENT_LEN], over the notification's
+//!                    base payload, like its own tag
+//!
+//! Without a record the own tag is under client_key_id 0, the key every app
+//! held before there were records. An app checks only the tag under its own
+//! id (see `verify`).
+//!
+//! `Records` keeps a record with the notification before it, dispatching on
+//! its leading byte as on a version byte, and `relay::Received` carries it
+//! on to the next hop. Apps that predate records read the notification and
+//! stop; repeaters that predate them skip the record as a malformed
+//! notification and relay the notification alone. Either way the own tag
+//! still verifies.
+//!
+//! ## Rolling the client key
+//!
+//! With the old key as id 0 and the new one as id 1:
+//!
+//! 1. give repeaters the new key alongside the old one and sign with both
+//!    (`client_key_ids = [0, 1]` in `repeater.toml`). The own tag stays
+//!    under the old key and the record adds one under the new key, so the
+//!    old app and the new one both verify;
+//! 2. ship the new app, checking id 1;
+//! 3. once the old app is gone, sign with the new key alone
+//!    (`client_key_ids = [1]`): the own tag is under it, and a record with
+//!    no further tags says so.
+//!
+//! ## Several apps on one mesh
+//!
+//! The mesh operator gives each app its own `client_key_id` and key, and
+//! repeaters sign with all of them (`client_key_ids = [0, 5]`). Each app
+//! holds only its own key and checks only the tag under its own id, so
+//! neither can forge a tag the other accepts. Both still trust the mesh:
+//! the broadcasters' infrastructure tag says what was announced, and every
+//! signing repeater holds every app's key. Tenants that don't trust the
+//! operator with their key need meshes of their own.
+//!
+//! The first id gets the notification's own tag, which is all an app that
+//! predates records checks; give it to the app that was there first. An
+//! app rolling its key while another is on the mesh takes the third tag.
+//!
+//! ## Space
+//!
+//! 3 bytes, plus `1 + HMAC_TAG_CLIENT_LEN` per further tag, with the
+//! default tag lengths: 3 bytes with one key, 8 with two (a roll, or two
+//! apps), 13 with three. A notification already needs extended
+//! advertising, which has the room. Sealed and v1 notifications have no
+//! record; they carry their own tag alone, under the first key, so only
+//! the first app reads them.
+//!
+//! ## Trust
+//!
+//! The record is outside both signed payloads, like `hops_remaining`.
+//! Anyone in range can strip it, which keeps apps on the other keys from
+//! verifying (no worse than jamming them), or change an id, which only makes
+//! a tag fail. Forging a tag still takes the key.
+
+use crate::consts::{ClientKey, CLIENT_TAGS_RECORD, HMAC_TAG_CLIENT_LEN};
+use crate::crypto::{compute_client_tag, verify_tag};
+use crate::notification::{extensions, ParseError, TransportNotification};
+
+/// Most tags a record carries besides the notification's own.
+pub const MAX_EXTRA_CLIENT_TAGS: usize = 2;
+
+/// Record bytes before the tags.
+const HEADER_LEN: usize = 3;
+
+/// Bytes per tag: its `client_key_id`, then the tag.
+const ENTRY_LEN: usize = 1 + HMAC_TAG_CLIENT_LEN;
+
+/// Bits of the flags byte holding the tag count.
+const COUNT_MASK: u8 = 0x03;
+
+/// A client-tag record; see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientTags {
+    bytes: [u8; ClientTags::MAX_SIZE],
+}
+
+impl ClientTags {
+    /// Size of a record with `MAX_EXTRA_CLIENT_TAGS` tags.
+    pub const MAX_SIZE: usize = HEADER_LEN + MAX_EXTRA_CLIENT_TAGS * ENTRY_LEN;
+
+    /// Size of a record with `count` tags.
+    pub const fn size_with(count: usize) -> usize {
+        HEADER_LEN + count * ENTRY_LEN
+    }
+
+    /// Sign `notif` with `keys`: its own client tag with the first, and a
+    /// tag in the record with each of the rest. Returns the record, or
+    /// `None` if it would say nothing: the one key is client_key_id 0.
+    ///
+    /// Panics if `keys` is empty or has more than `MAX_EXTRA_CLIENT_TAGS`
+    /// keys after the first.
+    pub fn sign(notif: &mut TransportNotification, keys: &[ClientKey]) -> Option<Self> {
+        let ((own_id, own_key), rest) = keys.split_first().expect("no client key to sign with");
+        assert!(
+            rest.len() <= MAX_EXTRA_CLIENT_TAGS,
+            "more client keys than a client-tag record holds"
+        );
+        notif.sign_client_with(own_key);
+        if *own_id == 0 && rest.is_empty() {
+            return None;
+        }
+        let mut bytes = [0u8; Self::MAX_SIZE];
+        bytes[0] = CLIENT_TAGS_RECORD;
+        bytes[1] = rest.len() as u8;
+        bytes[2] = *own_id;
+        for (entry, (id, key)) in bytes[HEADER_LEN..].chunks_exact_mut(ENTRY_LEN).zip(rest) {
+            entry[0] = *id;
+            entry[1..].copy_from_slice(&compute_client_tag(key, notif.base_payload()));
+        }
+        Some(Self { bytes })
+    }
+
+    /// Bytes the record at the start of `bytes` takes by its flags, as far
+    /// as `bytes` goes: what `Records` keeps with the notification.
+    pub(crate) fn claimed_len(bytes: &[u8]) -> usize {
+        let count = bytes.get(1).map_or(0, |flags| flags & COUNT_MASK);
+        Self::size_with(usize::from(count)).min(bytes.len())
+    }
+
+    /// Parse the record at the start of `bytes`. Trailing bytes are
+    /// ignored. A record with reserved flag bits set, or more tags than
+    /// `MAX_EXTRA_CLIENT_TAGS`, is of a layout this build doesn't know, and
+    /// reported as `UnsupportedVersion` of its leading byte.
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        let too_short = |need| ParseError::TooShort {
+            got: bytes.len(),
+            need,
+        };
+        let (&marker, &flags) = match bytes {
+            [marker, flags, ..] => (marker, flags),
+            _ => return Err(too_short(HEADER_LEN)),
+        };
+        let count = usize::from(flags & COUNT_MASK);
+        if marker != CLIENT_TAGS_RECORD || flags & !COUNT_MASK != 0 || count > MAX_EXTRA_CLIENT_TAGS
+        {
+            return Err(ParseError::UnsupportedVersion(marker));
+        }
+        let len = Self::size_with(count);
+        let record = bytes.get(..len).ok_or_else(|| too_short(len))?;
+        let mut tags = Self {
+            bytes: [0; Self::MAX_SIZE],
+        };
+        tags.bytes[..len].copy_from_slice(record);
+        Ok(tags)
+    }
+
+    /// The record among the extension records following the notification
+    /// at the start of `record`, one of `Records`; `None` if it has none.
+    pub fn find(record: &[u8]) -> Result<Option<Self>, ParseError> {
+        extensions(record)
+            .find(|ext| ext.first() == Some(&CLIENT_TAGS_RECORD))
+            .map(Self::parse)
+            .transpose()
+    }
+
+    /// The record as it goes on air.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..Self::size_with(self.count())]
+    }
+
+    /// The `client_key_id` of the notification's own client tag.
+    pub fn own_key_id(&self) -> u8 {
+        self.bytes[2]
+    }
+
+    /// The further tags, each with its `client_key_id`.
+    pub fn tags(&self) -> impl Iterator<Item = (u8, &[u8])> {
+        self.bytes[HEADER_LEN..Self::size_with(self.count())]
+            .chunks_exact(ENTRY_LEN)
+            .map(|entry| (entry[0], &entry[1..]))
+    }
+
+    fn count(&self) -> usize {
+        usize::from(self.bytes[1] & COUNT_MASK)
+    }
+}
+
+/// Check the client tag of `notif` under `key`, given `record`, the
+/// client-tag record that came with it, if any: the own tag if that is
+/// under the key's `client_key_id` (0 without a record), otherwise the
+/// record's tag under that id. Fails with `MissingClientTag` if there is no
+/// tag under the id, and with `ClientHmacMismatch` if there is one and it
+/// doesn't verify.
+pub fn verify(
+    notif: &TransportNotification,
+    record: Option<&ClientTags>,
+    (key_id, key): ClientKey,
+) -> Result<(), ParseError> {
+    let own_id = record.map_or(0, ClientTags::own_key_id);
+    let verified = if own_id == key_id {
+        if !notif.has_client_tag() {
+            return Err(ParseError::MissingClientTag);
+        }
+        notif.verify_client_with(key)
+    } else {
+        let (_, tag) = record
+            .and_then(|r| r.tags().find(|(id, _)| *id == key_id))
+            .ok_or(ParseError::MissingClientTag)?;
+        verify_tag(key, notif.base_payload(), tag)
+    };
+    if verified {
+        Ok(())
+    } else {
+        Err(ParseError::ClientHmacMismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::TransportNotificationBuilder;
+    use crate::consts::{HMAC_KEY_CLIENT, INFRA_KEY_CURRENT};
+    use crate::notification::{Records, TransportStatus, TransportType};
+
+    const OLD: ClientKey = (1, HMAC_KEY_CLIENT);
+    const NEW: ClientKey = (2, b"client-key-generation-2!");
+
+    fn broadcast() -> TransportNotification {
+        TransportNotificationBuilder::new()
+            .notification_id([7; 4])
+            .transport(TransportType::Bus)
+            .status(TransportStatus::Coming)
+            .duration_secs(30)
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap()
+    }
+
+    /// The notification and its record as they go on air, one record.
+    fn aired(notif: &TransportNotification, record: Option<&ClientTags>) -> Vec<u8> {
+        let mut bytes = notif.as_bytes().to_vec();
+        bytes.extend_from_slice(record.map_or(&[][..], ClientTags::as_bytes));
+        bytes
+    }
+
+    #[test]
+    fn a_second_generation_tag_verifies_only_under_that_generation() {
+        let mut notif = broadcast();
+        let record = ClientTags::sign(&mut notif, &[NEW]).unwrap();
+        assert_eq!(record.as_bytes(), [CLIENT_TAGS_RECORD, 0, 2]);
+        assert_eq!(verify(&notif, Some(&record), NEW), Ok(()));
+        // A generation-1 app finds no tag under its id; with the same id but
+        // the wrong key, the tag doesn't verify.
+        assert_eq!(
+            verify(&notif, Some(&record), OLD),
+            Err(ParseError::MissingClientTag)
+        );
+        assert_eq!(
+            verify(&notif, Some(&record), (2, HMAC_KEY_CLIENT)),
+            Err(ParseError::ClientHmacMismatch)
+        );
+    }
+
+    #[test]
+    fn both_generations_verify_during_the_overlap() {
+        let mut notif = broadcast();
+        let record = ClientTags::sign(&mut notif, &[(0, HMAC_KEY_CLIENT), NEW]).unwrap();
+        assert_eq!(record.as_bytes().len(), ClientTags::size_with(1));
+        // An app that predates records checks the own tag.
+        assert!(notif.verify_client_with(HMAC_KEY_CLIENT));
+        assert_eq!(verify(&notif, Some(&record), (0, HMAC_KEY_CLIENT)), Ok(()));
+        assert_eq!(verify(&notif, Some(&record), NEW), Ok(()));
+    }
+
+    #[test]
+    fn each_app_on_a_shared_mesh_verifies_only_its_own_tag() {
+        const AGENCY_A: ClientKey = (0, HMAC_KEY_CLIENT);
+        const AGENCY_B: ClientKey = (5, b"agency-b-client-key!!!!!");
+        let mut notif = broadcast();
+        let record = ClientTags::sign(&mut notif, &[AGENCY_A, AGENCY_B]).unwrap();
+        // The flags byte counts the tags after the own one.
+        assert_eq!(record.as_bytes()[1], 1);
+        assert_eq!(record.tags().count(), 1);
+
+        assert_eq!(verify(&notif, Some(&record), AGENCY_A), Ok(()));
+        assert_eq!(verify(&notif, Some(&record), AGENCY_B), Ok(()));
+        // Each app's key fails on the other's tag.
+        let (a, b) = (AGENCY_A.1, AGENCY_B.1);
+        assert_eq!(
+            verify(&notif, Some(&record), (AGENCY_B.0, a)),
+            Err(ParseError::ClientHmacMismatch)
+        );
+        assert_eq!(
+            verify(&notif, Some(&record), (AGENCY_A.0, b)),
+            Err(ParseError::ClientHmacMismatch)
+        );
+
+        // One of them rolling its key takes the third tag.
+        let mut notif = broadcast();
+        let record = ClientTags::sign(&mut notif, &[AGENCY_A, AGENCY_B, NEW]).unwrap();
+        assert_eq!(record.as_bytes().len(), ClientTags::MAX_SIZE);
+        for key in [AGENCY_A, AGENCY_B, NEW] {
+            assert_eq!(verify(&notif, Some(&record), key), Ok(()));
+        }
+    }
+
+    #[test]
+    fn key_zero_alone_needs_no_record() {
+        let mut notif = broadcast();
+        assert_eq!(ClientTags::sign(&mut notif, &[(0, HMAC_KEY_CLIENT)]), None);
+        assert_eq!(verify(&notif, None, (0, HMAC_KEY_CLIENT)), Ok(()));
+        assert_eq!(verify(&notif, None, NEW), Err(ParseError::MissingClientTag));
+        assert_eq!(
+            verify(&broadcast(), None, (0, HMAC_KEY_CLIENT)),
+            Err(ParseError::MissingClientTag)
+        );
+    }
+
+    #[test]
+    fn records_keep_the_record_with_its_notification() {
+        let mut first = broadcast();
+        let record = ClientTags::sign(&mut first, &[OLD, NEW]).unwrap();
+        let second = broadcast();
+        let payload = [aired(&first, Some(&record)), aired(&second, None)].concat();
+
+        let records: Vec<&[u8]> = Records(&payload).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], aired(&first, Some(&record)));
+        assert_eq!(ClientTags::find(records[0]), Ok(Some(record)));
+        assert_eq!(ClientTags::find(records[1]), Ok(None));
+        assert_eq!(
+            TransportNotification::decode_all(&payload)
+                .filter(Result::is_ok)
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        let mut notif = broadcast();
+        let record = ClientTags::sign(&mut notif, &[OLD, NEW]).unwrap();
+        let bytes = record.as_bytes();
+        assert_eq!(ClientTags::parse(bytes), Ok(record));
+
+        assert_eq!(
+            ClientTags::parse(&bytes[..bytes.len() - 1]),
+            Err(ParseError::TooShort {
+                got: bytes.len() - 1,
+                need: bytes.len()
+            })
+        );
+        for flags in [0x03, 0x80 | 0x01] {
+            let mut bad = bytes.to_vec();
+            bad[1] = flags;
+            bad.resize(ClientTags::size_with(3), 0);
+            assert_eq!(
+                ClientTags::parse(&bad),
+                Err(ParseError::UnsupportedVersion(CLIENT_TAGS_RECORD))
+            );
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Version 1 notifications, the layout the first broadcasters and repeaters
+//! shipped with, still parsed and relayed so a deployment can be upgraded
+//! one device at a time.
+//!
+//! Version 1 is 25 bytes:
+//!
+//!   [0]       version           u8 (1)
+//!   [1..5]    source_id         [u8; 4]
+//!   [5..9]    notification_id   [u8; 4]
+//!   [9]       event_dest        u8
+//!   [10]      type_status       u8
+//!   [11..13]  duration_secs     u16
+//!   [13..21]  hmac_tag_infra    over [0..13]
+//!   [21..25]  hmac_tag_client   over [0..13]
+//!
+//! v1 devices wrote `duration_secs` in their native byte order. Every one
+//! that shipped (the ESP32 and x86-64 or ARM Linux) is little-endian, so it
+//! is read as little-endian.
+//!
+//! There is no key id, `seq`, timestamp, hop count or CRC. The infra tag is
+//! checked against every key in the keyring, since v1 devices were built
+//! with a single key. A v1 packet can't be judged stale, so
+//! `from_payload_with` accepts it whatever `now_ms` says, and its copies
+//! can't be told from a repeater's relay of it: repeaters that can do
+//! without v1 broadcasters should stop relaying it.
+//!
+//! `TransportNotification::from_payload_with` dispatches on the version
+//! byte and hands back a v1 packet in the current form (see `to_current`).
+//! Its tags cover the v1 base payload, not the current one, so a repeater
+//! relays the `TransportNotificationV1` itself (see `relay::Received`)
+//! rather than re-encoding the converted copy. Sealed notifications only
+//! exist in the current version, and so do the longer tags of the `tag-*`
+//! features: v1 tags are always 8 and 4 bytes.
+
+use zerocopy::{FromBytes, Immutable, IntoBytes};
+
+use crate::consts::{InfraKey, DEFAULT_HOPS, PROTOCOL_VERSION_V1};
+use crate::crypto::{compute_tag, verify_tag};
+use crate::notification::{ParseError, TransportNotification};
+
+/// Bytes of a v1 infrastructure tag.
+pub const V1_TAG_INFRA_LEN: usize = 8;
+
+/// Bytes of a v1 client tag.
+pub const V1_TAG_CLIENT_LEN: usize = 4;
+
+/// A version 1 notification as it goes on air; see the module docs.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable)]
+pub struct TransportNotificationV1 {
+    pub version: u8,
+    pub source_id: [u8; 4],
+    pub notification_id: [u8; 4],
+    pub event_dest: u8,
+    pub type_status: u8,
+    pub duration_secs: [u8; 2],
+    pub hmac_tag_infra: [u8; V1_TAG_INFRA_LEN],
+    pub hmac_tag_client: [u8; V1_TAG_CLIENT_LEN],
+}
+
+impl TransportNotificationV1 {
+    /// Size of a v1 notification on the wire.
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    /// Bytes the HMAC tags cover.
+    pub const BASE_PAYLOAD_SIZE: usize = core::mem::offset_of!(Self, hmac_tag_infra);
+
+    /// `notif` in the v1 layout, keeping only the fields v1 has, signed
+    /// with `infra_key`. For broadcasters that still have v1 repeaters or
+    /// clients in range. The client tag is left zero.
+    pub fn downgrade(notif: &TransportNotification, (_, key): InfraKey) -> Self {
+        let mut v1 = Self {
+            version: PROTOCOL_VERSION_V1,
+            source_id: notif.source_id,
+            notification_id: notif.notification_id,
+            event_dest: notif.event_dest,
+            type_status: notif.type_status,
+            duration_secs: notif.duration_secs,
+            hmac_tag_infra: [0; V1_TAG_INFRA_LEN],
+            hmac_tag_client: [0; V1_TAG_CLIENT_LEN],
+        };
+        v1.hmac_tag_infra = compute_tag(key, v1.base_payload());
+        v1
+    }
+
+    /// The notification in the current form, `version` still 1. The fields
+    /// v1 lacks take what a v1 device assumed: `validity_secs` equal to
+    /// `duration_secs`, no flags, `priority` 0 and `DEFAULT_HOPS`. `seq`,
+    /// `key_id` and `timestamp_ms` are 0, which mean nothing here. Its tags
+    /// are this packet's (zero-padded if the current tags are longer) and
+    /// `crc16` is zero, so none of them verify against the converted base
+    /// payload.
+    pub fn to_current(&self) -> TransportNotification {
+        TransportNotification {
+            version: self.version,
+            source_id: self.source_id,
+            notification_id: self.notification_id,
+            event_dest: self.event_dest,
+            type_status: self.type_status,
+            duration_secs: self.duration_secs,
+            validity_secs: self.duration_secs,
+            flags: 0,
+            seq: [0; 4],
+            key_id: 0,
+            timestamp_ms: [0; 6],
+            priority: 0,
+            hmac_tag_infra: widen(self.hmac_tag_infra),
+            hmac_tag_client: widen(self.hmac_tag_client),
+            hops_remaining: DEFAULT_HOPS,
+            crc16: [0; 2],
+        }
+    }
+
+    /// Parse a v1 payload and verify its infra tag against any key in
+    /// `keyring`. There is no timestamp to check, so unlike
+    /// `TransportNotification::from_payload_with` this takes no `now_ms`.
+    pub fn from_payload_with(payload: &[u8], keyring: &[InfraKey]) -> Result<Self, ParseError> {
+        let v1 = Self::parse_unverified(payload)?;
+        let tag = v1.hmac_tag_infra;
+        if !keyring
+            .iter()
+            .any(|(_, key)| verify_tag(key, v1.base_payload(), &tag))
+        {
+            return Err(ParseError::InfraHmacMismatch);
+        }
+        Ok(v1)
+    }
+
+    /// Decode a v1 payload and check its version, enum nibbles and duration,
+    /// without verifying either tag. Trailing bytes are ignored.
+    pub fn parse_unverified(payload: &[u8]) -> Result<Self, ParseError> {
+        let (v1, _) = Self::read_from_prefix(payload).map_err(|_| ParseError::TooShort {
+            got: payload.len(),
+            need: Self::SIZE,
+        })?;
+        if { v1.version } != PROTOCOL_VERSION_V1 {
+            return Err(ParseError::UnsupportedVersion(v1.version));
+        }
+        v1.to_current().check_fields()?;
+        Ok(v1)
+    }
+
+    /// The bytes the HMAC tags cover.
+    pub fn base_payload(&self) -> &[u8] {
+        &self.as_bytes()[..Self::BASE_PAYLOAD_SIZE]
+    }
+
+    /// The full packet as a byte slice (for broadcast or re-broadcast).
+    pub fn as_bytes(&self) -> &[u8] {
+        IntoBytes::as_bytes(self)
+    }
+
+    /// Returns true if a repeater has signed the client tag.
+    pub fn has_client_tag(&self) -> bool {
+        ({ self.hmac_tag_client }) != [0u8; V1_TAG_CLIENT_LEN]
+    }
+
+    /// Sign the client tag over the v1 base payload with `key`.
+    pub fn sign_client_with(&mut self, key: &[u8]) {
+        self.hmac_tag_client = compute_tag(key, self.base_payload());
+    }
+
+    /// Verify the client tag over the v1 base payload with `key`.
+    pub fn verify_client_with(&self, key: &[u8]) -> bool {
+        let tag = self.hmac_tag_client;
+        verify_tag(key, self.base_payload(), &tag)
+    }
+}
+
+/// A v1 tag copied into a current tag field, which is at least as long.
+fn widen<const V1: usize, const N: usize>(tag: [u8; V1]) -> [u8; N] {
+    let mut out = [0u8; N];
+    out[..V1].copy_from_slice(&tag);
+    out
+}
+
+const _: () = {
+    type V1 = TransportNotificationV1;
+    assert!(V1::SIZE == 25, "v1 is 25 bytes on the wire");
+    assert!(
+        V1::BASE_PAYLOAD_SIZE == core::mem::offset_of!(TransportNotification, validity_secs),
+        "v1 is the current layout up to duration_secs"
+    );
+    assert!(
+        V1_TAG_INFRA_LEN == crate::consts::MIN_HMAC_TAG_INFRA_LEN
+            && V1_TAG_CLIENT_LEN == crate::consts::MIN_HMAC_TAG_CLIENT_LEN,
+        "v1 tags are the shortest current ones"
+    );
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::{HMAC_KEY_CLIENT, HMAC_KEY_INFRA, INFRA_KEYRING, INFRA_KEY_CURRENT};
+    use crate::{EventId, TransportNotificationBuilder, TransportStatus, TransportType};
+
+    fn current() -> TransportNotification {
+        TransportNotificationBuilder::new()
+            .source_id([0xA1, 0xB2, 0xC3, 0xD4])
+            .notification_id([5, 6, 7, 8])
+            .event(EventId::Arrival)
+            .destination(9)
+            .transport(TransportType::Train)
+            .status(TransportStatus::Coming)
+            .duration_secs(30)
+            .validity_secs(600)
+            .seq(42)
+            .priority(3)
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap()
+    }
+
+    fn v1() -> TransportNotificationV1 {
+        TransportNotificationV1::downgrade(&current(), INFRA_KEY_CURRENT)
+    }
+
+    /// A packet as the v1 broadcaster built it: its struct with a
+    /// native-endian `u16` duration, signed with its one infra key.
+    fn shipped_v1() -> [u8; 25] {
+        let mut bytes = [0u8; 25];
+        bytes[0] = 1;
+        bytes[1..5].copy_from_slice(&[0xA1, 0xB2, 0xC3, 0xD4]);
+        bytes[5..9].copy_from_slice(&[5, 6, 7, 8]);
+        bytes[9] = 0x09; // event 0, destination 9
+        bytes[10] = 0x23; // Train, Late
+        bytes[11..13].copy_from_slice(&300u16.to_le_bytes());
+        let tag: [u8; 8] = compute_tag(HMAC_KEY_INFRA, &bytes[..13]);
+        bytes[13..21].copy_from_slice(&tag);
+        bytes
+    }
+
+    #[test]
+    fn shipped_v1_payload_parses_into_the_current_form() {
+        let got = TransportNotification::from_payload(&shipped_v1()).unwrap();
+        assert_eq!(got.version, PROTOCOL_VERSION_V1);
+        assert_eq!(got.duration_secs(), 300);
+        assert_eq!(got.validity_secs(), 300);
+        assert_eq!(got.destination_id(), 9);
+        assert_eq!(got.transport_type(), Some(TransportType::Train));
+        assert_eq!(got.transport_status(), Some(TransportStatus::Late));
+        assert_eq!((got.priority, got.seq()), (0, 0));
+        assert!(!got.has_client_tag());
+    }
+
+    #[test]
+    fn v1_payload_is_verified_but_never_stale() {
+        let sent = shipped_v1();
+        let far_future = u64::MAX / 2;
+        assert!(
+            TransportNotification::from_payload_with(&sent, INFRA_KEYRING, Some(far_future))
+                .is_ok()
+        );
+
+        let mut forged = sent;
+        forged[11] ^= 1;
+        assert_eq!(
+            TransportNotification::from_payload(&forged).unwrap_err(),
+            ParseError::InfraHmacMismatch
+        );
+        let other_key = [(0, &b"another infra key"[..])];
+        assert_eq!(
+            TransportNotificationV1::from_payload_with(&sent, &other_key).unwrap_err(),
+            ParseError::InfraHmacMismatch
+        );
+        assert_eq!(
+            TransportNotification::from_payload(&sent[..24]).unwrap_err(),
+            ParseError::TooShort { got: 24, need: 25 }
+        );
+    }
+
+    #[test]
+    fn downgrade_matches_the_shipped_layout() {
+        let sent = v1();
+        assert_eq!(sent.as_bytes().len(), TransportNotificationV1::SIZE);
+        assert_eq!(sent.base_payload()[1..], current().base_payload()[1..13]);
+        let got = TransportNotification::from_payload(sent.as_bytes()).unwrap();
+        assert_eq!(got.notification_id, [5, 6, 7, 8]);
+        assert_eq!(got.duration_secs(), 30);
+    }
+
+    #[test]
+    fn client_tag_covers_the_v1_base_payload() {
+        let mut relayed = v1();
+        assert!(!relayed.has_client_tag());
+        relayed.sign_client_with(HMAC_KEY_CLIENT);
+        assert!(relayed.verify_client_with(HMAC_KEY_CLIENT));
+        let again =
+            TransportNotificationV1::from_payload_with(relayed.as_bytes(), INFRA_KEYRING).unwrap();
+        assert!(again.verify_client_with(HMAC_KEY_CLIENT));
+    }
+
+    #[test]
+    fn v1_records_packed_with_current_ones_are_each_decoded() {
+        let mut payload = shipped_v1().to_vec();
+        payload.extend_from_slice(current().as_bytes());
+        payload.extend_from_slice(v1().as_bytes());
+        let got: Vec<_> = TransportNotification::decode_all(&payload)
+            .map(|r| r.map(|n| (n.version, n.duration_secs())))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                Ok((PROTOCOL_VERSION_V1, 300)),
+                Ok((current().version, 30)),
+                Ok((PROTOCOL_VERSION_V1, 30)),
+            ]
+        );
+    }
+
+    #[test]
+    fn other_versions_are_not_v1() {
+        let current = current();
+        assert_eq!(
+            TransportNotificationV1::parse_unverified(current.as_bytes()).unwrap_err(),
+            ParseError::UnsupportedVersion(current.version)
+        );
+        let mut bytes = current.as_bytes().to_vec();
+        bytes[0] = 7;
+        assert_eq!(
+            TransportNotification::from_payload(&bytes).unwrap_err(),
+            ParseError::UnsupportedVersion(7)
+        );
+    }
+}
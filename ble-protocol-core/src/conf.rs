@@ -0,0 +1,372 @@
+//! Sealed notifications: the base payload encrypted with AES-128-CCM.
+//!
+//! The HMAC tags keep a notification from being forged, but anyone scanning
+//! can still read its `source_id`, destination and transport fields. Where
+//! those movements are sensitive, the broadcaster seals each notification
+//! instead: it encrypts the base payload under `CONF_KEY`, then signs the
+//! result (encrypt-then-sign). Receivers verify the envelope's infra or
+//! client tag first and only then decrypt.
+//!
+//! Only `version`, the infra `key_id` and the nonce are readable. The
+//! version and `key_id` are bound to the ciphertext as associated data.
+//! `hops_remaining` and the client tag stay outside the ciphertext, as in a
+//! plain notification, so a repeater relays a sealed notification without
+//! re-encrypting it. Receivers tell the two forms apart by length
+//! (`SealedNotification::SIZE` against `TransportNotification::SIZE`).
+//!
+//! Wire layout, with the default tag lengths:
+//!   [0]       version          u8  (clear)
+//!   [1]       key_id           u8  (clear; which infra key signed the envelope)
+//!   [2..14]   nonce            [u8; CONF_NONCE_LEN]  (clear)
+//!   [14..42]  ciphertext       the plain notification's base payload
+//!   [42..46]  ccm_tag          [u8; CONF_TAG_LEN]
+//!   [46..54]  hmac_tag_infra   over [0..46]
+//!   [54..58]  hmac_tag_client  over [0..46]
+//!   [58]      hops_remaining   u8
+//!   [59..61]  crc16            over [0..46]
+//!
+//! ## Nonces
+//!
+//! CCM loses both confidentiality and integrity if a nonce is ever reused
+//! with the same key. So every seal takes a fresh nonce of 96 random bits,
+//! and the broadcaster re-seals with a new one on each airing. After 2³²
+//! seals under one key, the chance of any repeat is still about 2⁻³³. A
+//! counter would also be unique, but it would be readable on air and would
+//! link every notification from one broadcaster. Repeaters never re-seal,
+//! so they never pick a nonce. Rotate `CONF_KEY` long before 2³² seals.
+
+use aes::Aes128;
+use ccm::aead::AeadInPlace;
+use ccm::consts::{U12, U4};
+use ccm::{Ccm, Key, KeyInit, Nonce, Tag};
+use zerocopy::{FromBytes, Immutable, IntoBytes};
+
+use crate::consts::{
+    InfraKey, HMAC_KEY_CLIENT, HMAC_TAG_CLIENT_LEN, HMAC_TAG_INFRA_LEN, MAX_FUTURE_SKEW_MS,
+    PROTOCOL_VERSION,
+};
+use crate::crc::crc16_ccitt;
+use crate::crypto::{compute_client_tag, compute_infra_tag, infra_key, verify_tag};
+use crate::notification::{ParseError, TransportNotification};
+
+/// Confidentiality key (AES-128) for sealed notifications. Shared by
+/// broadcasters, repeaters and the client app. In production, store it in
+/// eFuse on devices, like the HMAC keys.
+pub const CONF_KEY: &[u8; 16] = b"conf-secret-key!";
+
+/// Bytes of the CCM nonce; see the module docs on how they are chosen.
+pub const CONF_NONCE_LEN: usize = 12;
+
+/// Bytes of the CCM tag. It only guards decryption: the envelope's HMAC
+/// tags are what receivers trust, so the smallest size CCM allows will do.
+pub const CONF_TAG_LEN: usize = 4;
+
+type Aes128Ccm = Ccm<Aes128, U4, U12>;
+
+/// A notification with its base payload encrypted; see the module docs.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable)]
+pub struct SealedNotification {
+    pub version: u8,
+    pub key_id: u8,
+    pub nonce: [u8; CONF_NONCE_LEN],
+    pub ciphertext: [u8; TransportNotification::BASE_PAYLOAD_SIZE],
+    pub ccm_tag: [u8; CONF_TAG_LEN],
+    pub hmac_tag_infra: [u8; HMAC_TAG_INFRA_LEN],
+    pub hmac_tag_client: [u8; HMAC_TAG_CLIENT_LEN],
+    pub hops_remaining: u8,
+    pub crc16: [u8; 2],
+}
+
+impl SealedNotification {
+    /// Size of a sealed notification on the wire.
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    /// Bytes the HMAC tags and `crc16` cover: everything up to and
+    /// including `ccm_tag`.
+    pub const BASE_PAYLOAD_SIZE: usize = core::mem::offset_of!(Self, hmac_tag_infra);
+
+    /// Encrypt `notif`'s base payload under `conf_key` with `nonce`, then
+    /// sign the envelope with `infra_key`. `nonce` must never have been used
+    /// with `conf_key` before. `notif`'s own tags are not carried over.
+    pub fn seal(
+        notif: &TransportNotification,
+        (key_id, key): InfraKey,
+        conf_key: &[u8; 16],
+        nonce: [u8; CONF_NONCE_LEN],
+    ) -> Self {
+        let mut plain = *notif;
+        plain.key_id = key_id;
+        let mut ciphertext = [0u8; TransportNotification::BASE_PAYLOAD_SIZE];
+        ciphertext.copy_from_slice(plain.base_payload());
+        let ccm_tag = cipher(conf_key)
+            .encrypt_in_place_detached(
+                Nonce::<U12>::from_slice(&nonce),
+                &[PROTOCOL_VERSION, key_id],
+                &mut ciphertext,
+            )
+            .expect("base payload is within CCM's message length");
+
+        let mut sealed = Self {
+            version: PROTOCOL_VERSION,
+            key_id,
+            nonce,
+            ciphertext,
+            ccm_tag: ccm_tag.into(),
+            hmac_tag_infra: [0; HMAC_TAG_INFRA_LEN],
+            hmac_tag_client: [0; HMAC_TAG_CLIENT_LEN],
+            hops_remaining: notif.hops_remaining,
+            crc16: [0; 2],
+        };
+        sealed.hmac_tag_infra = compute_infra_tag(key, sealed.base_payload());
+        sealed.crc16 = crc16_ccitt(sealed.base_payload()).to_le_bytes();
+        sealed
+    }
+
+    /// Parse a sealed payload, verify its infra tag against `keyring`, then
+    /// decrypt it. Returns the envelope, to relay as is, and the decrypted
+    /// notification (see `decrypt`). `now_ms` is checked against the
+    /// decrypted `timestamp_ms` as in `TransportNotification::from_payload_with`.
+    pub fn open_with(
+        payload: &[u8],
+        keyring: &[InfraKey],
+        conf_key: &[u8; 16],
+        now_ms: Option<u64>,
+    ) -> Result<(Self, TransportNotification), ParseError> {
+        let sealed = Self::parse_unverified(payload)?;
+        if !sealed.verify_crc() {
+            return Err(ParseError::CrcMismatch);
+        }
+        let Some(key) = infra_key(keyring, sealed.key_id) else {
+            return Err(ParseError::UnknownKeyId(sealed.key_id));
+        };
+        let tag = sealed.hmac_tag_infra;
+        if !verify_tag(key, sealed.base_payload(), &tag) {
+            return Err(ParseError::InfraHmacMismatch);
+        }
+
+        let notif = sealed.decrypt(conf_key)?;
+        if let Some(now_ms) = now_ms {
+            notif.check_fresh(now_ms, MAX_FUTURE_SKEW_MS)?;
+        }
+        Ok((sealed, notif))
+    }
+
+    /// Decode a sealed payload and check its version, without verifying a
+    /// tag or decrypting. Trailing bytes are ignored.
+    pub fn parse_unverified(payload: &[u8]) -> Result<Self, ParseError> {
+        let (sealed, _) = Self::read_from_prefix(payload).map_err(|_| ParseError::TooShort {
+            got: payload.len(),
+            need: Self::SIZE,
+        })?;
+        if { sealed.version } != PROTOCOL_VERSION {
+            return Err(ParseError::UnsupportedVersion(sealed.version));
+        }
+        Ok(sealed)
+    }
+
+    /// Decrypt the base payload. The result carries the envelope's
+    /// `hops_remaining` and a matching `crc16`, but zero HMAC tags: only the
+    /// envelope's tags authenticate it, so check those first.
+    pub fn decrypt(&self, conf_key: &[u8; 16]) -> Result<TransportNotification, ParseError> {
+        let mut plain = self.ciphertext;
+        let ccm_tag = self.ccm_tag;
+        cipher(conf_key)
+            .decrypt_in_place_detached(
+                Nonce::<U12>::from_slice(&{ self.nonce }),
+                &[self.version, self.key_id],
+                &mut plain,
+                Tag::<U4>::from_slice(&ccm_tag),
+            )
+            .map_err(|_| ParseError::DecryptFailed)?;
+
+        let mut bytes = [0u8; TransportNotification::SIZE];
+        bytes[..plain.len()].copy_from_slice(&plain);
+        let mut notif = TransportNotification::parse_unverified(&bytes)?;
+        if notif.key_id != self.key_id {
+            return Err(ParseError::DecryptFailed);
+        }
+        notif.hops_remaining = self.hops_remaining;
+        notif.crc16 = crc16_ccitt(notif.base_payload()).to_le_bytes();
+        Ok(notif)
+    }
+
+    /// The bytes the HMAC tags and `crc16` cover.
+    pub fn base_payload(&self) -> &[u8] {
+        &self.as_bytes()[..Self::BASE_PAYLOAD_SIZE]
+    }
+
+    /// The full envelope as a byte slice (for broadcast or re-broadcast).
+    pub fn as_bytes(&self) -> &[u8] {
+        IntoBytes::as_bytes(self)
+    }
+
+    /// Whether `crc16` matches the envelope's base payload.
+    pub fn verify_crc(&self) -> bool {
+        u16::from_le_bytes(self.crc16) == crc16_ccitt(self.base_payload())
+    }
+
+    /// Returns true if a repeater has signed the client tag.
+    pub fn has_client_tag(&self) -> bool {
+        ({ self.hmac_tag_client }) != [0u8; HMAC_TAG_CLIENT_LEN]
+    }
+
+    /// Sign the client tag over the envelope with `HMAC_KEY_CLIENT` (called
+    /// by the first repeater).
+    pub fn sign_client(&mut self) {
+        self.sign_client_with(HMAC_KEY_CLIENT);
+    }
+
+    /// Sign the client tag over the envelope with `key`.
+    pub fn sign_client_with(&mut self, key: &[u8]) {
+        self.hmac_tag_client = compute_client_tag(key, self.base_payload());
+    }
+
+    /// Verify the client tag over the envelope with `key`.
+    pub fn verify_client_with(&self, key: &[u8]) -> bool {
+        let tag = self.hmac_tag_client;
+        verify_tag(key, self.base_payload(), &tag)
+    }
+
+    /// The copy to re-broadcast, as `TransportNotification::next_hop`.
+    pub fn next_hop(&self) -> Option<Self> {
+        let hops_remaining = self.hops_remaining.checked_sub(1)?;
+        Some(Self {
+            hops_remaining,
+            ..*self
+        })
+    }
+}
+
+fn cipher(conf_key: &[u8; 16]) -> Aes128Ccm {
+    Aes128Ccm::new(Key::<Aes128Ccm>::from_slice(conf_key))
+}
+
+const _: () = {
+    type S = SealedNotification;
+    assert!(
+        core::mem::offset_of!(S, hmac_tag_infra)
+            == core::mem::offset_of!(S, ccm_tag) + CONF_TAG_LEN,
+        "the HMAC tags must cover ccm_tag"
+    );
+    assert!(
+        S::SIZE != TransportNotification::SIZE,
+        "sealed and plain notifications are told apart by length"
+    );
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::{HMAC_KEY_CLIENT, INFRA_KEYRING, INFRA_KEY_CURRENT};
+    use crate::{EventId, TransportNotificationBuilder, TransportStatus, TransportType};
+
+    const NONCE: [u8; CONF_NONCE_LEN] = [7; CONF_NONCE_LEN];
+
+    fn plain() -> TransportNotification {
+        TransportNotificationBuilder::new()
+            .source_id([0xA1, 0xB2, 0xC3, 0xD4])
+            .notification_id([5, 6, 7, 8])
+            .event(EventId::Arrival)
+            .destination(9)
+            .transport(TransportType::Train)
+            .status(TransportStatus::Coming)
+            .duration_secs(30)
+            .validity_secs(600)
+            .seq(42)
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap()
+    }
+
+    fn sealed() -> SealedNotification {
+        SealedNotification::seal(&plain(), INFRA_KEY_CURRENT, CONF_KEY, NONCE)
+    }
+
+    /// Re-sign an edited envelope with the infra key, as only a key holder
+    /// could.
+    fn resign(s: &mut SealedNotification) {
+        s.hmac_tag_infra = compute_infra_tag(INFRA_KEY_CURRENT.1, s.base_payload());
+        s.crc16 = crc16_ccitt(s.base_payload()).to_le_bytes();
+    }
+
+    #[test]
+    fn sealed_notification_opens_to_the_original() {
+        let s = sealed();
+        assert_eq!(s.as_bytes().len(), SealedNotification::SIZE);
+        let (envelope, notif) =
+            SealedNotification::open_with(s.as_bytes(), INFRA_KEYRING, CONF_KEY, None).unwrap();
+        assert_eq!(envelope.as_bytes(), s.as_bytes());
+        assert_eq!(notif.base_payload(), plain().base_payload());
+        assert_eq!(notif.hops_remaining, plain().hops_remaining);
+        assert!(notif.verify_crc());
+    }
+
+    #[test]
+    fn content_is_not_readable_on_air() {
+        let s = sealed();
+        let p = plain();
+        let source_id = p.source_id;
+        assert!(!s.as_bytes().windows(4).any(|w| w == source_id));
+        assert_ne!(s.ciphertext[..], *p.base_payload());
+        let other = SealedNotification::seal(&p, INFRA_KEY_CURRENT, CONF_KEY, [8; CONF_NONCE_LEN]);
+        assert_ne!({ other.ciphertext }, { s.ciphertext });
+    }
+
+    #[test]
+    fn envelope_is_verified_before_decrypting() {
+        let mut s = sealed();
+        s.ciphertext[0] ^= 1;
+        let open = |s: &SealedNotification| {
+            SealedNotification::open_with(s.as_bytes(), INFRA_KEYRING, CONF_KEY, None).unwrap_err()
+        };
+        assert_eq!(open(&s), ParseError::CrcMismatch);
+        s.crc16 = crc16_ccitt(s.base_payload()).to_le_bytes();
+        assert_eq!(open(&s), ParseError::InfraHmacMismatch);
+        // Signed by a key holder, the CCM tag still catches it.
+        resign(&mut s);
+        assert_eq!(open(&s), ParseError::DecryptFailed);
+    }
+
+    #[test]
+    fn wrong_conf_key_or_key_id_does_not_decrypt() {
+        let s = sealed();
+        assert_eq!(
+            s.decrypt(b"another-key-16b!").unwrap_err(),
+            ParseError::DecryptFailed
+        );
+
+        // key_id is associated data, so switching it breaks decryption too.
+        let mut switched = s;
+        switched.key_id ^= 1;
+        assert_eq!(
+            switched.decrypt(CONF_KEY).unwrap_err(),
+            ParseError::DecryptFailed
+        );
+    }
+
+    #[test]
+    fn relaying_keeps_the_ciphertext_and_tags_valid() {
+        let mut relayed = sealed().next_hop().unwrap();
+        relayed.sign_client();
+        assert!(relayed.has_client_tag());
+        assert!(relayed.verify_client_with(HMAC_KEY_CLIENT));
+        assert_eq!({ relayed.ciphertext }, { sealed().ciphertext });
+        let (_, notif) =
+            SealedNotification::open_with(relayed.as_bytes(), INFRA_KEYRING, CONF_KEY, None)
+                .unwrap();
+        assert_eq!(notif.hops_remaining, plain().hops_remaining - 1);
+    }
+
+    #[test]
+    fn freshness_is_checked_on_the_decrypted_timestamp() {
+        assert!(matches!(
+            SealedNotification::open_with(
+                sealed().as_bytes(),
+                INFRA_KEYRING,
+                CONF_KEY,
+                Some(u64::MAX)
+            ),
+            Err(ParseError::Stale { .. })
+        ));
+    }
+}
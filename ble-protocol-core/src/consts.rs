@@ -0,0 +1,134 @@
+//! Protocol constants.
+
+/// Bluetooth SIG company ID 0xFFFF, reserved for testing. The default
+/// manufacturer ID in debug builds only: two deployments on it in the same
+/// area would hear each other's notifications, so a real deployment
+/// configures its own (repeater `manufacturer_id`, broadcaster
+/// `--manufacturer-id`).
+pub const MANUFACTURER_ID: u16 = 0xFFFF;
+
+/// Advertising data a legacy (BLE 4) advertising PDU can carry. Larger
+/// payloads need BLE 5 extended advertising.
+pub const LEGACY_ADV_DATA_LEN: usize = 31;
+
+/// Bytes the manufacturer-specific AD structure adds around a notification:
+/// AD length, AD type (0xFF) and the 2-byte company ID.
+pub const MFG_AD_OVERHEAD: usize = 4;
+
+/// Current protocol version.
+pub const PROTOCOL_VERSION: u8 = 8;
+
+/// The version the first broadcasters and repeaters shipped with. Still
+/// parsed and relayed (see `compat`), so a deployment needn't upgrade every
+/// device at once.
+pub const PROTOCOL_VERSION_V1: u8 = 1;
+
+/// Infrastructure key: shared between broadcaster and repeater.
+/// Used by the broadcaster to sign, and by the repeater to verify.
+/// For development only: a deployment burns its own key into eFuse (see
+/// `keys`).
+pub const HMAC_KEY_INFRA: &[u8] = b"infra-secret-key-efuse!!";
+
+/// An infrastructure key and the `key_id` that names it on the wire.
+pub type InfraKey = (u8, &'static [u8]);
+
+/// Key the broadcaster signs with unless told otherwise.
+pub const INFRA_KEY_CURRENT: InfraKey = (0, HMAC_KEY_INFRA);
+
+/// Every infrastructure key a repeater may accept. To roll the key, add the
+/// new entry here and flash repeaters first; then move broadcasters over;
+/// then drop the old entry. Both keys verify in between, so nothing has to be
+/// reflashed at the same moment.
+pub const INFRA_KEYRING: &[InfraKey] = &[INFRA_KEY_CURRENT];
+
+/// Client-facing key: used by the repeater to re-sign before broadcasting.
+/// Clients use this key to verify notifications.
+/// For development only: a deployment burns its own key into the repeater's
+/// eFuse (see `keys`) and distributes it to the app securely.
+/// The broadcaster never references it, so it is not linked in there.
+pub const HMAC_KEY_CLIENT: &[u8] = b"client-secret-key-app!!!";
+
+/// A client key and the `client_key_id` that names it in a client-tag
+/// record (see `client_tags`).
+pub type ClientKey = (u8, &'static [u8]);
+
+/// Every client key a repeater may sign with. Id 0 is the key clients held
+/// before there were client-tag records: a notification's own client tag is
+/// under it unless a record says otherwise.
+pub const CLIENT_KEYRING: &[ClientKey] = &[(0, HMAC_KEY_CLIENT)];
+
+/// Number of bytes of the truncated HMAC-SHA256 infrastructure tag.
+/// 8 bytes = 64-bit tag (strong enough for repeater-chain verification);
+/// the `tag-infra-12` and `tag-infra-16` features lengthen it, the longest
+/// enabled winning. Every device in a deployment must agree on it.
+pub const HMAC_TAG_INFRA_LEN: usize = if cfg!(feature = "tag-infra-16") {
+    16
+} else if cfg!(feature = "tag-infra-12") {
+    12
+} else {
+    MIN_HMAC_TAG_INFRA_LEN
+};
+
+/// Number of bytes of the truncated HMAC-SHA256 client tag.
+/// 4 bytes = 32-bit tag (sufficient for client-side verification,
+/// saves BLE advertisement space); 8 with the `tag-client-8` feature.
+pub const HMAC_TAG_CLIENT_LEN: usize = if cfg!(feature = "tag-client-8") {
+    8
+} else {
+    MIN_HMAC_TAG_CLIENT_LEN
+};
+
+/// Shortest infrastructure tag the protocol allows. Forging a 64-bit tag
+/// online, one advertisement per guess, is out of reach; anything shorter
+/// isn't.
+pub const MIN_HMAC_TAG_INFRA_LEN: usize = 8;
+
+/// Shortest client tag the protocol allows. A forger gets one guess per
+/// advertisement a client hears, so 2³² guesses are still impractical.
+pub const MIN_HMAC_TAG_CLIENT_LEN: usize = 4;
+
+const _: () = {
+    assert!(
+        HMAC_TAG_INFRA_LEN >= MIN_HMAC_TAG_INFRA_LEN,
+        "infra tag below the safety floor"
+    );
+    assert!(
+        HMAC_TAG_CLIENT_LEN >= MIN_HMAC_TAG_CLIENT_LEN,
+        "client tag below the safety floor"
+    );
+    // HMAC-SHA256 has no more to truncate from.
+    assert!(HMAC_TAG_INFRA_LEN <= 32 && HMAC_TAG_CLIENT_LEN <= 32);
+};
+
+/// Oldest `timestamp_ms` a receiver with a trusted clock accepts, relative to
+/// its own time. Generous enough for a notification to cross several
+/// repeaters, each re-broadcasting it for its `duration_secs`, but short of
+/// letting an old capture be replayed much later.
+pub const MAX_AGE_MS: u64 = 5 * 60 * 1000;
+
+/// How far ahead of a receiver's clock `timestamp_ms` may be, to absorb skew
+/// between broadcaster and receiver clocks.
+pub const MAX_FUTURE_SKEW_MS: u64 = 30 * 1000;
+
+/// Longest `duration_secs` a notification may carry. Anything longer would
+/// hold a repeater's active slot for most of a day. Builders refuse it and
+/// receivers reject it; clamping instead would break the infra tag, which
+/// covers the field.
+pub const MAX_DURATION_SECS: u16 = 60 * 60;
+
+/// `hops_remaining` a broadcaster starts a notification with: how many
+/// repeaters in a row may re-broadcast it.
+pub const DEFAULT_HOPS: u8 = 3;
+
+/// Leading byte of a client-tag record (see `client_tags`), read where a
+/// version byte would be. Never a protocol version.
+pub const CLIENT_TAGS_RECORD: u8 = 0xC1;
+
+/// Leading byte of a relay-path record (see `relay_path`), read where a
+/// version byte would be. Never a protocol version.
+pub const RELAY_PATH_RECORD: u8 = 0xC2;
+
+/// Flag bit: test/canary notification. Repeaters relay it like any other
+/// notification so the path is exercised end-to-end, but clients hide it
+/// from riders and only count it for diagnostics.
+pub const FLAG_CANARY: u8 = 0x01;
@@ -0,0 +1,31 @@
+//! CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF, no reflection, no final
+//! XOR), used for the `crc16` pre-filter. Not a security check: anyone can
+//! recompute it. It only lets a receiver throw away corrupt or foreign
+//! packets before spending an HMAC on them.
+
+/// CRC-16/CCITT-FALSE of `data`.
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_standard_check_value() {
+        assert_eq!(crc16_ccitt(b"123456789"), 0x29B1);
+        assert_eq!(crc16_ccitt(b""), 0xFFFF);
+    }
+}
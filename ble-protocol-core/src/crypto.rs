@@ -0,0 +1,44 @@
+//! Truncated HMAC-SHA256 tags.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::consts::{InfraKey, HMAC_TAG_CLIENT_LEN, HMAC_TAG_INFRA_LEN};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Compute a truncated HMAC-SHA256 tag of `N` bytes over the given data.
+pub fn compute_tag<const N: usize>(key: &[u8], data: &[u8]) -> [u8; N] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    let result = mac.finalize().into_bytes();
+    let mut tag = [0u8; N];
+    tag.copy_from_slice(&result[..N]);
+    tag
+}
+
+/// Check a truncated tag against the HMAC of `data` in constant time, so a
+/// partial match doesn't return earlier than a complete mismatch.
+pub fn verify_tag(key: &[u8], data: &[u8], tag: &[u8]) -> bool {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.verify_truncated_left(tag).is_ok()
+}
+
+/// Look up the infrastructure key named `key_id` in `keyring`.
+pub fn infra_key(keyring: &[InfraKey], key_id: u8) -> Option<&'static [u8]> {
+    keyring
+        .iter()
+        .find(|(id, _)| *id == key_id)
+        .map(|(_, key)| *key)
+}
+
+/// Compute the infrastructure tag (broadcaster → repeater chain).
+pub fn compute_infra_tag(key: &[u8], data: &[u8]) -> [u8; HMAC_TAG_INFRA_LEN] {
+    compute_tag(key, data)
+}
+
+/// Compute the client tag (repeater → client).
+pub fn compute_client_tag(key: &[u8], data: &[u8]) -> [u8; HMAC_TAG_CLIENT_LEN] {
+    compute_tag(key, data)
+}
@@ -0,0 +1,22 @@
+//! Internals exposed for white-box tests, with the `test-internals`
+//! feature. Not part of the public API: hidden from the docs, and free to
+//! change in any release. Nothing but tests should enable the feature.
+
+use crate::consts::InfraKey;
+use crate::notification::{ParseError, TransportNotification};
+
+/// The checks every version shares: the packed enum nibbles and the
+/// duration.
+pub fn check_fields(notif: &TransportNotification) -> Result<(), ParseError> {
+    notif.check_fields()
+}
+
+/// `TransportNotification::from_payload_with` for the current layout only,
+/// without the dispatch on the version byte.
+pub fn parse_current(
+    payload: &[u8],
+    keyring: &[InfraKey],
+    now_ms: Option<u64>,
+) -> Result<TransportNotification, ParseError> {
+    TransportNotification::parse_v8(payload, keyring, now_ms)
+}
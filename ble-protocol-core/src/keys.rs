@@ -0,0 +1,186 @@
+//! Where the protocol keys come from.
+//!
+//! Signing and verifying code asks a `KeyProvider` for keys rather than
+//! naming the constants in `consts`, so a device can keep its keys somewhere
+//! other than the firmware image without touching the crypto call sites.
+//! `StaticKeys` hands out the built-in development keys. The repeater also
+//! has an eFuse provider, which reads keys burned in the format below.
+//!
+//! # Key blocks
+//!
+//! A key stored outside the image (e.g. in a 256-bit eFuse block) is a
+//! `KEY_BLOCK_LEN`-byte block:
+//!
+//! | Offset | Size   | Field                                            |
+//! |--------|--------|--------------------------------------------------|
+//! | 0      | 1      | `key_id`, or `client_key_id` for a client key    |
+//! | 1      | 1      | key length, 1..=`MAX_BLOCK_KEY_LEN`              |
+//! | 2      | len    | key bytes, then zeros up to offset 30            |
+//! | 30     | 2      | CRC-16/CCITT-FALSE of bytes 0..30, little-endian |
+//!
+//! The CRC catches a block that was burned wrong or only in part. An unburned
+//! block reads as all zeros and is reported as `KeyBlockError::Blank`.
+
+use core::fmt;
+
+use crate::consts::{
+    ClientKey, InfraKey, CLIENT_KEYRING, HMAC_KEY_CLIENT, INFRA_KEYRING, INFRA_KEY_CURRENT,
+};
+use crate::crc::crc16_ccitt;
+
+/// Source of the keys a device signs and verifies with.
+pub trait KeyProvider {
+    /// Every infrastructure key this device accepts.
+    fn infra_keyring(&self) -> &[InfraKey];
+
+    /// The infrastructure key this device signs with.
+    fn current_infra_key(&self) -> InfraKey;
+
+    /// The client key: repeaters sign the client tag with it, clients
+    /// verify it.
+    fn client_key(&self) -> &'static [u8];
+
+    /// Every client key this device holds, by `client_key_id`: the one
+    /// `client_key` returns first, then any others, e.g. the next
+    /// generation while the client key is being rolled (see
+    /// `client_tags`).
+    fn client_keyring(&self) -> &[ClientKey];
+}
+
+/// Which infrastructure key a notification verified under, for logging
+/// and for acting on who signed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyInfo {
+    /// The generation: the `key_id` naming the key in the keyring.
+    pub key_id: u8,
+}
+
+/// The keys compiled in from `consts`. For development only: every image
+/// carries the same keys, and anyone with an image can read them out.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StaticKeys;
+
+impl KeyProvider for StaticKeys {
+    fn infra_keyring(&self) -> &[InfraKey] {
+        INFRA_KEYRING
+    }
+
+    fn current_infra_key(&self) -> InfraKey {
+        INFRA_KEY_CURRENT
+    }
+
+    fn client_key(&self) -> &'static [u8] {
+        HMAC_KEY_CLIENT
+    }
+
+    fn client_keyring(&self) -> &[ClientKey] {
+        CLIENT_KEYRING
+    }
+}
+
+/// Size of a key block.
+pub const KEY_BLOCK_LEN: usize = 32;
+
+/// Longest key a block holds.
+pub const MAX_BLOCK_KEY_LEN: usize = KEY_BLOCK_LEN - 4;
+
+/// Offset of the CRC within a key block.
+const KEY_BLOCK_CRC: usize = KEY_BLOCK_LEN - 2;
+
+/// Why a key block was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyBlockError {
+    /// The block is all zeros: no key has been burned.
+    Blank,
+    /// The CRC doesn't match the block's contents.
+    ChecksumMismatch,
+    /// The length byte is 0 or over `MAX_BLOCK_KEY_LEN`.
+    BadLength(u8),
+}
+
+impl fmt::Display for KeyBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Blank => write!(f, "no key burned"),
+            Self::ChecksumMismatch => write!(f, "key block checksum mismatch"),
+            Self::BadLength(len) => write!(f, "key length {} is out of range", len),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for KeyBlockError {}
+
+/// Lay out `key` as a key block, or `None` if it is empty or longer than
+/// `MAX_BLOCK_KEY_LEN`.
+pub fn encode_key_block(key_id: u8, key: &[u8]) -> Option<[u8; KEY_BLOCK_LEN]> {
+    if key.is_empty() || key.len() > MAX_BLOCK_KEY_LEN {
+        return None;
+    }
+    let mut block = [0u8; KEY_BLOCK_LEN];
+    block[0] = key_id;
+    block[1] = key.len() as u8;
+    block[2..2 + key.len()].copy_from_slice(key);
+    let crc = crc16_ccitt(&block[..KEY_BLOCK_CRC]);
+    block[KEY_BLOCK_CRC..].copy_from_slice(&crc.to_le_bytes());
+    Some(block)
+}
+
+/// The `key_id` and key held in `block`.
+pub fn decode_key_block(block: &[u8; KEY_BLOCK_LEN]) -> Result<(u8, &[u8]), KeyBlockError> {
+    if block.iter().all(|&b| b == 0) {
+        return Err(KeyBlockError::Blank);
+    }
+    let crc = u16::from_le_bytes([block[KEY_BLOCK_CRC], block[KEY_BLOCK_CRC + 1]]);
+    if crc != crc16_ccitt(&block[..KEY_BLOCK_CRC]) {
+        return Err(KeyBlockError::ChecksumMismatch);
+    }
+    let len = block[1];
+    if len == 0 || usize::from(len) > MAX_BLOCK_KEY_LEN {
+        return Err(KeyBlockError::BadLength(len));
+    }
+    Ok((block[0], &block[2..2 + usize::from(len)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_blocks_round_trip() {
+        let block = encode_key_block(3, HMAC_KEY_CLIENT).unwrap();
+        assert_eq!(decode_key_block(&block), Ok((3, HMAC_KEY_CLIENT)));
+
+        let longest = [0xAB; MAX_BLOCK_KEY_LEN];
+        let block = encode_key_block(0, &longest).unwrap();
+        assert_eq!(decode_key_block(&block), Ok((0, &longest[..])));
+
+        assert!(encode_key_block(0, &[]).is_none());
+        assert!(encode_key_block(0, &[0; MAX_BLOCK_KEY_LEN + 1]).is_none());
+    }
+
+    #[test]
+    fn damaged_key_blocks_are_rejected() {
+        assert_eq!(
+            decode_key_block(&[0; KEY_BLOCK_LEN]),
+            Err(KeyBlockError::Blank)
+        );
+
+        let good = encode_key_block(1, b"infra").unwrap();
+        for i in 0..KEY_BLOCK_LEN {
+            let mut bad = good;
+            bad[i] ^= 0x01;
+            assert_eq!(decode_key_block(&bad), Err(KeyBlockError::ChecksumMismatch));
+        }
+
+        // A length that passes the CRC but not the range check.
+        let mut bad = good;
+        bad[1] = MAX_BLOCK_KEY_LEN as u8 + 1;
+        let crc = crc16_ccitt(&bad[..KEY_BLOCK_CRC]);
+        bad[KEY_BLOCK_CRC..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(
+            decode_key_block(&bad),
+            Err(KeyBlockError::BadLength(MAX_BLOCK_KEY_LEN as u8 + 1))
+        );
+    }
+}
@@ -0,0 +1,67 @@
+//! Wire format shared by the broadcaster, the repeater and (in spirit) the
+//! web client: the `TransportNotification` layout, its HMAC tags and the
+//! protocol constants. `compat` keeps version 1 packets parsing and
+//! relaying alongside the current version; `ack` is the beacon a repeater
+//! answers with to say it is relaying a notification, and `capability` the
+//! one it tells its neighbours what it can relay with. `client_tags` adds
+//! client tags under further client keys, for rolling the client key, and
+//! `relay_path` the list of repeaters a notification went through, for
+//! debugging.
+//!
+//! `no_std` unless the default `std` feature is enabled, so the same code
+//! runs on the ESP32 repeater and on the Linux broadcaster. The optional
+//! `serde` feature gives `TransportNotification` a readable serde form, with
+//! the packed nibbles split into named fields and ids as hex strings. The
+//! optional `encrypt` feature adds AES-CCM sealed notifications (`conf`) and
+//! the repeater's relay path over both kinds (`relay`), and with `std` a
+//! relay over any `transport`, UDP included. The `tag-*`
+//! features lengthen the HMAC tags; see `HMAC_TAG_INFRA_LEN`. The
+//! `test-internals` feature exposes a few private functions to integration
+//! tests (`internals`); it is not for use outside them.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod ack;
+pub mod builder;
+pub mod capability;
+pub mod client_tags;
+pub mod compat;
+#[cfg(feature = "encrypt")]
+pub mod conf;
+pub mod consts;
+pub mod crc;
+pub mod crypto;
+#[cfg(feature = "test-internals")]
+#[doc(hidden)]
+pub mod internals;
+pub mod keys;
+pub mod notification;
+#[cfg(feature = "encrypt")]
+pub mod relay;
+pub mod relay_path;
+pub mod seq;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(all(feature = "std", feature = "encrypt"))]
+pub mod transport;
+// The vectors are for the default tag lengths.
+#[cfg(all(
+    test,
+    not(any(
+        feature = "tag-infra-12",
+        feature = "tag-infra-16",
+        feature = "tag-client-8"
+    ))
+))]
+mod vectors;
+
+pub use ack::AckBeacon;
+pub use builder::{BuildError, TransportNotificationBuilder};
+pub use capability::{CapabilityBeacon, NeighborTable};
+pub use client_tags::ClientTags;
+pub use compat::TransportNotificationV1;
+pub use consts::*;
+pub use keys::{KeyInfo, KeyProvider, StaticKeys};
+pub use notification::{
+    EventId, ParseError, Records, TransportNotification, TransportStatus, TransportType,
+};
@@ -0,0 +1,1431 @@
+//! The `TransportNotification` wire struct.
+
+use core::fmt;
+
+use log::debug;
+use zerocopy::{FromBytes, Immutable, IntoBytes};
+
+use crate::client_tags::ClientTags;
+use crate::compat::TransportNotificationV1;
+use crate::consts::MAX_DURATION_SECS;
+use crate::consts::PROTOCOL_VERSION;
+use crate::consts::PROTOCOL_VERSION_V1;
+use crate::consts::{InfraKey, HMAC_KEY_CLIENT, INFRA_KEYRING, INFRA_KEY_CURRENT};
+use crate::consts::{
+    CLIENT_TAGS_RECORD, FLAG_CANARY, HMAC_TAG_CLIENT_LEN, HMAC_TAG_INFRA_LEN, RELAY_PATH_RECORD,
+};
+use crate::consts::{LEGACY_ADV_DATA_LEN, MAX_AGE_MS, MAX_FUTURE_SKEW_MS, MFG_AD_OVERHEAD};
+use crate::crc::crc16_ccitt;
+use crate::crypto::{compute_client_tag, compute_infra_tag, infra_key, verify_tag};
+use crate::keys::KeyInfo;
+use crate::relay_path::RelayPath;
+
+/// Why a manufacturer-data payload was not accepted as a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// Fewer bytes than a full `TransportNotification`.
+    TooShort { got: usize, need: usize },
+    /// `version` is neither `PROTOCOL_VERSION` nor `PROTOCOL_VERSION_V1`.
+    UnsupportedVersion(u8),
+    /// High nibble of `type_status` is not a `TransportType`.
+    BadTransportType(u8),
+    /// Low nibble of `type_status` is not a `TransportStatus`.
+    BadTransportStatus(u8),
+    /// `duration_secs` is over `MAX_DURATION_SECS`.
+    DurationTooLong(u16),
+    /// `crc16` doesn't match the base payload: corrupt, or not one of ours.
+    CrcMismatch,
+    /// `key_id` names no key in the verifying keyring.
+    UnknownKeyId(u8),
+    /// The infrastructure tag doesn't match: forged or corrupted in transit.
+    InfraHmacMismatch,
+    /// A sealed notification's ciphertext didn't decrypt under the
+    /// confidentiality key, or decrypted to something that isn't a
+    /// notification.
+    DecryptFailed,
+    /// The client tag is all zeroes: no repeater signed this notification.
+    MissingClientTag,
+    /// The client tag is set but doesn't match.
+    ClientHmacMismatch,
+    /// `timestamp_ms` is more than `MAX_AGE_MS` behind the receiver's clock.
+    Stale { age_ms: u64 },
+    /// `timestamp_ms` is further ahead of the receiver's clock than it
+    /// tolerates (`MAX_FUTURE_SKEW_MS` unless it says otherwise): the
+    /// broadcaster's clock is off, or the packet was pre-dated to stay fresh.
+    FromFuture { ahead_ms: u64 },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort { got, need } => {
+                write!(f, "payload too short: {} < {} bytes", got, need)
+            }
+            Self::UnsupportedVersion(v) => write!(f, "unsupported protocol version {}", v),
+            Self::BadTransportType(v) => write!(f, "unknown transport type {}", v),
+            Self::BadTransportStatus(v) => write!(f, "unknown transport status {}", v),
+            Self::DurationTooLong(secs) => {
+                write!(f, "duration {} s is over {} s", secs, MAX_DURATION_SECS)
+            }
+            Self::CrcMismatch => write!(f, "CRC mismatch"),
+            Self::UnknownKeyId(id) => write!(f, "unknown infra key id {}", id),
+            Self::InfraHmacMismatch => write!(f, "infra HMAC mismatch"),
+            Self::DecryptFailed => write!(f, "decryption failed"),
+            Self::MissingClientTag => write!(f, "client tag not set"),
+            Self::ClientHmacMismatch => write!(f, "client HMAC mismatch"),
+            Self::Stale { age_ms } => write!(f, "stale: emitted {} ms ago", age_ms),
+            Self::FromFuture { ahead_ms } => {
+                write!(f, "timestamp {} ms in the future", ahead_ms)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TransportType {
+    Bus = 1,
+    Train = 2,
+}
+
+impl TransportType {
+    /// Every variant, for exhaustive checks.
+    pub const ALL: [Self; 2] = [Self::Bus, Self::Train];
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(Self::Bus),
+            2 => Some(Self::Train),
+            _ => None,
+        }
+    }
+}
+
+/// What a notification is about: the `event_id` nibble. Values without a
+/// name are reserved and decode as `Unknown`, so a receiver running an older
+/// registry still handles notifications using newer events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventId {
+    Departure,
+    Arrival,
+    Delay,
+    Cancellation,
+    Diversion,
+    PlatformChange,
+    ReplacementService,
+    Disruption,
+    /// A reserved value (0 or 9–15).
+    Unknown(u8),
+}
+
+impl EventId {
+    /// Every named event, for exhaustive checks and random picks.
+    pub const ALL: [Self; 8] = [
+        Self::Departure,
+        Self::Arrival,
+        Self::Delay,
+        Self::Cancellation,
+        Self::Diversion,
+        Self::PlatformChange,
+        Self::ReplacementService,
+        Self::Disruption,
+    ];
+
+    /// Decode the low nibble of `v`.
+    pub fn from_u4(v: u8) -> Self {
+        match v & 0x0F {
+            1 => Self::Departure,
+            2 => Self::Arrival,
+            3 => Self::Delay,
+            4 => Self::Cancellation,
+            5 => Self::Diversion,
+            6 => Self::PlatformChange,
+            7 => Self::ReplacementService,
+            8 => Self::Disruption,
+            other => Self::Unknown(other),
+        }
+    }
+
+    /// The nibble value on the wire.
+    pub fn to_u4(self) -> u8 {
+        match self {
+            Self::Departure => 1,
+            Self::Arrival => 2,
+            Self::Delay => 3,
+            Self::Cancellation => 4,
+            Self::Diversion => 5,
+            Self::PlatformChange => 6,
+            Self::ReplacementService => 7,
+            Self::Disruption => 8,
+            Self::Unknown(v) => v & 0x0F,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TransportStatus {
+    Passing = 1,
+    Coming = 2,
+    Late = 3,
+}
+
+impl TransportStatus {
+    /// Every variant, for exhaustive checks.
+    pub const ALL: [Self; 3] = [Self::Passing, Self::Coming, Self::Late];
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(Self::Passing),
+            2 => Some(Self::Coming),
+            3 => Some(Self::Late),
+            _ => None,
+        }
+    }
+}
+
+/// Every field is a byte or a byte array and the struct is packed, so
+/// zerocopy can check at compile time that any byte string of the right
+/// length is a valid value and that the struct has no padding to leak.
+/// Multi-byte values are little-endian on the wire whatever the target's
+/// byte order, and read through accessors (`duration_secs`, `seq`, ...).
+#[repr(C, packed)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, Immutable)]
+pub struct TransportNotification {
+    pub version: u8,
+    pub source_id: [u8; 4],
+    pub notification_id: [u8; 4],
+    /// High nibble = event_id (0–15), low nibble = destination_id (0–15).
+    pub event_dest: u8,
+    /// High nibble = transport_type, low nibble = transport_status.
+    pub type_status: u8,
+    /// How long (in seconds) repeaters keep re-broadcasting this notification,
+    /// at most `MAX_DURATION_SECS`. 0 cancels it: repeaters drop their active
+    /// entry with this `notification_id`. Little-endian; see
+    /// `duration_secs`.
+    pub duration_secs: [u8; 2],
+    /// How long (in seconds) clients treat this notification as relevant,
+    /// counted from first reception. Independent of `duration_secs`: a
+    /// notification can stay relevant long after repeaters stop amplifying it.
+    /// Little-endian; see `validity_secs`.
+    pub validity_secs: [u8; 2],
+    /// Bit flags (`FLAG_*`). Covered by both HMAC tags, so a flag can't be
+    /// stripped or added in transit.
+    pub flags: u8,
+    /// Per-source sequence number, little-endian; see `seq`.
+    pub seq: [u8; 4],
+    /// Which infrastructure key signed `hmac_tag_infra` (see `INFRA_KEYRING`).
+    /// Part of the signed payload, so it can't be switched to another key.
+    pub key_id: u8,
+    /// When the broadcaster emitted the notification: unix-epoch
+    /// milliseconds, 48-bit little-endian; see `timestamp_ms`.
+    pub timestamp_ms: [u8; 6],
+    /// How much the notification matters next to others, higher first
+    /// (0 = routine). A repeater whose active list is full evicts a
+    /// lower-priority entry to make room for it. Signed, so a relay can't
+    /// promote its own traffic.
+    pub priority: u8,
+    /// HMAC tag signed by the broadcaster (infrastructure key).
+    /// Verified by every repeater in the chain — never modified.
+    pub hmac_tag_infra: [u8; HMAC_TAG_INFRA_LEN],
+    /// HMAC tag signed by the first repeater (client key).
+    /// Verified by the client app. Set to zeroes by the broadcaster.
+    pub hmac_tag_client: [u8; HMAC_TAG_CLIENT_LEN],
+    /// How many more repeaters may re-broadcast this notification; see
+    /// `next_hop`. Bounds how far it spreads through a mesh of repeaters in
+    /// range of each other.
+    ///
+    /// Deliberately outside `base_payload`: every repeater changes it, and
+    /// only the broadcaster can sign the infra tag. The cost is that anyone
+    /// can rewrite it in transit, which at worst cuts a notification's reach
+    /// short or lets it travel further; `seq` still stops it looping.
+    pub hops_remaining: u8,
+    /// CRC-16/CCITT of `base_payload`, little-endian; see `verify_crc`.
+    /// Checked before either HMAC to cheaply reject corrupt or foreign
+    /// packets. Not a security feature, so it sits outside the signed
+    /// payload; set alongside the infra tag by `sign_infra_with`.
+    pub crc16: [u8; 2],
+}
+
+impl TransportNotification {
+    /// Size of the full struct in bytes (including both HMAC tags).
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    /// Byte size of the base payload (everything before the two HMAC tags).
+    /// This is what both HMAC tags authenticate.
+    pub const BASE_PAYLOAD_SIZE: usize = core::mem::offset_of!(Self, hmac_tag_infra);
+
+    /// Advertising data taken by the manufacturer-specific AD structure
+    /// carrying one notification.
+    pub const MFG_AD_LEN: usize = Self::SIZE + MFG_AD_OVERHEAD;
+
+    /// Whether a notification fits a legacy advertisement on its own. It
+    /// stopped fitting when `flags` took it from 31 bytes of advertising
+    /// data to 32; senders need extended advertising (BLE 5) to carry it.
+    /// See `MFG_AD_BUDGET` for what it may grow to.
+    pub const FITS_LEGACY_ADV: bool = Self::MFG_AD_LEN <= LEGACY_ADV_DATA_LEN;
+
+    // ── Nibble accessors ────────────────────────────────────────────
+
+    pub fn event_id(&self) -> u8 {
+        ({ self.event_dest } >> 4) & 0x0F
+    }
+
+    pub fn event(&self) -> EventId {
+        EventId::from_u4(self.event_id())
+    }
+
+    pub fn destination_id(&self) -> u8 {
+        ({ self.event_dest }) & 0x0Fu8
+    }
+
+    pub fn transport_type(&self) -> Option<TransportType> {
+        TransportType::from_u8(({ self.type_status } >> 4) & 0x0F)
+    }
+
+    pub fn transport_status(&self) -> Option<TransportStatus> {
+        TransportStatus::from_u8({ self.type_status } & 0x0F)
+    }
+
+    /// How long repeaters keep re-broadcasting this notification, in
+    /// seconds; 0 cancels it.
+    pub fn duration_secs(&self) -> u16 {
+        u16::from_le_bytes(self.duration_secs)
+    }
+
+    /// How long clients treat this notification as relevant, in seconds.
+    pub fn validity_secs(&self) -> u16 {
+        u16::from_le_bytes(self.validity_secs)
+    }
+
+    /// Sequence number the broadcaster assigned to this notification.
+    /// Incremented per notification and compared with wraparound by
+    /// `seq::SeqTracker`.
+    pub fn seq(&self) -> u32 {
+        u32::from_le_bytes(self.seq)
+    }
+
+    /// Emission time in unix-epoch milliseconds. 48 bits last until the
+    /// year 10889.
+    pub fn timestamp_ms(&self) -> u64 {
+        let b = self.timestamp_ms;
+        u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], 0, 0])
+    }
+
+    /// Encode unix-epoch milliseconds for the `timestamp_ms` field, keeping
+    /// the low 48 bits.
+    pub fn timestamp_bytes(ms: u64) -> [u8; 6] {
+        let b = ms.to_le_bytes();
+        [b[0], b[1], b[2], b[3], b[4], b[5]]
+    }
+
+    /// `notification_id` as 8 lowercase hex digits.
+    #[cfg(feature = "std")]
+    pub fn id_hex(&self) -> String {
+        Hex(&self.notification_id).to_string()
+    }
+
+    /// `source_id` as 8 lowercase hex digits.
+    #[cfg(feature = "std")]
+    pub fn source_hex(&self) -> String {
+        Hex(&self.source_id).to_string()
+    }
+
+    /// Returns true if this is a test/canary notification.
+    pub fn is_canary(&self) -> bool {
+        ({ self.flags } & FLAG_CANARY) != 0
+    }
+
+    /// Return the full struct as a byte slice (for re-broadcast).
+    pub fn as_bytes(&self) -> &[u8] {
+        IntoBytes::as_bytes(self)
+    }
+
+    /// Return only the base payload (everything before both HMAC tags).
+    pub fn base_payload(&self) -> &[u8] {
+        &self.as_bytes()[..Self::BASE_PAYLOAD_SIZE]
+    }
+
+    /// Whether `crc16` matches the base payload. A cheap sanity check, not
+    /// an authenticity one.
+    pub fn verify_crc(&self) -> bool {
+        u16::from_le_bytes(self.crc16) == crc16_ccitt(self.base_payload())
+    }
+
+    /// Verify the infrastructure HMAC tag (broadcaster → repeater chain)
+    /// against `INFRA_KEYRING`.
+    pub fn verify_infra(&self) -> bool {
+        self.verify_infra_with(INFRA_KEYRING)
+    }
+
+    /// Verify the infrastructure HMAC tag with the key `key_id` names in
+    /// `keyring`. An id missing from the keyring, e.g. a revoked
+    /// generation, never verifies.
+    pub fn verify_infra_with(&self, keyring: &[InfraKey]) -> bool {
+        self.infra_key_info(keyring).is_some()
+    }
+
+    /// `verify_infra_with`, saying which key generation the tag verified
+    /// under, or `None` if it didn't.
+    pub fn infra_key_info(&self, keyring: &[InfraKey]) -> Option<KeyInfo> {
+        let key = infra_key(keyring, self.key_id)?;
+        let tag = self.hmac_tag_infra;
+        verify_tag(key, self.base_payload(), &tag).then_some(KeyInfo {
+            key_id: self.key_id,
+        })
+    }
+
+    /// Verify the client HMAC tag (repeater → client).
+    pub fn verify_client(&self) -> bool {
+        self.verify_client_with(HMAC_KEY_CLIENT)
+    }
+
+    /// Verify the client HMAC tag with `key`, for a receiver holding its own
+    /// copy of the client key.
+    pub fn verify_client_with(&self, key: &[u8]) -> bool {
+        let tag = self.hmac_tag_client;
+        verify_tag(key, self.base_payload(), &tag)
+    }
+
+    /// Sign the infrastructure tag in-place with `INFRA_KEY_CURRENT`
+    /// (called by the broadcaster).
+    pub fn sign_infra(&mut self) {
+        self.sign_infra_with(INFRA_KEY_CURRENT);
+    }
+
+    /// Set `key_id` and sign the infrastructure tag with that key. Also
+    /// sets `crc16`, since the base payload is final at this point.
+    pub fn sign_infra_with(&mut self, (key_id, key): InfraKey) {
+        self.key_id = key_id;
+        let tag = compute_infra_tag(key, self.base_payload());
+        self.hmac_tag_infra = tag;
+        self.crc16 = crc16_ccitt(self.base_payload()).to_le_bytes();
+    }
+
+    /// Sign the client tag in-place with `HMAC_KEY_CLIENT` (called by the
+    /// first repeater).
+    pub fn sign_client(&mut self) {
+        self.sign_client_with(HMAC_KEY_CLIENT);
+    }
+
+    /// Sign the client tag in-place with `key`.
+    pub fn sign_client_with(&mut self, key: &[u8]) {
+        let tag = compute_client_tag(key, self.base_payload());
+        self.hmac_tag_client = tag;
+    }
+
+    /// The copy to re-broadcast: `hops_remaining` decremented, or `None` once
+    /// it has reached zero and this notification must not travel further.
+    pub fn next_hop(&self) -> Option<Self> {
+        let hops_remaining = self.hops_remaining.checked_sub(1)?;
+        Some(Self {
+            hops_remaining,
+            ..*self
+        })
+    }
+
+    /// Returns true if the client tag has been set (non-zero).
+    pub fn has_client_tag(&self) -> bool {
+        ({ self.hmac_tag_client }) != [0u8; HMAC_TAG_CLIENT_LEN]
+    }
+
+    /// Parse and verify a notification from the manufacturer-data payload.
+    /// Verifies the infrastructure HMAC tag against `INFRA_KEYRING`; the
+    /// error says which check failed. Does not check `timestamp_ms`.
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ParseError> {
+        Self::from_payload_with(payload, INFRA_KEYRING, None)
+    }
+
+    /// `from_payload`, accepting only infrastructure keys in `keyring`.
+    ///
+    /// With `now_ms` (the receiver's unix-epoch time, when it has a clock it
+    /// trusts), also rejects a `timestamp_ms` older than `MAX_AGE_MS` or
+    /// further ahead than `MAX_FUTURE_SKEW_MS`. Without it every timestamp is
+    /// accepted, which is all a receiver without a real-time clock can do.
+    /// A v1 payload has no timestamp and is accepted either way.
+    pub fn from_payload_with(
+        payload: &[u8],
+        keyring: &[InfraKey],
+        now_ms: Option<u64>,
+    ) -> Result<Self, ParseError> {
+        match payload.first() {
+            Some(&PROTOCOL_VERSION_V1) => Self::parse_v1(payload, keyring),
+            _ => Self::parse_v8(payload, keyring, now_ms),
+        }
+    }
+
+    /// A v1 payload, verified over its own layout and converted; see
+    /// `compat`.
+    fn parse_v1(payload: &[u8], keyring: &[InfraKey]) -> Result<Self, ParseError> {
+        TransportNotificationV1::from_payload_with(payload, keyring).map(|v1| v1.to_current())
+    }
+
+    /// A payload in the current layout.
+    pub(crate) fn parse_v8(
+        payload: &[u8],
+        keyring: &[InfraKey],
+        now_ms: Option<u64>,
+    ) -> Result<Self, ParseError> {
+        let notif = Self::parse_unverified(payload)?;
+
+        // Cheap reject before the HMAC: most packets that got this far are
+        // noise or someone else's 0xFFFF beacon.
+        if !notif.verify_crc() {
+            return Err(ParseError::CrcMismatch);
+        }
+
+        // Verify infrastructure HMAC tag (set by broadcaster, never changes)
+        if infra_key(keyring, notif.key_id).is_none() {
+            return Err(ParseError::UnknownKeyId(notif.key_id));
+        }
+        if !notif.verify_infra_with(keyring) {
+            return Err(ParseError::InfraHmacMismatch);
+        }
+
+        // Only judged once the tag proves the timestamp is the broadcaster's.
+        if let Some(now_ms) = now_ms {
+            notif.check_fresh(now_ms, MAX_FUTURE_SKEW_MS)?;
+        }
+
+        Ok(notif)
+    }
+
+    /// Parse and verify every notification packed back to back in a
+    /// manufacturer-data payload, as `from_payload` does for one. The payload
+    /// is walked record by record (see `Records`); a trailing partial record
+    /// yields `ParseError::TooShort` and ends the walk, and an empty payload
+    /// yields nothing.
+    pub fn decode_all(payload: &[u8]) -> impl Iterator<Item = Result<Self, ParseError>> + '_ {
+        Self::decode_all_with(payload, INFRA_KEYRING, None)
+    }
+
+    /// `decode_all`, checking each notification as `from_payload_with`.
+    pub fn decode_all_with<'a>(
+        payload: &'a [u8],
+        keyring: &'a [InfraKey],
+        now_ms: Option<u64>,
+    ) -> impl Iterator<Item = Result<Self, ParseError>> + 'a {
+        Records(payload).map(move |record| Self::from_payload_with(record, keyring, now_ms))
+    }
+
+    /// Reject a `timestamp_ms` older than `MAX_AGE_MS`, or further ahead of
+    /// `now_ms` than `max_future_skew_ms`. The two limits are separate: the
+    /// forward one only absorbs clock skew, and a packet accepted within it
+    /// still goes stale `MAX_AGE_MS` after its timestamp, so pre-dating
+    /// buys an attacker at most `max_future_skew_ms`. `from_payload_with`
+    /// checks against `MAX_FUTURE_SKEW_MS`; a receiver with its own
+    /// tolerance parses without `now_ms` and calls this itself.
+    pub fn check_fresh(&self, now_ms: u64, max_future_skew_ms: u64) -> Result<(), ParseError> {
+        let emitted = self.timestamp_ms();
+        if emitted > now_ms.saturating_add(max_future_skew_ms) {
+            return Err(ParseError::FromFuture {
+                ahead_ms: emitted - now_ms,
+            });
+        }
+        let age_ms = now_ms.saturating_sub(emitted);
+        if age_ms > MAX_AGE_MS {
+            return Err(ParseError::Stale { age_ms });
+        }
+        Ok(())
+    }
+
+    /// Decode a payload and check its version, enum nibbles and duration,
+    /// without verifying either tag. For receivers that check a tag
+    /// themselves, such as a client that holds only the client key.
+    pub fn parse_unverified(payload: &[u8]) -> Result<Self, ParseError> {
+        debug!("parsing payload ({} bytes)", payload.len());
+        // Copies out of the buffer, so its alignment doesn't matter; only a
+        // short buffer fails. Trailing bytes are ignored.
+        let (notif, _) = Self::read_from_prefix(payload).map_err(|_| ParseError::TooShort {
+            got: payload.len(),
+            need: Self::SIZE,
+        })?;
+
+        // Validate protocol version
+        if { notif.version } != PROTOCOL_VERSION {
+            return Err(ParseError::UnsupportedVersion(notif.version));
+        }
+        notif.check_fields()?;
+        Ok(notif)
+    }
+
+    /// Check the packed enum nibbles and the duration, the fields every
+    /// version shares.
+    pub(crate) fn check_fields(&self) -> Result<(), ParseError> {
+        if self.transport_type().is_none() {
+            return Err(ParseError::BadTransportType({ self.type_status } >> 4));
+        }
+        if self.transport_status().is_none() {
+            return Err(ParseError::BadTransportStatus({ self.type_status } & 0x0F));
+        }
+        if self.duration_secs() > MAX_DURATION_SECS {
+            return Err(ParseError::DurationTooLong(self.duration_secs()));
+        }
+        Ok(())
+    }
+}
+
+/// Structural decoding only: the length, version, enum nibbles and
+/// duration are checked, as by `parse_unverified`, and a v1 payload is
+/// converted as by `from_payload_with`. Neither tag is verified and neither
+/// is the CRC, so the result is **not** authenticated: anyone can produce
+/// bytes that pass. Call `verify_infra_with` (or `verify_client_with`)
+/// before trusting it, or use `from_payload_with`, which does both.
+impl TryFrom<&[u8]> for TransportNotification {
+    type Error = ParseError;
+
+    fn try_from(payload: &[u8]) -> Result<Self, ParseError> {
+        match payload.first() {
+            Some(&PROTOCOL_VERSION_V1) => {
+                TransportNotificationV1::parse_unverified(payload).map(|v1| v1.to_current())
+            }
+            _ => Self::parse_unverified(payload),
+        }
+    }
+}
+
+/// The notifications packed back to back in a manufacturer-data payload,
+/// each as long as its own version byte says: `TransportNotificationV1::SIZE`
+/// for v1, `TransportNotification::SIZE` for anything else (which fails to
+/// parse if it isn't the current version). A current-version record also
+/// takes the extension records that follow it, such as a client-tag record
+/// (see `client_tags`) or a relay path (see `relay_path`); parsing it as a
+/// notification ignores them. The last
+/// record may be short.
+#[derive(Debug, Clone)]
+pub struct Records<'a>(pub &'a [u8]);
+
+impl<'a> Iterator for Records<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let mut len = match self.0.first()? {
+            &PROTOCOL_VERSION_V1 => TransportNotificationV1::SIZE,
+            _ => TransportNotification::SIZE,
+        };
+        if self.0[0] != PROTOCOL_VERSION_V1 && self.0.len() > len {
+            len += extensions(self.0).map(<[u8]>::len).sum::<usize>();
+        }
+        let (record, rest) = self.0.split_at(len.min(self.0.len()));
+        self.0 = rest;
+        Some(record)
+    }
+}
+
+/// Length of the extension record `bytes` starts with, as far as `bytes`
+/// goes, or `None` if it doesn't start with one.
+fn extension_len(bytes: &[u8]) -> Option<usize> {
+    match *bytes.first()? {
+        CLIENT_TAGS_RECORD => Some(ClientTags::claimed_len(bytes)),
+        RELAY_PATH_RECORD => Some(RelayPath::SIZE.min(bytes.len())),
+        _ => None,
+    }
+}
+
+/// The extension records following the notification at the start of
+/// `record`, one of `Records`, in order.
+pub(crate) fn extensions(record: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = record.get(TransportNotification::SIZE..).unwrap_or(&[]);
+    core::iter::from_fn(move || {
+        let (ext, after) = rest.split_at(extension_len(rest)?);
+        rest = after;
+        Some(ext)
+    })
+}
+
+// ── Formatting ──────────────────────────────────────────────────────────
+
+/// Bytes as lowercase hex digits, without allocating.
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+impl fmt::Debug for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// One line for logs, e.g. `id=a50607ff source=01020304 Bus/Coming → dest 3
+/// dur=30s`. An invalid type or status nibble shows as its number.
+impl fmt::Display for TransportNotification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "id={} source={} ",
+            Hex(&self.notification_id),
+            Hex(&self.source_id)
+        )?;
+        match self.transport_type() {
+            Some(t) => write!(f, "{:?}", t)?,
+            None => write!(f, "type {}", { self.type_status } >> 4)?,
+        }
+        match self.transport_status() {
+            Some(s) => write!(f, "/{:?}", s)?,
+            None => write!(f, "/status {}", { self.type_status } & 0x0F)?,
+        }
+        write!(
+            f,
+            " → dest {} dur={}s",
+            self.destination_id(),
+            self.duration_secs()
+        )
+    }
+}
+
+/// Every field, decoded through the accessors: ids, tags and the CRC as
+/// hex, the packed nibbles split into named values.
+impl fmt::Debug for TransportNotification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransportNotification")
+            .field("version", &self.version)
+            .field("source_id", &Hex(&self.source_id))
+            .field("notification_id", &Hex(&self.notification_id))
+            .field("event", &self.event())
+            .field("destination_id", &self.destination_id())
+            .field("transport_type", &self.transport_type())
+            .field("transport_status", &self.transport_status())
+            .field("duration_secs", &self.duration_secs())
+            .field("validity_secs", &self.validity_secs())
+            .field("flags", &self.flags)
+            .field("seq", &self.seq())
+            .field("key_id", &self.key_id)
+            .field("timestamp_ms", &self.timestamp_ms())
+            .field("priority", &self.priority)
+            .field("hmac_tag_infra", &Hex(&self.hmac_tag_infra))
+            .field("hmac_tag_client", &Hex(&self.hmac_tag_client))
+            .field("hops_remaining", &self.hops_remaining)
+            .field("crc16", &Hex(&self.crc16))
+            .finish()
+    }
+}
+
+// ── Layout guardrails ───────────────────────────────────────────────────
+
+/// Byte sum of every field the HMAC tags authenticate, computed from the
+/// field types rather than from `SIZE`. Adding or reordering fields without
+/// updating this (and the tag placement) fails to compile.
+pub const BASE_FIELDS_SIZE: usize = core::mem::size_of::<u8>() // version
+    + core::mem::size_of::<[u8; 4]>() // source_id
+    + core::mem::size_of::<[u8; 4]>() // notification_id
+    + core::mem::size_of::<u8>() // event_dest
+    + core::mem::size_of::<u8>() // type_status
+    + core::mem::size_of::<[u8; 2]>() // duration_secs
+    + core::mem::size_of::<[u8; 2]>() // validity_secs
+    + core::mem::size_of::<u8>() // flags
+    + core::mem::size_of::<[u8; 4]>() // seq
+    + core::mem::size_of::<u8>() // key_id
+    + core::mem::size_of::<[u8; 6]>() // timestamp_ms
+    + core::mem::size_of::<u8>(); // priority
+
+/// Advertising data a notification may take (`MFG_AD_LEN`): 31 bytes of
+/// fields besides the two tags, whose length the `tag-*` features set (47
+/// with the defaults). Past `LEGACY_ADV_DATA_LEN` since `flags`, so only
+/// extended advertising carries it. Adding a field has to raise this on
+/// purpose, after weighing the bytes against what senders and scanners can
+/// carry; it doesn't follow the struct.
+pub const MFG_AD_BUDGET: usize = 31 + HMAC_TAG_INFRA_LEN + HMAC_TAG_CLIENT_LEN + MFG_AD_OVERHEAD;
+
+// Both enums are packed into nibbles of `type_status`, so every
+// discriminant must fit in 4 bits.
+const _: () = {
+    let mut i = 0;
+    while i < TransportType::ALL.len() {
+        assert!(
+            TransportType::ALL[i] as u8 <= 0x0F,
+            "TransportType does not fit in a nibble"
+        );
+        i += 1;
+    }
+    let mut i = 0;
+    while i < TransportStatus::ALL.len() {
+        assert!(
+            TransportStatus::ALL[i] as u8 <= 0x0F,
+            "TransportStatus does not fit in a nibble"
+        );
+        i += 1;
+    }
+};
+
+const _: () = {
+    type N = TransportNotification;
+    assert!(
+        BASE_FIELDS_SIZE == N::BASE_PAYLOAD_SIZE,
+        "BASE_PAYLOAD_SIZE does not match the sum of the base fields"
+    );
+    assert!(
+        core::mem::offset_of!(N, hmac_tag_infra) == N::BASE_PAYLOAD_SIZE,
+        "hmac_tag_infra must immediately follow the base payload"
+    );
+    assert!(
+        core::mem::offset_of!(N, hmac_tag_client) == N::BASE_PAYLOAD_SIZE + HMAC_TAG_INFRA_LEN,
+        "hmac_tag_client must immediately follow hmac_tag_infra"
+    );
+    assert!(
+        core::mem::offset_of!(N, hops_remaining)
+            == N::BASE_PAYLOAD_SIZE + HMAC_TAG_INFRA_LEN + HMAC_TAG_CLIENT_LEN,
+        "hops_remaining must follow the tags, outside the signed payload"
+    );
+    assert!(
+        core::mem::offset_of!(N, crc16) == core::mem::offset_of!(N, hops_remaining) + 1,
+        "crc16 must be last, outside the signed payload"
+    );
+    assert!(
+        N::MFG_AD_LEN <= MFG_AD_BUDGET,
+        "a notification outgrew MFG_AD_BUDGET: raise it deliberately or pack the field"
+    );
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::DEFAULT_HOPS;
+    use proptest::prelude::*;
+
+    /// `timestamp_ms` of `sample`: 2026-01-01T00:00:00Z.
+    const NOW_MS: u64 = 1_767_225_600_000;
+
+    /// Recompute `crc16` after editing the base payload, as anyone can.
+    fn reseal_crc(notif: &mut TransportNotification) {
+        notif.crc16 = crc16_ccitt(notif.base_payload()).to_le_bytes();
+    }
+
+    /// A signed notification with the given type/status nibbles.
+    fn sample(transport_type: TransportType, status: TransportStatus) -> TransportNotification {
+        let mut notif = TransportNotification {
+            version: PROTOCOL_VERSION,
+            source_id: [1, 2, 3, 4],
+            notification_id: [5, 6, 7, 8],
+            event_dest: 0xF0,
+            type_status: ((transport_type as u8) << 4) | status as u8,
+            duration_secs: 30u16.to_le_bytes(),
+            validity_secs: 600u16.to_le_bytes(),
+            flags: 0,
+            seq: 7u32.to_le_bytes(),
+            key_id: 0,
+            timestamp_ms: TransportNotification::timestamp_bytes(NOW_MS),
+            priority: 0,
+            hmac_tag_infra: [0; HMAC_TAG_INFRA_LEN],
+            hmac_tag_client: [0; HMAC_TAG_CLIENT_LEN],
+            hops_remaining: DEFAULT_HOPS,
+            crc16: [0; 2],
+        };
+        notif.sign_infra();
+        notif
+    }
+
+    #[test]
+    fn base_payload_covers_exactly_the_base_fields() {
+        assert_eq!(TransportNotification::BASE_PAYLOAD_SIZE, BASE_FIELDS_SIZE);
+        assert_eq!(
+            core::mem::offset_of!(TransportNotification, hmac_tag_infra),
+            BASE_FIELDS_SIZE
+        );
+    }
+
+    #[test]
+    fn struct_has_no_padding() {
+        assert_eq!(
+            core::mem::size_of::<TransportNotification>(),
+            BASE_FIELDS_SIZE + HMAC_TAG_INFRA_LEN + HMAC_TAG_CLIENT_LEN + 1 + 2
+        );
+    }
+
+    #[test]
+    fn notification_uses_its_advertising_budget() {
+        // Shrinking the struct should lower the budget too, and show
+        // whether it fits a legacy advertisement again.
+        assert_eq!(TransportNotification::MFG_AD_LEN, MFG_AD_BUDGET);
+        assert_eq!(
+            TransportNotification::FITS_LEGACY_ADV,
+            MFG_AD_BUDGET <= LEGACY_ADV_DATA_LEN
+        );
+    }
+
+    #[test]
+    fn byte_views_match_layout_constants() {
+        let notif = sample(TransportType::Bus, TransportStatus::Late);
+        assert_eq!(notif.as_bytes().len(), TransportNotification::SIZE);
+        assert_eq!(
+            notif.base_payload().len(),
+            TransportNotification::BASE_PAYLOAD_SIZE
+        );
+        assert!(notif.as_bytes().starts_with(notif.base_payload()));
+    }
+
+    #[test]
+    fn round_trip_for_every_variant() {
+        for transport_type in TransportType::ALL {
+            for status in TransportStatus::ALL {
+                let notif = sample(transport_type, status);
+                let parsed = TransportNotification::from_payload(notif.as_bytes()).unwrap();
+                assert_eq!(parsed.transport_type(), Some(transport_type));
+                assert_eq!(parsed.transport_status(), Some(status));
+                assert_eq!((parsed.event_id(), parsed.destination_id()), (15, 0));
+                assert_eq!(parsed.seq(), 7);
+            }
+        }
+    }
+
+    #[test]
+    fn duration_over_the_maximum_is_rejected_though_signed() {
+        let mut notif = sample(TransportType::Bus, TransportStatus::Coming);
+        notif.duration_secs = MAX_DURATION_SECS.to_le_bytes();
+        notif.sign_infra();
+        assert!(TransportNotification::from_payload(notif.as_bytes()).is_ok());
+
+        notif.duration_secs = (MAX_DURATION_SECS + 1).to_le_bytes();
+        notif.sign_infra();
+        assert_eq!(
+            TransportNotification::from_payload(notif.as_bytes()).unwrap_err(),
+            ParseError::DurationTooLong(MAX_DURATION_SECS + 1)
+        );
+    }
+
+    #[test]
+    fn decode_all_walks_every_packed_notification() {
+        let first = sample(TransportType::Bus, TransportStatus::Coming);
+        let mut second = sample(TransportType::Train, TransportStatus::Late);
+        second.notification_id = [9, 9, 9, 9];
+        second.sign_infra();
+        let mut packed = [first.as_bytes(), second.as_bytes()].concat();
+
+        let decoded: Vec<_> = TransportNotification::decode_all(&packed)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].as_bytes(), first.as_bytes());
+        assert_eq!(decoded[1].as_bytes(), second.as_bytes());
+
+        // A damaged chunk doesn't stop the walk; a partial one ends it.
+        packed[1] ^= 0x01;
+        packed.extend_from_slice(&first.as_bytes()[..10]);
+        let decoded: Vec<_> = TransportNotification::decode_all(&packed)
+            .map(|r| r.map(|n| n.notification_id))
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                Err(ParseError::CrcMismatch),
+                Ok([9, 9, 9, 9]),
+                Err(ParseError::TooShort {
+                    got: 10,
+                    need: TransportNotification::SIZE
+                }),
+            ]
+        );
+        assert_eq!(TransportNotification::decode_all(&[]).count(), 0);
+    }
+
+    #[test]
+    fn event_and_destination_nibbles_stay_independent() {
+        for event_id in 0..=0x0F {
+            for destination_id in 0..=0x0F {
+                let mut notif = sample(TransportType::Train, TransportStatus::Late);
+                notif.event_dest = (event_id << 4) | destination_id;
+                notif.sign_infra();
+                let parsed = TransportNotification::from_payload(notif.as_bytes()).unwrap();
+                assert_eq!(parsed.as_bytes(), notif.as_bytes());
+                assert_eq!(parsed.event_id(), event_id);
+                assert_eq!(parsed.destination_id(), destination_id);
+                assert_eq!(parsed.transport_type(), Some(TransportType::Train));
+                assert_eq!(parsed.transport_status(), Some(TransportStatus::Late));
+            }
+        }
+    }
+
+    #[test]
+    fn every_event_nibble_round_trips() {
+        for v in 0..=0x0F {
+            assert_eq!(EventId::from_u4(v).to_u4(), v);
+        }
+        for event in EventId::ALL {
+            assert!(!matches!(
+                EventId::from_u4(event.to_u4()),
+                EventId::Unknown(_)
+            ));
+        }
+        assert_eq!(EventId::from_u4(0), EventId::Unknown(0));
+        assert_eq!(EventId::from_u4(15), EventId::Unknown(15));
+        assert_eq!(
+            sample(TransportType::Bus, TransportStatus::Late).event(),
+            EventId::Unknown(15)
+        );
+    }
+
+    #[test]
+    fn client_tag_is_signed_and_verified() {
+        let mut notif = sample(TransportType::Train, TransportStatus::Coming);
+        assert!(!notif.has_client_tag());
+        notif.sign_client();
+        assert!(notif.has_client_tag());
+        assert!(notif.verify_client());
+        // Signing the client tag leaves the infra tag valid.
+        assert!(TransportNotification::from_payload(notif.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn tag_one_byte_off_is_rejected() {
+        let mut notif = sample(TransportType::Bus, TransportStatus::Coming);
+        notif.sign_client();
+        assert!(notif.verify_infra() && notif.verify_client());
+
+        for i in 0..HMAC_TAG_INFRA_LEN {
+            let mut bad = notif;
+            bad.hmac_tag_infra[i] ^= 0x01;
+            assert!(!bad.verify_infra(), "infra tag byte {} flipped", i);
+        }
+        for i in 0..HMAC_TAG_CLIENT_LEN {
+            let mut bad = notif;
+            bad.hmac_tag_client[i] ^= 0x01;
+            assert!(!bad.verify_client(), "client tag byte {} flipped", i);
+        }
+    }
+
+    #[test]
+    fn tampered_payload_is_rejected() {
+        let mut notif = sample(TransportType::Bus, TransportStatus::Passing);
+        notif.event_dest ^= 0x01; // flip a destination bit
+        assert_eq!(
+            TransportNotification::from_payload(notif.as_bytes()).unwrap_err(),
+            ParseError::CrcMismatch
+        );
+        // Fixing up the CRC is trivial; the HMAC still catches it.
+        reseal_crc(&mut notif);
+        assert_eq!(
+            TransportNotification::from_payload(notif.as_bytes()).unwrap_err(),
+            ParseError::InfraHmacMismatch
+        );
+    }
+
+    #[test]
+    fn crc_and_hops_do_not_affect_the_tags() {
+        let notif = sample(TransportType::Bus, TransportStatus::Passing);
+        let relayed = notif.next_hop().unwrap();
+        assert!(relayed.verify_crc() && relayed.verify_infra());
+
+        let mut corrupt = notif;
+        corrupt.crc16[0] ^= 0xFF;
+        assert!(corrupt.verify_infra(), "tags don't cover crc16");
+        assert_eq!(
+            TransportNotification::from_payload(corrupt.as_bytes()).unwrap_err(),
+            ParseError::CrcMismatch
+        );
+    }
+
+    #[test]
+    fn truncated_payload_is_rejected() {
+        let notif = sample(TransportType::Bus, TransportStatus::Passing);
+        let bytes = notif.as_bytes();
+        for len in 0..TransportNotification::SIZE {
+            assert_eq!(
+                TransportNotification::from_payload(&bytes[..len]).unwrap_err(),
+                ParseError::TooShort {
+                    got: len,
+                    need: TransportNotification::SIZE
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn header_checks_report_the_offending_value() {
+        let notif = sample(TransportType::Bus, TransportStatus::Passing);
+        let parse_with = |offset: usize, value: u8| {
+            let mut bytes = [0u8; TransportNotification::SIZE];
+            bytes.copy_from_slice(notif.as_bytes());
+            bytes[offset] = value;
+            TransportNotification::from_payload(&bytes).unwrap_err()
+        };
+        assert_eq!(parse_with(0, 9), ParseError::UnsupportedVersion(9));
+        assert_eq!(parse_with(10, 0x71), ParseError::BadTransportType(7));
+        assert_eq!(parse_with(10, 0x1E), ParseError::BadTransportStatus(0x0E));
+    }
+
+    #[test]
+    fn unaligned_payload_with_trailing_bytes_parses() {
+        let notif = sample(TransportType::Train, TransportStatus::Late);
+        let mut buf = [0xEEu8; TransportNotification::SIZE + 3];
+        buf[1..=TransportNotification::SIZE].copy_from_slice(notif.as_bytes());
+        let parsed = TransportNotification::from_payload(&buf[1..]).unwrap();
+        assert_eq!(parsed.as_bytes(), notif.as_bytes());
+    }
+
+    const OLD_KEY: InfraKey = (1, b"old-infra-key");
+    const NEW_KEY: InfraKey = (2, b"new-infra-key");
+
+    #[test]
+    fn any_key_in_the_keyring_verifies_during_rollover() {
+        let rollover = [OLD_KEY, NEW_KEY];
+        for key in rollover {
+            let mut notif = sample(TransportType::Bus, TransportStatus::Coming);
+            notif.sign_infra_with(key);
+            assert_eq!({ notif.key_id }, key.0);
+            assert!(
+                TransportNotification::from_payload_with(notif.as_bytes(), &rollover, None).is_ok()
+            );
+        }
+
+        let mut notif = sample(TransportType::Bus, TransportStatus::Coming);
+        notif.sign_infra_with(NEW_KEY);
+        assert_eq!(
+            TransportNotification::from_payload_with(notif.as_bytes(), &[OLD_KEY], None)
+                .unwrap_err(),
+            ParseError::UnknownKeyId(2)
+        );
+        assert!(
+            !notif.verify_infra(),
+            "default keyring doesn't hold NEW_KEY"
+        );
+    }
+
+    #[test]
+    fn try_from_decodes_structure_without_authenticating() {
+        let notif = sample(TransportType::Bus, TransportStatus::Late);
+        let got = TransportNotification::try_from(notif.as_bytes()).unwrap();
+        assert_eq!(got.as_bytes(), notif.as_bytes());
+
+        // A forged tag still decodes; only verification catches it.
+        let mut forged = notif;
+        forged.hmac_tag_infra[0] ^= 1;
+        let got = TransportNotification::try_from(forged.as_bytes()).unwrap();
+        assert!(!got.verify_infra());
+
+        let bytes = notif.as_bytes();
+        assert_eq!(
+            TransportNotification::try_from(&bytes[..10]).unwrap_err(),
+            ParseError::TooShort {
+                got: 10,
+                need: TransportNotification::SIZE
+            }
+        );
+        assert_eq!(
+            TransportNotification::try_from(&[][..]).unwrap_err(),
+            ParseError::TooShort {
+                got: 0,
+                need: TransportNotification::SIZE
+            }
+        );
+        let mut bad = bytes.to_vec();
+        bad[0] = 9;
+        assert_eq!(
+            TransportNotification::try_from(&bad[..]).unwrap_err(),
+            ParseError::UnsupportedVersion(9)
+        );
+        let mut bad = bytes.to_vec();
+        bad[core::mem::offset_of!(TransportNotification, type_status)] = 0x71;
+        assert_eq!(
+            TransportNotification::try_from(&bad[..]).unwrap_err(),
+            ParseError::BadTransportType(7)
+        );
+
+        let v1 = TransportNotificationV1::downgrade(&notif, INFRA_KEY_CURRENT);
+        let got = TransportNotification::try_from(v1.as_bytes()).unwrap();
+        assert_eq!(got.version, PROTOCOL_VERSION_V1);
+    }
+
+    #[test]
+    fn verification_names_the_key_generation() {
+        let mut notif = sample(TransportType::Bus, TransportStatus::Coming);
+        notif.sign_infra_with(NEW_KEY);
+        assert_eq!(
+            notif.infra_key_info(&[OLD_KEY, NEW_KEY]),
+            Some(KeyInfo { key_id: 2 })
+        );
+        assert!(notif.verify_infra_with(&[NEW_KEY]));
+
+        // Generation 2 revoked: dropped from the keyring.
+        assert_eq!(notif.infra_key_info(&[OLD_KEY]), None);
+        assert!(!notif.verify_infra_with(&[OLD_KEY]));
+        // Nor does a generation verify under another's key.
+        assert_eq!(notif.infra_key_info(&[(2, OLD_KEY.1)]), None);
+    }
+
+    #[test]
+    fn key_id_is_covered_by_the_infra_tag() {
+        let mut notif = sample(TransportType::Train, TransportStatus::Passing);
+        notif.sign_infra_with(NEW_KEY);
+        notif.key_id = OLD_KEY.0;
+        reseal_crc(&mut notif);
+        assert_eq!(
+            TransportNotification::from_payload_with(notif.as_bytes(), &[OLD_KEY, NEW_KEY], None)
+                .unwrap_err(),
+            ParseError::InfraHmacMismatch
+        );
+    }
+
+    #[test]
+    fn priority_is_covered_by_the_infra_tag() {
+        let mut notif = sample(TransportType::Bus, TransportStatus::Coming);
+        notif.priority = u8::MAX;
+        reseal_crc(&mut notif);
+        assert_eq!(
+            TransportNotification::from_payload(notif.as_bytes()).unwrap_err(),
+            ParseError::InfraHmacMismatch
+        );
+    }
+
+    #[test]
+    fn timestamp_round_trips_through_48_bits() {
+        let notif = sample(TransportType::Bus, TransportStatus::Late);
+        assert_eq!(notif.timestamp_ms(), NOW_MS);
+        let max = (1u64 << 48) - 1;
+        let mut notif = notif;
+        notif.timestamp_ms = TransportNotification::timestamp_bytes(max);
+        assert_eq!(notif.timestamp_ms(), max);
+    }
+
+    #[test]
+    fn staleness_is_checked_only_with_a_clock() {
+        let notif = sample(TransportType::Bus, TransportStatus::Late);
+        let parse = |now_ms| {
+            TransportNotification::from_payload_with(notif.as_bytes(), INFRA_KEYRING, now_ms)
+        };
+
+        assert!(parse(None).is_ok(), "no clock accepts any timestamp");
+        assert!(parse(Some(NOW_MS)).is_ok());
+        assert!(parse(Some(NOW_MS + MAX_AGE_MS)).is_ok());
+        assert_eq!(
+            parse(Some(NOW_MS + MAX_AGE_MS + 1)).unwrap_err(),
+            ParseError::Stale {
+                age_ms: MAX_AGE_MS + 1
+            }
+        );
+        assert!(parse(Some(NOW_MS - MAX_FUTURE_SKEW_MS)).is_ok());
+        assert_eq!(
+            parse(Some(NOW_MS - MAX_FUTURE_SKEW_MS - 1)).unwrap_err(),
+            ParseError::FromFuture {
+                ahead_ms: MAX_FUTURE_SKEW_MS + 1
+            }
+        );
+    }
+
+    #[test]
+    fn forward_skew_tolerance_is_separate_from_the_age_limit() {
+        let notif = sample(TransportType::Bus, TransportStatus::Late);
+        let ahead = |skew| ParseError::FromFuture { ahead_ms: skew + 1 };
+
+        for skew in [0, 5_000, MAX_AGE_MS] {
+            assert!(notif.check_fresh(NOW_MS - skew, skew).is_ok());
+            assert_eq!(notif.check_fresh(NOW_MS - skew - 1, skew), Err(ahead(skew)));
+        }
+        // However far ahead it may be, it goes stale on its own timestamp.
+        assert!(notif.check_fresh(NOW_MS + MAX_AGE_MS, u64::MAX).is_ok());
+        assert_eq!(
+            notif.check_fresh(NOW_MS + MAX_AGE_MS + 1, u64::MAX),
+            Err(ParseError::Stale {
+                age_ms: MAX_AGE_MS + 1
+            })
+        );
+    }
+
+    #[test]
+    fn exhausted_hop_count_is_not_rebroadcast() {
+        let mut notif = sample(TransportType::Bus, TransportStatus::Passing);
+        notif.sign_client();
+
+        let mut current = notif;
+        for expected in [2, 1, 0] {
+            current = current.next_hop().unwrap();
+            assert_eq!(current.hops_remaining, expected);
+            // The tags don't cover the hop count, so every relayed copy
+            // still verifies.
+            assert!(current.verify_infra() && current.verify_client());
+        }
+        assert!(current.next_hop().is_none());
+
+        notif.hops_remaining = 0;
+        assert!(notif.next_hop().is_none());
+    }
+
+    #[test]
+    fn display_is_one_log_line() {
+        let mut notif = sample(TransportType::Bus, TransportStatus::Coming);
+        assert_eq!(
+            notif.to_string(),
+            "id=05060708 source=01020304 Bus/Coming → dest 0 dur=30s"
+        );
+        assert_eq!(
+            (notif.id_hex(), notif.source_hex()),
+            ("05060708".into(), "01020304".into())
+        );
+
+        // Parsing rejects bad nibbles, but a hand-built struct can carry them.
+        notif.type_status = 0xEE;
+        assert!(notif.to_string().contains(" type 14/status 14 → "));
+    }
+
+    #[test]
+    fn debug_decodes_the_packed_fields() {
+        let notif = sample(TransportType::Train, TransportStatus::Passing);
+        let debug = format!("{:?}", notif);
+        assert!(debug.contains("notification_id: 05060708"));
+        assert!(debug.contains("transport_type: Some(Train)"));
+        assert!(debug.contains("transport_status: Some(Passing)"));
+        assert!(debug.contains("seq: 7"));
+        assert!(debug.contains(&format!("timestamp_ms: {}", NOW_MS)));
+    }
+
+    /// What a parse of arbitrary bytes may return: an error, or a
+    /// notification that is exactly the bytes it was read from and carries a
+    /// valid infra tag and CRC.
+    fn check_parse(payload: &[u8], now_ms: Option<u64>) -> Result<(), TestCaseError> {
+        if let Ok(notif) = TransportNotification::from_payload_with(payload, INFRA_KEYRING, now_ms)
+        {
+            prop_assert_eq!(notif.as_bytes(), &payload[..TransportNotification::SIZE]);
+            prop_assert!(notif.verify_infra());
+            prop_assert!(notif.verify_crc());
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(4096))]
+
+        #[test]
+        fn random_bytes_never_panic(
+            payload in proptest::collection::vec(any::<u8>(), 0..=2 * TransportNotification::SIZE),
+            now_ms in proptest::option::of(any::<u64>()),
+        ) {
+            check_parse(&payload, now_ms)?;
+        }
+
+        /// Mostly-valid payloads get past the version, nibble and (when
+        /// resealed) CRC checks that random bytes almost never do.
+        #[test]
+        fn mutated_payloads_never_panic(
+            edits in proptest::collection::vec(
+                (0..TransportNotification::SIZE, any::<u8>()),
+                0..4,
+            ),
+            reseal in any::<bool>(),
+            extra in proptest::collection::vec(any::<u8>(), 0..=TransportNotification::SIZE),
+            now_ms in proptest::option::of(any::<u64>()),
+        ) {
+            let mut notif = sample(TransportType::Bus, TransportStatus::Coming);
+            let bytes = notif.as_mut_bytes();
+            for (i, b) in edits {
+                bytes[i] = b;
+            }
+            if reseal {
+                reseal_crc(&mut notif);
+            }
+            let mut payload = notif.as_bytes().to_vec();
+            payload.extend_from_slice(&extra);
+            check_parse(&payload, now_ms)?;
+        }
+
+        /// `Records` splits any payload into records that are each as long
+        /// as their version byte says, bar a short last one, and that put
+        /// back together are the payload; `decode_all_with` yields one
+        /// result per record.
+        #[test]
+        fn records_partition_any_payload(
+            payload in proptest::collection::vec(any::<u8>(), 0..=3 * TransportNotification::SIZE),
+            v1_at in proptest::collection::vec(any::<proptest::sample::Index>(), 0..3),
+            now_ms in proptest::option::of(any::<u64>()),
+        ) {
+            // Random bytes rarely start a record with the v1 byte.
+            let mut payload = payload;
+            for at in v1_at {
+                if !payload.is_empty() {
+                    let i = at.index(payload.len());
+                    payload[i] = PROTOCOL_VERSION_V1;
+                }
+            }
+
+            let records: Vec<&[u8]> = Records(&payload).collect();
+            prop_assert!(records.len() <= payload.len());
+            prop_assert_eq!(records.concat(), payload.clone());
+            for (i, record) in records.iter().enumerate() {
+                let len = match record[0] {
+                    PROTOCOL_VERSION_V1 => TransportNotificationV1::SIZE,
+                    _ => TransportNotification::SIZE,
+                };
+                // Anything past the notification is its extension records.
+                let extended = record.len() > len;
+                prop_assert!(
+                    !extended || [CLIENT_TAGS_RECORD, RELAY_PATH_RECORD].contains(&record[len])
+                );
+                prop_assert!(!extended || record[0] != PROTOCOL_VERSION_V1);
+                if i + 1 < records.len() {
+                    prop_assert!(record.len() >= len);
+                } else {
+                    prop_assert!(!record.is_empty());
+                }
+            }
+
+            let results: Vec<_> =
+                TransportNotification::decode_all_with(&payload, INFRA_KEYRING, now_ms).collect();
+            prop_assert_eq!(results.len(), records.len());
+            for (record, result) in records.iter().zip(&results) {
+                if record.first() != Some(&PROTOCOL_VERSION_V1) {
+                    check_parse(record, now_ms)?;
+                }
+                if let Err(ParseError::TooShort { got, .. }) = result {
+                    prop_assert_eq!(*got, record.len());
+                }
+            }
+        }
+
+        /// Signed current and v1 notifications packed back to back decode
+        /// to themselves, in order, and a payload cut short loses only the
+        /// record it cuts.
+        #[test]
+        fn packed_notifications_round_trip(
+            packets in proptest::collection::vec((any::<bool>(), any::<[u8; 4]>(), any::<u32>()), 0..6),
+            cut in any::<proptest::sample::Index>(),
+        ) {
+            let mut payload = Vec::new();
+            let mut sent = Vec::new();
+            // Where each record ends in `payload`.
+            let mut ends = Vec::new();
+            for (v1, notification_id, seq) in packets {
+                let mut notif = sample(TransportType::Bus, TransportStatus::Coming);
+                notif.notification_id = notification_id;
+                notif.seq = seq.to_le_bytes();
+                notif.sign_infra();
+                if v1 {
+                    let v1 = TransportNotificationV1::downgrade(&notif, INFRA_KEY_CURRENT);
+                    payload.extend_from_slice(v1.as_bytes());
+                    sent.push(v1.to_current());
+                } else {
+                    payload.extend_from_slice(notif.as_bytes());
+                    sent.push(notif);
+                }
+                ends.push(payload.len());
+            }
+
+            let decoded: Vec<_> = TransportNotification::decode_all_with(&payload, INFRA_KEYRING, None)
+                .collect::<Result<_, _>>()
+                .unwrap();
+            prop_assert_eq!(decoded.len(), sent.len());
+            for (got, want) in decoded.iter().zip(&sent) {
+                prop_assert_eq!(got.as_bytes(), want.as_bytes());
+            }
+
+            let cut = cut.index(payload.len() + 1);
+            let results: Vec<_> =
+                TransportNotification::decode_all_with(&payload[..cut], INFRA_KEYRING, None).collect();
+            // Records wholly before the cut.
+            let whole = ends.iter().take_while(|&&end| end <= cut).count();
+            prop_assert_eq!(results.len(), whole + usize::from(!ends.contains(&cut) && cut > 0));
+            for (i, result) in results.iter().enumerate() {
+                match result {
+                    Ok(got) => prop_assert_eq!(got.as_bytes(), sent[i].as_bytes()),
+                    Err(ParseError::TooShort { .. }) => prop_assert_eq!(i, results.len() - 1),
+                    Err(e) => prop_assert!(false, "record {} of a cut payload: {:?}", i, e),
+                }
+            }
+        }
+    }
+}
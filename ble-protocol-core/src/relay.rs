@@ -0,0 +1,188 @@
+//! What a repeater does to a notification it relays, once it has decided
+//! to: open the payload, take a hop off, and sign the client tag, or tags
+//! (see `client_tags`), and on a debug build add itself to the relay path
+//! (see `relay_path`).
+//!
+//! Kept apart from the repeater's policy (block lists, RSSI thresholds,
+//! replay tracking) so the path a payload takes from broadcaster to client
+//! can be exercised on the host, without a radio.
+
+use crate::client_tags::{ClientTags, MAX_EXTRA_CLIENT_TAGS};
+use crate::compat::TransportNotificationV1;
+use crate::conf::SealedNotification;
+use crate::consts::{ClientKey, InfraKey, PROTOCOL_VERSION_V1};
+use crate::notification::{ParseError, Records, TransportNotification};
+use crate::relay_path::RelayPath;
+
+// A sealed notification is told apart by its length, so no run of plain
+// notifications, of either version, may have that length.
+const _: () = assert!(SealedNotification::SIZE % TransportNotification::SIZE != 0);
+const _: () = assert!(SealedNotification::SIZE % TransportNotificationV1::SIZE != 0);
+// Nor may a notification with its client-tag record.
+const _: () = {
+    let mut count = 0;
+    while count <= MAX_EXTRA_CLIENT_TAGS {
+        assert!(
+            SealedNotification::SIZE != TransportNotification::SIZE + ClientTags::size_with(count)
+        );
+        count += 1;
+    }
+};
+// A relay-path record alone is longer than the envelope's overhead.
+const _: () = assert!(SealedNotification::SIZE < TransportNotification::SIZE + RelayPath::SIZE);
+
+/// A verified notification as a repeater heard it.
+#[derive(Debug, Clone, Copy)]
+pub struct Received {
+    /// The notification, decrypted if it arrived sealed and converted if it
+    /// arrived as v1.
+    pub notification: TransportNotification,
+    /// The envelope a sealed notification arrived in. That, not the
+    /// plaintext, is what goes back on air.
+    pub sealed: Option<SealedNotification>,
+    /// The packet a v1 notification arrived as. Its tags cover the v1
+    /// layout, so it goes back on air as v1, unchanged but for the client
+    /// tag.
+    pub v1: Option<TransportNotificationV1>,
+    /// The client-tag record that followed the notification, aired after
+    /// it. Only a current-version notification in the clear has one.
+    pub client_tags: Option<ClientTags>,
+    /// The relay-path record that followed the notification, aired after
+    /// the client-tag record. Like that, only a current-version
+    /// notification in the clear has one.
+    pub relay_path: Option<RelayPath>,
+}
+
+impl Received {
+    /// Verify and decode a manufacturer-data payload. Sealed notifications
+    /// are told apart by length, verified, then decrypted with `conf_key`;
+    /// v1 ones by their version byte. A v1 packet has no timestamp, so
+    /// `now_ms` doesn't apply to it. A current-version notification may be
+    /// followed by its client-tag and relay-path records, either of which
+    /// fails the payload if it is malformed.
+    pub fn open(
+        payload: &[u8],
+        keyring: &[InfraKey],
+        conf_key: &[u8; 16],
+        now_ms: Option<u64>,
+    ) -> Result<Self, ParseError> {
+        if payload.len() == SealedNotification::SIZE {
+            let (envelope, notification) =
+                SealedNotification::open_with(payload, keyring, conf_key, now_ms)?;
+            Ok(Self {
+                notification,
+                sealed: Some(envelope),
+                v1: None,
+                client_tags: None,
+                relay_path: None,
+            })
+        } else if payload.first() == Some(&PROTOCOL_VERSION_V1) {
+            let v1 = TransportNotificationV1::from_payload_with(payload, keyring)?;
+            Ok(Self {
+                notification: v1.to_current(),
+                sealed: None,
+                v1: Some(v1),
+                client_tags: None,
+                relay_path: None,
+            })
+        } else {
+            Ok(Self {
+                notification: TransportNotification::from_payload_with(payload, keyring, now_ms)?,
+                sealed: None,
+                v1: None,
+                client_tags: ClientTags::find(payload)?,
+                relay_path: RelayPath::find(payload)?,
+            })
+        }
+    }
+
+    /// Split a manufacturer-data payload into the payloads to `open`: a
+    /// sealed notification whole, otherwise record by record, as
+    /// `TransportNotification::decode_all` walks them.
+    pub fn chunks(payload: &[u8]) -> impl Iterator<Item = &[u8]> {
+        let sealed = payload.len() == SealedNotification::SIZE;
+        let (whole, records) = if sealed {
+            (Some(payload), Records(&[]))
+        } else {
+            (None, Records(payload))
+        };
+        whole.into_iter().chain(records)
+    }
+
+    /// The copy to re-broadcast, one hop fewer, or `None` once the
+    /// notification has none left. A sealed notification carries the hop
+    /// count of what arrived, so that has one to spare too. A v1 packet has
+    /// no hop count and goes out as it came; `notification` still counts
+    /// down, which is what limits how far it is relayed.
+    pub fn next_hop(&self) -> Option<Self> {
+        Some(Self {
+            notification: self.notification.next_hop()?,
+            sealed: match self.sealed {
+                Some(envelope) => Some(envelope.next_hop()?),
+                None => None,
+            },
+            v1: self.v1,
+            client_tags: self.client_tags,
+            relay_path: self.relay_path,
+        })
+    }
+
+    /// Whether what goes on air already carries a client tag.
+    pub fn has_client_tag(&self) -> bool {
+        match (&self.sealed, &self.v1) {
+            (Some(envelope), _) => envelope.has_client_tag(),
+            (None, Some(v1)) => v1.has_client_tag(),
+            (None, None) => self.notification.has_client_tag(),
+        }
+    }
+
+    /// Sign the client tag of what goes on air: the envelope's if sealed,
+    /// over the v1 layout if v1.
+    pub fn sign_client_with(&mut self, key: &[u8]) {
+        match (&mut self.sealed, &mut self.v1) {
+            (Some(envelope), _) => envelope.sign_client_with(key),
+            (None, Some(v1)) => v1.sign_client_with(key),
+            (None, None) => self.notification.sign_client_with(key),
+        }
+    }
+
+    /// Sign the client tags of what goes on air with `keys`: the own tag
+    /// with the first, and with the rest, tags in a client-tag record (see
+    /// `ClientTags::sign`). Sealed and v1 notifications take no record, so
+    /// only the first key signs those.
+    pub fn sign_client_keys(&mut self, keys: &[ClientKey]) {
+        if self.sealed.is_some() || self.v1.is_some() {
+            let (_, key) = keys.first().expect("no client key to sign with");
+            self.sign_client_with(key);
+        } else {
+            self.client_tags = ClientTags::sign(&mut self.notification, keys);
+        }
+    }
+
+    /// The payload to re-broadcast after the company ID, up to its
+    /// extension records; `extensions` follow it on air.
+    pub fn as_bytes(&self) -> &[u8] {
+        match (&self.sealed, &self.v1) {
+            (Some(envelope), _) => envelope.as_bytes(),
+            (None, Some(v1)) => v1.as_bytes(),
+            (None, None) => self.notification.as_bytes(),
+        }
+    }
+
+    /// The extension records aired after `as_bytes`: the client-tag record
+    /// and the relay path, those there are.
+    pub fn extensions(&self) -> impl Iterator<Item = &[u8]> {
+        let tags = self.client_tags.iter().map(ClientTags::as_bytes);
+        tags.chain(self.relay_path.iter().map(RelayPath::as_bytes))
+    }
+
+    /// Add `repeater_id` to the relay path, starting one if the
+    /// notification has none. Sealed and v1 notifications take no record.
+    pub fn record_hop(&mut self, repeater_id: [u8; 4]) {
+        if self.sealed.is_none() && self.v1.is_none() {
+            self.relay_path
+                .get_or_insert_with(RelayPath::new)
+                .push(repeater_id);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Relay-path records: the repeaters a notification went through, for
+//! debugging a multi-hop mesh. Diagnostic only; production repeaters
+//! neither write nor forward them.
+//!
+//! A repeater built with its `relay-path` feature follows a notification it
+//! relays, in the same manufacturer data, with a relay-path record holding
+//! its `repeater_id` after those of the repeaters before it:
+//!
+//!   [0]      RELAY_PATH_RECORD (0xC2), where a version byte would be
+//!   [1]      flags: bits 0–3 how many ids are filled in, at most
+//!            `MAX_RELAY_PATH`; bit 7 set once a hop found the path full;
+//!            the other bits are 0
+//!   [2..34]  `MAX_RELAY_PATH` repeater_ids, first hop first, the unused
+//!            ones zero
+//!
+//! The record is always `RelayPath::SIZE` bytes, so a notification's length
+//! doesn't change from hop to hop, and the bound keeps a loop from growing
+//! it. A hop past the bound only sets the truncated bit.
+//!
+//! Like a client-tag record (see `client_tags`), it is outside both signed
+//! payloads, so appending to it changes no tag and the notification still
+//! verifies at every hop and in every app. The price is that nothing
+//! vouches for the path: anyone in range can rewrite it. Trust it only on a
+//! bench or a test mesh.
+//!
+//! 34 bytes a notification, on top of a client-tag record: extended
+//! advertising only. A repeater without the feature drops the record, so a
+//! path that reaches a monitor lists every hop.
+
+use crate::consts::RELAY_PATH_RECORD;
+use crate::notification::{extensions, ParseError};
+
+/// Most repeater ids a record holds.
+pub const MAX_RELAY_PATH: usize = 8;
+
+/// Record bytes before the ids.
+const HEADER_LEN: usize = 2;
+
+/// Bits of the flags byte holding the id count.
+const COUNT_MASK: u8 = 0x0F;
+
+/// Flag bit: a hop found the path full and couldn't add its id.
+const TRUNCATED: u8 = 0x80;
+
+/// A relay-path record; see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayPath {
+    bytes: [u8; RelayPath::SIZE],
+}
+
+impl Default for RelayPath {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RelayPath {
+    /// Size of every record.
+    pub const SIZE: usize = HEADER_LEN + MAX_RELAY_PATH * 4;
+
+    /// An empty path, for the first repeater to start.
+    pub const fn new() -> Self {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0] = RELAY_PATH_RECORD;
+        Self { bytes }
+    }
+
+    /// Append `repeater_id`, or mark the path truncated if it is full.
+    pub fn push(&mut self, repeater_id: [u8; 4]) {
+        let count = self.len();
+        if count == MAX_RELAY_PATH {
+            self.bytes[1] |= TRUNCATED;
+            return;
+        }
+        let at = HEADER_LEN + count * 4;
+        self.bytes[at..at + 4].copy_from_slice(&repeater_id);
+        self.bytes[1] += 1;
+    }
+
+    /// Parse the record at the start of `bytes`. Trailing bytes are
+    /// ignored. A record with reserved flag bits set, or more ids than
+    /// `MAX_RELAY_PATH`, is of a layout this build doesn't know, and
+    /// reported as `UnsupportedVersion` of its leading byte.
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        let record = bytes.get(..Self::SIZE).ok_or(ParseError::TooShort {
+            got: bytes.len(),
+            need: Self::SIZE,
+        })?;
+        let flags = record[1];
+        if record[0] != RELAY_PATH_RECORD
+            || flags & !(COUNT_MASK | TRUNCATED) != 0
+            || usize::from(flags & COUNT_MASK) > MAX_RELAY_PATH
+        {
+            return Err(ParseError::UnsupportedVersion(record[0]));
+        }
+        let mut path = Self::new();
+        path.bytes.copy_from_slice(record);
+        Ok(path)
+    }
+
+    /// The record among the extension records following the notification
+    /// at the start of `record`, one of `Records`; `None` if it has none.
+    pub fn find(record: &[u8]) -> Result<Option<Self>, ParseError> {
+        extensions(record)
+            .find(|ext| ext.first() == Some(&RELAY_PATH_RECORD))
+            .map(Self::parse)
+            .transpose()
+    }
+
+    /// The record as it goes on air.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The repeater ids, first hop first.
+    pub fn hops(&self) -> impl Iterator<Item = [u8; 4]> + '_ {
+        self.bytes[HEADER_LEN..HEADER_LEN + self.len() * 4]
+            .chunks_exact(4)
+            .map(|id| [id[0], id[1], id[2], id[3]])
+    }
+
+    /// How many repeater ids the path holds.
+    pub fn len(&self) -> usize {
+        usize::from(self.bytes[1] & COUNT_MASK)
+    }
+
+    /// Whether no repeater has added its id yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the notification went through more repeaters than the path
+    /// holds.
+    pub fn is_truncated(&self) -> bool {
+        self.bytes[1] & TRUNCATED != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::TransportNotificationBuilder;
+    use crate::client_tags::ClientTags;
+    use crate::consts::{HMAC_KEY_CLIENT, INFRA_KEY_CURRENT};
+    use crate::notification::{Records, TransportNotification, TransportStatus, TransportType};
+
+    fn broadcast() -> TransportNotification {
+        TransportNotificationBuilder::new()
+            .notification_id([7; 4])
+            .transport(TransportType::Bus)
+            .status(TransportStatus::Coming)
+            .duration_secs(30)
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap()
+    }
+
+    #[test]
+    fn the_path_keeps_the_first_hops_and_says_when_it_is_full() {
+        let mut path = RelayPath::new();
+        assert!(path.is_empty());
+        for hop in 1..=MAX_RELAY_PATH as u8 + 2 {
+            path.push([hop; 4]);
+        }
+        assert_eq!(path.len(), MAX_RELAY_PATH);
+        assert!(path.is_truncated());
+        let hops: Vec<u8> = path.hops().map(|id| id[0]).collect();
+        assert_eq!(hops, (1..=MAX_RELAY_PATH as u8).collect::<Vec<_>>());
+        assert_eq!(RelayPath::parse(path.as_bytes()), Ok(path));
+    }
+
+    #[test]
+    fn records_keep_the_path_with_its_notification() {
+        let mut notif = broadcast();
+        let tags = ClientTags::sign(&mut notif, &[(0, HMAC_KEY_CLIENT), (1, b"next")]).unwrap();
+        let mut path = RelayPath::new();
+        path.push([0xAB; 4]);
+        let payload = [
+            notif.as_bytes(),
+            tags.as_bytes(),
+            path.as_bytes(),
+            broadcast().as_bytes(),
+        ]
+        .concat();
+
+        let records: Vec<&[u8]> = Records(&payload).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(RelayPath::find(records[0]), Ok(Some(path)));
+        assert_eq!(ClientTags::find(records[0]), Ok(Some(tags)));
+        assert_eq!(RelayPath::find(records[1]), Ok(None));
+        // The notification, and its client tag, are untouched by the path.
+        assert!(TransportNotification::from_payload(records[0])
+            .unwrap()
+            .verify_client_with(HMAC_KEY_CLIENT));
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        let path = RelayPath::new();
+        let bytes = path.as_bytes();
+        assert_eq!(
+            RelayPath::parse(&bytes[..RelayPath::SIZE - 1]),
+            Err(ParseError::TooShort {
+                got: RelayPath::SIZE - 1,
+                need: RelayPath::SIZE
+            })
+        );
+        for flags in [MAX_RELAY_PATH as u8 + 1, 0x40] {
+            let mut bad = bytes.to_vec();
+            bad[1] = flags;
+            assert_eq!(
+                RelayPath::parse(&bad),
+                Err(ParseError::UnsupportedVersion(RELAY_PATH_RECORD))
+            );
+        }
+    }
+}
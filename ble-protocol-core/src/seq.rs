@@ -0,0 +1,230 @@
+//! Per-notification sequence tracking against replayed packets.
+//!
+//! Every notification carries a `seq` that its broadcaster increments per
+//! notification (or airing). A repeater remembers the newest `seq` it has
+//! accepted for each `(source_id, notification_id)` and rejects anything not
+//! strictly newer, so a captured packet can't be replayed once the
+//! notification has moved on, a cancellation included.
+//!
+//! The key is per notification rather than per source: a broadcaster airing
+//! several notifications at once stamps them from one counter, and the
+//! copies reach a repeater in any order. Tracked per source, whichever was
+//! stamped first would look like a replay of the other.
+//!
+//! `seq` is a `u32` and wraps. Newness is judged with serial-number
+//! arithmetic (RFC 1982): `a` is newer than `b` when `a - b` (mod 2³²) is in
+//! `1..2³¹`. A source that wraps from `0xFFFF_FFFF` to `0` keeps being
+//! accepted, and only a packet more than 2³¹ behind the newest one looks
+//! "newer" again. That is far outside any replay window a broadcaster
+//! sending a few notifications a minute could produce.
+
+/// Whether `a` is strictly newer than `b` in serial-number arithmetic.
+pub fn seq_newer(a: u32, b: u32) -> bool {
+    let d = a.wrapping_sub(b);
+    d != 0 && d < 0x8000_0000
+}
+
+/// `(source_id, notification_id)`.
+pub type SeqKey = ([u8; 4], [u8; 4]);
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    key: SeqKey,
+    newest: u32,
+    /// Value of `SeqTracker::clock` when this slot was last accepted into.
+    used_at: u32,
+    /// Caller's stamp for `newest` (see `SeqTracker::accept_stamped`).
+    stamp: u64,
+}
+
+/// Newest accepted `seq` per notification, for up to `N` notifications.
+///
+/// When all `N` slots are taken, a new notification replaces the one that
+/// has gone longest without an accepted packet. A forgotten notification is
+/// treated as new again, so `N` should comfortably exceed the number of
+/// notifications in range at once.
+#[derive(Debug, Clone)]
+pub struct SeqTracker<const N: usize> {
+    slots: [Option<Slot>; N],
+    clock: u32,
+}
+
+impl<const N: usize> Default for SeqTracker<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SeqTracker<N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: [None; N],
+            clock: 0,
+        }
+    }
+
+    /// Accept `seq` for `key` if it is newer than anything accepted for that
+    /// notification before, remembering it. Returns `false` for a replay or
+    /// stale copy.
+    pub fn accept(&mut self, key: SeqKey, seq: u32) -> bool {
+        self.accept_stamped(key, seq, 0)
+    }
+
+    /// `accept`, keeping `stamp` with `seq` if it is taken: e.g. the
+    /// packet's `timestamp_ms`, so a saved tracker can later be pruned of
+    /// entries whose packets are too old to replay anyway.
+    pub fn accept_stamped(&mut self, key: SeqKey, seq: u32, stamp: u64) -> bool {
+        self.clock = self.clock.wrapping_add(1);
+        let clock = self.clock;
+
+        if let Some(slot) = self.slots.iter_mut().flatten().find(|s| s.key == key) {
+            if !seq_newer(seq, slot.newest) {
+                return false;
+            }
+            slot.newest = seq;
+            slot.used_at = clock;
+            slot.stamp = stamp;
+            return true;
+        }
+
+        let new = Slot {
+            key,
+            newest: seq,
+            used_at: clock,
+            stamp,
+        };
+        if let Some(free) = self.slots.iter_mut().find(|s| s.is_none()) {
+            *free = Some(new);
+        } else if let Some(oldest) = self
+            .slots
+            .iter_mut()
+            .max_by_key(|s| s.map_or(0, |s| clock.wrapping_sub(s.used_at)))
+        {
+            *oldest = Some(new);
+        }
+        true
+    }
+
+    /// Newest accepted `seq` for `key`, if it is being tracked.
+    pub fn newest(&self, key: SeqKey) -> Option<u32> {
+        self.slots
+            .iter()
+            .flatten()
+            .find(|s| s.key == key)
+            .map(|s| s.newest)
+    }
+
+    /// Every tracked notification with its newest `seq` and that `seq`'s
+    /// stamp, least recently accepted first: accepting them in this order
+    /// into an empty tracker rebuilds this one, eviction order included.
+    pub fn entries(&self) -> impl Iterator<Item = (SeqKey, u32, u64)> + '_ {
+        let mut slots: [Option<&Slot>; N] = [None; N];
+        for (to, from) in slots.iter_mut().zip(&self.slots) {
+            *to = from.as_ref();
+        }
+        slots.sort_unstable_by_key(|s| s.map(|s| self.clock.wrapping_sub(s.used_at)));
+        slots
+            .into_iter()
+            .rev()
+            .flatten()
+            .map(|s| (s.key, s.newest, s.stamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: SeqKey = ([0xA1, 0xA2, 0xA3, 0xA4], [1; 4]);
+    const B: SeqKey = ([0xB1, 0xB2, 0xB3, 0xB4], [2; 4]);
+
+    #[test]
+    fn increasing_seq_is_accepted_and_repeats_are_not() {
+        let mut t = SeqTracker::<4>::new();
+        assert!(t.accept(A, 10));
+        assert!(t.accept(A, 11));
+        assert!(!t.accept(A, 11), "equal seq is a replay");
+        assert!(!t.accept(A, 5), "lower seq is a replay");
+        assert!(t.accept(A, 40), "gaps are fine");
+        assert_eq!(t.newest(A), Some(40));
+    }
+
+    #[test]
+    fn notifications_from_one_source_are_tracked_independently() {
+        // One counter stamps both; the later-stamped copy is heard first.
+        let second = (A.0, [2; 4]);
+        let mut t = SeqTracker::<4>::new();
+        assert!(t.accept(second, 6));
+        assert!(t.accept(A, 5));
+        assert!(!t.accept(A, 5));
+        assert!(!t.accept(second, 4));
+    }
+
+    #[test]
+    fn sources_are_tracked_independently() {
+        let mut t = SeqTracker::<4>::new();
+        assert!(t.accept(A, 100));
+        assert!(t.accept(B, 1));
+        assert!(!t.accept(A, 1));
+        assert!(!t.accept(B, 1));
+    }
+
+    #[test]
+    fn wraparound_does_not_lock_out_a_source() {
+        let mut t = SeqTracker::<4>::new();
+        assert!(t.accept(A, u32::MAX - 1));
+        assert!(t.accept(A, u32::MAX));
+        assert!(t.accept(A, 0));
+        assert!(t.accept(A, 1));
+        assert!(
+            !t.accept(A, u32::MAX),
+            "pre-wrap packet replayed after wrap"
+        );
+    }
+
+    #[test]
+    fn serial_comparison_window() {
+        assert!(seq_newer(1, 0));
+        assert!(seq_newer(0, u32::MAX));
+        assert!(seq_newer(0x7FFF_FFFF, 0));
+        assert!(!seq_newer(0x8000_0000, 0));
+        assert!(!seq_newer(7, 7));
+    }
+
+    #[test]
+    fn full_tracker_evicts_the_least_recently_accepted_source() {
+        let mut t = SeqTracker::<2>::new();
+        assert!(t.accept(A, 5));
+        assert!(t.accept(B, 5));
+        assert!(t.accept(A, 6)); // A is now the most recent
+        let c = ([0xC1, 0xC2, 0xC3, 0xC4], [3; 4]);
+        assert!(t.accept(c, 1));
+        assert_eq!(t.newest(B), None);
+        assert_eq!(t.newest(A), Some(6));
+        assert_eq!(t.newest(c), Some(1));
+    }
+
+    #[test]
+    fn entries_rebuild_the_tracker() {
+        let c = ([0xC1, 0xC2, 0xC3, 0xC4], [3; 4]);
+        let mut t = SeqTracker::<2>::new();
+        t.accept_stamped(A, 5, 1_000);
+        t.accept_stamped(B, 9, 2_000);
+        t.accept_stamped(A, 6, 3_000);
+        assert_eq!(
+            t.entries().collect::<Vec<_>>(),
+            [(B, 9, 2_000), (A, 6, 3_000)]
+        );
+
+        let mut rebuilt = SeqTracker::<2>::new();
+        for (key, seq, stamp) in t.entries() {
+            rebuilt.accept_stamped(key, seq, stamp);
+        }
+        assert!(!rebuilt.accept(A, 6));
+        assert!(!rebuilt.accept(B, 9));
+        // B is still the one evicted first.
+        assert!(rebuilt.accept(c, 1));
+        assert_eq!(rebuilt.newest(B), None);
+        assert_eq!(rebuilt.newest(A), Some(6));
+    }
+}
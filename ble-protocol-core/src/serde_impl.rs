@@ -0,0 +1,242 @@
+//! Readable serde form of `TransportNotification` (`serde` feature).
+//!
+//! The nibble-packed bytes are split into `event_id`/`destination_id` and
+//! `transport_type`/`transport_status`, integers are decoded from their
+//! little-endian byte arrays, and ids and tags are lowercase hex strings:
+//!
+//! ```text
+//! {"version":8,"source_id":"01020304","notification_id":"05060708",
+//!  "event_id":15,"destination_id":0,"transport_type":"Bus",
+//!  "transport_status":"Late","duration_secs":30,"validity_secs":600,
+//!  "flags":0,"seq":7,"key_id":0,"timestamp_ms":1767225600000,
+//!  "priority":0,"hmac_tag_infra":"…","hmac_tag_client":"00000000",
+//!  "hops_remaining":3,"crc16":"…"}
+//! ```
+//!
+//! Deserializing rebuilds the wire struct as-is: tags are not checked, so run
+//! the result through `verify_infra` before trusting it.
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::consts::{HMAC_TAG_CLIENT_LEN, HMAC_TAG_INFRA_LEN};
+use crate::notification::{TransportNotification, TransportStatus, TransportType};
+
+#[derive(Serialize, Deserialize)]
+struct Readable {
+    version: u8,
+    #[serde(with = "hex")]
+    source_id: [u8; 4],
+    #[serde(with = "hex")]
+    notification_id: [u8; 4],
+    event_id: u8,
+    destination_id: u8,
+    transport_type: TransportType,
+    transport_status: TransportStatus,
+    duration_secs: u16,
+    validity_secs: u16,
+    flags: u8,
+    seq: u32,
+    key_id: u8,
+    timestamp_ms: u64,
+    priority: u8,
+    #[serde(with = "hex")]
+    hmac_tag_infra: [u8; HMAC_TAG_INFRA_LEN],
+    #[serde(with = "hex")]
+    hmac_tag_client: [u8; HMAC_TAG_CLIENT_LEN],
+    hops_remaining: u8,
+    #[serde(with = "hex")]
+    crc16: [u8; 2],
+}
+
+impl Serialize for TransportNotification {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Invalid nibbles only exist in notifications that never went
+        // through `from_payload`; refuse rather than invent a variant.
+        let transport_type = self
+            .transport_type()
+            .ok_or_else(|| serde::ser::Error::custom("invalid transport_type nibble"))?;
+        let transport_status = self
+            .transport_status()
+            .ok_or_else(|| serde::ser::Error::custom("invalid transport_status nibble"))?;
+        Readable {
+            version: self.version,
+            source_id: self.source_id,
+            notification_id: self.notification_id,
+            event_id: self.event_id(),
+            destination_id: self.destination_id(),
+            transport_type,
+            transport_status,
+            duration_secs: self.duration_secs(),
+            validity_secs: self.validity_secs(),
+            flags: self.flags,
+            seq: self.seq(),
+            key_id: self.key_id,
+            timestamp_ms: self.timestamp_ms(),
+            priority: self.priority,
+            hmac_tag_infra: self.hmac_tag_infra,
+            hmac_tag_client: self.hmac_tag_client,
+            hops_remaining: self.hops_remaining,
+            crc16: self.crc16,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TransportNotification {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let r = Readable::deserialize(deserializer)?;
+        if r.event_id > 0x0F || r.destination_id > 0x0F {
+            return Err(D::Error::custom(
+                "event_id and destination_id must fit in a nibble",
+            ));
+        }
+        if r.timestamp_ms >> 48 != 0 {
+            return Err(D::Error::custom("timestamp_ms must fit in 48 bits"));
+        }
+        Ok(TransportNotification {
+            version: r.version,
+            source_id: r.source_id,
+            notification_id: r.notification_id,
+            event_dest: (r.event_id << 4) | r.destination_id,
+            type_status: ((r.transport_type as u8) << 4) | r.transport_status as u8,
+            duration_secs: r.duration_secs.to_le_bytes(),
+            validity_secs: r.validity_secs.to_le_bytes(),
+            flags: r.flags,
+            seq: r.seq.to_le_bytes(),
+            key_id: r.key_id,
+            timestamp_ms: TransportNotification::timestamp_bytes(r.timestamp_ms),
+            priority: r.priority,
+            hmac_tag_infra: r.hmac_tag_infra,
+            hmac_tag_client: r.hmac_tag_client,
+            hops_remaining: r.hops_remaining,
+            crc16: r.crc16,
+        })
+    }
+}
+
+/// Fixed-size byte arrays as lowercase hex strings, without allocating.
+mod hex {
+    use core::fmt;
+
+    use serde::de::{self, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        // Longest array serialized this way is a tag, at most a whole
+        // HMAC-SHA256 (see `HMAC_TAG_INFRA_LEN`).
+        let mut buf = [0u8; 64];
+        let out = &mut buf[..2 * N];
+        for (pair, byte) in out.chunks_exact_mut(2).zip(bytes) {
+            pair[0] = DIGITS[usize::from(byte >> 4)];
+            pair[1] = DIGITS[usize::from(byte & 0x0F)];
+        }
+        serializer.serialize_str(core::str::from_utf8(out).expect("hex digits are ASCII"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        deserializer.deserialize_str(HexVisitor::<N>)
+    }
+
+    struct HexVisitor<const N: usize>;
+
+    impl<const N: usize> Visitor<'_> for HexVisitor<N> {
+        type Value = [u8; N];
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "a hex string of {} bytes", N)
+        }
+
+        fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
+            if s.len() != 2 * N {
+                return Err(E::invalid_length(s.len(), &self));
+            }
+            let mut out = [0u8; N];
+            for (byte, pair) in out.iter_mut().zip(s.as_bytes().chunks_exact(2)) {
+                let nibble = |c: u8| {
+                    (c as char)
+                        .to_digit(16)
+                        .ok_or_else(|| E::invalid_value(de::Unexpected::Str(s), &self))
+                };
+                *byte = (nibble(pair[0])? << 4 | nibble(pair[1])?) as u8;
+            }
+            Ok(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::consts::{HMAC_TAG_CLIENT_LEN, HMAC_TAG_INFRA_LEN, PROTOCOL_VERSION};
+    use crate::notification::{TransportNotification, TransportStatus, TransportType};
+
+    fn sample() -> TransportNotification {
+        let mut notif = TransportNotification {
+            version: PROTOCOL_VERSION,
+            source_id: [0x01, 0x02, 0x03, 0x04],
+            notification_id: [0xA5, 0x06, 0x07, 0xFF],
+            event_dest: 0xF0,
+            type_status: ((TransportType::Bus as u8) << 4) | TransportStatus::Late as u8,
+            duration_secs: 30u16.to_le_bytes(),
+            validity_secs: 600u16.to_le_bytes(),
+            flags: 0,
+            seq: 7u32.to_le_bytes(),
+            key_id: 0,
+            timestamp_ms: TransportNotification::timestamp_bytes(1_767_225_600_000),
+            priority: 0,
+            hmac_tag_infra: [0; HMAC_TAG_INFRA_LEN],
+            hmac_tag_client: [0; HMAC_TAG_CLIENT_LEN],
+            hops_remaining: 3,
+            crc16: [0; 2],
+        };
+        notif.sign_infra();
+        notif
+    }
+
+    #[test]
+    fn json_uses_readable_keys() {
+        let json = serde_json::to_value(sample()).unwrap();
+        assert_eq!(json["version"], PROTOCOL_VERSION);
+        assert_eq!(json["source_id"], "01020304");
+        assert_eq!(json["notification_id"], "a50607ff");
+        assert_eq!(json["event_id"], 15);
+        assert_eq!(json["destination_id"], 0);
+        assert_eq!(json["transport_type"], "Bus");
+        assert_eq!(json["transport_status"], "Late");
+        assert_eq!(json["seq"], 7);
+        assert_eq!(json["timestamp_ms"], 1_767_225_600_000u64);
+        assert_eq!(json["hmac_tag_client"], "00".repeat(HMAC_TAG_CLIENT_LEN));
+        assert!(json.get("event_dest").is_none() && json.get("type_status").is_none());
+    }
+
+    #[test]
+    fn json_round_trip_preserves_the_wire_bytes() {
+        let notif = sample();
+        let json = serde_json::to_string(&notif).unwrap();
+        let back: TransportNotification = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.as_bytes(), notif.as_bytes());
+        assert!(back.verify_infra());
+    }
+
+    #[test]
+    fn out_of_range_fields_are_rejected() {
+        let mut json = serde_json::to_value(sample()).unwrap();
+        json["event_id"] = 16.into();
+        assert!(serde_json::from_value::<TransportNotification>(json).is_err());
+
+        let mut json = serde_json::to_value(sample()).unwrap();
+        json["source_id"] = "0102030".into();
+        assert!(serde_json::from_value::<TransportNotification>(json).is_err());
+
+        let mut json = serde_json::to_value(sample()).unwrap();
+        json["transport_type"] = "Tram".into();
+        assert!(serde_json::from_value::<TransportNotification>(json).is_err());
+    }
+}
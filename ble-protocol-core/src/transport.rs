@@ -0,0 +1,226 @@
+//! Carrying manufacturer data between devices over something other than a
+//! particular radio, so the relay path can run anywhere.
+//!
+//! A `Transport` hands over what it has heard since it was last asked and
+//! puts data on the air, or whatever stands in for it. The broadcaster
+//! implements it over BlueZ for `--relay`; `UdpTransport` carries the same
+//! data in UDP datagrams, for host testbeds and CI. `Relay` is a repeater
+//! over any of them: the verify, replay, hop and client-tag steps of
+//! `relay::Received`, without the ESP32 repeater's radio policy (RSSI
+//! thresholds, block lists, airtime scheduling).
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use crate::conf::CONF_KEY;
+use crate::consts::InfraKey;
+use crate::notification::TransportNotification;
+use crate::relay::Received;
+use crate::seq::SeqTracker;
+
+/// One advertisement heard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanRecord {
+    /// The sender's address.
+    pub addr: [u8; 6],
+    pub rssi: i8,
+    /// The manufacturer data: the company ID, little-endian, then the
+    /// payload.
+    pub data: Vec<u8>,
+}
+
+impl ScanRecord {
+    /// The company ID `data` starts with, or `None` if it is too short to.
+    pub fn company_id(&self) -> Option<u16> {
+        self.data
+            .get(..2)
+            .map(|id| u16::from_le_bytes([id[0], id[1]]))
+    }
+
+    /// `data` after the company ID.
+    pub fn payload(&self) -> &[u8] {
+        self.data.get(2..).unwrap_or(&[])
+    }
+}
+
+/// Something manufacturer data can be heard on and sent over.
+pub trait Transport {
+    /// Everything heard since the last call. May wait a while for the first
+    /// record, as a scan window does.
+    fn receive(&mut self) -> Vec<ScanRecord>;
+
+    /// Put `data`, manufacturer data as in `ScanRecord::data`, on the air.
+    /// Best effort, as on a radio: nothing says whether anyone heard it.
+    fn broadcast(&mut self, data: &[u8]);
+}
+
+/// Manufacturer data in UDP datagrams, each the sender's 6-byte address
+/// then the data. Sent to a fixed list of peers; there is no RSSI on a
+/// socket, so every record is reported at `rssi`.
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+    addr: [u8; 6],
+    peers: Vec<SocketAddr>,
+    rssi: i8,
+}
+
+impl UdpTransport {
+    /// Bind to `local`, sending as `addr`. `receive` waits up to `wait` for
+    /// the first datagram.
+    pub fn bind(local: impl ToSocketAddrs, addr: [u8; 6], wait: Duration) -> io::Result<Self> {
+        let socket = UdpSocket::bind(local)?;
+        socket.set_read_timeout(Some(wait))?;
+        Ok(Self {
+            socket,
+            addr,
+            peers: Vec::new(),
+            rssi: -50,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Send to `peer` too.
+    pub fn add_peer(&mut self, peer: SocketAddr) {
+        self.peers.push(peer);
+    }
+
+    /// The RSSI records are reported at.
+    pub fn set_rssi(&mut self, rssi: i8) {
+        self.rssi = rssi;
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> Option<ScanRecord> {
+        let (len, _) = self.socket.recv_from(buf).ok()?;
+        let (addr, data) = buf[..len].split_first_chunk::<6>()?;
+        Some(ScanRecord {
+            addr: *addr,
+            rssi: self.rssi,
+            data: data.to_vec(),
+        })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn receive(&mut self) -> Vec<ScanRecord> {
+        let mut buf = [0u8; 256];
+        let mut heard = Vec::new();
+        // Wait for the first datagram, then take whatever else is queued.
+        heard.extend(self.recv(&mut buf));
+        if self.socket.set_nonblocking(true).is_ok() {
+            while let Some(record) = self.recv(&mut buf) {
+                heard.push(record);
+            }
+            let _ = self.socket.set_nonblocking(false);
+        }
+        heard
+    }
+
+    fn broadcast(&mut self, data: &[u8]) {
+        let mut datagram = self.addr.to_vec();
+        datagram.extend_from_slice(data);
+        for peer in &self.peers {
+            let _ = self.socket.send_to(&datagram, peer);
+        }
+    }
+}
+
+/// Notifications whose newest `seq` a `Relay` remembers.
+const RELAY_TRACKED_NOTIFICATIONS: usize = 64;
+
+/// A repeater over a `Transport`; see the module docs. Every notification
+/// heard under its company ID that verifies, has a newer `seq` than any
+/// copy relayed before and has a hop left is re-broadcast at once, one hop
+/// fewer, with the client tag signed if nobody has yet. A copy with a `seq`
+/// already relayed is a duplicate, and dropped. v1 packets have no `seq`,
+/// so each is relayed once.
+pub struct Relay<'k> {
+    keyring: &'k [InfraKey],
+    client_key: &'k [u8],
+    company_id: u16,
+    seen: SeqTracker<RELAY_TRACKED_NOTIFICATIONS>,
+}
+
+impl<'k> Relay<'k> {
+    pub fn new(keyring: &'k [InfraKey], client_key: &'k [u8], company_id: u16) -> Self {
+        Self {
+            keyring,
+            client_key,
+            company_id,
+            seen: SeqTracker::new(),
+        }
+    }
+
+    /// Receive once from `transport` and re-broadcast what is to be
+    /// relayed, checking timestamps against `now_ms` as
+    /// `TransportNotification::from_payload_with` does. Returns the
+    /// notifications relayed.
+    pub fn step(
+        &mut self,
+        transport: &mut dyn Transport,
+        now_ms: Option<u64>,
+    ) -> Vec<TransportNotification> {
+        let mut relayed = Vec::new();
+        for record in transport.receive() {
+            if record.company_id() != Some(self.company_id) {
+                continue;
+            }
+            for chunk in Received::chunks(record.payload()) {
+                let Ok(heard) = Received::open(chunk, self.keyring, CONF_KEY, now_ms) else {
+                    continue;
+                };
+                let notif = heard.notification;
+                let key = ({ notif.source_id }, { notif.notification_id });
+                if !self.seen.accept(key, notif.seq()) {
+                    continue;
+                }
+                let Some(mut copy) = heard.next_hop() else {
+                    continue;
+                };
+                if !copy.has_client_tag() {
+                    copy.sign_client_with(self.client_key);
+                }
+                let mut data = self.company_id.to_le_bytes().to_vec();
+                data.extend_from_slice(copy.as_bytes());
+                copy.extensions()
+                    .for_each(|ext| data.extend_from_slice(ext));
+                transport.broadcast(&data);
+                relayed.push(notif);
+            }
+        }
+        relayed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bind(addr: u8) -> UdpTransport {
+        UdpTransport::bind("127.0.0.1:0", [addr; 6], Duration::from_millis(200)).unwrap()
+    }
+
+    #[test]
+    fn udp_carries_the_sender_and_its_data() {
+        let mut a = bind(0xA1);
+        let mut b = bind(0xB2);
+        b.set_rssi(-70);
+        a.add_peer(b.local_addr().unwrap());
+
+        a.broadcast(&[0xFF, 0xFF, 1, 2, 3]);
+        a.broadcast(&[0x34, 0x12]);
+        let heard = b.receive();
+        assert_eq!(heard.len(), 2);
+        assert_eq!((heard[0].addr, heard[0].rssi), ([0xA1; 6], -70));
+        assert_eq!(heard[0].company_id(), Some(0xFFFF));
+        assert_eq!(heard[0].payload(), [1, 2, 3]);
+        assert_eq!(heard[1].company_id(), Some(0x1234));
+        assert!(heard[1].payload().is_empty());
+
+        assert!(b.receive().is_empty(), "nothing more queued");
+    }
+}
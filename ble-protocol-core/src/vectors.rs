@@ -0,0 +1,103 @@
+//! Known-answer vectors for the wire format.
+//!
+//! The expected bytes were computed independently of this crate (Python's
+//! `hmac`/`hashlib` and a CRC-16/CCITT-FALSE by hand), from the development
+//! keys in `consts`. If one of these tests fails, a change has altered the
+//! bytes on air, and deployed repeaters and clients would stop
+//! interoperating with the new build. Only update the vectors together with
+//! a `PROTOCOL_VERSION` bump.
+
+use crate::consts::{HMAC_KEY_CLIENT, HMAC_KEY_INFRA, INFRA_KEY_CURRENT, PROTOCOL_VERSION};
+use crate::crypto::{compute_client_tag, compute_infra_tag, compute_tag};
+use crate::notification::{EventId, TransportNotification, TransportStatus, TransportType};
+
+/// The notification every vector is about, field by field: Train / Late,
+/// event Delay to destination 5, on air 120 s, valid 900 s, seq 42, key 0,
+/// stamped 2026-01-01T00:00:00Z, priority 5, 3 hops left.
+fn vector() -> TransportNotification {
+    TransportNotification {
+        version: PROTOCOL_VERSION,
+        source_id: [0xA1, 0xB2, 0xC3, 0xD4],
+        notification_id: [0x12, 0x34, 0x56, 0x78],
+        event_dest: 0x35,
+        type_status: 0x23,
+        duration_secs: 120u16.to_le_bytes(),
+        validity_secs: 900u16.to_le_bytes(),
+        flags: 0,
+        seq: 42u32.to_le_bytes(),
+        key_id: 0,
+        timestamp_ms: TransportNotification::timestamp_bytes(1_767_225_600_000),
+        priority: 5,
+        hmac_tag_infra: [0; 8],
+        hmac_tag_client: [0; 4],
+        hops_remaining: 3,
+        crc16: [0; 2],
+    }
+}
+
+/// `base_payload` of `vector()`: what both tags cover.
+const BASE_PAYLOAD: [u8; 28] = [
+    0x08, 0xa1, 0xb2, 0xc3, 0xd4, 0x12, 0x34, 0x56, //
+    0x78, 0x35, 0x23, 0x78, 0x00, 0x84, 0x03, 0x00, //
+    0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0xa8, 0xda, //
+    0x76, 0x9b, 0x01, 0x05,
+];
+
+/// Infra tag of `BASE_PAYLOAD` under `HMAC_KEY_INFRA`.
+const INFRA_TAG: [u8; 8] = [0x35, 0x21, 0x09, 0x2e, 0x4f, 0xf3, 0x1a, 0x55];
+
+/// Client tag of `BASE_PAYLOAD` under `HMAC_KEY_CLIENT`.
+const CLIENT_TAG: [u8; 4] = [0x66, 0x57, 0xd0, 0x38];
+
+/// `vector()` signed with both tags, as a client hears it from the first
+/// repeater before the hop count is decremented.
+const PAYLOAD: [u8; 43] = [
+    0x08, 0xa1, 0xb2, 0xc3, 0xd4, 0x12, 0x34, 0x56, //
+    0x78, 0x35, 0x23, 0x78, 0x00, 0x84, 0x03, 0x00, //
+    0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0xa8, 0xda, //
+    0x76, 0x9b, 0x01, 0x05, 0x35, 0x21, 0x09, 0x2e, //
+    0x4f, 0xf3, 0x1a, 0x55, 0x66, 0x57, 0xd0, 0x38, //
+    0x03, 0x06, 0x9a,
+];
+
+#[test]
+fn base_payload_matches_the_vector() {
+    assert_eq!(
+        PROTOCOL_VERSION, 8,
+        "new protocol version: update the vectors"
+    );
+    assert_eq!(vector().base_payload(), BASE_PAYLOAD);
+}
+
+#[test]
+fn tags_match_the_vector() {
+    assert_eq!(compute_tag::<8>(HMAC_KEY_INFRA, &BASE_PAYLOAD), INFRA_TAG);
+    assert_eq!(compute_infra_tag(HMAC_KEY_INFRA, &BASE_PAYLOAD), INFRA_TAG);
+    assert_eq!(compute_tag::<4>(HMAC_KEY_CLIENT, &BASE_PAYLOAD), CLIENT_TAG);
+    assert_eq!(
+        compute_client_tag(HMAC_KEY_CLIENT, &BASE_PAYLOAD),
+        CLIENT_TAG
+    );
+}
+
+#[test]
+fn signed_notification_matches_the_vector() {
+    let mut notif = vector();
+    notif.sign_infra_with(INFRA_KEY_CURRENT);
+    notif.sign_client();
+    assert_eq!(notif.as_bytes(), PAYLOAD);
+}
+
+#[test]
+fn vector_payload_parses_to_its_fields() {
+    let notif = TransportNotification::from_payload(&PAYLOAD).unwrap();
+    assert!(notif.verify_client());
+    assert_eq!(notif.event(), EventId::Delay);
+    assert_eq!(notif.destination_id(), 5);
+    assert_eq!(notif.transport_type(), Some(TransportType::Train));
+    assert_eq!(notif.transport_status(), Some(TransportStatus::Late));
+    assert_eq!((notif.duration_secs(), notif.validity_secs()), (120, 900));
+    assert_eq!(notif.seq(), 42);
+    assert_eq!(notif.timestamp_ms(), 1_767_225_600_000);
+    assert_eq!((notif.priority, notif.hops_remaining), (5, 3));
+}
@@ -0,0 +1,57 @@
+//! White-box tests over `internals`, which only exists with the
+//! `test-internals` feature.
+
+use ble_protocol_core::internals::{check_fields, parse_current};
+use ble_protocol_core::{
+    ParseError, TransportNotification, TransportNotificationBuilder, TransportNotificationV1,
+    TransportStatus, TransportType, INFRA_KEYRING, INFRA_KEY_CURRENT, MAX_DURATION_SECS,
+};
+
+fn notification() -> TransportNotification {
+    TransportNotificationBuilder::new()
+        .notification_id([0x12, 0x34, 0x56, 0x78])
+        .transport(TransportType::Train)
+        .status(TransportStatus::Coming)
+        .duration_secs(60)
+        .seq(7)
+        .build_signed(INFRA_KEY_CURRENT)
+        .unwrap()
+}
+
+#[test]
+fn field_checks_catch_what_the_builder_never_produces() {
+    let notif = notification();
+    assert_eq!(check_fields(&notif), Ok(()));
+
+    let mut bad = notif;
+    bad.type_status = 0x71;
+    assert_eq!(check_fields(&bad), Err(ParseError::BadTransportType(7)));
+    let mut long = notif;
+    long.duration_secs = (MAX_DURATION_SECS + 1).to_le_bytes();
+    assert_eq!(
+        check_fields(&long),
+        Err(ParseError::DurationTooLong(MAX_DURATION_SECS + 1))
+    );
+}
+
+#[test]
+fn current_layout_parse_does_not_fall_back_to_v1() {
+    let notif = notification();
+    assert!(parse_current(notif.as_bytes(), INFRA_KEYRING, None).is_ok());
+
+    let v1 = TransportNotificationV1::downgrade(&notif, INFRA_KEY_CURRENT);
+    assert!(TransportNotification::from_payload(v1.as_bytes()).is_ok());
+    assert_eq!(
+        parse_current(v1.as_bytes(), INFRA_KEYRING, None).unwrap_err(),
+        ParseError::TooShort {
+            got: TransportNotificationV1::SIZE,
+            need: TransportNotification::SIZE
+        }
+    );
+    let mut padded = v1.as_bytes().to_vec();
+    padded.resize(TransportNotification::SIZE, 0);
+    assert_eq!(
+        parse_current(&padded, INFRA_KEYRING, None).unwrap_err(),
+        ParseError::UnsupportedVersion(1)
+    );
+}
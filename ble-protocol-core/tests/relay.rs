@@ -0,0 +1,181 @@
+//! Broadcaster → repeater → client, on the host.
+//!
+//! Each test builds a notification the way the broadcaster does (the
+//! builder, signed with the current infra key, and sealed for `--encrypt`),
+//! relays the bytes through `relay::Received` as the repeater does, and then
+//! opens what the repeater would air as a client would.
+
+use ble_protocol_core::conf::{SealedNotification, CONF_KEY};
+use ble_protocol_core::relay::Received;
+use ble_protocol_core::seq::SeqTracker;
+use ble_protocol_core::{
+    EventId, KeyProvider, StaticKeys, TransportNotification, TransportNotificationBuilder,
+    TransportNotificationV1, TransportStatus, TransportType, DEFAULT_HOPS, PROTOCOL_VERSION_V1,
+};
+
+const NOW_MS: u64 = 1_767_225_600_000;
+
+/// What `NotificationSpec::pack` and `Signer::sign` produce in the
+/// broadcaster.
+fn broadcast() -> TransportNotification {
+    builder()
+        .build_signed(StaticKeys.current_infra_key())
+        .unwrap()
+}
+
+fn builder() -> TransportNotificationBuilder {
+    TransportNotificationBuilder::new()
+        .source_id([0xA1, 0xB2, 0xC3, 0xD4])
+        .notification_id([0x12, 0x34, 0x56, 0x78])
+        .event(EventId::Delay)
+        .destination(5)
+        .transport(TransportType::Train)
+        .status(TransportStatus::Late)
+        .duration_secs(120)
+        .validity_secs(900)
+        .seq(42)
+        .timestamp_ms(NOW_MS)
+}
+
+/// The first repeater's part: verify, take a hop off, sign the client tag.
+fn relay(payload: &[u8]) -> Vec<u8> {
+    let keys = StaticKeys;
+    let heard = Received::open(payload, keys.infra_keyring(), CONF_KEY, Some(NOW_MS)).unwrap();
+    assert!(!heard.has_client_tag(), "the broadcaster doesn't sign it");
+    let mut relayed = heard.next_hop().unwrap();
+    relayed.sign_client_with(keys.client_key());
+    relayed.as_bytes().to_vec()
+}
+
+#[test]
+fn plain_notification_reaches_the_client_signed() {
+    let sent = broadcast();
+    let aired = relay(sent.as_bytes());
+
+    let got =
+        TransportNotification::from_payload_with(&aired, StaticKeys.infra_keyring(), None).unwrap();
+    assert!(got.verify_client_with(StaticKeys.client_key()));
+    assert_eq!({ got.hmac_tag_infra }, { sent.hmac_tag_infra });
+    assert_eq!(got.hops_remaining, DEFAULT_HOPS - 1);
+    assert_eq!(got.base_payload(), sent.base_payload());
+}
+
+#[test]
+fn sealed_notification_reaches_the_client_signed() {
+    let sent = SealedNotification::seal(
+        &broadcast(),
+        StaticKeys.current_infra_key(),
+        CONF_KEY,
+        [7; 12],
+    );
+    let aired = relay(sent.as_bytes());
+
+    let (got, plain) =
+        SealedNotification::open_with(&aired, StaticKeys.infra_keyring(), CONF_KEY, None).unwrap();
+    assert!(got.verify_client_with(StaticKeys.client_key()));
+    assert_eq!({ got.hmac_tag_infra }, { sent.hmac_tag_infra });
+    assert_eq!(got.hops_remaining, DEFAULT_HOPS - 1);
+    assert_eq!(plain.base_payload(), broadcast().base_payload());
+}
+
+#[test]
+fn last_hop_is_not_relayed() {
+    let sent = builder()
+        .hops_remaining(0)
+        .build_signed(StaticKeys.current_infra_key())
+        .unwrap();
+    let heard =
+        Received::open(sent.as_bytes(), StaticKeys.infra_keyring(), CONF_KEY, None).unwrap();
+    assert!(heard.next_hop().is_none());
+}
+
+#[test]
+fn packed_notifications_are_relayed_one_by_one() {
+    let first = broadcast();
+    let second = builder()
+        .notification_id([0x9A; 4])
+        .build_signed(StaticKeys.current_infra_key())
+        .unwrap();
+    let packed = [first.as_bytes(), second.as_bytes()].concat();
+
+    let aired: Vec<_> = Received::chunks(&packed).map(relay).collect();
+    assert_eq!(aired.len(), 2);
+    for (aired, sent) in aired.iter().zip([first, second]) {
+        let got = TransportNotification::from_payload_with(aired, StaticKeys.infra_keyring(), None)
+            .unwrap();
+        assert!(got.verify_client_with(StaticKeys.client_key()));
+        assert_eq!(got.base_payload(), sent.base_payload());
+    }
+}
+
+#[test]
+fn notifications_aired_together_pass_the_replay_check_in_any_order() {
+    // The broadcaster restamps every airing from one counter, so two live
+    // notifications alternate seqs; a repeater hears them in any order.
+    let keys = StaticKeys;
+    let airing = |nid: u8, seq: u32| {
+        builder()
+            .notification_id([nid; 4])
+            .seq(seq)
+            .build_signed(keys.current_infra_key())
+            .unwrap()
+    };
+    let heard = [airing(2, 6), airing(1, 5), airing(1, 7), airing(2, 8)];
+
+    let mut seen = SeqTracker::<8>::new();
+    for sent in heard {
+        let got = Received::open(
+            sent.as_bytes(),
+            keys.infra_keyring(),
+            CONF_KEY,
+            Some(NOW_MS),
+        )
+        .unwrap()
+        .notification;
+        let key = ({ got.source_id }, { got.notification_id });
+        assert!(
+            seen.accept(key, got.seq()),
+            "seq {} taken for a replay",
+            got.seq()
+        );
+    }
+    // An earlier airing heard late is still a replay.
+    assert!(!seen.accept(([0xA1, 0xB2, 0xC3, 0xD4], [1; 4]), 5));
+}
+
+#[test]
+fn v1_notification_is_relayed_as_v1() {
+    let sent = TransportNotificationV1::downgrade(&broadcast(), StaticKeys.current_infra_key());
+    let aired = relay(sent.as_bytes());
+    assert_eq!(aired.len(), TransportNotificationV1::SIZE);
+
+    let got =
+        TransportNotificationV1::from_payload_with(&aired, StaticKeys.infra_keyring()).unwrap();
+    assert!(got.verify_client_with(StaticKeys.client_key()));
+    assert_eq!(got.base_payload(), sent.base_payload());
+
+    let current =
+        TransportNotification::from_payload_with(&aired, StaticKeys.infra_keyring(), None).unwrap();
+    assert_eq!(current.version, PROTOCOL_VERSION_V1);
+    assert_eq!(current.duration_secs(), 120);
+}
+
+#[test]
+fn v1_and_current_records_in_one_payload_are_relayed_apart() {
+    let v1 = TransportNotificationV1::downgrade(&broadcast(), StaticKeys.current_infra_key());
+    let mut payload = v1.as_bytes().to_vec();
+    payload.extend_from_slice(broadcast().as_bytes());
+
+    let chunks: Vec<&[u8]> = Received::chunks(&payload).collect();
+    assert_eq!(
+        chunks.iter().map(|c| c.len()).collect::<Vec<_>>(),
+        [TransportNotificationV1::SIZE, TransportNotification::SIZE]
+    );
+    for chunk in chunks {
+        let aired = relay(chunk);
+        let got =
+            TransportNotification::from_payload_with(&aired, StaticKeys.infra_keyring(), None)
+                .unwrap();
+        assert_eq!(got.notification_id, [0x12, 0x34, 0x56, 0x78]);
+    }
+}
@@ -0,0 +1,368 @@
+//! Broadcaster → repeaters → client over a lossy simulated network.
+//!
+//! Each trial chains a broadcaster, `SIM_REPEATERS` `transport::Relay`s and
+//! a client on an in-memory `Transport` that loses, duplicates, delays and
+//! reorders every copy it carries, each at random. The broadcaster issues
+//! `SIM_UPDATES` updates of one notification, each under the next `seq`
+//! and re-aired `SIM_REAIRS` times; the client accepts a copy only if it
+//! verifies, carries the client tag, is fresh and has a newer `seq` than
+//! anything accepted before.
+//!
+//! The assertions are statistical, over `SIM_TRIALS` trials: enough trials
+//! deliver something, no repeater relays an update twice, and the client
+//! never accepts an update older than one it already has, however late it
+//! arrives. Every parameter is an environment variable, so a run can be
+//! made harsher (e.g. `SIM_LOSS=0.5 SIM_TRIALS=2000`); the defaults are
+//! what CI runs, seeded so a failure reproduces.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use ble_protocol_core::seq::SeqTracker;
+use ble_protocol_core::transport::{Relay, ScanRecord, Transport};
+use ble_protocol_core::{
+    EventId, KeyProvider, StaticKeys, TransportNotification, TransportNotificationBuilder,
+    TransportStatus, TransportType, DEFAULT_HOPS, MANUFACTURER_ID,
+};
+
+const START_MS: u64 = 1_767_225_600_000;
+
+/// Simulated time per tick.
+const TICK_MS: u64 = 100;
+
+/// Scenario parameters, each from the environment variable named.
+#[derive(Debug, Clone, Copy)]
+struct Params {
+    /// `SIM_SEED`
+    seed: u64,
+    /// `SIM_TRIALS`
+    trials: usize,
+    /// `SIM_REPEATERS`: repeaters in the chain, at most `DEFAULT_HOPS`.
+    repeaters: usize,
+    /// `SIM_UPDATES`: `seq`s issued per trial.
+    updates: u32,
+    /// `SIM_REAIRS`: ticks each update is on air.
+    reairs: usize,
+    /// `SIM_LOSS`: chance a copy is lost.
+    loss: f64,
+    /// `SIM_DUP`: chance a copy is delivered twice.
+    dup: f64,
+    /// `SIM_REORDER`: chance a copy is held back by up to
+    /// `SIM_REORDER_TICKS` more, behind copies sent after it.
+    reorder: f64,
+    /// `SIM_REORDER_TICKS`
+    reorder_ticks: u64,
+    /// `SIM_LATENCY_TICKS`: most ticks a copy takes, at least one.
+    latency_ticks: u64,
+    /// `SIM_MIN_DELIVERY`: fraction of trials the client must accept at
+    /// least one update in.
+    min_delivery: f64,
+}
+
+impl Params {
+    fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            match std::env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("{name}={value} doesn't parse")),
+                Err(_) => default,
+            }
+        }
+        let params = Self {
+            seed: var("SIM_SEED", 0x5EED_0239),
+            trials: var("SIM_TRIALS", 300),
+            repeaters: var("SIM_REPEATERS", usize::from(DEFAULT_HOPS)),
+            updates: var("SIM_UPDATES", 8),
+            reairs: var("SIM_REAIRS", 3),
+            loss: var("SIM_LOSS", 0.3),
+            dup: var("SIM_DUP", 0.2),
+            reorder: var("SIM_REORDER", 0.3),
+            reorder_ticks: var("SIM_REORDER_TICKS", 8),
+            latency_ticks: var("SIM_LATENCY_TICKS", 3),
+            min_delivery: var("SIM_MIN_DELIVERY", 0.85),
+        };
+        assert!(
+            (1..=usize::from(DEFAULT_HOPS)).contains(&params.repeaters),
+            "SIM_REPEATERS must be 1..={DEFAULT_HOPS}: the last hop is spent on the client"
+        );
+        assert!(
+            params.latency_ticks >= 1,
+            "SIM_LATENCY_TICKS must be at least 1"
+        );
+        params
+    }
+
+    /// Ticks until the last copy of the last update has landed.
+    fn ticks(&self) -> u64 {
+        let airing = u64::from(self.updates) * self.reairs as u64;
+        let per_hop = self.latency_ticks + self.reorder_ticks;
+        airing + per_hop * (self.repeaters as u64 + 1) + 1
+    }
+}
+
+/// xorshift64*: deterministic from the seed, and good enough for coin flips.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    /// Uniform in `1..=max`.
+    fn ticks(&mut self, max: u64) -> u64 {
+        1 + self.next() % max
+    }
+}
+
+/// A copy in flight.
+struct InFlight {
+    due: u64,
+    /// Sending order, to keep copies due on the same tick in order.
+    sent: u64,
+    to: usize,
+    record: ScanRecord,
+}
+
+/// Nodes 0..=n in a line, each heard only by the next.
+struct Network {
+    params: Params,
+    rng: Rng,
+    tick: u64,
+    sent: u64,
+    in_flight: Vec<InFlight>,
+    /// The broadcaster, the repeaters and the client.
+    nodes: usize,
+}
+
+impl Network {
+    fn new(params: Params, rng: Rng) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            params,
+            rng,
+            tick: 0,
+            sent: 0,
+            in_flight: Vec::new(),
+            nodes: params.repeaters + 2,
+        }))
+    }
+
+    fn send(&mut self, from: usize, data: &[u8]) {
+        let to = from + 1;
+        if to >= self.nodes || self.rng.chance(self.params.loss) {
+            return;
+        }
+        let copies = if self.rng.chance(self.params.dup) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut delay = self.rng.ticks(self.params.latency_ticks);
+            if self.rng.chance(self.params.reorder) {
+                delay += self.rng.ticks(self.params.reorder_ticks);
+            }
+            self.sent += 1;
+            self.in_flight.push(InFlight {
+                due: self.tick + delay,
+                sent: self.sent,
+                to,
+                record: ScanRecord {
+                    addr: [from as u8; 6],
+                    rssi: -60,
+                    data: data.to_vec(),
+                },
+            });
+        }
+    }
+
+    fn deliver(&mut self, to: usize) -> Vec<ScanRecord> {
+        let tick = self.tick;
+        let (mut due, rest) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition::<Vec<_>, _>(|copy| copy.to == to && copy.due <= tick);
+        self.in_flight = rest;
+        due.sort_by_key(|copy| (copy.due, copy.sent));
+        due.into_iter().map(|copy| copy.record).collect()
+    }
+}
+
+/// One node's view of the network.
+struct SimTransport {
+    net: Rc<RefCell<Network>>,
+    node: usize,
+}
+
+impl Transport for SimTransport {
+    fn receive(&mut self) -> Vec<ScanRecord> {
+        self.net.borrow_mut().deliver(self.node)
+    }
+
+    fn broadcast(&mut self, data: &[u8]) {
+        self.net.borrow_mut().send(self.node, data);
+    }
+}
+
+fn update(seq: u32, issued_ms: u64) -> TransportNotification {
+    TransportNotificationBuilder::new()
+        .source_id([0xA1, 0xB2, 0xC3, 0xD4])
+        .notification_id([0x12, 0x34, 0x56, 0x78])
+        .event(EventId::Delay)
+        .destination(5)
+        .transport(TransportType::Bus)
+        .status(TransportStatus::Late)
+        .duration_secs(60 + seq as u16)
+        .validity_secs(900)
+        .seq(seq)
+        .timestamp_ms(issued_ms)
+        .build_signed(StaticKeys.current_infra_key())
+        .unwrap()
+}
+
+/// What one trial saw.
+#[derive(Debug, Default)]
+struct Trial {
+    /// `seq`s the client accepted, in order.
+    accepted: Vec<u32>,
+    /// Copies the client turned away as replays or stale.
+    refused: usize,
+    /// Of those, copies older than the newest accepted, not just repeats.
+    stale: usize,
+    /// Whether some repeater relayed one `seq` twice.
+    relayed_twice: bool,
+}
+
+fn run(params: Params, rng: Rng) -> Trial {
+    let keys = StaticKeys;
+    let net = Network::new(params, rng);
+    let client_node = params.repeaters + 1;
+    let transport = |node| SimTransport {
+        net: Rc::clone(&net),
+        node,
+    };
+    let mut broadcaster = transport(0);
+    let mut repeaters: Vec<_> = (1..=params.repeaters)
+        .map(|node| {
+            let relay = Relay::new(keys.infra_keyring(), keys.client_key(), MANUFACTURER_ID);
+            (relay, transport(node), BTreeSet::new())
+        })
+        .collect();
+    let mut client = transport(client_node);
+    let mut client_seen = SeqTracker::<4>::new();
+
+    let mut trial = Trial::default();
+    for tick in 0..params.ticks() {
+        net.borrow_mut().tick = tick;
+        let now_ms = START_MS + tick * TICK_MS;
+
+        let seq = tick / params.reairs as u64 + 1;
+        if seq <= u64::from(params.updates) {
+            let seq = seq as u32;
+            // Issued when first aired.
+            let issued_ms = START_MS + u64::from(seq - 1) * params.reairs as u64 * TICK_MS;
+            let mut data = MANUFACTURER_ID.to_le_bytes().to_vec();
+            data.extend_from_slice(update(seq, issued_ms).as_bytes());
+            broadcaster.broadcast(&data);
+        }
+
+        for (relay, transport, relayed) in &mut repeaters {
+            for notif in relay.step(transport, Some(now_ms)) {
+                trial.relayed_twice |= !relayed.insert(notif.seq());
+            }
+        }
+
+        for record in client.receive() {
+            let got = TransportNotification::from_payload_with(
+                record.payload(),
+                keys.infra_keyring(),
+                Some(now_ms),
+            )
+            .expect("everything on this network verifies");
+            assert!(got.verify_client_with(keys.client_key()));
+            assert_eq!(
+                got.hops_remaining,
+                DEFAULT_HOPS - params.repeaters as u8,
+                "one hop per repeater"
+            );
+            let key = ({ got.source_id }, { got.notification_id });
+            if client_seen.accept(key, got.seq()) {
+                trial.accepted.push(got.seq());
+            } else {
+                trial.refused += 1;
+                trial.stale += usize::from(trial.accepted.last() > Some(&got.seq()));
+            }
+        }
+    }
+    trial
+}
+
+#[test]
+fn lossy_network_delivers_fresh_signed_updates_once() {
+    let params = Params::from_env();
+    let mut seeds = Rng::new(params.seed);
+    let trials: Vec<Trial> = (0..params.trials)
+        .map(|_| run(params, Rng::new(seeds.next())))
+        .collect();
+
+    let delivered = trials.iter().filter(|t| !t.accepted.is_empty()).count();
+    let refused: usize = trials.iter().map(|t| t.refused).sum();
+    let stale: usize = trials.iter().map(|t| t.stale).sum();
+    eprintln!(
+        "{params:?}: delivered in {delivered}/{} trials, client refused {refused} copies ({stale} stale)",
+        params.trials
+    );
+
+    let rate = delivered as f64 / params.trials as f64;
+    assert!(
+        rate >= params.min_delivery,
+        "delivered in {rate:.3} of trials, under SIM_MIN_DELIVERY={}",
+        params.min_delivery
+    );
+    for (i, trial) in trials.iter().enumerate() {
+        assert!(
+            !trial.relayed_twice,
+            "trial {i}: a repeater relayed a seq twice"
+        );
+        assert!(
+            trial.accepted.windows(2).all(|w| w[0] < w[1]),
+            "trial {i}: the client accepted {:?}, out of order",
+            trial.accepted
+        );
+    }
+    // With these on, over enough trials, the client is sure to hear repeats
+    // and late copies; they must all have been refused above.
+    if params.dup > 0.0 && params.trials >= 100 {
+        assert!(refused > 0, "no duplicate ever reached the client");
+    }
+    if params.reorder > 0.0 && params.trials >= 100 {
+        assert!(stale > 0, "no copy ever arrived behind a newer one");
+    }
+}
+
+#[test]
+fn clean_network_delivers_every_update_in_order() {
+    let params = Params {
+        loss: 0.0,
+        dup: 0.0,
+        reorder: 0.0,
+        latency_ticks: 1,
+        ..Params::from_env()
+    };
+    let trial = run(params, Rng::new(params.seed));
+    let every: Vec<u32> = (1..=params.updates).collect();
+    assert_eq!(trial.accepted, every);
+    assert_eq!(trial.refused, 0, "the repeaters dedup the re-airings");
+    assert!(!trial.relayed_twice);
+}
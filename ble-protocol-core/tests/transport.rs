@@ -0,0 +1,95 @@
+//! Broadcaster → repeater → repeater → client, over UDP.
+//!
+//! Two `transport::Relay`s run in-process, each on its own socket, chained
+//! so the broadcaster's datagrams reach the first, the first's the second,
+//! and the second's the client. What the client hears must verify, carry
+//! the client tag, and be two hops down.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use ble_protocol_core::transport::{Relay, Transport, UdpTransport};
+use ble_protocol_core::{
+    EventId, KeyProvider, StaticKeys, TransportNotification, TransportNotificationBuilder,
+    TransportStatus, TransportType, DEFAULT_HOPS, MANUFACTURER_ID,
+};
+
+const NOW_MS: u64 = 1_767_225_600_000;
+
+fn bind(addr: u8) -> UdpTransport {
+    UdpTransport::bind("127.0.0.1:0", [addr; 6], Duration::from_millis(500)).unwrap()
+}
+
+fn peer(transport: &UdpTransport) -> SocketAddr {
+    transport.local_addr().unwrap()
+}
+
+fn broadcast() -> Vec<u8> {
+    let notif = TransportNotificationBuilder::new()
+        .source_id([0xA1, 0xB2, 0xC3, 0xD4])
+        .notification_id([0x12, 0x34, 0x56, 0x78])
+        .event(EventId::Delay)
+        .destination(5)
+        .transport(TransportType::Bus)
+        .status(TransportStatus::Late)
+        .duration_secs(120)
+        .validity_secs(900)
+        .seq(7)
+        .timestamp_ms(NOW_MS)
+        .build_signed(StaticKeys.current_infra_key())
+        .unwrap();
+    let mut data = MANUFACTURER_ID.to_le_bytes().to_vec();
+    data.extend_from_slice(notif.as_bytes());
+    data
+}
+
+#[test]
+fn two_udp_repeaters_relay_a_broadcast_to_the_client() {
+    let keys = StaticKeys;
+    let mut broadcaster = bind(0x01);
+    let mut first = bind(0x02);
+    let mut second = bind(0x03);
+    let mut client = bind(0x04);
+    broadcaster.add_peer(peer(&first));
+    first.add_peer(peer(&second));
+    second.add_peer(peer(&client));
+
+    let mut first_relay = Relay::new(keys.infra_keyring(), keys.client_key(), MANUFACTURER_ID);
+    let mut second_relay = Relay::new(keys.infra_keyring(), keys.client_key(), MANUFACTURER_ID);
+
+    let sent = broadcast();
+    broadcaster.broadcast(&sent);
+    assert_eq!(first_relay.step(&mut first, Some(NOW_MS)).len(), 1);
+    assert_eq!(second_relay.step(&mut second, Some(NOW_MS)).len(), 1);
+
+    let heard = client.receive();
+    assert_eq!(heard.len(), 1);
+    assert_eq!(heard[0].addr, [0x03; 6], "heard from the second repeater");
+    assert_eq!(heard[0].company_id(), Some(MANUFACTURER_ID));
+    let got = TransportNotification::from_payload_with(
+        heard[0].payload(),
+        keys.infra_keyring(),
+        Some(NOW_MS),
+    )
+    .unwrap();
+    assert!(got.verify_client_with(keys.client_key()));
+    assert_eq!(got.hops_remaining, DEFAULT_HOPS - 2);
+    assert_eq!(got.seq(), 7);
+
+    // The first repeater hears the broadcast again: same seq, so it is a
+    // duplicate and goes no further.
+    broadcaster.broadcast(&sent);
+    assert!(first_relay.step(&mut first, Some(NOW_MS)).is_empty());
+}
+
+#[test]
+fn other_company_ids_are_not_relayed() {
+    let keys = StaticKeys;
+    let mut broadcaster = bind(0x01);
+    let mut repeater = bind(0x02);
+    broadcaster.add_peer(peer(&repeater));
+    let mut relay = Relay::new(keys.infra_keyring(), keys.client_key(), 0x1234);
+
+    broadcaster.broadcast(&broadcast());
+    assert!(relay.step(&mut repeater, Some(NOW_MS)).is_empty());
+}
@@ -0,0 +1,113 @@
+//! Ack beacons (`RepeaterConfig::ack_every_cycles`).
+//!
+//! Every so many re-broadcast cycles, after airing its notifications, the
+//! repeater airs one `AckBeacon` for each, so a broadcaster scanning nearby
+//! can count the repeaters relaying it. Each ack is signed with the infra
+//! key its notification verified against. Sealed entries get none (see
+//! `ble_protocol_core::ack`).
+
+use ble_protocol_core::crypto::infra_key;
+use ble_protocol_core::{AckBeacon, InfraKey};
+
+use crate::active::ActiveNotification;
+
+/// Manufacturer-data payload of one ack: company ID, then the beacon.
+pub type AckPayload = [u8; 2 + AckBeacon::SIZE];
+
+/// Decides which cycles are followed by acks, and builds them.
+pub struct Acker {
+    repeater_id: [u8; 4],
+    keyring: Vec<InfraKey>,
+    /// `ack_every_cycles`; 0 = never.
+    every: u32,
+    /// Cycles to go before the next one followed by acks.
+    countdown: u32,
+}
+
+impl Acker {
+    pub fn new(repeater_id: [u8; 4], keyring: Vec<InfraKey>, every: u32) -> Self {
+        Self {
+            repeater_id,
+            keyring,
+            every,
+            countdown: 0,
+        }
+    }
+
+    /// Count a re-broadcast cycle that aired `entries`, and return the acks
+    /// to air after it under `company_id`: one per entry on every
+    /// `every`-th cycle, starting with the first; none otherwise.
+    pub fn after_cycle<'a>(
+        &mut self,
+        entries: impl IntoIterator<Item = &'a ActiveNotification>,
+        company_id: u16,
+    ) -> Vec<AckPayload> {
+        if self.every == 0 {
+            return Vec::new();
+        }
+        let due = self.countdown == 0;
+        self.countdown = if due { self.every } else { self.countdown } - 1;
+        if !due {
+            return Vec::new();
+        }
+        entries
+            .into_iter()
+            .filter(|entry| entry.sealed.is_none())
+            .filter_map(|entry| {
+                let notif = &entry.notification;
+                let key = infra_key(&self.keyring, notif.key_id)?;
+                let ack = AckBeacon::new(notif, self.repeater_id, (notif.key_id, key));
+                let mut payload = [0u8; 2 + AckBeacon::SIZE];
+                payload[..2].copy_from_slice(&company_id.to_le_bytes());
+                payload[2..].copy_from_slice(ack.as_bytes());
+                Some(payload)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_protocol_core::{
+        TransportNotificationBuilder, TransportStatus, TransportType, INFRA_KEYRING,
+        INFRA_KEY_CURRENT, MANUFACTURER_ID,
+    };
+
+    fn entry(id: u8) -> ActiveNotification {
+        let notif = TransportNotificationBuilder::new()
+            .notification_id([id; 4])
+            .transport(TransportType::Bus)
+            .status(TransportStatus::Coming)
+            .duration_secs(30)
+            .seq(u32::from(id))
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap();
+        ActiveNotification::new(notif, MANUFACTURER_ID, 0)
+    }
+
+    #[test]
+    fn acks_follow_every_nth_cycle() {
+        let entries = [entry(1), entry(2)];
+        let mut acker = Acker::new([0xAB; 4], INFRA_KEYRING.to_vec(), 3);
+        let counts: Vec<usize> = (0..7)
+            .map(|_| acker.after_cycle(&entries, MANUFACTURER_ID).len())
+            .collect();
+        assert_eq!(counts, [2, 0, 0, 2, 0, 0, 2]);
+
+        let mut off = Acker::new([0xAB; 4], INFRA_KEYRING.to_vec(), 0);
+        assert!(off.after_cycle(&entries, MANUFACTURER_ID).is_empty());
+    }
+
+    #[test]
+    fn ack_names_the_entry_and_the_repeater() {
+        let entries = [entry(1)];
+        let mut acker = Acker::new([0xAB; 4], INFRA_KEYRING.to_vec(), 1);
+        let payload = acker.after_cycle(&entries, 0x1234)[0];
+
+        assert_eq!(payload[..2], 0x1234u16.to_le_bytes());
+        let ack = AckBeacon::from_payload_with(&payload[2..], INFRA_KEYRING).unwrap();
+        assert!(ack.acknowledges(&entries[0].notification));
+        assert_eq!(ack.repeater_id, [0xAB; 4]);
+    }
+}
@@ -0,0 +1,355 @@
+//! Entries of the active list, and the queue a scan collects them in.
+
+use ble_protocol_core::conf::SealedNotification;
+use ble_protocol_core::relay::Received;
+use ble_protocol_core::relay_path::RelayPath;
+use ble_protocol_core::{ClientTags, TransportNotification, PROTOCOL_VERSION_V1};
+
+/// Largest manufacturer-data payload an entry re-broadcasts: the 2-byte
+/// company ID and a plaintext notification with its client-tag record (and
+/// relay path, with `relay-path`) or a sealed envelope, whichever is
+/// longer.
+pub const MAX_MFG_LEN: usize = 2 + if SealedNotification::SIZE > PLAIN_MAX_LEN {
+    SealedNotification::SIZE
+} else {
+    PLAIN_MAX_LEN
+};
+
+const PLAIN_MAX_LEN: usize = TransportNotification::SIZE
+    + ClientTags::MAX_SIZE
+    + if cfg!(feature = "relay-path") {
+        RelayPath::SIZE
+    } else {
+        0
+    };
+
+/// `ActiveNotification::rssi` of an entry whose copy wasn't heard by this
+/// repeater's scan, e.g. one restored from NVS. Any copy heard is stronger.
+pub const UNKNOWN_RSSI: i8 = i8::MIN;
+
+// `raw_mfg_len` is a `u8`.
+const _: () = assert!(MAX_MFG_LEN <= u8::MAX as usize);
+
+/// A notification we are actively re-broadcasting, with an expiry timestamp.
+#[derive(Clone)]
+pub struct ActiveNotification {
+    /// The notification, decrypted if it arrived sealed.
+    pub notification: TransportNotification,
+    /// The envelope a sealed notification arrived in. That is what gets
+    /// re-broadcast, and such entries are never saved to NVS, so the
+    /// plaintext neither reaches flash nor goes back on air.
+    pub sealed: Option<SealedNotification>,
+    /// Raw manufacturer-data payload (including the 2-byte company ID) for
+    /// direct re-broadcast, in the first `raw_mfg_len` bytes. Held inline so
+    /// that copying entries out of the active list each cycle doesn't touch
+    /// the heap.
+    raw_mfg: [u8; MAX_MFG_LEN],
+    raw_mfg_len: u8,
+    /// Monotonic timestamp (in microseconds) at which this entry expires.
+    pub expires_at_us: i64,
+    /// Signal strength the copy being re-broadcast was heard at, in dBm;
+    /// set when a scan queues it.
+    pub rssi: i8,
+    /// Whether a scan heard this as another copy of a notification already
+    /// relayed. A copy can refresh or replace the entry for it (see
+    /// `preferred_over`) but never adds one back once it is gone.
+    pub copy: bool,
+    /// Monotonic timestamp (in microseconds) at which the notification was
+    /// added to the active list, which a better copy replacing it keeps;
+    /// `None` until then, and for entries restored after a reboot. Fresh
+    /// high-priority entries get extra airtime (see `schedule::escalation`).
+    pub accepted_at_us: Option<i64>,
+}
+
+impl ActiveNotification {
+    pub fn new(notification: TransportNotification, company_id: u16, expires_at_us: i64) -> Self {
+        Self::with_envelope(notification, None, company_id, expires_at_us)
+    }
+
+    pub fn with_envelope(
+        notification: TransportNotification,
+        sealed: Option<SealedNotification>,
+        company_id: u16,
+        expires_at_us: i64,
+    ) -> Self {
+        // Re-broadcast: company ID + full struct (both tags), or the envelope
+        let body = match &sealed {
+            Some(envelope) => envelope.as_bytes(),
+            None => notification.as_bytes(),
+        };
+        Self::with_body(notification, sealed, body, company_id, expires_at_us)
+    }
+
+    /// An entry airing what `received` would relay: the notification, its
+    /// envelope, or the v1 packet it arrived as, and any extension records
+    /// after it. Only the notification is saved to NVS, so a restored entry
+    /// airs without its client-tag record until a fresh copy replaces it.
+    pub fn relayed(received: &Received, company_id: u16, expires_at_us: i64) -> Self {
+        let mut entry = Self::with_body(
+            received.notification,
+            received.sealed,
+            received.as_bytes(),
+            company_id,
+            expires_at_us,
+        );
+        for ext in received.extensions() {
+            let len = usize::from(entry.raw_mfg_len);
+            entry.raw_mfg[len..len + ext.len()].copy_from_slice(ext);
+            entry.raw_mfg_len += ext.len() as u8;
+        }
+        entry
+    }
+
+    fn with_body(
+        notification: TransportNotification,
+        sealed: Option<SealedNotification>,
+        body: &[u8],
+        company_id: u16,
+        expires_at_us: i64,
+    ) -> Self {
+        let mut raw_mfg = [0u8; MAX_MFG_LEN];
+        raw_mfg[..2].copy_from_slice(&company_id.to_le_bytes());
+        raw_mfg[2..2 + body.len()].copy_from_slice(body);
+        Self {
+            notification,
+            sealed,
+            raw_mfg,
+            raw_mfg_len: (2 + body.len()) as u8,
+            expires_at_us,
+            rssi: UNKNOWN_RSSI,
+            copy: false,
+            accepted_at_us: None,
+        }
+    }
+
+    /// Manufacturer-data payload to re-broadcast: company ID, then the
+    /// notification or its envelope, and any extension records.
+    pub fn raw_mfg_payload(&self) -> &[u8] {
+        &self.raw_mfg[..usize::from(self.raw_mfg_len)]
+    }
+
+    /// Whether this copy of a notification may refresh `held`'s expiry: it
+    /// has been through no more repeaters than `held`. One that has been
+    /// through more is a neighbour's relay, and taking it would let two
+    /// repeaters in range of each other keep an entry alive, and whittle its
+    /// hops down, between them. A v1 packet has no hop count to tell the
+    /// two apart by, so no copy of one refreshes.
+    pub fn refreshes(&self, held: &Self) -> bool {
+        self.notification.version != PROTOCOL_VERSION_V1
+            && self.notification.hops_remaining >= held.notification.hops_remaining
+    }
+
+    /// Whether this copy of a notification should replace `held`: it
+    /// refreshes it and was heard at least as strongly. The strongest copy
+    /// most likely came from the broadcaster or the nearest hop, so it is
+    /// the cleanest one to pass on.
+    pub fn preferred_over(&self, held: &Self) -> bool {
+        self.refreshes(held) && self.rssi >= held.rssi
+    }
+
+    /// Whether this entry airs a cancellation (a zero `duration_secs`)
+    /// rather than a notification.
+    pub fn is_cancellation(&self) -> bool {
+        self.notification.duration_secs() == 0
+    }
+
+    /// Whether the entry has expired at `now_us`.
+    pub fn is_expired(&self, now_us: i64) -> bool {
+        self.expires_at_us <= now_us
+    }
+
+    /// Time left before the entry expires at `now_us`, in microseconds; 0
+    /// once it has.
+    pub fn remaining_us(&self, now_us: i64) -> i64 {
+        (self.expires_at_us - now_us).max(0)
+    }
+}
+
+/// Notifications collected during a single scan window, bounded in size.
+///
+/// The scan callback fires for every advertisement heard, so a flooded
+/// channel (or a deliberate flood of valid-looking packets) could otherwise
+/// grow this list without limit. Overflow policy: repeated copies of the
+/// same `notification_id` collapse into one entry keeping the preferred copy
+/// (see `ActiveNotification::preferred_over`); when the queue is full, a new
+/// notification evicts the weakest-RSSI entry if it is stronger, otherwise
+/// it is dropped, and a copy of one already relayed is dropped. All three
+/// count as overflow.
+pub struct ScanQueue {
+    entries: Vec<ActiveNotification>,
+    capacity: usize,
+    /// `notification_id`s cancelled during the scan.
+    cancelled: Vec<[u8; 4]>,
+    /// Number of notifications evicted or dropped because the queue was full.
+    pub overflowed: u32,
+}
+
+impl ScanQueue {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            capacity,
+            cancelled: Vec::new(),
+            overflowed: 0,
+        }
+    }
+
+    /// Offer a notification received at `rssi`, applying the overflow policy.
+    pub fn push(&mut self, mut entry: ActiveNotification, rssi: i8) {
+        entry.rssi = rssi;
+        let nid = { entry.notification.notification_id };
+        if let Some(slot) = self
+            .entries
+            .iter_mut()
+            .find(|e| { e.notification.notification_id } == nid)
+        {
+            // Same notification heard again: keep the preferred copy, still
+            // new if any copy of it was. One re-issued after a cancellation
+            // heard earlier in the scan replaces the cancellation.
+            let copy = slot.copy && entry.copy;
+            let reissued = slot.is_cancellation() && !entry.is_cancellation();
+            if reissued || entry.preferred_over(slot) {
+                *slot = entry;
+            }
+            slot.copy = copy;
+            return;
+        }
+
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+            return;
+        }
+
+        self.overflowed += 1;
+        // A copy would at most refresh an entry; it doesn't push out a new
+        // notification.
+        if entry.copy {
+            return;
+        }
+        if let Some(weakest) = self.entries.iter_mut().min_by_key(|e| e.rssi) {
+            if rssi > weakest.rssi {
+                *weakest = entry;
+            }
+        }
+    }
+
+    /// Record a cancellation of `nid`, dropping any copy queued before it.
+    /// A copy offered after it is queued again: the notification was
+    /// re-issued.
+    pub fn cancel(&mut self, nid: [u8; 4]) {
+        self.entries
+            .retain(|e| { e.notification.notification_id } != nid);
+        if !self.cancelled.contains(&nid) {
+            self.cancelled.push(nid);
+        }
+    }
+
+    /// The notifications to merge, and the `notification_id`s to remove from
+    /// the active list before merging them.
+    pub fn into_parts(self) -> (Vec<ActiveNotification>, Vec<[u8; 4]>) {
+        (self.entries, self.cancelled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_protocol_core::conf::CONF_KEY;
+    use ble_protocol_core::{
+        TransportNotificationBuilder, TransportStatus, TransportType, INFRA_KEY_CURRENT,
+        MANUFACTURER_ID,
+    };
+
+    fn entry(id: u8, expires_at_us: i64) -> ActiveNotification {
+        let notif = TransportNotificationBuilder::new()
+            .notification_id([id; 4])
+            .transport(TransportType::Bus)
+            .status(TransportStatus::Coming)
+            .duration_secs(30)
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap();
+        ActiveNotification::new(notif, MANUFACTURER_ID, expires_at_us)
+    }
+
+    #[test]
+    fn sealed_entries_air_their_envelope() {
+        let plain = entry(5, 0);
+        let envelope =
+            SealedNotification::seal(&plain.notification, INFRA_KEY_CURRENT, CONF_KEY, [1; 12]);
+        let sealed = ActiveNotification::with_envelope(
+            plain.notification,
+            Some(envelope),
+            MANUFACTURER_ID,
+            0,
+        );
+        assert_eq!(
+            &plain.raw_mfg_payload()[..2],
+            &MANUFACTURER_ID.to_le_bytes()
+        );
+        assert_eq!(&plain.raw_mfg_payload()[2..], plain.notification.as_bytes());
+        assert_eq!(&sealed.raw_mfg_payload()[2..], envelope.as_bytes());
+    }
+
+    #[test]
+    fn full_scan_queue_keeps_the_strongest_copies() {
+        let mut queue = ScanQueue::with_capacity(2);
+        queue.push(entry(1, 0), -70);
+        queue.push(entry(1, 0), -50);
+        queue.push(entry(2, 0), -80);
+        queue.push(entry(3, 0), -60);
+        queue.push(entry(4, 0), -90);
+        assert_eq!(queue.overflowed, 2);
+        let kept: Vec<(u8, i8)> = queue
+            .entries
+            .iter()
+            .map(|e| ({ e.notification.notification_id }[0], e.rssi))
+            .collect();
+        assert_eq!(kept, [(1, -50), (3, -60)]);
+    }
+
+    #[test]
+    fn collapsed_copies_stay_new_if_any_of_them_was() {
+        let heard = |rssi, hops, copy| {
+            let mut e = entry(1, 0);
+            e.notification.hops_remaining = hops;
+            e.copy = copy;
+            (e, rssi)
+        };
+        let mut queue = ScanQueue::with_capacity(1);
+        for (e, rssi) in [
+            heard(-70, 3, false),
+            heard(-50, 3, true),
+            heard(-40, 2, true),
+        ] {
+            queue.push(e, rssi);
+        }
+        // A copy doesn't evict a new notification from a full queue.
+        let (mut other, rssi) = heard(-30, 3, true);
+        other.notification.notification_id = [2; 4];
+        queue.push(other, rssi);
+
+        let (entries, _) = queue.into_parts();
+        assert_eq!(entries.len(), 1);
+        // The stronger copy, not the one with fewer hops left.
+        assert_eq!((entries[0].rssi, entries[0].copy), (-50, false));
+        assert_eq!(entries[0].notification.hops_remaining, 3);
+    }
+
+    #[test]
+    fn cancelling_drops_earlier_copies_only() {
+        let mut queue = ScanQueue::with_capacity(4);
+        queue.push(entry(1, 0), -50);
+        queue.push(entry(2, 0), -50);
+        queue.cancel([1; 4]);
+        queue.cancel([2; 4]);
+        queue.push(entry(2, 0), -60);
+        queue.cancel([1; 4]);
+        let (entries, cancelled) = queue.into_parts();
+        let kept: Vec<u8> = entries
+            .iter()
+            .map(|e| { e.notification.notification_id }[0])
+            .collect();
+        assert_eq!(kept, [2]);
+        assert_eq!(cancelled, [[1; 4], [2; 4]]);
+    }
+}
@@ -0,0 +1,237 @@
+//! Starting an advertisement and confirming the radio is actually on air.
+//!
+//! `BLEAdvertising::start()` returning `Ok` doesn't guarantee the controller
+//! is advertising; on some NimBLE versions it succeeds but nothing is
+//! emitted. `start_confirmed` checks the stack's own advertising state after
+//! each start and retries before giving up.
+//!
+//! `StopOnDrop` wraps the advertiser for one re-broadcast so that however the
+//! loop leaves it (`continue`, `break`, an early return), the advertisement is
+//! stopped rather than left on air with nothing maintaining it.
+//!
+//! Both work on any `Advertiser`; the NimBLE one is `Radio` in the firmware.
+
+use core::ops::{Deref, DerefMut};
+
+use ble_protocol_core::LEGACY_ADV_DATA_LEN;
+
+/// The advertiser operations the re-broadcast path relies on.
+pub trait Advertiser {
+    type Error;
+
+    fn start(&mut self) -> Result<(), Self::Error>;
+    fn stop(&mut self);
+    fn is_advertising(&self) -> bool;
+}
+
+/// Whether a manufacturer-data payload (company ID included) fits a legacy
+/// advertisement, after its 2-byte AD header.
+pub fn fits_legacy(mfg_payload_len: usize) -> bool {
+    mfg_payload_len + 2 <= LEGACY_ADV_DATA_LEN
+}
+
+/// Result of `start_confirmed`.
+#[derive(Debug, PartialEq)]
+pub enum StartOutcome<E> {
+    /// The radio reported advertising after this many `start` calls.
+    Active { attempts: u32 },
+    /// Every start returned `Ok`, but the radio never reported advertising.
+    Silent { attempts: u32 },
+    /// `start` itself returned an error.
+    Failed(E),
+}
+
+/// Start advertising and confirm the radio is on air, retrying up to
+/// `retries` extra times (stopping in between) when the start succeeds but
+/// the radio stays silent.
+pub fn start_confirmed<A: Advertiser>(adv: &mut A, retries: u32) -> StartOutcome<A::Error> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        if let Err(e) = adv.start() {
+            return StartOutcome::Failed(e);
+        }
+        if adv.is_advertising() {
+            return StartOutcome::Active { attempts };
+        }
+        if attempts > retries {
+            return StartOutcome::Silent { attempts };
+        }
+        adv.stop();
+    }
+}
+
+/// Stops advertising when dropped.
+pub struct StopOnDrop<'a, A: Advertiser>(&'a mut A);
+
+impl<'a, A: Advertiser> StopOnDrop<'a, A> {
+    pub fn new(adv: &'a mut A) -> Self {
+        Self(adv)
+    }
+}
+
+impl<A: Advertiser> Deref for StopOnDrop<'_, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        self.0
+    }
+}
+
+impl<A: Advertiser> DerefMut for StopOnDrop<'_, A> {
+    fn deref_mut(&mut self) -> &mut A {
+        self.0
+    }
+}
+
+impl<A: Advertiser> Drop for StopOnDrop<'_, A> {
+    fn drop(&mut self) {
+        self.0.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simulated radio: `start` results are consumed in order, and the radio
+    /// only comes on air from the `on_air_from`-th start onwards.
+    struct FakeRadio {
+        start_results: Vec<Result<(), &'static str>>,
+        on_air_from: Option<u32>,
+        starts: u32,
+        stops: u32,
+        on_air: bool,
+    }
+
+    impl FakeRadio {
+        fn new(on_air_from: Option<u32>) -> Self {
+            FakeRadio {
+                start_results: Vec::new(),
+                on_air_from,
+                starts: 0,
+                stops: 0,
+                on_air: false,
+            }
+        }
+    }
+
+    impl Advertiser for FakeRadio {
+        type Error = &'static str;
+
+        fn start(&mut self) -> Result<(), &'static str> {
+            self.starts += 1;
+            if let Some(result) = self.start_results.get(self.starts as usize - 1) {
+                (*result)?;
+            }
+            self.on_air = self.on_air_from.is_some_and(|n| self.starts >= n);
+            Ok(())
+        }
+
+        fn stop(&mut self) {
+            self.stops += 1;
+            self.on_air = false;
+        }
+
+        fn is_advertising(&self) -> bool {
+            self.on_air
+        }
+    }
+
+    #[test]
+    fn legacy_fit_counts_the_ad_header() {
+        assert!(fits_legacy(LEGACY_ADV_DATA_LEN - 2));
+        assert!(!fits_legacy(LEGACY_ADV_DATA_LEN - 1));
+    }
+
+    #[test]
+    fn healthy_radio_is_active_on_first_start() {
+        let mut radio = FakeRadio::new(Some(1));
+        assert_eq!(
+            start_confirmed(&mut radio, 2),
+            StartOutcome::Active { attempts: 1 }
+        );
+        assert_eq!((radio.starts, radio.stops), (1, 0));
+    }
+
+    #[test]
+    fn silent_start_is_retried_until_on_air() {
+        let mut radio = FakeRadio::new(Some(3));
+        assert_eq!(
+            start_confirmed(&mut radio, 2),
+            StartOutcome::Active { attempts: 3 }
+        );
+        assert_eq!((radio.starts, radio.stops), (3, 2));
+    }
+
+    #[test]
+    fn radio_that_never_comes_on_air_escalates() {
+        let mut radio = FakeRadio::new(None);
+        assert_eq!(
+            start_confirmed(&mut radio, 2),
+            StartOutcome::Silent { attempts: 3 }
+        );
+        assert_eq!(radio.starts, 3);
+    }
+
+    #[test]
+    fn zero_retries_escalates_after_one_silent_start() {
+        let mut radio = FakeRadio::new(Some(2));
+        assert_eq!(
+            start_confirmed(&mut radio, 0),
+            StartOutcome::Silent { attempts: 1 }
+        );
+        assert_eq!((radio.starts, radio.stops), (1, 0));
+    }
+
+    #[test]
+    fn start_error_is_reported_without_retrying() {
+        let mut radio = FakeRadio::new(None);
+        radio.start_results = vec![Ok(()), Err("EBUSY")];
+        assert_eq!(
+            start_confirmed(&mut radio, 5),
+            StartOutcome::Failed("EBUSY")
+        );
+        assert_eq!(radio.starts, 2);
+    }
+
+    #[test]
+    fn guard_stops_advertising_when_dropped() {
+        let mut radio = FakeRadio::new(Some(1));
+        {
+            let mut adv = StopOnDrop::new(&mut radio);
+            assert_eq!(
+                start_confirmed(&mut *adv, 0),
+                StartOutcome::Active { attempts: 1 }
+            );
+            assert!(adv.is_advertising());
+        }
+        assert!(!radio.is_advertising());
+        assert_eq!(radio.stops, 1);
+    }
+
+    #[test]
+    fn bounded_run_leaves_advertiser_stopped() {
+        // Mirrors the re-broadcast loop: one guard per entry, with the run
+        // cut short by a `--max-cycles`-style bound mid-list.
+        fn run(radio: &mut FakeRadio, entries: usize, max_aired: usize) -> usize {
+            let mut aired = 0;
+            for _ in 0..entries {
+                let mut adv = StopOnDrop::new(radio);
+                if start_confirmed(&mut *adv, 0) != (StartOutcome::Active { attempts: 1 }) {
+                    continue;
+                }
+                aired += 1;
+                if aired == max_aired {
+                    return aired;
+                }
+            }
+            aired
+        }
+
+        let mut radio = FakeRadio::new(Some(1));
+        assert_eq!(run(&mut radio, 5, 2), 2);
+        assert!(!radio.is_advertising());
+        assert_eq!((radio.starts, radio.stops), (2, 2));
+    }
+}
@@ -0,0 +1,97 @@
+//! Capability beacons (`RepeaterConfig::capability_every_cycles`).
+//!
+//! Every so many re-broadcast cycles that air notifications, after its acks,
+//! the repeater airs a `CapabilityBeacon` saying which protocol versions it
+//! relays and which features it has, for neighbours with
+//! `neighbor_aware_relay`. An idle repeater airs none, so once its entry in
+//! their tables goes stale they relay everything towards it again.
+
+use ble_protocol_core::capability::{FEATURE_ACKS, FEATURE_SEALED, SUPPORTED_VERSIONS};
+use ble_protocol_core::{CapabilityBeacon, InfraKey, PROTOCOL_VERSION_V1};
+
+use crate::config::RepeaterConfig;
+
+/// Manufacturer-data payload of a beacon: company ID, then the beacon.
+pub type CapabilityPayload = [u8; 2 + CapabilityBeacon::SIZE];
+
+/// Decides which cycles are followed by a beacon, and holds it.
+pub struct Announcer {
+    payload: CapabilityPayload,
+    /// `capability_every_cycles`; 0 = never.
+    every: u32,
+    /// Cycles to go before the next one followed by a beacon.
+    countdown: u32,
+}
+
+impl Announcer {
+    /// `repeater_id`'s beacon for what `cfg` relays, signed with
+    /// `infra_key`.
+    pub fn new(repeater_id: [u8; 4], cfg: &RepeaterConfig, infra_key: InfraKey) -> Self {
+        let mut versions = SUPPORTED_VERSIONS;
+        if !cfg.relay_v1 {
+            versions &= !(1 << PROTOCOL_VERSION_V1);
+        }
+        let mut features = FEATURE_SEALED;
+        if cfg.ack_every_cycles > 0 {
+            features |= FEATURE_ACKS;
+        }
+        let beacon = CapabilityBeacon::new(repeater_id, versions, features, infra_key);
+        let mut payload = [0u8; 2 + CapabilityBeacon::SIZE];
+        payload[..2].copy_from_slice(&cfg.manufacturer_id.to_le_bytes());
+        payload[2..].copy_from_slice(beacon.as_bytes());
+        Self {
+            payload,
+            every: cfg.capability_every_cycles,
+            countdown: 0,
+        }
+    }
+
+    /// Count a re-broadcast cycle, and return the beacon to air after it:
+    /// on every `every`-th cycle, starting with the first; none otherwise.
+    pub fn after_cycle(&mut self) -> Option<&CapabilityPayload> {
+        if self.every == 0 {
+            return None;
+        }
+        let due = self.countdown == 0;
+        self.countdown = if due { self.every } else { self.countdown } - 1;
+        due.then_some(&self.payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_protocol_core::{INFRA_KEYRING, INFRA_KEY_CURRENT, PROTOCOL_VERSION};
+
+    #[test]
+    fn beacons_follow_every_nth_cycle() {
+        let cfg = RepeaterConfig {
+            capability_every_cycles: 3,
+            ..RepeaterConfig::default()
+        };
+        let mut announcer = Announcer::new([0xAB; 4], &cfg, INFRA_KEY_CURRENT);
+        let aired: Vec<bool> = (0..7).map(|_| announcer.after_cycle().is_some()).collect();
+        assert_eq!(aired, [true, false, false, true, false, false, true]);
+
+        let mut off = Announcer::new([0xAB; 4], &RepeaterConfig::default(), INFRA_KEY_CURRENT);
+        assert!(off.after_cycle().is_none());
+    }
+
+    #[test]
+    fn beacon_says_what_the_config_relays() {
+        let cfg = RepeaterConfig {
+            manufacturer_id: 0x1234,
+            capability_every_cycles: 1,
+            relay_v1: false,
+            ..RepeaterConfig::default()
+        };
+        let mut announcer = Announcer::new([0xAB; 4], &cfg, INFRA_KEY_CURRENT);
+        let payload = *announcer.after_cycle().unwrap();
+
+        assert_eq!(payload[..2], 0x1234u16.to_le_bytes());
+        let beacon = CapabilityBeacon::from_payload_with(&payload[2..], INFRA_KEYRING).unwrap();
+        assert_eq!(beacon.repeater_id, [0xAB; 4]);
+        assert!(beacon.speaks(PROTOCOL_VERSION) && !beacon.speaks(PROTOCOL_VERSION_V1));
+        assert!(beacon.has(FEATURE_SEALED) && !beacon.has(FEATURE_ACKS));
+    }
+}
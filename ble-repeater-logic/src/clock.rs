@@ -0,0 +1,41 @@
+//! Monotonic time, as the repeater's expiry logic reads it.
+//!
+//! Entries expire, and relayed notifications are forgotten, at monotonic
+//! microsecond timestamps. `Repeater` reads the time through `Clock`: on the
+//! ESP32 that is `esp_timer_get_time` (`EspClock` in the firmware), in host
+//! tests a `MockClock` the test moves by hand.
+
+#[cfg(test)]
+use std::cell::Cell;
+#[cfg(test)]
+use std::rc::Rc;
+
+/// Where the current monotonic time comes from.
+pub trait Clock {
+    /// Monotonic time in microseconds, from some fixed point such as boot.
+    fn now_us(&self) -> i64;
+}
+
+/// A clock that only moves when told to; starts at 0. Clones share the
+/// time, so a test keeps one and hands the other to the code under test.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockClock(Rc<Cell<i64>>);
+
+#[cfg(test)]
+impl MockClock {
+    pub fn set_us(&self, now_us: i64) {
+        self.0.set(now_us);
+    }
+
+    pub fn advance_us(&self, by_us: i64) {
+        self.0.set(self.0.get() + by_us);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now_us(&self) -> i64 {
+        self.0.get()
+    }
+}
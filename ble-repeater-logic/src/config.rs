@@ -0,0 +1,803 @@
+//! Repeater configuration.
+//!
+//! All tuning knobs live in `RepeaterConfig`. Defaults are defined here; a
+//! deployment can override any field from `repeater.toml` in the firmware
+//! crate (see `ble-repeater/repeater.example.toml`), which its `build.rs`
+//! turns into assignments in `apply_overrides` at compile time.
+
+use core::fmt;
+
+use ble_protocol_core::client_tags::MAX_EXTRA_CLIENT_TAGS;
+use ble_protocol_core::crypto::infra_key;
+use ble_protocol_core::{
+    ClientKey, InfraKey, KeyProvider, MANUFACTURER_ID, MAX_AGE_MS, MAX_FUTURE_SKEW_MS,
+};
+
+/// Default `adv_interval_jitter`: up to 10 ms on top of `adv_interval`.
+pub const ADV_INTERVAL_JITTER: u16 = 16;
+
+/// Default `dwell_jitter_ms`.
+pub const DWELL_JITTER_MS: u32 = 50;
+
+/// Legal BLE advertising interval range, in 0.625 ms units (20 ms – 10.24 s).
+const ADV_INTERVAL_RANGE: core::ops::RangeInclusive<u16> = 0x0020..=0x4000;
+
+/// Upper bound on `blocked_notifications`; it is scanned for every packet.
+pub const MAX_BLOCKED_NOTIFICATIONS: usize = 32;
+
+/// Upper bound on `allowed_sources` and on `denied_sources`; both are
+/// scanned for every verified packet.
+pub const MAX_LISTED_SOURCES: usize = 64;
+
+/// Default `persist_refresh_secs`.
+pub const PERSIST_REFRESH_SECS: u32 = 300;
+
+/// Default `idle_cycles_before_backoff`: cycles without an active
+/// notification before the scan backs off.
+pub const IDLE_CYCLES_BEFORE_BACKOFF: u32 = 5;
+
+/// Default `idle_scan_duration_ms`: scan window while backed off.
+pub const IDLE_SCAN_DURATION_MS: i32 = 1_000;
+
+/// Default `idle_scan_period_ms`: time between backed-off scans.
+pub const IDLE_SCAN_PERIOD_MS: u32 = 10_000;
+
+/// Tunable repeater parameters.
+#[derive(Debug, Clone)]
+pub struct RepeaterConfig {
+    /// Bluetooth SIG company ID of this deployment's advertisements. Only
+    /// these are scanned and re-broadcast. The 0xFFFF testing ID is the
+    /// default, and is rejected in release builds.
+    pub manufacturer_id: u16,
+    /// Duration to scan for advertisements (ms).
+    pub scan_duration_ms: i32,
+    /// Airtime each active notification gets per cycle (ms), in dwells of
+    /// `schedule::ENTRY_DWELL_MS` taken in turn with the other entries.
+    pub rebroadcast_duration_ms: u32,
+    /// Advertising interval while re-broadcasting, in 0.625 ms units.
+    pub adv_interval: u16,
+    /// Up to this much is added to `adv_interval` (0.625 ms units), drawn
+    /// afresh for every dwell, so co-located repeaters don't advertise in
+    /// step. 0 disables it.
+    pub adv_interval_jitter: u16,
+    /// Up to this much is added to every dwell (ms), for the same reason.
+    /// 0 disables it.
+    pub dwell_jitter_ms: u32,
+    /// Extra start attempts when `start()` succeeds but the radio does not
+    /// report advertising.
+    pub adv_start_retries: u32,
+    /// Maximum number of notifications aired (`set_data` + `start`) per
+    /// re-broadcast cycle; the rest wait for the next cycle.
+    pub max_advertise_ops_per_cycle: usize,
+    /// Notifications of at least this priority get extra dwells for
+    /// `escalate_for_ms` after joining the active list, so urgent news
+    /// spreads fast (see `schedule::escalation`). 0 = never.
+    pub escalate_min_priority: u8,
+    /// How long an escalated notification's extra airtime lasts (ms),
+    /// decaying to the usual share over it.
+    pub escalate_for_ms: u32,
+    /// Maximum number of notifications kept in the active list.
+    pub max_active_notifications: usize,
+    /// Maximum number of distinct notifications collected during one scan.
+    pub max_scan_queue: usize,
+    /// How long the re-broadcast task waits before looking again when there
+    /// is nothing to re-broadcast (ms).
+    pub idle_delay_ms: u32,
+    /// Consecutive cycles ending with an empty active list after which the
+    /// scan backs off to `idle_scan_duration_ms` every `idle_scan_period_ms`,
+    /// keeping the radio off in between to save power. The first
+    /// notification heard brings back back-to-back `scan_duration_ms` scans.
+    /// 0 = never back off.
+    pub idle_cycles_before_backoff: u32,
+    /// Scan window while backed off (ms).
+    pub idle_scan_duration_ms: i32,
+    /// Time from the start of one backed-off scan to the start of the next
+    /// (ms).
+    pub idle_scan_period_ms: u32,
+    /// If non-empty, only sign the client tag for notifications from these
+    /// `source_id`s; others are relayed with their client tag left unset.
+    /// Used when a repeater is paired with its own station's broadcaster.
+    pub sign_only_sources: Vec<[u8; 4]>,
+    /// Weakest RSSI (dBm) at which a notification is relayed at all.
+    pub min_rssi_relay: i8,
+    /// Weakest RSSI (dBm) at which this repeater stamps the client tag. A
+    /// strong reception of an untagged notification is most likely the
+    /// broadcaster itself; weaker ones are relayed unsigned.
+    pub min_rssi_sign: i8,
+    /// `notification_id`s this repeater refuses to relay, e.g. to silence an
+    /// erroneous notification during an incident. At most
+    /// `MAX_BLOCKED_NOTIFICATIONS` entries.
+    pub blocked_notifications: Vec<[u8; 4]>,
+    /// If non-empty, the only `source_id`s relayed at all. A notification
+    /// from any other station is dropped even though its infra tag
+    /// verifies, e.g. one from a rogue broadcaster using a leaked key. At
+    /// most `MAX_LISTED_SOURCES` entries; can be replaced from NVS (see
+    /// `persist::NVS_ALLOWED_SOURCES_KEY`).
+    pub allowed_sources: Vec<[u8; 4]>,
+    /// `source_id`s never relayed, even if also in `allowed_sources`. At
+    /// most `MAX_LISTED_SOURCES` entries; can be replaced from NVS (see
+    /// `persist::NVS_DENIED_SOURCES_KEY`).
+    pub denied_sources: Vec<[u8; 4]>,
+    /// If non-empty, the only `destination_id`s (0–15) relayed, so a
+    /// repeater in a segmented deployment keeps its active list for its own
+    /// zone. Unlike the source lists this is about where a transport is
+    /// headed, not who announced it.
+    pub destinations: Vec<u8>,
+    /// `key_id`s from the key provider's keyring this repeater accepts.
+    /// Empty = every key in the keyring. Narrow it to retire an old key once every
+    /// broadcaster has moved to the new one.
+    pub infra_key_ids: Vec<u8>,
+    /// `client_key_id`s from the key provider's client keyring to sign
+    /// client tags with, the first as the notification's own tag and the
+    /// rest in a client-tag record (see `ble_protocol_core::client_tags`).
+    /// Empty = the provider's first client key alone. List two while the
+    /// client key is being rolled, so clients on either generation verify.
+    pub client_key_ids: Vec<u8>,
+    /// Whether to relay protocol v1 notifications, from broadcasters not
+    /// yet upgraded. They go back on air as v1, as they arrived. v1 has no
+    /// seq, timestamp or hop count, so they get no replay or freshness
+    /// check: turn off once every broadcaster emits the current version.
+    pub relay_v1: bool,
+    /// Whether to ignore this repeater's own re-broadcasts when it hears
+    /// them back (see `repeater::is_own_echo`). Peers relaying the same
+    /// bytes are still taken.
+    pub ignore_own_echo: bool,
+    /// Every this many re-broadcast cycles, air an ack beacon for each
+    /// notification aired, so a broadcaster scanning nearby can tell it was
+    /// relayed (see `ack`). 0 = never. For deployment checks: acks take
+    /// airtime from the notifications themselves.
+    pub ack_every_cycles: u32,
+    /// Every this many re-broadcast cycles that air notifications, air a
+    /// capability beacon saying which versions this repeater relays, so
+    /// neighbours with `neighbor_aware_relay` know (see
+    /// `ble_protocol_core::capability`). 0 = never.
+    pub capability_every_cycles: u32,
+    /// Whether to skip relaying what no neighbour heard beaconing would
+    /// relay further, e.g. a current-version notification when every
+    /// neighbour only relays v1. With no fresh neighbour known, everything
+    /// is relayed. Clients and older repeaters don't beacon: only for
+    /// repeaters whose relays reach other repeaters alone.
+    pub neighbor_aware_relay: bool,
+    /// How long a neighbour's capability beacon counts for without being
+    /// heard again. A few of its beacon periods, so a lost beacon doesn't
+    /// drop it.
+    pub neighbor_stale_secs: u32,
+    /// Whether the system clock holds real time (e.g. synced over SNTP). The
+    /// ESP32 has no battery-backed clock and boots at the epoch, so this is
+    /// off by default and `timestamp_ms` staleness goes unchecked; when on,
+    /// notifications older than `MAX_AGE_MS`, or further ahead than
+    /// `max_future_skew_ms`, are rejected.
+    pub has_clock: bool,
+    /// With `has_clock`, how far ahead of this repeater's clock a
+    /// `timestamp_ms` may be, for broadcaster clocks that run ahead. Apart
+    /// from the age limit, and at most `MAX_AGE_MS`: every millisecond of it
+    /// is a millisecond a pre-dated packet stays fresh for.
+    pub max_future_skew_ms: u64,
+    /// Save the replay cache to NVS whenever it takes a new `seq` and
+    /// restore it at boot, so a packet captured before a reboot can't be
+    /// replayed after it (see `persist`). Needs `has_clock`: restored entries are
+    /// aged by their packets' timestamps.
+    pub persist_replay_cache: bool,
+    /// How often the active list is saved to NVS while only the entries'
+    /// expiry times have changed, in seconds; 0 = only when entries are
+    /// added, removed or replaced. Any such change is saved the cycle it
+    /// happens. Each save is a flash write, and a cycle every few seconds
+    /// would otherwise wear the flash out.
+    pub persist_refresh_secs: u32,
+    /// Run a single scan and re-broadcast cycle, then return from `main`
+    /// instead of looping forever. For test harnesses and CI, not for
+    /// deployment.
+    pub once: bool,
+}
+
+impl Default for RepeaterConfig {
+    fn default() -> Self {
+        Self {
+            manufacturer_id: MANUFACTURER_ID,
+            scan_duration_ms: 3000,
+            rebroadcast_duration_ms: 2000,
+            adv_interval: 32, // 32 × 0.625 ms = 20 ms
+            adv_interval_jitter: ADV_INTERVAL_JITTER,
+            dwell_jitter_ms: DWELL_JITTER_MS,
+            adv_start_retries: 2,
+            max_advertise_ops_per_cycle: 16,
+            escalate_min_priority: 0,
+            escalate_for_ms: 6000,
+            max_active_notifications: 16,
+            max_scan_queue: 32,
+            idle_delay_ms: 500,
+            idle_cycles_before_backoff: IDLE_CYCLES_BEFORE_BACKOFF,
+            idle_scan_duration_ms: IDLE_SCAN_DURATION_MS,
+            idle_scan_period_ms: IDLE_SCAN_PERIOD_MS,
+            sign_only_sources: Vec::new(),
+            min_rssi_relay: i8::MIN,
+            min_rssi_sign: i8::MIN,
+            blocked_notifications: Vec::new(),
+            allowed_sources: Vec::new(),
+            denied_sources: Vec::new(),
+            destinations: Vec::new(),
+            infra_key_ids: Vec::new(),
+            client_key_ids: Vec::new(),
+            relay_v1: true,
+            ignore_own_echo: true,
+            ack_every_cycles: 0,
+            capability_every_cycles: 0,
+            neighbor_aware_relay: false,
+            neighbor_stale_secs: 900,
+            has_clock: false,
+            max_future_skew_ms: MAX_FUTURE_SKEW_MS,
+            persist_replay_cache: false,
+            persist_refresh_secs: PERSIST_REFRESH_SECS,
+            once: false,
+        }
+    }
+}
+
+/// What to do with a verified notification heard during a scan.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelayDecision {
+    /// Heard below `min_rssi_relay`.
+    Drop,
+    /// Already carries a client tag from an earlier repeater.
+    PassThrough,
+    /// Untagged and heard strongly enough to sign.
+    Sign,
+    /// Untagged, relayed without a client tag.
+    RelayUnsigned { reason: &'static str },
+}
+
+/// A configuration field holds a value the repeater cannot run with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub field: &'static str,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid `{}`: {}", self.field, self.reason)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn invalid(field: &'static str, reason: &'static str) -> ConfigError {
+    ConfigError { field, reason }
+}
+
+impl RepeaterConfig {
+    /// Whether this repeater should sign the client tag for `source_id`.
+    pub fn should_sign_client(&self, source_id: [u8; 4]) -> bool {
+        self.sign_only_sources.is_empty() || self.sign_only_sources.contains(&source_id)
+    }
+
+    /// Whether `notification_id` is on the operator block list. Only call this
+    /// for notifications whose infrastructure tag has already been verified,
+    /// so the list can't be used to tell forged packets from genuine ones.
+    pub fn is_blocked(&self, notification_id: [u8; 4]) -> bool {
+        self.blocked_notifications.contains(&notification_id)
+    }
+
+    /// Why notifications from `source_id` must not be relayed, if they must
+    /// not. Like `is_blocked`, only call this once the infrastructure tag has
+    /// verified.
+    pub fn source_rejection(&self, source_id: [u8; 4]) -> Option<&'static str> {
+        if self.denied_sources.contains(&source_id) {
+            Some("source in denied_sources")
+        } else if !self.allowed_sources.is_empty() && !self.allowed_sources.contains(&source_id) {
+            Some("source not in allowed_sources")
+        } else {
+            None
+        }
+    }
+
+    /// Whether notifications for `destination_id` are relayed here.
+    pub fn relays_destination(&self, destination_id: u8) -> bool {
+        self.destinations.is_empty() || self.destinations.contains(&destination_id)
+    }
+
+    /// The infrastructure keys notifications are verified against: those of
+    /// `keys` that `infra_key_ids` selects. Checked here rather than in
+    /// `validate`, since the keyring is only known once keys are loaded.
+    pub fn infra_keyring(&self, keys: &dyn KeyProvider) -> Result<Vec<InfraKey>, ConfigError> {
+        let available = keys.infra_keyring();
+        if self
+            .infra_key_ids
+            .iter()
+            .any(|id| infra_key(available, *id).is_none())
+        {
+            return Err(invalid(
+                "infra_key_ids",
+                "every id must name a key in the keyring",
+            ));
+        }
+        Ok(available
+            .iter()
+            .copied()
+            .filter(|(id, _)| self.infra_key_ids.is_empty() || self.infra_key_ids.contains(id))
+            .collect())
+    }
+
+    /// The client keys tags are signed with: those of `keys` that
+    /// `client_key_ids` selects, in its order. Checked here rather than in
+    /// `validate`, like `infra_keyring`.
+    pub fn client_keys(&self, keys: &dyn KeyProvider) -> Result<Vec<ClientKey>, ConfigError> {
+        let available = keys.client_keyring();
+        if self.client_key_ids.is_empty() {
+            return Ok(available.iter().copied().take(1).collect());
+        }
+        self.client_key_ids
+            .iter()
+            .map(|id| {
+                available
+                    .iter()
+                    .copied()
+                    .find(|(k, _)| k == id)
+                    .ok_or(invalid(
+                        "client_key_ids",
+                        "every id must name a key in the client keyring",
+                    ))
+            })
+            .collect()
+    }
+
+    /// Decide how to relay a notification from `source_id` heard at `rssi`.
+    pub fn relay_decision(
+        &self,
+        rssi: i8,
+        source_id: [u8; 4],
+        has_client_tag: bool,
+    ) -> RelayDecision {
+        if rssi < self.min_rssi_relay {
+            RelayDecision::Drop
+        } else if has_client_tag {
+            RelayDecision::PassThrough
+        } else if !self.should_sign_client(source_id) {
+            RelayDecision::RelayUnsigned {
+                reason: "source not in sign_only_sources",
+            }
+        } else if rssi < self.min_rssi_sign {
+            RelayDecision::RelayUnsigned {
+                reason: "below min_rssi_sign",
+            }
+        } else {
+            RelayDecision::Sign
+        }
+    }
+
+    /// Whether this is the 0xFFFF company ID reserved for testing.
+    pub fn uses_test_manufacturer_id(&self) -> bool {
+        self.manufacturer_id == MANUFACTURER_ID
+    }
+
+    /// Check field ranges and the relationships between fields.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.validate_for(cfg!(debug_assertions))
+    }
+
+    /// `validate`, for a debug build or a release one. Only debug builds may
+    /// run on the testing company ID.
+    fn validate_for(&self, debug_build: bool) -> Result<(), ConfigError> {
+        if !debug_build && self.uses_test_manufacturer_id() {
+            return Err(invalid(
+                "manufacturer_id",
+                "0xFFFF is reserved for testing; set this deployment's company ID",
+            ));
+        }
+        if self.scan_duration_ms <= 0 {
+            return Err(invalid("scan_duration_ms", "must be positive"));
+        }
+        if self.idle_scan_duration_ms <= 0 {
+            return Err(invalid("idle_scan_duration_ms", "must be positive"));
+        }
+        if self.idle_scan_period_ms < self.idle_scan_duration_ms as u32 {
+            return Err(invalid(
+                "idle_scan_period_ms",
+                "must be at least idle_scan_duration_ms",
+            ));
+        }
+        if self.rebroadcast_duration_ms == 0 {
+            return Err(invalid("rebroadcast_duration_ms", "must be positive"));
+        }
+        if !ADV_INTERVAL_RANGE.contains(&self.adv_interval) {
+            return Err(invalid(
+                "adv_interval",
+                "must be within 0x0020..=0x4000 (20 ms – 10.24 s)",
+            ));
+        }
+        if u32::from(self.adv_interval) + u32::from(self.adv_interval_jitter)
+            > u32::from(*ADV_INTERVAL_RANGE.end())
+        {
+            return Err(invalid(
+                "adv_interval_jitter",
+                "adv_interval + adv_interval_jitter must be at most 0x4000 (10.24 s)",
+            ));
+        }
+        if self.max_active_notifications == 0 {
+            return Err(invalid("max_active_notifications", "must be at least 1"));
+        }
+        if self.max_advertise_ops_per_cycle == 0 {
+            return Err(invalid("max_advertise_ops_per_cycle", "must be at least 1"));
+        }
+        if self.escalate_min_priority > 0 && self.escalate_for_ms == 0 {
+            return Err(invalid(
+                "escalate_for_ms",
+                "must be positive with escalate_min_priority",
+            ));
+        }
+        if self.max_scan_queue < self.max_active_notifications {
+            return Err(invalid(
+                "max_scan_queue",
+                "must be at least max_active_notifications",
+            ));
+        }
+        if self.blocked_notifications.len() > MAX_BLOCKED_NOTIFICATIONS {
+            return Err(invalid(
+                "blocked_notifications",
+                "must hold at most MAX_BLOCKED_NOTIFICATIONS ids",
+            ));
+        }
+        if self.allowed_sources.len() > MAX_LISTED_SOURCES {
+            return Err(invalid(
+                "allowed_sources",
+                "must hold at most MAX_LISTED_SOURCES ids",
+            ));
+        }
+        if self.denied_sources.len() > MAX_LISTED_SOURCES {
+            return Err(invalid(
+                "denied_sources",
+                "must hold at most MAX_LISTED_SOURCES ids",
+            ));
+        }
+        if self.destinations.iter().any(|&d| d > 0x0F) {
+            return Err(invalid("destinations", "must hold destination_ids 0–15"));
+        }
+        if self.client_key_ids.len() > 1 + MAX_EXTRA_CLIENT_TAGS {
+            return Err(invalid(
+                "client_key_ids",
+                "must hold at most 1 + MAX_EXTRA_CLIENT_TAGS ids",
+            ));
+        }
+        if (1..self.client_key_ids.len())
+            .any(|i| self.client_key_ids[..i].contains(&self.client_key_ids[i]))
+        {
+            return Err(invalid("client_key_ids", "must not repeat an id"));
+        }
+        if self.min_rssi_sign < self.min_rssi_relay {
+            return Err(invalid("min_rssi_sign", "must be at least min_rssi_relay"));
+        }
+        if self.neighbor_aware_relay && self.neighbor_stale_secs == 0 {
+            return Err(invalid(
+                "neighbor_stale_secs",
+                "must be positive with neighbor_aware_relay",
+            ));
+        }
+        if self.max_future_skew_ms > MAX_AGE_MS {
+            return Err(invalid(
+                "max_future_skew_ms",
+                "must be at most MAX_AGE_MS (300000)",
+            ));
+        }
+        if self.persist_replay_cache && !self.has_clock {
+            return Err(invalid("persist_replay_cache", "needs has_clock"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_protocol_core::{StaticKeys, INFRA_KEYRING};
+
+    const STATION: [u8; 4] = [0xA1, 0xB2, 0xC3, 0xD4];
+
+    fn thresholds(relay: i8, sign: i8) -> RepeaterConfig {
+        RepeaterConfig {
+            min_rssi_relay: relay,
+            min_rssi_sign: sign,
+            ..RepeaterConfig::default()
+        }
+    }
+
+    #[test]
+    fn untagged_notifications_across_rssi() {
+        let cfg = thresholds(-90, -60);
+        assert_eq!(cfg.relay_decision(-40, STATION, false), RelayDecision::Sign);
+        assert_eq!(cfg.relay_decision(-60, STATION, false), RelayDecision::Sign);
+        assert_eq!(
+            cfg.relay_decision(-61, STATION, false),
+            RelayDecision::RelayUnsigned {
+                reason: "below min_rssi_sign"
+            }
+        );
+        assert_eq!(
+            cfg.relay_decision(-90, STATION, false),
+            RelayDecision::RelayUnsigned {
+                reason: "below min_rssi_sign"
+            }
+        );
+        assert_eq!(cfg.relay_decision(-91, STATION, false), RelayDecision::Drop);
+    }
+
+    #[test]
+    fn tagged_notifications_are_never_resigned() {
+        let cfg = thresholds(-90, -60);
+        assert_eq!(
+            cfg.relay_decision(-40, STATION, true),
+            RelayDecision::PassThrough
+        );
+        assert_eq!(
+            cfg.relay_decision(-80, STATION, true),
+            RelayDecision::PassThrough
+        );
+        assert_eq!(cfg.relay_decision(-100, STATION, true), RelayDecision::Drop);
+    }
+
+    #[test]
+    fn source_list_applies_before_rssi() {
+        let cfg = RepeaterConfig {
+            sign_only_sources: vec![[0x01, 0x02, 0x03, 0x04]],
+            ..thresholds(-90, -60)
+        };
+        assert_eq!(
+            cfg.relay_decision(-40, STATION, false),
+            RelayDecision::RelayUnsigned {
+                reason: "source not in sign_only_sources"
+            }
+        );
+    }
+
+    #[test]
+    fn defaults_relay_and_sign_everything() {
+        let cfg = RepeaterConfig::default();
+        assert_eq!(
+            cfg.relay_decision(i8::MIN, STATION, false),
+            RelayDecision::Sign
+        );
+    }
+
+    #[test]
+    fn sign_threshold_below_relay_threshold_is_rejected() {
+        assert_eq!(
+            thresholds(-60, -90).validate().unwrap_err().field,
+            "min_rssi_sign"
+        );
+        assert!(thresholds(-70, -70).validate().is_ok());
+    }
+
+    #[test]
+    fn only_listed_notifications_are_blocked() {
+        let blocked = [0xDE, 0xAD, 0xBE, 0xEF];
+        let cfg = RepeaterConfig {
+            blocked_notifications: vec![blocked],
+            ..RepeaterConfig::default()
+        };
+        assert!(cfg.is_blocked(blocked));
+        assert!(!cfg.is_blocked([0xDE, 0xAD, 0xBE, 0xEE]));
+        assert!(!RepeaterConfig::default().is_blocked(blocked));
+    }
+
+    #[test]
+    fn block_list_is_bounded() {
+        let mut cfg = RepeaterConfig {
+            blocked_notifications: vec![[0; 4]; MAX_BLOCKED_NOTIFICATIONS],
+            ..RepeaterConfig::default()
+        };
+        assert!(cfg.validate().is_ok());
+        cfg.blocked_notifications.push([1; 4]);
+        assert_eq!(cfg.validate().unwrap_err().field, "blocked_notifications");
+    }
+
+    #[test]
+    fn destinations_filter_only_when_set() {
+        let mut cfg = RepeaterConfig {
+            destinations: vec![3, 4],
+            ..RepeaterConfig::default()
+        };
+        assert!(cfg.relays_destination(3) && cfg.relays_destination(4));
+        assert!(!cfg.relays_destination(7));
+        assert!(RepeaterConfig::default().relays_destination(7));
+        assert!(cfg.validate().is_ok());
+        cfg.destinations.push(16);
+        assert_eq!(cfg.validate().unwrap_err().field, "destinations");
+    }
+
+    #[test]
+    fn future_skew_is_bounded_by_the_age_limit() {
+        let mut cfg = RepeaterConfig {
+            max_future_skew_ms: MAX_AGE_MS,
+            ..RepeaterConfig::default()
+        };
+        assert!(cfg.validate().is_ok());
+        cfg.max_future_skew_ms += 1;
+        assert_eq!(cfg.validate().unwrap_err().field, "max_future_skew_ms");
+    }
+
+    #[test]
+    fn neighbor_aware_relay_needs_a_staleness_limit() {
+        let mut cfg = RepeaterConfig {
+            neighbor_aware_relay: true,
+            ..RepeaterConfig::default()
+        };
+        assert!(cfg.validate().is_ok());
+        cfg.neighbor_stale_secs = 0;
+        assert_eq!(cfg.validate().unwrap_err().field, "neighbor_stale_secs");
+    }
+
+    #[test]
+    fn escalation_needs_a_window() {
+        let mut cfg = RepeaterConfig {
+            escalate_min_priority: 5,
+            ..RepeaterConfig::default()
+        };
+        assert!(cfg.validate().is_ok());
+        cfg.escalate_for_ms = 0;
+        assert_eq!(cfg.validate().unwrap_err().field, "escalate_for_ms");
+    }
+
+    #[test]
+    fn persisting_the_replay_cache_needs_a_clock() {
+        let mut cfg = RepeaterConfig {
+            persist_replay_cache: true,
+            ..RepeaterConfig::default()
+        };
+        assert_eq!(cfg.validate().unwrap_err().field, "persist_replay_cache");
+        cfg.has_clock = true;
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn idle_scan_must_fit_in_its_period() {
+        let mut cfg = RepeaterConfig {
+            idle_scan_period_ms: IDLE_SCAN_DURATION_MS as u32 - 1,
+            ..RepeaterConfig::default()
+        };
+        assert_eq!(cfg.validate().unwrap_err().field, "idle_scan_period_ms");
+        cfg.idle_scan_duration_ms = 0;
+        assert_eq!(cfg.validate().unwrap_err().field, "idle_scan_duration_ms");
+    }
+
+    #[test]
+    fn jittered_interval_must_stay_legal() {
+        let mut cfg = RepeaterConfig {
+            adv_interval: 0x4000 - ADV_INTERVAL_JITTER,
+            ..RepeaterConfig::default()
+        };
+        assert!(cfg.validate().is_ok());
+        cfg.adv_interval += 1;
+        assert_eq!(cfg.validate().unwrap_err().field, "adv_interval_jitter");
+    }
+
+    #[test]
+    fn source_lists_deny_first_then_allow() {
+        let rogue = [0x66, 0x66, 0x66, 0x66];
+        assert_eq!(RepeaterConfig::default().source_rejection(rogue), None);
+
+        let cfg = RepeaterConfig {
+            allowed_sources: vec![STATION],
+            ..RepeaterConfig::default()
+        };
+        assert_eq!(cfg.source_rejection(STATION), None);
+        assert_eq!(
+            cfg.source_rejection(rogue),
+            Some("source not in allowed_sources")
+        );
+
+        let cfg = RepeaterConfig {
+            denied_sources: vec![STATION],
+            ..cfg
+        };
+        assert_eq!(
+            cfg.source_rejection(STATION),
+            Some("source in denied_sources")
+        );
+    }
+
+    #[test]
+    fn source_lists_are_bounded() {
+        let mut cfg = RepeaterConfig {
+            allowed_sources: vec![STATION; MAX_LISTED_SOURCES],
+            denied_sources: vec![STATION; MAX_LISTED_SOURCES],
+            ..RepeaterConfig::default()
+        };
+        assert!(cfg.validate().is_ok());
+        cfg.denied_sources.push(STATION);
+        assert_eq!(cfg.validate().unwrap_err().field, "denied_sources");
+        cfg.allowed_sources.push(STATION);
+        assert_eq!(cfg.validate().unwrap_err().field, "allowed_sources");
+    }
+
+    #[test]
+    fn keyring_defaults_to_every_key_and_rejects_unknown_ids() {
+        assert_eq!(
+            RepeaterConfig::default()
+                .infra_keyring(&StaticKeys)
+                .unwrap(),
+            INFRA_KEYRING
+        );
+
+        let (id, _) = INFRA_KEYRING[0];
+        let cfg = RepeaterConfig {
+            infra_key_ids: vec![id],
+            ..RepeaterConfig::default()
+        };
+        assert_eq!(cfg.infra_keyring(&StaticKeys).unwrap(), [INFRA_KEYRING[0]]);
+
+        let unknown = (0..=u8::MAX)
+            .find(|id| infra_key(INFRA_KEYRING, *id).is_none())
+            .unwrap();
+        let cfg = RepeaterConfig {
+            infra_key_ids: vec![id, unknown],
+            ..RepeaterConfig::default()
+        };
+        assert_eq!(
+            cfg.infra_keyring(&StaticKeys).unwrap_err().field,
+            "infra_key_ids"
+        );
+    }
+
+    #[test]
+    fn client_keys_follow_the_configured_order() {
+        struct TwoGenerations;
+        impl KeyProvider for TwoGenerations {
+            fn infra_keyring(&self) -> &[InfraKey] {
+                INFRA_KEYRING
+            }
+            fn current_infra_key(&self) -> InfraKey {
+                INFRA_KEYRING[0]
+            }
+            fn client_key(&self) -> &'static [u8] {
+                b"generation-1"
+            }
+            fn client_keyring(&self) -> &[ClientKey] {
+                &[(1, b"generation-1"), (2, b"generation-2")]
+            }
+        }
+
+        let keys = RepeaterConfig::default()
+            .client_keys(&TwoGenerations)
+            .unwrap();
+        assert_eq!(keys, [(1, &b"generation-1"[..])]);
+
+        let cfg = RepeaterConfig {
+            client_key_ids: vec![2, 1],
+            ..RepeaterConfig::default()
+        };
+        assert!(cfg.validate().is_ok());
+        let keys = cfg.client_keys(&TwoGenerations).unwrap();
+        assert_eq!(keys, [(2, &b"generation-2"[..]), (1, &b"generation-1"[..])]);
+
+        let cfg = RepeaterConfig {
+            client_key_ids: vec![1, 3],
+            ..RepeaterConfig::default()
+        };
+        assert_eq!(
+            cfg.client_keys(&TwoGenerations).unwrap_err().field,
+            "client_key_ids"
+        );
+
+        let cfg = RepeaterConfig {
+            client_key_ids: vec![1, 2, 1],
+            ..RepeaterConfig::default()
+        };
+        assert_eq!(cfg.validate().unwrap_err().field, "client_key_ids");
+    }
+
+    #[test]
+    fn test_manufacturer_id_is_debug_only() {
+        let cfg = RepeaterConfig::default();
+        assert!(cfg.uses_test_manufacturer_id());
+        assert!(cfg.validate_for(true).is_ok());
+        assert_eq!(
+            cfg.validate_for(false).unwrap_err().field,
+            "manufacturer_id"
+        );
+
+        let cfg = RepeaterConfig {
+            manufacturer_id: 0x1234,
+            ..RepeaterConfig::default()
+        };
+        assert!(cfg.validate_for(false).is_ok());
+    }
+}
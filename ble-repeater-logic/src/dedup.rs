@@ -0,0 +1,116 @@
+//! Remembering notifications already relayed.
+//!
+//! A broadcaster airs the same packet for its whole broadcast window, so a
+//! repeater hears it again every scan. The active list only matches on
+//! `notification_id` while the entry is alive; once it is pruned, the next
+//! copy would be verified, re-signed and re-added as if it were new.
+//! `DedupCache` keeps `(source_id, notification_id)` pairs for a while after
+//! they were relayed so those copies are recognised: they may refresh the
+//! active entry, but don't add it back.
+
+/// `(source_id, notification_id)`.
+pub type NotificationKey = ([u8; 4], [u8; 4]);
+
+/// Up to `N` recently relayed notifications, each remembered until its own
+/// expiry time (monotonic microseconds).
+///
+/// Entries are kept in a ring: when all `N` slots are taken, a new entry
+/// overwrites the oldest one inserted, expired or not.
+pub struct DedupCache<const N: usize> {
+    slots: [Option<(NotificationKey, i64)>; N],
+    /// Slot the next new entry goes into; always the oldest one.
+    next: usize,
+}
+
+impl<const N: usize> Default for DedupCache<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> DedupCache<N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: [None; N],
+            next: 0,
+        }
+    }
+
+    /// Whether `key` was inserted and has not expired by `now_us`.
+    pub fn contains(&self, key: NotificationKey, now_us: i64) -> bool {
+        self.slots
+            .iter()
+            .flatten()
+            .any(|&(k, expires_at_us)| k == key && expires_at_us > now_us)
+    }
+
+    /// Remember `key` until `expires_at_us`. A key already present keeps its
+    /// slot and takes the new expiry.
+    pub fn insert(&mut self, key: NotificationKey, expires_at_us: i64) {
+        if let Some(slot) = self.slots.iter_mut().flatten().find(|(k, _)| *k == key) {
+            slot.1 = expires_at_us;
+            return;
+        }
+        if N == 0 {
+            return;
+        }
+        self.slots[self.next] = Some((key, expires_at_us));
+        self.next = (self.next + 1) % N;
+    }
+
+    /// Forget `key`, so the next copy heard is no longer a duplicate.
+    pub fn remove(&mut self, key: NotificationKey) {
+        for slot in &mut self.slots {
+            if slot.is_some_and(|(k, _)| k == key) {
+                *slot = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: NotificationKey = ([0xA1; 4], [1; 4]);
+    const B: NotificationKey = ([0xB1; 4], [2; 4]);
+    const C: NotificationKey = ([0xC1; 4], [3; 4]);
+
+    #[test]
+    fn inserted_key_is_seen_until_it_expires() {
+        let mut cache = DedupCache::<4>::new();
+        assert!(!cache.contains(A, 0));
+        cache.insert(A, 100);
+        assert!(cache.contains(A, 0));
+        assert!(cache.contains(A, 99));
+        assert!(!cache.contains(A, 100));
+    }
+
+    #[test]
+    fn same_notification_id_from_another_source_is_distinct() {
+        let mut cache = DedupCache::<4>::new();
+        cache.insert(A, 100);
+        assert!(!cache.contains((B.0, A.1), 0));
+    }
+
+    #[test]
+    fn reinserting_extends_the_expiry_without_taking_a_slot() {
+        let mut cache = DedupCache::<2>::new();
+        cache.insert(A, 100);
+        cache.insert(A, 200);
+        cache.insert(B, 100);
+        assert!(cache.contains(A, 150));
+        assert!(cache.contains(B, 0));
+    }
+
+    #[test]
+    fn full_cache_evicts_the_oldest_entry() {
+        let mut cache = DedupCache::<2>::new();
+        cache.insert(A, 100);
+        cache.insert(B, 100);
+        cache.insert(C, 100);
+        assert!(!cache.contains(A, 0));
+        assert!(cache.contains(B, 0));
+        assert!(cache.contains(C, 0));
+    }
+}
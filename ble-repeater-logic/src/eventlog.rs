@@ -0,0 +1,336 @@
+//! Per-notification log lines.
+//!
+//! With the `compact` feature, on by default, each event is logged as one
+//! short line, so a busy channel doesn't flood the UART or hold up the scan
+//! loop:
+//!
+//! ```text
+//! RX nid=a1b2c3d4 rssi=-62 ok
+//! RX nid=a1b2c3d4 rssi=-62 drop=replay
+//! RX rssi=-70 drop=CRC mismatch
+//! ADD nid=a1b2c3d4 rssi=-62
+//! EXP nid=a1b2c3d4
+//! TX nid=a1b2c3d4 left=25s
+//! ```
+//!
+//! The `verbose` feature logs the full description of each notification
+//! and decision instead (`verbose!`), over several lines. With neither,
+//! only warnings and errors are logged. Those (a forged tag, an unlisted
+//! source, a full list or scan queue) are compact lines too, logged at
+//! their own level whatever the features:
+//!
+//! ```text
+//! RX rssi=-62 drop=infra HMAC mismatch
+//! EVICT nid=a1b2c3d4 prio=0 for nid=e5f6a7b8 prio=2
+//! FULL nid=e5f6a7b8 prio=0 drop
+//! QUEUE full drop=3
+//! ```
+
+use core::fmt;
+
+use ble_protocol_core::TransportNotification;
+use log::{info, log, Level};
+
+use crate::active::ActiveNotification;
+use crate::telemetry::Event;
+
+/// Whether events are logged as compact lines: `compact`, and not
+/// `verbose`, which takes over when both are on.
+const COMPACT: bool = cfg!(all(feature = "compact", not(feature = "verbose")));
+
+/// Whether the `verbose` feature is on, for `verbose!` in other crates.
+#[doc(hidden)]
+pub const VERBOSE: bool = cfg!(feature = "verbose");
+
+/// `info!` with the `verbose` feature, nothing otherwise: the detailed
+/// lines the compact ones stand in for. Like `info!`, it checks the log
+/// level before evaluating its arguments.
+#[macro_export]
+macro_rules! verbose {
+    ($($arg:tt)+) => {
+        if $crate::eventlog::VERBOSE {
+            log::info!($($arg)+)
+        }
+    };
+}
+pub use crate::verbose;
+
+/// A notification heard and verified, now queued for relay.
+pub fn relayed(notif: &TransportNotification, rssi: i8) {
+    emit(Line::Rx {
+        notif: Some(notif),
+        rssi,
+        outcome: Outcome::Relayed,
+    });
+}
+
+/// A verified cancellation of `notif`'s `notification_id`.
+pub fn cancels(notif: &TransportNotification, rssi: i8) {
+    emit(Line::Rx {
+        notif: Some(notif),
+        rssi,
+        outcome: Outcome::Cancels,
+    });
+}
+
+/// An advertisement not relayed. `notif` is `None` if it didn't verify.
+pub fn dropped(notif: Option<&TransportNotification>, rssi: i8, reason: &dyn fmt::Display) {
+    emit(Line::Rx {
+        notif,
+        rssi,
+        outcome: Outcome::Dropped(reason),
+    });
+}
+
+/// An advertisement refused for a reason someone should look into: a
+/// forged tag, a source that isn't listed. Logged at `level` either way.
+pub fn refused(
+    level: Level,
+    notif: Option<&TransportNotification>,
+    rssi: i8,
+    reason: &dyn fmt::Display,
+) {
+    emit_at(
+        level,
+        Line::Rx {
+            notif,
+            rssi,
+            outcome: Outcome::Dropped(reason),
+        },
+    );
+}
+
+/// The active list was full and `victim` made way for `by`, of higher
+/// priority.
+pub fn evicted(victim: &ActiveNotification, by: &ActiveNotification) {
+    emit_at(Level::Warn, Line::Evicted { victim, by });
+}
+
+/// The active list was full of entries of at least `entry`'s priority, so
+/// it was dropped.
+pub fn list_full(entry: &ActiveNotification) {
+    emit_at(Level::Error, Line::Full { entry });
+}
+
+/// The scan queue overflowed, and `count` notifications were dropped or
+/// evicted from it.
+pub fn queue_full(count: u32) {
+    emit_at(Level::Error, Line::QueueFull { count });
+}
+
+/// Something happened to an active entry.
+pub fn entry(event: Event, entry: &ActiveNotification) {
+    emit(Line::Entry { event, entry });
+}
+
+/// An active entry went on air, `now_us` being the monotonic time.
+pub fn aired(entry: &ActiveNotification, now_us: i64) {
+    emit(Line::Aired {
+        entry,
+        left_secs: entry.remaining_us(now_us) / 1_000_000,
+    });
+}
+
+fn emit(line: Line<'_>) {
+    if COMPACT {
+        info!("{}", line);
+    }
+}
+
+fn emit_at(level: Level, line: Line<'_>) {
+    log!(level, "{}", line);
+}
+
+/// One compact line. Formatted only when it is written.
+enum Line<'a> {
+    Rx {
+        notif: Option<&'a TransportNotification>,
+        rssi: i8,
+        outcome: Outcome<'a>,
+    },
+    Entry {
+        event: Event,
+        entry: &'a ActiveNotification,
+    },
+    Aired {
+        entry: &'a ActiveNotification,
+        left_secs: i64,
+    },
+    Evicted {
+        victim: &'a ActiveNotification,
+        by: &'a ActiveNotification,
+    },
+    Full {
+        entry: &'a ActiveNotification,
+    },
+    QueueFull {
+        count: u32,
+    },
+}
+
+enum Outcome<'a> {
+    Relayed,
+    Cancels,
+    Dropped(&'a dyn fmt::Display),
+}
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Rx {
+                notif,
+                rssi,
+                outcome,
+            } => {
+                f.write_str("RX ")?;
+                if let Some(notif) = notif {
+                    write!(f, "nid={} ", Nid(notif))?;
+                }
+                write!(f, "rssi={} ", rssi)?;
+                match outcome {
+                    Outcome::Relayed => f.write_str("ok"),
+                    Outcome::Cancels => f.write_str("cancel"),
+                    Outcome::Dropped(reason) => write!(f, "drop={}", reason),
+                }
+            }
+            Line::Entry { event, entry } => {
+                let tag = match event {
+                    Event::Received | Event::Rejected => "RX",
+                    Event::Added => "ADD",
+                    Event::Updated => "UPD",
+                    Event::Cancelled => "CXL",
+                    Event::Pruned => "EXP",
+                    Event::Rebroadcast => "TX",
+                };
+                write!(f, "{} nid={}", tag, Nid(&entry.notification))?;
+                if matches!(event, Event::Added | Event::Updated) {
+                    write!(f, " rssi={}", entry.rssi)?;
+                }
+                Ok(())
+            }
+            Line::Aired { entry, left_secs } => {
+                write!(f, "TX nid={} left={}s", Nid(&entry.notification), left_secs)
+            }
+            Line::Evicted { victim, by } => write!(
+                f,
+                "EVICT nid={} prio={} for nid={} prio={}",
+                Nid(&victim.notification),
+                victim.notification.priority,
+                Nid(&by.notification),
+                by.notification.priority
+            ),
+            Line::Full { entry } => write!(
+                f,
+                "FULL nid={} prio={} drop",
+                Nid(&entry.notification),
+                entry.notification.priority
+            ),
+            Line::QueueFull { count } => write!(f, "QUEUE full drop={}", count),
+        }
+    }
+}
+
+/// `notification_id` as 8 lowercase hex digits, without the allocation of
+/// `TransportNotification::id_hex`.
+struct Nid<'a>(&'a TransportNotification);
+
+impl fmt::Display for Nid<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0.notification_id {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_protocol_core::{
+        ParseError, TransportNotificationBuilder, TransportStatus, TransportType,
+        INFRA_KEY_CURRENT, MANUFACTURER_ID,
+    };
+
+    fn notification() -> TransportNotification {
+        TransportNotificationBuilder::new()
+            .notification_id([0xA1, 0xB2, 0xC3, 0xD4])
+            .transport(TransportType::Bus)
+            .status(TransportStatus::Late)
+            .duration_secs(30)
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap()
+    }
+
+    #[test]
+    fn events_are_one_terse_line() {
+        let notif = notification();
+        let rx = |outcome| {
+            Line::Rx {
+                notif: Some(&notif),
+                rssi: -62,
+                outcome,
+            }
+            .to_string()
+        };
+        assert_eq!(rx(Outcome::Relayed), "RX nid=a1b2c3d4 rssi=-62 ok");
+        assert_eq!(rx(Outcome::Cancels), "RX nid=a1b2c3d4 rssi=-62 cancel");
+        assert_eq!(
+            rx(Outcome::Dropped(&"replay")),
+            "RX nid=a1b2c3d4 rssi=-62 drop=replay"
+        );
+        let unverified = Line::Rx {
+            notif: None,
+            rssi: -70,
+            outcome: Outcome::Dropped(&"CRC mismatch"),
+        };
+        assert_eq!(unverified.to_string(), "RX rssi=-70 drop=CRC mismatch");
+
+        let mut entry = ActiveNotification::new(notif, MANUFACTURER_ID, 25_500_000);
+        entry.rssi = -62;
+        let line = |event| {
+            Line::Entry {
+                event,
+                entry: &entry,
+            }
+            .to_string()
+        };
+        assert_eq!(line(Event::Added), "ADD nid=a1b2c3d4 rssi=-62");
+        assert_eq!(line(Event::Pruned), "EXP nid=a1b2c3d4");
+        let aired = Line::Aired {
+            entry: &entry,
+            left_secs: entry.remaining_us(0) / 1_000_000,
+        };
+        assert_eq!(aired.to_string(), "TX nid=a1b2c3d4 left=25s");
+    }
+
+    #[test]
+    fn warnings_are_one_terse_line_too() {
+        let low = ActiveNotification::new(notification(), MANUFACTURER_ID, 0);
+        let mut high = low.clone();
+        high.notification.notification_id = [0xE5, 0xF6, 0xA7, 0xB8];
+        high.notification.priority = 2;
+
+        let forged = Line::Rx {
+            notif: None,
+            rssi: -62,
+            outcome: Outcome::Dropped(&ParseError::InfraHmacMismatch),
+        };
+        assert_eq!(forged.to_string(), "RX rssi=-62 drop=infra HMAC mismatch");
+        let evicted = Line::Evicted {
+            victim: &low,
+            by: &high,
+        };
+        assert_eq!(
+            evicted.to_string(),
+            "EVICT nid=a1b2c3d4 prio=0 for nid=e5f6a7b8 prio=2"
+        );
+        assert_eq!(
+            Line::Full { entry: &low }.to_string(),
+            "FULL nid=a1b2c3d4 prio=0 drop"
+        );
+        assert_eq!(
+            Line::QueueFull { count: 3 }.to_string(),
+            "QUEUE full drop=3"
+        );
+    }
+}
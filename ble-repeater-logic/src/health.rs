@@ -0,0 +1,125 @@
+//! Repeater status for the health GATT characteristic (`health` feature).
+//!
+//! A technician connects with any BLE app and reads one small record
+//! instead of attaching to the serial console. Service
+//! `HEALTH_SERVICE_UUID`, read-only characteristic `HEALTH_STATUS_UUID`,
+//! little-endian throughout:
+//!
+//! ```text
+//!   [0]       format        u8   STATUS_FORMAT
+//!   [1..3]    active        u16  entries in the active list
+//!   [3..7]    uptime_secs   u32  since boot
+//!   [7..11]   cycles        u32  scan cycles completed
+//!   [11..27]  last cycle    u16 each: seen, matched, rejected, added,
+//!                           updated, cancelled, dropped_full, pruned
+//! ```
+//!
+//! The last-cycle counts are those of `RepeaterMetrics` over the most
+//! recent scan cycle, `rejected` summing every reason a notification was
+//! turned away. Each count saturates at `u16::MAX`.
+
+use crate::metrics::RepeaterMetrics;
+
+/// UUID of the health GATT service.
+pub const HEALTH_SERVICE_UUID: &str = "5b1f0c7e-3d0a-4c55-9a4e-2f7d1b8e6a10";
+
+/// UUID of the read-only status characteristic.
+pub const HEALTH_STATUS_UUID: &str = "5b1f0c7e-3d0a-4c55-9a4e-2f7d1b8e6a11";
+
+/// Layout version of the status record, bumped on any change to it.
+pub const STATUS_FORMAT: u8 = 1;
+
+/// The scan task's side of the record: updated after each cycle, read by
+/// the characteristic.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CycleStats {
+    /// Scan cycles completed since boot.
+    pub cycles: u32,
+    /// What the last of them counted.
+    pub last: RepeaterMetrics,
+}
+
+impl CycleStats {
+    /// Record a completed cycle that counted `last`.
+    pub fn record(&mut self, last: RepeaterMetrics) {
+        self.cycles = self.cycles.wrapping_add(1);
+        self.last = last;
+    }
+}
+
+/// One status record, as the characteristic returns it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthStatus {
+    pub active: usize,
+    pub uptime_us: i64,
+    pub stats: CycleStats,
+}
+
+impl HealthStatus {
+    /// Bytes of the encoded record.
+    pub const SIZE: usize = 27;
+
+    /// The record in the layout the module docs describe.
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let narrow = |n: u64| u16::try_from(n).unwrap_or(u16::MAX).to_le_bytes();
+        let last = &self.stats.last;
+        let uptime_secs = u32::try_from(self.uptime_us.max(0) / 1_000_000).unwrap_or(u32::MAX);
+
+        let mut out = [0u8; Self::SIZE];
+        out[0] = STATUS_FORMAT;
+        out[1..3].copy_from_slice(&narrow(self.active as u64));
+        out[3..7].copy_from_slice(&uptime_secs.to_le_bytes());
+        out[7..11].copy_from_slice(&self.stats.cycles.to_le_bytes());
+        let counts = [
+            last.seen,
+            last.matched,
+            last.rejected(),
+            last.added,
+            last.updated,
+            last.cancelled,
+            last.dropped_full,
+            last.pruned,
+        ];
+        for (chunk, count) in out[11..].chunks_exact_mut(2).zip(counts) {
+            chunk.copy_from_slice(&narrow(count));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_record_is_packed_little_endian() {
+        let mut stats = CycleStats::default();
+        stats.record(RepeaterMetrics::default());
+        stats.record(RepeaterMetrics {
+            seen: 70_000,
+            matched: 5,
+            infra_fail: 2,
+            stale: 1,
+            added: 3,
+            pruned: 1,
+            ..RepeaterMetrics::default()
+        });
+        let status = HealthStatus {
+            active: 4,
+            uptime_us: 90_500_000,
+            stats,
+        };
+
+        let bytes = status.encode();
+        assert_eq!(bytes[0], STATUS_FORMAT);
+        assert_eq!(bytes[1..3], 4u16.to_le_bytes());
+        assert_eq!(bytes[3..7], 90u32.to_le_bytes());
+        assert_eq!(bytes[7..11], 2u32.to_le_bytes());
+        let counts: Vec<u16> = bytes[11..]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        // seen, saturated; matched; rejected; added; …; pruned
+        assert_eq!(counts, [u16::MAX, 5, 3, 3, 0, 0, 0, 1]);
+    }
+}
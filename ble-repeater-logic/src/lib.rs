@@ -0,0 +1,25 @@
+//! Everything the repeater decides, without the radio or the flash.
+//!
+//! The firmware (`ble-repeater`) is built for an ESP32 target with no test
+//! harness, so what can be tested lives here and builds for the host: the
+//! scan cycle (`Repeater`) and the states it moves through (`state`), the
+//! active list, deduplication, scheduling, the configuration and the NVS
+//! blob formats. The firmware supplies the
+//! NimBLE scanner and advertiser, the ESP timer as a `Clock`, and the NVS
+//! store, and drives the rest.
+
+pub mod ack;
+pub mod active;
+pub mod advertise;
+pub mod capability;
+pub mod clock;
+pub mod config;
+pub mod dedup;
+pub mod eventlog;
+pub mod health;
+pub mod metrics;
+pub mod persist;
+pub mod repeater;
+pub mod schedule;
+pub mod state;
+pub mod telemetry;
@@ -0,0 +1,155 @@
+//! Running counters for field debugging.
+//!
+//! Counted by the scan task and logged as one line after each scan window.
+//! They never reset, so successive lines show trends: a climbing
+//! `infra_fail` points at a forger or a broadcaster on a retired key, a
+//! climbing `source_rejected` at a leaked key, a climbing `dropped_full` at
+//! `max_active_notifications` being too small.
+
+use core::fmt;
+
+use ble_protocol_core::ParseError;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RepeaterMetrics {
+    /// Advertisements heard, of any kind.
+    pub seen: u64,
+    /// Advertisements carrying our manufacturer ID.
+    pub matched: u64,
+    /// Rejected for length, protocol version, a transport type/status
+    /// nibble or a `duration_secs` over `MAX_DURATION_SECS`.
+    pub version_fail: u64,
+    /// Rejected for the CRC, an unknown key id, the infra HMAC or a sealed
+    /// payload that didn't decrypt.
+    pub infra_fail: u64,
+    /// Rejected as too old or from the future (repeaters with a clock only).
+    pub stale: u64,
+    /// Verified, but from a `source_id` that `allowed_sources` or
+    /// `denied_sources` rules out.
+    pub source_rejected: u64,
+    /// Notifications added to the active list.
+    pub added: u64,
+    /// Active entries refreshed by a newer copy.
+    pub updated: u64,
+    /// Active entries removed by a cancellation (`duration_secs` 0).
+    pub cancelled: u64,
+    /// Notifications dropped because the active list was full, or evicted
+    /// from it by a higher-priority one.
+    pub dropped_full: u64,
+    /// Active entries removed on expiry.
+    pub pruned: u64,
+}
+
+impl RepeaterMetrics {
+    /// Count a payload that matched our manufacturer ID but didn't parse.
+    pub fn record_parse_error(&mut self, e: &ParseError) {
+        match e {
+            ParseError::TooShort { .. }
+            | ParseError::UnsupportedVersion(_)
+            | ParseError::BadTransportType(_)
+            | ParseError::BadTransportStatus(_)
+            | ParseError::DurationTooLong(_) => self.version_fail += 1,
+            ParseError::CrcMismatch
+            | ParseError::UnknownKeyId(_)
+            | ParseError::InfraHmacMismatch
+            | ParseError::DecryptFailed => self.infra_fail += 1,
+            ParseError::Stale { .. } | ParseError::FromFuture { .. } => self.stale += 1,
+            // Only a client checks the client tag.
+            ParseError::MissingClientTag | ParseError::ClientHmacMismatch => {}
+        }
+    }
+
+    /// What was counted since `earlier`, a copy of these counters.
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            seen: self.seen - earlier.seen,
+            matched: self.matched - earlier.matched,
+            version_fail: self.version_fail - earlier.version_fail,
+            infra_fail: self.infra_fail - earlier.infra_fail,
+            stale: self.stale - earlier.stale,
+            source_rejected: self.source_rejected - earlier.source_rejected,
+            added: self.added - earlier.added,
+            updated: self.updated - earlier.updated,
+            cancelled: self.cancelled - earlier.cancelled,
+            dropped_full: self.dropped_full - earlier.dropped_full,
+            pruned: self.pruned - earlier.pruned,
+        }
+    }
+
+    /// Notifications heard with our manufacturer ID but not relayed, for
+    /// whichever reason.
+    pub fn rejected(&self) -> u64 {
+        self.version_fail + self.infra_fail + self.stale + self.source_rejected
+    }
+}
+
+impl fmt::Display for RepeaterMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seen={} matched={} version_fail={} infra_fail={} stale={} source_rejected={} \
+             added={} updated={} cancelled={} dropped_full={} pruned={}",
+            self.seen,
+            self.matched,
+            self.version_fail,
+            self.infra_fail,
+            self.stale,
+            self.source_rejected,
+            self.added,
+            self.updated,
+            self.cancelled,
+            self.dropped_full,
+            self.pruned
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_errors_land_in_their_counter() {
+        let mut m = RepeaterMetrics::default();
+        m.record_parse_error(&ParseError::UnsupportedVersion(6));
+        m.record_parse_error(&ParseError::BadTransportType(7));
+        m.record_parse_error(&ParseError::InfraHmacMismatch);
+        m.record_parse_error(&ParseError::CrcMismatch);
+        m.record_parse_error(&ParseError::UnknownKeyId(9));
+        m.record_parse_error(&ParseError::DecryptFailed);
+        m.record_parse_error(&ParseError::Stale { age_ms: 1 });
+        assert_eq!((m.version_fail, m.infra_fail, m.stale), (2, 4, 1));
+    }
+
+    #[test]
+    fn since_counts_only_the_difference() {
+        let earlier = RepeaterMetrics {
+            seen: 10,
+            infra_fail: 1,
+            ..RepeaterMetrics::default()
+        };
+        let now = RepeaterMetrics {
+            seen: 14,
+            infra_fail: 2,
+            stale: 1,
+            added: 3,
+            ..RepeaterMetrics::default()
+        };
+        let delta = now.since(&earlier);
+        assert_eq!((delta.seen, delta.added), (4, 3));
+        assert_eq!(delta.rejected(), 2);
+    }
+
+    #[test]
+    fn summary_is_one_line() {
+        let m = RepeaterMetrics {
+            seen: 12,
+            pruned: 1,
+            ..RepeaterMetrics::default()
+        };
+        let line = m.to_string();
+        assert!(!line.contains('\n'));
+        assert!(line.starts_with("seen=12 matched=0 "));
+        assert!(line.ends_with(" pruned=1"));
+    }
+}
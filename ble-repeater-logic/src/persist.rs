@@ -0,0 +1,366 @@
+//! Keeping the active list across reboots in NVS flash.
+//!
+//! After a watchdog reset or a power glitch the repeater would otherwise go
+//! quiet until each broadcaster came back in range. The active list is
+//! saved to NVS after a cycle that changed its entries, or every
+//! `persist_refresh_secs` while only their expiry moved, and restored at
+//! startup.
+//!
+//! `esp_timer_get_time` restarts from zero at boot, so an absolute
+//! `expires_at_us` means nothing after a reboot. Each entry is stored with
+//! the time it had *left* when saved instead. With `has_clock`, the
+//! wall-clock time of the save is stored too, and the downtime is deducted on
+//! restore. Without a clock the downtime can't be known. It is assumed to be
+//! zero, which is close for a reset but extends every entry by however long
+//! the repeater was actually off.
+//!
+//! Only the blob formats live here; the firmware's `store` reads and writes
+//! them in NVS.
+//!
+//! Blob layout (little-endian):
+//!   [0]       format       u8  (`FORMAT_VERSION`)
+//!   [1..9]    saved_at_ms  u64 (unix-epoch ms, 0 = no clock)
+//!   [9..11]   count        u16
+//!   then `count` times:
+//!     remaining_ms  u32
+//!     notification  [u8; TransportNotification::SIZE] (as aired)
+//!
+//! With `persist_replay_cache`, the replay cache (the newest `seq` taken per
+//! notification, see `ble_protocol_core::seq`) is saved too, under its own
+//! key, after any cycle that took a new `seq`: a cancellation or a fresher
+//! copy can change it while the active list stays as it was. Otherwise a
+//! reboot forgets it, and a packet captured before the reboot can be
+//! replayed as new for as long as it stays fresh. Each entry
+//! keeps the `timestamp_ms` of the packet its `seq` came from; on restore,
+//! entries whose packet is older than `MAX_AGE_MS` are dropped, since a
+//! repeater with a clock rejects a replay of it as stale anyway. That needs
+//! real time on both sides, hence `has_clock`.
+//!
+//! Replay blob layout (little-endian):
+//!   [0]       format       u8  (`FORMAT_VERSION`)
+//!   [1..3]    count        u16
+//!   then `count` times, least recently accepted first:
+//!     source_id        [u8; 4]
+//!     notification_id  [u8; 4]
+//!     seq              u32
+//!     timestamp_ms     u64
+//!
+//! The same namespace can also hold `allowed_sources` and `denied_sources`,
+//! written by an operator rather than by the repeater, so a deployment can
+//! change them by flashing an NVS partition instead of rebuilding the
+//! firmware. Each is a blob of concatenated 4-byte `source_id`s; a key that
+//! is present replaces the list from `repeater.toml`, even when empty.
+
+use ble_protocol_core::seq::SeqKey;
+use ble_protocol_core::{InfraKey, TransportNotification, MAX_AGE_MS};
+
+/// Bumped whenever the blob layout changes; older blobs are discarded.
+const FORMAT_VERSION: u8 = 1;
+
+const HEADER_SIZE: usize = 1 + 8 + 2;
+const ENTRY_SIZE: usize = 4 + TransportNotification::SIZE;
+
+const REPLAY_HEADER_SIZE: usize = 1 + 2;
+const REPLAY_ENTRY_SIZE: usize = 4 + 4 + 4 + 8;
+
+/// One active notification as saved: the payload being aired and how long
+/// it had left to run.
+#[derive(Debug, Clone, Copy)]
+pub struct SavedEntry {
+    pub notification: TransportNotification,
+    pub remaining_ms: u32,
+}
+
+/// The decoded contents of a saved blob.
+#[derive(Debug)]
+pub struct Snapshot {
+    /// Wall-clock time of the save, if the repeater had a clock then.
+    pub saved_at_ms: Option<u64>,
+    pub entries: Vec<SavedEntry>,
+}
+
+impl Snapshot {
+    /// Entries still running `elapsed_ms` after the save, with their
+    /// remaining time reduced accordingly.
+    pub fn remaining_after(self, elapsed_ms: u64) -> impl Iterator<Item = SavedEntry> {
+        self.entries.into_iter().filter_map(move |e| {
+            let left = u64::from(e.remaining_ms).checked_sub(elapsed_ms)?;
+            (left > 0).then_some(SavedEntry {
+                remaining_ms: left as u32,
+                ..e
+            })
+        })
+    }
+}
+
+/// Serialize `entries`, saved at wall-clock `saved_at_ms` (if known).
+pub fn encode(entries: &[SavedEntry], saved_at_ms: Option<u64>) -> Vec<u8> {
+    let count = entries.len().min(u16::MAX as usize);
+    let mut blob = Vec::with_capacity(HEADER_SIZE + count * ENTRY_SIZE);
+    blob.push(FORMAT_VERSION);
+    blob.extend_from_slice(&saved_at_ms.unwrap_or(0).to_le_bytes());
+    blob.extend_from_slice(&(count as u16).to_le_bytes());
+    for e in &entries[..count] {
+        blob.extend_from_slice(&e.remaining_ms.to_le_bytes());
+        blob.extend_from_slice(e.notification.as_bytes());
+    }
+    blob
+}
+
+/// Parse a blob written by `encode`. Returns `None` for a blob of another
+/// format or the wrong length. Each notification is verified again against
+/// `keyring`, so entries from a retired key or an older protocol version are
+/// dropped rather than aired.
+pub fn decode(blob: &[u8], keyring: &[InfraKey]) -> Option<Snapshot> {
+    if blob.len() < HEADER_SIZE {
+        return None;
+    }
+    let (header, body) = blob.split_at(HEADER_SIZE);
+    if header[0] != FORMAT_VERSION {
+        return None;
+    }
+    let saved_at_ms = u64::from_le_bytes(header[1..9].try_into().unwrap());
+    let count = u16::from_le_bytes([header[9], header[10]]) as usize;
+    if body.len() != count * ENTRY_SIZE {
+        return None;
+    }
+
+    let entries = body
+        .chunks_exact(ENTRY_SIZE)
+        .filter_map(|chunk| {
+            let (remaining, payload) = chunk.split_at(4);
+            let notification =
+                TransportNotification::from_payload_with(payload, keyring, None).ok()?;
+            Some(SavedEntry {
+                notification,
+                remaining_ms: u32::from_le_bytes(remaining.try_into().unwrap()),
+            })
+        })
+        .collect();
+    Some(Snapshot {
+        saved_at_ms: (saved_at_ms != 0).then_some(saved_at_ms),
+        entries,
+    })
+}
+
+/// One replay cache entry as saved: the newest `seq` taken for a
+/// notification, and the `timestamp_ms` of the packet it came in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeenEntry {
+    pub key: SeqKey,
+    pub seq: u32,
+    pub timestamp_ms: u64,
+}
+
+impl SeenEntry {
+    /// Whether a replay of this entry's packet would still be fresh at
+    /// wall-clock `now_ms`, i.e. the entry is still worth keeping.
+    pub fn replayable_at(&self, now_ms: u64) -> bool {
+        now_ms.saturating_sub(self.timestamp_ms) <= MAX_AGE_MS
+    }
+}
+
+/// Serialize the replay cache `entries`, least recently accepted first.
+pub fn encode_replay(entries: &[SeenEntry]) -> Vec<u8> {
+    let count = entries.len().min(u16::MAX as usize);
+    let mut blob = Vec::with_capacity(REPLAY_HEADER_SIZE + count * REPLAY_ENTRY_SIZE);
+    blob.push(FORMAT_VERSION);
+    blob.extend_from_slice(&(count as u16).to_le_bytes());
+    for e in &entries[..count] {
+        blob.extend_from_slice(&e.key.0);
+        blob.extend_from_slice(&e.key.1);
+        blob.extend_from_slice(&e.seq.to_le_bytes());
+        blob.extend_from_slice(&e.timestamp_ms.to_le_bytes());
+    }
+    blob
+}
+
+/// Parse a blob written by `encode_replay`, keeping the entries still
+/// replayable at wall-clock `now_ms` and, of those, only the `max` most
+/// recently accepted. Returns `None` for a blob of another format or the
+/// wrong length.
+pub fn decode_replay(blob: &[u8], now_ms: u64, max: usize) -> Option<Vec<SeenEntry>> {
+    if blob.len() < REPLAY_HEADER_SIZE {
+        return None;
+    }
+    let (header, body) = blob.split_at(REPLAY_HEADER_SIZE);
+    if header[0] != FORMAT_VERSION {
+        return None;
+    }
+    let count = u16::from_le_bytes([header[1], header[2]]) as usize;
+    if body.len() != count * REPLAY_ENTRY_SIZE {
+        return None;
+    }
+
+    let mut entries: Vec<SeenEntry> = body
+        .chunks_exact(REPLAY_ENTRY_SIZE)
+        .map(|chunk| SeenEntry {
+            key: (
+                chunk[0..4].try_into().unwrap(),
+                chunk[4..8].try_into().unwrap(),
+            ),
+            seq: u32::from_le_bytes(chunk[8..12].try_into().unwrap()),
+            timestamp_ms: u64::from_le_bytes(chunk[12..20].try_into().unwrap()),
+        })
+        .filter(|e| e.replayable_at(now_ms))
+        .collect();
+    entries.drain(..entries.len().saturating_sub(max));
+    Some(entries)
+}
+
+/// Parse a source list blob: concatenated 4-byte `source_id`s. Returns
+/// `None` if its length is not a multiple of 4.
+pub fn decode_sources(blob: &[u8]) -> Option<Vec<[u8; 4]>> {
+    let ids = blob.chunks_exact(4);
+    if !ids.remainder().is_empty() {
+        return None;
+    }
+    Some(ids.map(|id| id.try_into().unwrap()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_protocol_core::{
+        DEFAULT_HOPS, HMAC_TAG_CLIENT_LEN, HMAC_TAG_INFRA_LEN, INFRA_KEYRING, PROTOCOL_VERSION,
+    };
+
+    const NOW_MS: u64 = 1_767_225_600_000;
+
+    fn entry(nid: u8, remaining_ms: u32) -> SavedEntry {
+        let mut notification = TransportNotification {
+            version: PROTOCOL_VERSION,
+            source_id: [0xA1, 0xB2, 0xC3, 0xD4],
+            notification_id: [nid; 4],
+            event_dest: 0x12,
+            type_status: 0x11,
+            duration_secs: 60u16.to_le_bytes(),
+            validity_secs: 120u16.to_le_bytes(),
+            flags: 0,
+            seq: 7u32.to_le_bytes(),
+            key_id: 0,
+            timestamp_ms: [0; 6],
+            priority: 0,
+            hmac_tag_infra: [0; HMAC_TAG_INFRA_LEN],
+            hmac_tag_client: [0; HMAC_TAG_CLIENT_LEN],
+            hops_remaining: DEFAULT_HOPS,
+            crc16: [0; 2],
+        };
+        notification.sign_infra();
+        SavedEntry {
+            notification,
+            remaining_ms,
+        }
+    }
+
+    #[test]
+    fn round_trip_keeps_entries_and_save_time() {
+        let blob = encode(
+            &[entry(1, 5_000), entry(2, 60_000)],
+            Some(1_700_000_000_000),
+        );
+        let snap = decode(&blob, INFRA_KEYRING).unwrap();
+        assert_eq!(snap.saved_at_ms, Some(1_700_000_000_000));
+        let got: Vec<_> = snap
+            .entries
+            .iter()
+            .map(|e| ({ e.notification.notification_id }[0], e.remaining_ms))
+            .collect();
+        assert_eq!(got, [(1, 5_000), (2, 60_000)]);
+    }
+
+    #[test]
+    fn save_without_clock_has_no_time() {
+        let snap = decode(&encode(&[], None), INFRA_KEYRING).unwrap();
+        assert_eq!(snap.saved_at_ms, None);
+        assert!(snap.entries.is_empty());
+    }
+
+    #[test]
+    fn downtime_is_deducted_and_expired_entries_dropped() {
+        let blob = encode(&[entry(1, 5_000), entry(2, 60_000)], None);
+        let left: Vec<_> = decode(&blob, INFRA_KEYRING)
+            .unwrap()
+            .remaining_after(10_000)
+            .map(|e| e.remaining_ms)
+            .collect();
+        assert_eq!(left, [50_000]);
+    }
+
+    #[test]
+    fn tampered_entry_is_dropped() {
+        let mut blob = encode(&[entry(1, 5_000), entry(2, 5_000)], None);
+        // duration_secs of the first notification
+        blob[HEADER_SIZE + 4 + 11] ^= 0xFF;
+        let snap = decode(&blob, INFRA_KEYRING).unwrap();
+        assert_eq!(snap.entries.len(), 1);
+        assert_eq!({ snap.entries[0].notification.notification_id }, [2; 4]);
+    }
+
+    fn seen(nid: u8, seq: u32, timestamp_ms: u64) -> SeenEntry {
+        SeenEntry {
+            key: ([0xA1, 0xB2, 0xC3, 0xD4], [nid; 4]),
+            seq,
+            timestamp_ms,
+        }
+    }
+
+    #[test]
+    fn replay_cache_round_trips() {
+        let entries = [seen(1, 7, NOW_MS - 1_000), seen(2, u32::MAX, NOW_MS)];
+        let blob = encode_replay(&entries);
+        assert_eq!(
+            blob.len(),
+            REPLAY_HEADER_SIZE + entries.len() * REPLAY_ENTRY_SIZE
+        );
+        assert_eq!(decode_replay(&blob, NOW_MS, 64).unwrap(), entries);
+    }
+
+    #[test]
+    fn replay_entries_too_old_to_replay_are_pruned() {
+        let blob = encode_replay(&[
+            seen(1, 7, NOW_MS - MAX_AGE_MS - 1),
+            seen(2, 8, NOW_MS - MAX_AGE_MS),
+            seen(3, 9, 0),
+        ]);
+        let kept = decode_replay(&blob, NOW_MS, 64).unwrap();
+        assert_eq!(kept, [seen(2, 8, NOW_MS - MAX_AGE_MS)]);
+    }
+
+    #[test]
+    fn replay_cache_keeps_the_most_recently_accepted() {
+        let blob = encode_replay(&[seen(1, 1, NOW_MS), seen(2, 2, NOW_MS), seen(3, 3, NOW_MS)]);
+        let kept = decode_replay(&blob, NOW_MS, 2).unwrap();
+        assert_eq!(kept, [seen(2, 2, NOW_MS), seen(3, 3, NOW_MS)]);
+    }
+
+    #[test]
+    fn truncated_or_foreign_replay_blob_is_rejected() {
+        let blob = encode_replay(&[seen(1, 7, NOW_MS)]);
+        assert!(decode_replay(&blob[..blob.len() - 1], NOW_MS, 64).is_none());
+        assert!(decode_replay(&blob[..2], NOW_MS, 64).is_none());
+        let mut other = blob.clone();
+        other[0] = FORMAT_VERSION + 1;
+        assert!(decode_replay(&other, NOW_MS, 64).is_none());
+    }
+
+    #[test]
+    fn source_blob_is_a_run_of_ids() {
+        assert_eq!(
+            decode_sources(&[0xA1, 0xB2, 0xC3, 0xD4, 1, 2, 3, 4]),
+            Some(vec![[0xA1, 0xB2, 0xC3, 0xD4], [1, 2, 3, 4]])
+        );
+        assert_eq!(decode_sources(&[]), Some(Vec::new()));
+        assert_eq!(decode_sources(&[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn truncated_or_foreign_blob_is_rejected() {
+        let blob = encode(&[entry(1, 5_000)], None);
+        assert!(decode(&blob[..blob.len() - 1], INFRA_KEYRING).is_none());
+        assert!(decode(&blob[..3], INFRA_KEYRING).is_none());
+        let mut other = blob.clone();
+        other[0] = FORMAT_VERSION + 1;
+        assert!(decode(&other, INFRA_KEYRING).is_none());
+    }
+}
@@ -0,0 +1,1889 @@
+//! The repeater's scan cycle, apart from the radio.
+//!
+//! `Repeater` holds the active list and everything that decides what goes on
+//! it: verification, the block list, dedup, replay rejection and the relay
+//! decision. It hears advertisements through the `Scanner` trait and reads
+//! time through the `Clock` trait, so a cycle runs the same on the host, with
+//! a mock scanner and clock, as on the ESP32 with NimBLE. Airing the list
+//! stays in the firmware's `main`, on the re-broadcast task.
+
+use std::sync::{Arc, Mutex};
+
+use ble_protocol_core::conf::CONF_KEY;
+use ble_protocol_core::relay::Received;
+use ble_protocol_core::seq::{SeqKey, SeqTracker};
+use ble_protocol_core::{
+    AckBeacon, CapabilityBeacon, ClientKey, InfraKey, KeyProvider, NeighborTable, ParseError,
+    TransportNotification, MAX_AGE_MS, PROTOCOL_VERSION, PROTOCOL_VERSION_V1,
+};
+use log::{debug, Level};
+
+use crate::active::{ActiveNotification, ScanQueue};
+use crate::clock::Clock;
+use crate::config::{RelayDecision, RepeaterConfig};
+use crate::dedup::DedupCache;
+use crate::eventlog::{self, verbose};
+use crate::metrics::RepeaterMetrics;
+use crate::state::{Action, RepeaterState};
+use crate::telemetry::{self, Event};
+
+/// Notifications whose newest `seq` is remembered for replay rejection. A
+/// notification evicted from this set is accepted afresh, so keep it well
+/// above the number of notifications in range at once.
+pub const SEQ_TRACKED_NOTIFICATIONS: usize = 64;
+
+/// Notifications remembered after being relayed, so that copies heard again
+/// (even after the active entry is pruned) are never re-added. Oldest
+/// evicted first.
+const DEDUP_CACHE_SIZE: usize = 64;
+
+/// How long a relayed notification is remembered. Past `MAX_AGE_MS` a
+/// repeater with a clock rejects the copy as stale anyway.
+const DEDUP_TTL_US: i64 = MAX_AGE_MS as i64 * 1000;
+
+/// How long a relayed cancellation stays on the active list, so repeaters
+/// further out hear it too. It replaces the notification it cancels.
+const CANCEL_AIRTIME_US: i64 = 30 * 1_000_000;
+
+/// Neighbours whose capability beacons are remembered (see
+/// `RepeaterConfig::neighbor_aware_relay`). A full table replaces the one
+/// heard from longest ago.
+const NEIGHBOR_TABLE_SIZE: usize = 16;
+
+/// One advertisement, as a `Scanner` hands it over.
+pub struct Heard<'a> {
+    /// Advertiser address, most significant byte first.
+    pub addr: [u8; 6],
+    pub rssi: i8,
+    /// Company ID and payload of the manufacturer data, if any.
+    pub manufacturer_data: Option<(u16, &'a [u8])>,
+}
+
+/// Where advertisements come from: NimBLE on the device, a mock in tests.
+pub trait Scanner {
+    /// Scan for `duration_ms`, passing every advertisement heard to
+    /// `on_heard`.
+    fn scan(&mut self, duration_ms: i32, on_heard: &mut dyn FnMut(Heard<'_>));
+}
+
+/// Wall-clock time in unix-epoch milliseconds. Only meaningful once the
+/// system clock has been set (e.g. by SNTP); see `RepeaterConfig::has_clock`.
+pub fn unix_now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Whether `payload`, one notification heard from `from`, is this
+/// repeater's own output coming back: sent from `own_addr` and identical to
+/// the body of an entry in `airing`. A peer relaying the same bytes, as two
+/// repeaters signing with the same client key do, has another address and
+/// is not an echo; nor is anything sent from our address that we aren't
+/// airing. Without a known `own_addr` nothing is.
+pub fn is_own_echo(
+    own_addr: Option<[u8; 6]>,
+    from: [u8; 6],
+    payload: &[u8],
+    airing: &[ActiveNotification],
+) -> bool {
+    own_addr == Some(from) && airing.iter().any(|a| &a.raw_mfg_payload()[2..] == payload)
+}
+
+/// What decides whether a heard advertisement is relayed. Kept apart from
+/// the scanner so the scan callback can borrow it while the scanner runs.
+struct Intake<C> {
+    cfg: RepeaterConfig,
+    keyring: Vec<InfraKey>,
+    /// Keys client tags are signed with (see `RepeaterConfig::client_keys`).
+    client_keys: Vec<ClientKey>,
+    /// Newest `seq` relayed per notification; anything not newer is a
+    /// replay.
+    seen_seq: SeqTracker<SEQ_TRACKED_NOTIFICATIONS>,
+    /// Whether `seen_seq` took a `seq` since `Repeater::take_replay_changed`
+    /// last looked.
+    replay_changed: bool,
+    /// Notifications already relayed, so repeated copies are only taken as
+    /// copies.
+    relayed: DedupCache<DEDUP_CACHE_SIZE>,
+    /// Neighbours' capability beacons, kept with `neighbor_aware_relay`.
+    neighbors: NeighborTable<NEIGHBOR_TABLE_SIZE>,
+    /// Running totals, logged after every scan window.
+    metrics: RepeaterMetrics,
+    /// Monotonic time, for expiry.
+    clock: C,
+    /// This repeater's advertising address, to recognise its own output
+    /// (see `is_own_echo`); `None` until `Repeater::with_own_addr`.
+    own_addr: Option<[u8; 6]>,
+    /// The id this repeater adds to relay paths; `None` until
+    /// `Repeater::with_repeater_id`.
+    #[cfg(feature = "relay-path")]
+    repeater_id: Option<[u8; 4]>,
+}
+
+impl<C: Clock> Intake<C> {
+    /// Verify one advertisement and queue in `found` the copy to air of
+    /// each notification it carries that is to be relayed. `active` is what
+    /// this repeater is airing, for telling its own output apart.
+    fn consider(
+        &mut self,
+        heard: Heard<'_>,
+        active: &Mutex<Vec<ActiveNotification>>,
+        found: &mut ScanQueue,
+    ) {
+        self.metrics.seen += 1;
+        // Only look at advertisements with our manufacturer ID
+        let Some((company_id, payload)) = heard.manufacturer_data else {
+            return;
+        };
+        if company_id != self.cfg.manufacturer_id {
+            return;
+        }
+        self.metrics.matched += 1;
+        // Another repeater's ack, for broadcasters; nothing to relay.
+        if AckBeacon::is_ack(payload) {
+            return;
+        }
+        // A neighbour saying what it relays; only worth an HMAC if the
+        // relay decision asks.
+        if CapabilityBeacon::is_capability(payload) {
+            if self.cfg.neighbor_aware_relay {
+                self.record_neighbor(payload);
+            }
+            return;
+        }
+        // Too weak to relay: skip it before spending an HMAC (and a log
+        // line) on a far-away station that closer repeaters already cover.
+        if heard.rssi < self.cfg.min_rssi_relay {
+            debug!(
+                "    → not relaying (RSSI {} below min_rssi_relay {})",
+                heard.rssi, self.cfg.min_rssi_relay
+            );
+            return;
+        }
+
+        // One advertisement may pack several notifications back to back.
+        for payload in Received::chunks(payload) {
+            // Our own re-broadcast heard back: not a copy from upstream, so
+            // it must neither refresh its entry nor be relayed again. The
+            // address is compared first so that other stations' traffic
+            // doesn't take the lock.
+            if self.cfg.ignore_own_echo
+                && self.own_addr == Some(heard.addr)
+                && is_own_echo(self.own_addr, heard.addr, payload, &active.lock().unwrap())
+            {
+                debug!("    → own re-broadcast heard back — ignoring");
+                continue;
+            }
+            self.consider_payload(&heard, payload, found);
+        }
+    }
+
+    /// Verify a capability beacon and note what its repeater relays.
+    fn record_neighbor(&mut self, payload: &[u8]) {
+        match CapabilityBeacon::from_payload_with(payload, &self.keyring) {
+            Ok(beacon) => {
+                debug!(
+                    "    → neighbour {:02x?} relays versions {:#06x}, features {:#04x}",
+                    beacon.repeater_id,
+                    u16::from_le_bytes(beacon.versions),
+                    beacon.features
+                );
+                self.neighbors.record(beacon, self.now_ms());
+            }
+            Err(e) => verbose!("    ✗ ignoring capability beacon: {}", e),
+        }
+    }
+
+    /// Take `notif`'s `seq` if it is newer than any taken for it (see
+    /// `SeqTracker::accept_stamped`), noting that the replay cache changed.
+    fn accept_seq(&mut self, notif: &TransportNotification) -> bool {
+        let key = ({ notif.source_id }, { notif.notification_id });
+        let taken = self
+            .seen_seq
+            .accept_stamped(key, notif.seq(), notif.timestamp_ms());
+        self.replay_changed |= taken;
+        taken
+    }
+
+    /// Monotonic milliseconds, for the neighbour table.
+    fn now_ms(&self) -> u64 {
+        self.clock.now_us().max(0) as u64 / 1000
+    }
+
+    /// Sign `relay`'s client tags as `decision` says, and add this repeater
+    /// to its relay path.
+    fn prepare_relay(&self, relay: &mut Received, decision: RelayDecision) {
+        match decision {
+            RelayDecision::Sign => {
+                relay.sign_client_keys(&self.client_keys);
+                verbose!("    → signed client HMAC tag");
+            }
+            RelayDecision::RelayUnsigned { reason } => {
+                verbose!("    → relaying unsigned ({})", reason);
+            }
+            RelayDecision::PassThrough | RelayDecision::Drop => {}
+        }
+
+        // A debug build adds itself to the relay path; any other drops it,
+        // so a path that gets anywhere lists every hop (see
+        // `ble_protocol_core::relay_path`).
+        #[cfg(feature = "relay-path")]
+        if let Some(id) = self.repeater_id {
+            relay.record_hop(id);
+        }
+        #[cfg(not(feature = "relay-path"))]
+        {
+            relay.relay_path = None;
+        }
+    }
+
+    /// Verify one notification from `heard` and, if it is to be relayed,
+    /// queue it in `found` with the copy to air.
+    fn consider_payload(&mut self, heard: &Heard<'_>, payload: &[u8], found: &mut ScanQueue) {
+        let parsed = Received::open(
+            payload,
+            &self.keyring,
+            CONF_KEY,
+            // Checked below, against the configured forward skew.
+            None,
+        );
+        if let Err(e) = &parsed {
+            self.metrics.record_parse_error(e);
+            telemetry::rejected(None, heard.rssi, e);
+        }
+        match &parsed {
+            Ok(_) => {}
+            Err(e @ ParseError::InfraHmacMismatch) => {
+                // Signed with a key we don't hold, or tampered with.
+                eventlog::refused(Level::Error, None, heard.rssi, e);
+            }
+            Err(e) => {
+                verbose!("    ✗ ignoring payload: {}", e);
+                eventlog::dropped(None, heard.rssi, e);
+            }
+        }
+        if let Ok(received) = parsed {
+            let notif = received.notification;
+            let sealed = received.sealed.is_some();
+            let v1 = received.v1.is_some();
+
+            // Only once the tag proves the timestamp is the broadcaster's.
+            // v1 has none.
+            if self.cfg.has_clock && !v1 {
+                if let Err(e) = notif.check_fresh(unix_now_ms(), self.cfg.max_future_skew_ms) {
+                    self.metrics.record_parse_error(&e);
+                    telemetry::rejected(Some(&notif), heard.rssi, &e);
+                    if let ParseError::FromFuture { .. } = e {
+                        // A broadcaster clock running ahead, or a packet
+                        // pre-dated to stay fresh: worth a warning.
+                        eventlog::refused(Level::Warn, Some(&notif), heard.rssi, &e);
+                    } else {
+                        verbose!("    ✗ {} — not relaying", e);
+                        eventlog::dropped(Some(&notif), heard.rssi, &e);
+                    }
+                    return;
+                }
+            }
+            let sid = { notif.source_id };
+            let nid = { notif.notification_id };
+            let dur = notif.duration_secs();
+
+            // Another copy of a notification already relayed: it goes through
+            // the same checks and may refresh or replace the active entry,
+            // but never adds one back (see `ActiveNotification::copy`). A
+            // cancellation must get past these.
+            let copy = dur > 0 && self.relayed.contains((sid, nid), self.clock.now_us());
+            if copy {
+                debug!(
+                    "    → another copy of {} (RSSI {})",
+                    notif.id_hex(),
+                    heard.rssi
+                );
+            } else {
+                verbose!(
+                    "  ✓ verified {} event {:?} seq {} validity {}s via {:02x?} (RSSI {}){}{}{}",
+                    notif,
+                    notif.event(),
+                    notif.seq(),
+                    notif.validity_secs(),
+                    heard.addr,
+                    heard.rssi,
+                    if notif.is_canary() { " [canary]" } else { "" },
+                    if sealed { " [sealed]" } else { "" },
+                    if v1 { " [v1]" } else { "" },
+                );
+                telemetry::received(&notif, heard.rssi);
+                #[cfg(feature = "relay-path")]
+                if let Some(path) = &received.relay_path {
+                    debug!(
+                        "    path {:02x?}{}",
+                        path.hops().collect::<Vec<_>>(),
+                        if path.is_truncated() { " …" } else { "" }
+                    );
+                }
+            }
+
+            if v1 && !self.cfg.relay_v1 {
+                verbose!("    ✗ protocol v1 and relay_v1 is off — not relaying");
+                telemetry::rejected(Some(&notif), heard.rssi, &"protocol v1");
+                eventlog::dropped(Some(&notif), heard.rssi, &"protocol v1");
+                return;
+            }
+
+            // Checked only after the infra tag verified above.
+            if self.cfg.is_blocked(nid) {
+                verbose!("    ✗ notification is on the block list — not relaying");
+                telemetry::rejected(Some(&notif), heard.rssi, &"blocked");
+                eventlog::dropped(Some(&notif), heard.rssi, &"blocked");
+                return;
+            }
+            // Also only after the infra tag: a station we don't expect that
+            // still signs correctly has our key, so say so loudly.
+            if let Some(reason) = self.cfg.source_rejection(sid) {
+                eventlog::refused(Level::Warn, Some(&notif), heard.rssi, &reason);
+                self.metrics.source_rejected += 1;
+                telemetry::rejected(Some(&notif), heard.rssi, &reason);
+                return;
+            }
+            // Another zone's traffic, cancellations included: routine here,
+            // so logged like any other drop rather than warned about.
+            if !self.cfg.relays_destination(notif.destination_id()) {
+                verbose!(
+                    "    → destination {} not in destinations — not relaying",
+                    notif.destination_id()
+                );
+                eventlog::dropped(Some(&notif), heard.rssi, &"destination");
+                return;
+            }
+
+            // A zero duration cancels the notification. It is only honoured
+            // with a verified infra tag and a fresh seq, so a cancellation
+            // can't be forged or replayed against a re-issued notification.
+            // v1 has no seq, and never had cancellations.
+            if dur == 0 && v1 {
+                verbose!("    ✗ v1 cancellation — v1 has no seq to check it against, ignoring");
+                telemetry::rejected(Some(&notif), heard.rssi, &"v1 cancellation");
+                eventlog::dropped(Some(&notif), heard.rssi, &"v1 cancellation");
+                return;
+            }
+            if dur == 0 {
+                if !self.accept_seq(&notif) {
+                    // A neighbour relaying the cancellation we took.
+                    if self.seen_seq.newest((sid, nid)) == Some(notif.seq()) {
+                        debug!(
+                            "    → another copy of the cancellation of {}",
+                            notif.id_hex()
+                        );
+                        return;
+                    }
+                    verbose!(
+                        "    ✗ seq {} not newer than {:?} for this notification — replayed cancellation, ignoring",
+                        notif.seq(),
+                        self.seen_seq.newest((sid, nid))
+                    );
+                    telemetry::rejected(Some(&notif), heard.rssi, &"replay");
+                    eventlog::dropped(Some(&notif), heard.rssi, &"replay");
+                    return;
+                }
+                verbose!("    → cancels notification {}", notif.id_hex());
+                eventlog::cancels(&notif, heard.rssi);
+                self.relayed.remove((sid, nid));
+                found.cancel(nid);
+
+                // Passed on like the notification was, so it reaches every
+                // repeater that relays it.
+                let Some(mut relay) = received.next_hop() else {
+                    return;
+                };
+                let decision = self
+                    .cfg
+                    .relay_decision(heard.rssi, sid, relay.has_client_tag());
+                if decision == RelayDecision::Drop {
+                    return;
+                }
+                self.prepare_relay(&mut relay, decision);
+                let expires = self.clock.now_us() + CANCEL_AIRTIME_US;
+                found.push(
+                    ActiveNotification::relayed(&relay, self.cfg.manufacturer_id, expires),
+                    heard.rssi,
+                );
+                return;
+            }
+
+            // The copy we air carries one hop fewer; a notification with
+            // none left stops here.
+            let Some(mut relay) = received.next_hop() else {
+                // A neighbour's last hop, heard every scan.
+                if copy {
+                    return;
+                }
+                verbose!("    ✗ no hops remaining — not relaying");
+                telemetry::rejected(Some(&notif), heard.rssi, &"no hops remaining");
+                eventlog::dropped(Some(&notif), heard.rssi, &"no hops remaining");
+                return;
+            };
+
+            // Every neighbour known would drop it: don't spend the airtime.
+            let version = if v1 {
+                PROTOCOL_VERSION_V1
+            } else {
+                PROTOCOL_VERSION
+            };
+            if self.cfg.neighbor_aware_relay
+                && !self
+                    .neighbors
+                    .worth_relaying(version, sealed, self.now_ms())
+            {
+                if copy {
+                    return;
+                }
+                verbose!("    ✗ no neighbour relays it — not relaying");
+                telemetry::rejected(Some(&notif), heard.rssi, &"no neighbour relays it");
+                eventlog::dropped(Some(&notif), heard.rssi, &"no neighbour relays it");
+                return;
+            }
+
+            // First repeater signs the client tag, if it heard the
+            // broadcaster strongly enough; subsequent repeaters pass it
+            // through unchanged. A sealed notification's client tag is on
+            // its envelope.
+            let decision = self
+                .cfg
+                .relay_decision(heard.rssi, sid, relay.has_client_tag());
+
+            // Only packets we would relay advance the notification's seq, so
+            // a weak first copy doesn't shadow a stronger one heard later. A
+            // copy may repeat the seq taken, but not go back past it. v1
+            // has no seq to check: relaying it at all (`relay_v1`) trades
+            // replay protection for reaching broadcasters not yet upgraded.
+            let seq_checked = decision != RelayDecision::Drop && !v1;
+            if seq_checked
+                && !self.accept_seq(&notif)
+                && !(copy && self.seen_seq.newest((sid, nid)) == Some(notif.seq()))
+            {
+                // E.g. a neighbour still airing an earlier stamp of it.
+                if copy {
+                    debug!("    → older copy of {} — skipping", notif.id_hex());
+                    return;
+                }
+                verbose!(
+                    "    ✗ seq {} not newer than {:?} for this notification — replay, not relaying",
+                    notif.seq(),
+                    self.seen_seq.newest((sid, nid))
+                );
+                telemetry::rejected(Some(&notif), heard.rssi, &"replay");
+                eventlog::dropped(Some(&notif), heard.rssi, &"replay");
+                return;
+            }
+
+            if decision != RelayDecision::Drop {
+                self.prepare_relay(&mut relay, decision);
+
+                // Repeaters expire on `duration_secs`; `validity_secs` is for
+                // clients only.
+                let now = self.clock.now_us();
+                let expires = now + (dur as i64) * 1_000_000;
+                self.relayed.insert((sid, nid), now + DEDUP_TTL_US);
+
+                let mut entry =
+                    ActiveNotification::relayed(&relay, self.cfg.manufacturer_id, expires);
+                entry.copy = copy;
+                found.push(entry, heard.rssi);
+                if !copy {
+                    eventlog::relayed(&notif, heard.rssi);
+                }
+            }
+        }
+    }
+}
+
+/// The repeater minus the radio: the active list, shared with the
+/// re-broadcast task, and the state each scan cycle updates.
+pub struct Repeater<S, C> {
+    scanner: S,
+    intake: Intake<C>,
+    active: Arc<Mutex<Vec<ActiveNotification>>>,
+    /// The notifications of the list last handed out for saving, and when,
+    /// so cycles that change nothing but expiry times skip the flash write.
+    saved: Vec<TransportNotification>,
+    saved_at_us: i64,
+    /// Where the scan task is between cycles (see `state`).
+    state: RepeaterState,
+    /// What the last cycle left the scan task to do.
+    next_action: Action,
+}
+
+impl<S, C: Clock> Repeater<S, C> {
+    /// A repeater starting from `restored`, the list saved before a reboot,
+    /// verifying against `keyring` (see `RepeaterConfig::infra_keyring`),
+    /// signing with the client keys from `keys` that `cfg` selects (see
+    /// `RepeaterConfig::client_keys`, which the caller has checked) and
+    /// expiring entries by `clock`.
+    pub fn new(
+        cfg: RepeaterConfig,
+        keyring: Vec<InfraKey>,
+        keys: &dyn KeyProvider,
+        scanner: S,
+        restored: Vec<ActiveNotification>,
+        clock: C,
+    ) -> Self {
+        let now = clock.now_us();
+        let neighbors = NeighborTable::new(u64::from(cfg.neighbor_stale_secs) * 1000);
+        let mut intake = Intake {
+            keyring,
+            client_keys: cfg
+                .client_keys(keys)
+                .expect("client_key_ids not in the client keyring"),
+            cfg,
+            seen_seq: SeqTracker::new(),
+            replay_changed: false,
+            relayed: DedupCache::new(),
+            neighbors,
+            metrics: RepeaterMetrics::default(),
+            clock,
+            own_addr: None,
+            #[cfg(feature = "relay-path")]
+            repeater_id: None,
+        };
+        // Restored entries were relayed before the reboot; seed both so their
+        // copies are still recognised.
+        for a in &restored {
+            let key = ({ a.notification.source_id }, {
+                a.notification.notification_id
+            });
+            let n = &a.notification;
+            intake
+                .seen_seq
+                .accept_stamped(key, n.seq(), n.timestamp_ms());
+            // As when it was heard: a cancellation is never a relayed copy.
+            if !a.is_cancellation() {
+                intake.relayed.insert(key, now + DEDUP_TTL_US);
+            }
+        }
+        Self {
+            scanner,
+            intake,
+            saved: restored.iter().map(|a| a.notification).collect(),
+            saved_at_us: now,
+            state: RepeaterState::default(),
+            next_action: Action::Scan,
+            active: Arc::new(Mutex::new(restored)),
+        }
+    }
+
+    /// Recognise advertisements from `addr`, this repeater's own
+    /// advertising address, that repeat what it is airing (see
+    /// `is_own_echo`).
+    pub fn with_own_addr(mut self, addr: [u8; 6]) -> Self {
+        self.intake.own_addr = Some(addr);
+        self
+    }
+
+    /// Add `repeater_id` to the relay path of every notification relayed
+    /// (see `ble_protocol_core::relay_path`).
+    #[cfg(feature = "relay-path")]
+    pub fn with_repeater_id(mut self, repeater_id: [u8; 4]) -> Self {
+        self.intake.repeater_id = Some(repeater_id);
+        self
+    }
+
+    /// Seed replay rejection with `entries` from `replay_cache`, as saved
+    /// before a reboot, so their packets are still taken as replays.
+    pub fn with_replay_cache(
+        mut self,
+        entries: impl IntoIterator<Item = (SeqKey, u32, u64)>,
+    ) -> Self {
+        for (key, seq, timestamp_ms) in entries {
+            self.intake.seen_seq.accept_stamped(key, seq, timestamp_ms);
+        }
+        self
+    }
+
+    /// The newest `seq` taken per notification, with the `timestamp_ms` of
+    /// the packet it came in, least recently taken first: what
+    /// `with_replay_cache` restores.
+    pub fn replay_cache(&self) -> impl Iterator<Item = (SeqKey, u32, u64)> + '_ {
+        self.intake.seen_seq.entries()
+    }
+
+    /// Whether the replay cache took a new `seq` since the last call, so it
+    /// only needs saving when this says so. Entries seeded at startup don't
+    /// count: they came from NVS or the restored active list.
+    pub fn take_replay_changed(&mut self) -> bool {
+        core::mem::take(&mut self.intake.replay_changed)
+    }
+
+    /// Where the scan task is between cycles.
+    pub fn state(&self) -> RepeaterState {
+        self.state
+    }
+
+    /// What the scan task is to do now the last cycle is done: scan again,
+    /// sleep first while backed off, or, with `once`, air and stop.
+    pub fn next_action(&self) -> Action {
+        self.next_action
+    }
+
+    /// Whether enough quiet cycles have passed to back the scan off (see
+    /// `RepeaterConfig::idle_cycles_before_backoff`).
+    pub fn backed_off(&self) -> bool {
+        self.state == RepeaterState::BackedOff
+    }
+
+    /// How long the next scan lasts.
+    pub fn scan_duration_ms(&self) -> i32 {
+        self.state.scan_duration_ms(&self.intake.cfg)
+    }
+
+    /// The active list, for the re-broadcast task.
+    pub fn active(&self) -> Arc<Mutex<Vec<ActiveNotification>>> {
+        Arc::clone(&self.active)
+    }
+
+    pub fn metrics(&self) -> &RepeaterMetrics {
+        &self.intake.metrics
+    }
+
+    /// Drop the entries expired at `now_us`. Returns how many were dropped.
+    pub fn prune(&mut self, now_us: i64) -> usize {
+        let mut active = self.active.lock().unwrap();
+        let before = active.len();
+        active.retain(|n| {
+            let expired = n.is_expired(now_us);
+            if expired {
+                telemetry::entry(Event::Pruned, n);
+                eventlog::entry(Event::Pruned, n);
+            }
+            !expired
+        });
+        let pruned = before - active.len();
+        self.intake.metrics.pruned += pruned as u64;
+        pruned
+    }
+
+    /// Remove the active entries whose `notification_id` is in `cancelled`.
+    /// A cancellation still being aired makes way for a newer one, and
+    /// doesn't count as cancelled itself.
+    pub fn cancel(&mut self, cancelled: &[[u8; 4]]) {
+        let mut active = self.active.lock().unwrap();
+        for nid in cancelled {
+            let Some(i) = active
+                .iter()
+                .position(|a| { a.notification.notification_id } == *nid)
+            else {
+                continue;
+            };
+            let cancelled = active.remove(i);
+            if cancelled.is_cancellation() {
+                continue;
+            }
+            verbose!(
+                "  cancelled notification {}",
+                cancelled.notification.id_hex()
+            );
+            telemetry::entry(Event::Cancelled, &cancelled);
+            eventlog::entry(Event::Cancelled, &cancelled);
+            self.intake.metrics.cancelled += 1;
+        }
+    }
+
+    /// Merge the notifications heard in one scan into the active list. A
+    /// `notification_id` already there has its expiry refreshed, and takes
+    /// the new copy if it is preferred (see
+    /// `ActiveNotification::preferred_over`); a copy relayed through more
+    /// repeaters than the one held leaves it alone. A new one is added while
+    /// there is room; a copy of one no longer on the list (pruned, or
+    /// evicted) is dropped. When the list is full, a new notification
+    /// takes the place of the lowest-priority entry (the one expiring
+    /// soonest among equals) if it has a strictly higher priority, and is
+    /// dropped otherwise. A notification re-issued while its cancellation is
+    /// still aired takes the cancellation's place as a new entry. Whatever
+    /// joins the list is stamped with the time
+    /// (`ActiveNotification::accepted_at_us`).
+    pub fn merge(&mut self, heard: Vec<ActiveNotification>) {
+        let now = self.intake.clock.now_us();
+        let metrics = &mut self.intake.metrics;
+        let mut active = self.active.lock().unwrap();
+        for mut new in heard {
+            let new_nid = { new.notification.notification_id };
+            if let Some(existing) = active
+                .iter_mut()
+                .find(|a| { a.notification.notification_id } == new_nid)
+            {
+                if existing.is_cancellation() && !new.is_cancellation() {
+                    new.accepted_at_us = Some(now);
+                    verbose!("  re-issued {} after its cancellation", new.notification);
+                    telemetry::entry(Event::Added, &new);
+                    eventlog::entry(Event::Added, &new);
+                    *existing = new;
+                    metrics.added += 1;
+                } else if new.preferred_over(existing) {
+                    new.accepted_at_us = existing.accepted_at_us;
+                    metrics.updated += 1;
+                    telemetry::entry(Event::Updated, &new);
+                    eventlog::entry(Event::Updated, &new);
+                    *existing = new;
+                    verbose!(
+                        "  updated notification {} with a copy at {} dBm",
+                        existing.notification.id_hex(),
+                        existing.rssi
+                    );
+                } else if new.refreshes(existing) {
+                    verbose!(
+                        "  refreshed notification {} expiry, keeping the copy at {} dBm over one at {} dBm",
+                        existing.notification.id_hex(),
+                        existing.rssi,
+                        new.rssi
+                    );
+                    existing.expires_at_us = new.expires_at_us;
+                }
+            } else if new.copy {
+                debug!(
+                    "  copy of {} no longer on the active list — dropped",
+                    new.notification.id_hex()
+                );
+            } else if active.len() < self.intake.cfg.max_active_notifications {
+                new.accepted_at_us = Some(now);
+                verbose!("  added {} to active list", new.notification);
+                telemetry::entry(Event::Added, &new);
+                eventlog::entry(Event::Added, &new);
+                active.push(new);
+                metrics.added += 1;
+            } else if let Some(victim) = active
+                .iter_mut()
+                .min_by_key(|a| (a.notification.priority, a.expires_at_us))
+                .filter(|a| a.notification.priority < new.notification.priority)
+            {
+                new.accepted_at_us = Some(now);
+                eventlog::evicted(victim, &new);
+                telemetry::entry(Event::Added, &new);
+                eventlog::entry(Event::Added, &new);
+                *victim = new;
+                metrics.dropped_full += 1;
+            } else {
+                eventlog::list_full(&new);
+                telemetry::rejected(Some(&new.notification), new.rssi, &"active list full");
+                metrics.dropped_full += 1;
+            }
+        }
+    }
+}
+
+impl<S: Scanner, C: Clock> Repeater<S, C> {
+    /// Scan for one window and return what is to be relayed, and the
+    /// `notification_id`s cancelled.
+    pub fn scan(&mut self) -> (Vec<ActiveNotification>, Vec<[u8; 4]>) {
+        let duration_ms = self.scan_duration_ms();
+        let intake = &mut self.intake;
+        let active = &self.active;
+        let mut found = ScanQueue::with_capacity(intake.cfg.max_scan_queue);
+        self.scanner.scan(duration_ms, &mut |heard| {
+            intake.consider(heard, active, &mut found)
+        });
+
+        if found.overflowed > 0 {
+            eventlog::queue_full(found.overflowed);
+        }
+        found.into_parts()
+    }
+
+    /// One cycle: prune, scan, merge. Returns a copy of the active list to
+    /// save if its entries changed since the last copy handed out, or if
+    /// that was `persist_refresh_secs` ago and the list isn't empty.
+    pub fn run_cycle(&mut self) -> Option<Vec<ActiveNotification>> {
+        // ── Prune expired notifications ─────────────────────────────────
+        let pruned = self.prune(self.intake.clock.now_us());
+        if pruned > 0 {
+            verbose!("Pruned {} expired notification(s)", pruned);
+        }
+
+        // ── Scan ────────────────────────────────────────────────────────
+        debug!(
+            "── Scanning for {} ms (active list: {}) ──",
+            self.scan_duration_ms(),
+            self.active.lock().unwrap().len()
+        );
+        let (new_notifications, cancelled) = self.scan();
+
+        // ── Merge new notifications into active list ────────────────────
+        self.cancel(&cancelled);
+        self.merge(new_notifications);
+        debug!("── Scan complete ── {}", self.intake.metrics);
+
+        // ── Next state: back the scan off while nothing is active ───────
+        let cfg = &self.intake.cfg;
+        let active_empty = self.active.lock().unwrap().is_empty();
+        let (state, action) = self.state.step(active_empty, cfg);
+        match (self.state, state) {
+            (RepeaterState::Scanning { idle_cycles }, RepeaterState::BackedOff) => verbose!(
+                "── Quiet for {} cycles — backing off to {} ms scans every {} ms ──",
+                idle_cycles + 1,
+                cfg.idle_scan_duration_ms,
+                cfg.idle_scan_period_ms
+            ),
+            (RepeaterState::BackedOff, RepeaterState::Scanning { .. }) => verbose!(
+                "── Activity — back to continuous {} ms scans ──",
+                cfg.scan_duration_ms
+            ),
+            _ => {}
+        }
+        self.state = state;
+        self.next_action = action;
+
+        // ── Hand out a copy to save, if worth a flash write ─────────────
+        let now = self.intake.clock.now_us();
+        let refresh_us = i64::from(cfg.persist_refresh_secs) * 1_000_000;
+        let active = self.active.lock().unwrap();
+        let changed = active.len() != self.saved.len()
+            || active
+                .iter()
+                .zip(&self.saved)
+                .any(|(a, saved)| a.notification.as_bytes() != saved.as_bytes());
+        let due = refresh_us > 0 && !active.is_empty() && now - self.saved_at_us >= refresh_us;
+        if !changed && !due {
+            return None;
+        }
+        self.saved = active.iter().map(|a| a.notification).collect();
+        self.saved_at_us = now;
+        // Copied out so the flash write happens outside the lock.
+        Some(active.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    use crate::clock::MockClock;
+    use crate::config::{IDLE_CYCLES_BEFORE_BACKOFF, IDLE_SCAN_DURATION_MS, IDLE_SCAN_PERIOD_MS};
+    use crate::schedule::Jitter;
+    use ble_protocol_core::capability::SUPPORTED_VERSIONS;
+    use ble_protocol_core::client_tags::{self, ClientTags};
+    use ble_protocol_core::crypto::compute_client_tag;
+    use ble_protocol_core::{InfraKey, KeyProvider};
+    use ble_protocol_core::{
+        StaticKeys, TransportNotification, TransportNotificationBuilder, TransportNotificationV1,
+        TransportStatus, TransportType, DEFAULT_HOPS, HMAC_KEY_CLIENT, INFRA_KEYRING,
+        INFRA_KEY_CURRENT, MANUFACTURER_ID, PROTOCOL_VERSION_V1,
+    };
+
+    /// Address every `MockScanner` advertisement comes from.
+    const PEER_ADDR: [u8; 6] = [0xC0, 0xFF, 0xEE, 0, 0, 1];
+
+    /// Hands out one batch of `(company_id, payload, rssi)` per scan.
+    #[derive(Default)]
+    struct MockScanner(VecDeque<Vec<(u16, Vec<u8>, i8)>>);
+
+    impl Scanner for MockScanner {
+        fn scan(&mut self, _duration_ms: i32, on_heard: &mut dyn FnMut(Heard<'_>)) {
+            for (company_id, payload, rssi) in self.0.pop_front().unwrap_or_default() {
+                on_heard(Heard {
+                    addr: PEER_ADDR,
+                    rssi,
+                    manufacturer_data: Some((company_id, &payload)),
+                });
+            }
+        }
+    }
+
+    fn notification(id: u8) -> TransportNotification {
+        prioritized(id, 0)
+    }
+
+    fn prioritized(id: u8, priority: u8) -> TransportNotification {
+        TransportNotificationBuilder::new()
+            .source_id([id; 4])
+            .notification_id([id; 4])
+            .transport(TransportType::Bus)
+            .status(TransportStatus::Coming)
+            .duration_secs(30)
+            .seq(1)
+            .priority(priority)
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap()
+    }
+
+    fn entry(id: u8, expires_at_us: i64) -> ActiveNotification {
+        ActiveNotification::new(notification(id), MANUFACTURER_ID, expires_at_us)
+    }
+
+    type MockRepeater = Repeater<MockScanner, MockClock>;
+
+    fn repeater(scans: Vec<Vec<(u16, Vec<u8>, i8)>>) -> MockRepeater {
+        repeater_keyed(&StaticKeys, MockClock::default(), scans)
+    }
+
+    fn repeater_keyed(
+        keys: &dyn KeyProvider,
+        clock: MockClock,
+        scans: Vec<Vec<(u16, Vec<u8>, i8)>>,
+    ) -> MockRepeater {
+        let cfg = RepeaterConfig {
+            max_active_notifications: 2,
+            ..RepeaterConfig::default()
+        };
+        let keyring = cfg.infra_keyring(keys).unwrap();
+        Repeater::new(
+            cfg,
+            keyring,
+            keys,
+            MockScanner(scans.into()),
+            Vec::new(),
+            clock,
+        )
+    }
+
+    fn ids(repeater: &MockRepeater) -> Vec<u8> {
+        repeater
+            .active()
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|a| { a.notification.notification_id }[0])
+            .collect()
+    }
+
+    #[test]
+    fn prune_drops_only_expired_entries() {
+        let mut r = repeater(Vec::new());
+        r.merge(vec![entry(1, 100), entry(2, 300)]);
+        assert_eq!(r.prune(100), 1);
+        assert_eq!(ids(&r), [2]);
+        assert_eq!(r.prune(200), 0);
+        assert_eq!(r.metrics().pruned, 1);
+    }
+
+    #[test]
+    fn cycle_prunes_entries_once_the_clock_passes_their_expiry() {
+        let clock = MockClock::default();
+        let heard = vec![(MANUFACTURER_ID, notification(1).as_bytes().to_vec(), -40)];
+        let mut r = repeater_keyed(&StaticKeys, clock.clone(), vec![heard]);
+        r.run_cycle();
+        let expires_at_us = r.active().lock().unwrap()[0].expires_at_us;
+        assert_eq!(expires_at_us, 30_000_000);
+
+        clock.set_us(expires_at_us - 1);
+        r.run_cycle();
+        assert_eq!(ids(&r), [1]);
+
+        clock.advance_us(1);
+        r.run_cycle();
+        assert!(ids(&r).is_empty());
+        assert_eq!(r.metrics().pruned, 1);
+    }
+
+    #[test]
+    fn merge_refreshes_adds_and_drops_when_full() {
+        let mut r = repeater(Vec::new());
+        r.merge(vec![entry(1, 100), entry(2, 100)]);
+        r.merge(vec![entry(2, 900), entry(3, 900)]);
+
+        assert_eq!(ids(&r), [1, 2]);
+        assert_eq!(r.active().lock().unwrap()[1].expires_at_us, 900);
+        let m = r.metrics();
+        assert_eq!((m.added, m.updated, m.dropped_full), (2, 1, 1));
+    }
+
+    #[test]
+    fn merge_stamps_when_an_entry_is_accepted() {
+        let clock = MockClock::default();
+        let mut r = repeater_keyed(&StaticKeys, clock.clone(), Vec::new());
+        clock.set_us(500);
+        r.merge(vec![entry(1, 10_000)]);
+        clock.set_us(900);
+        let mut stronger = entry(1, 20_000);
+        stronger.rssi = -30;
+        r.merge(vec![stronger, entry(2, 10_000)]);
+
+        let active = r.active.lock().unwrap();
+        assert_eq!(active[0].rssi, -30, "the stronger copy took over");
+        assert_eq!(active[0].accepted_at_us, Some(500));
+        assert_eq!(active[1].accepted_at_us, Some(900));
+    }
+
+    #[test]
+    fn full_list_evicts_the_lowest_priority_soonest_expiring_entry() {
+        let with_priority = |id, priority, expires_at_us| {
+            ActiveNotification::new(prioritized(id, priority), MANUFACTURER_ID, expires_at_us)
+        };
+        let mut r = repeater(Vec::new());
+        r.merge(vec![with_priority(1, 1, 500), with_priority(2, 1, 300)]);
+
+        // Equal priority never evicts; among equals the soonest expiry goes.
+        r.merge(vec![with_priority(3, 1, 900)]);
+        assert_eq!(ids(&r), [1, 2]);
+        r.merge(vec![with_priority(4, 5, 200)]);
+        assert_eq!(ids(&r), [1, 4]);
+
+        // The lowest priority goes before a sooner-expiring higher one.
+        r.merge(vec![with_priority(5, 9, 900)]);
+        assert_eq!(ids(&r), [5, 4]);
+        r.merge(vec![with_priority(6, 5, 100)]);
+        assert_eq!(ids(&r), [5, 4]);
+
+        let m = r.metrics();
+        assert_eq!((m.added, m.dropped_full), (2, 4));
+    }
+
+    #[test]
+    fn merge_and_prune_hold_their_invariants_under_load() {
+        let cfg = RepeaterConfig::default();
+        let cap = cfg.max_active_notifications;
+        let keyring = cfg.infra_keyring(&StaticKeys).unwrap();
+        let mut r = Repeater::new(
+            cfg,
+            keyring,
+            &StaticKeys,
+            MockScanner::default(),
+            Vec::new(),
+            MockClock::default(),
+        );
+        // Three times as many ids as fit, each in four priorities; signed
+        // once up front, since only merge and prune are under test.
+        let templates: Vec<TransportNotification> = (0..3 * cap as u8)
+            .flat_map(|id| (0..4).map(move |priority| prioritized(id, priority)))
+            .collect();
+        let mut rng = Jitter::new(0x5EED);
+        let mut now = 0i64;
+        let mut heard_total = 0;
+
+        for _ in 0..5_000 {
+            // Mostly forward, now and then back a little: the scan and
+            // re-broadcast tasks read the clock at different moments.
+            now += i64::from(rng.add(0, 2_000_000)) - 250_000;
+
+            let expired = {
+                let active = r.active();
+                let active = active.lock().unwrap();
+                active.iter().filter(|a| a.expires_at_us <= now).count()
+            };
+            assert_eq!(r.prune(now), expired);
+            assert!(r
+                .active()
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|a| a.expires_at_us > now));
+
+            if rng.add(0, 9) == 0 {
+                let id = rng.add(0, 3 * cap as u32 - 1) as u8;
+                r.cancel(&[[id; 4]]);
+            }
+
+            // Expiries from already past to 30 s out, so some entries are
+            // dead on arrival and others outlive many rounds.
+            let heard: Vec<ActiveNotification> = (0..rng.add(0, 8))
+                .map(|_| {
+                    let notif = templates[rng.add(0, templates.len() as u32 - 1) as usize];
+                    let expires = now + i64::from(rng.add(0, 30_000_000)) - 1_000_000;
+                    let mut entry = ActiveNotification::new(notif, MANUFACTURER_ID, expires);
+                    entry.rssi = -(rng.add(30, 60) as i8);
+                    entry
+                })
+                .collect();
+            heard_total += heard.len();
+            r.merge(heard);
+
+            let active = r.active();
+            let active = active.lock().unwrap();
+            assert!(active.len() <= cap);
+            let mut nids: Vec<[u8; 4]> = active
+                .iter()
+                .map(|a| a.notification.notification_id)
+                .collect();
+            nids.sort_unstable();
+            nids.dedup();
+            assert_eq!(nids.len(), active.len(), "duplicate notification_id");
+            let m = r.metrics();
+            assert_eq!(
+                active.len() as u64,
+                m.added - m.pruned - m.cancelled,
+                "entries unaccounted for"
+            );
+        }
+
+        // The load actually filled the list and churned through it.
+        let m = r.metrics();
+        assert!(heard_total > 10_000);
+        assert!(m.dropped_full > 0 && m.pruned > 0 && m.updated > 0 && m.cancelled > 0);
+    }
+
+    #[test]
+    fn cycle_relays_only_verified_notifications() {
+        let good = notification(1);
+        let mut forged = notification(2);
+        forged.duration_secs = 600u16.to_le_bytes();
+        forged.crc16 = ble_protocol_core::crc::crc16_ccitt(forged.base_payload()).to_le_bytes();
+        let mut r = repeater(vec![vec![
+            (MANUFACTURER_ID, good.as_bytes().to_vec(), -40),
+            (MANUFACTURER_ID, forged.as_bytes().to_vec(), -40),
+            (0x1234, good.as_bytes().to_vec(), -40),
+        ]]);
+
+        let saved = r.run_cycle().unwrap();
+        assert_eq!(saved.len(), 1);
+        let relayed = saved[0].notification;
+        assert_eq!(relayed.hops_remaining, DEFAULT_HOPS - 1);
+        assert_eq!(
+            { relayed.hmac_tag_client },
+            compute_client_tag(StaticKeys.client_key(), relayed.base_payload())
+        );
+        assert_eq!(saved[0].expires_at_us, 30_000_000);
+
+        let m = r.metrics();
+        assert_eq!((m.seen, m.matched, m.infra_fail, m.added), (3, 2, 1, 1));
+    }
+
+    /// `StaticKeys` with a different client key, so a repeater holding it
+    /// that re-signed a client tag would change it.
+    struct OtherClientKey;
+
+    impl KeyProvider for OtherClientKey {
+        fn infra_keyring(&self) -> &[InfraKey] {
+            INFRA_KEYRING
+        }
+
+        fn current_infra_key(&self) -> InfraKey {
+            INFRA_KEY_CURRENT
+        }
+
+        fn client_key(&self) -> &'static [u8] {
+            b"another-client-key"
+        }
+
+        fn client_keyring(&self) -> &[ClientKey] {
+            &[(0, b"another-client-key")]
+        }
+    }
+
+    #[test]
+    fn second_hop_passes_the_client_tag_through() {
+        let sent = notification(1);
+        let aired = |r: &mut MockRepeater| {
+            let saved = r.run_cycle().unwrap();
+            let payload = saved[0].raw_mfg_payload()[2..].to_vec();
+            TransportNotification::from_payload(&payload).unwrap()
+        };
+
+        let mut first = repeater(vec![vec![(MANUFACTURER_ID, sent.as_bytes().to_vec(), -40)]]);
+        let hop1 = aired(&mut first);
+        let mut second = repeater_keyed(
+            &OtherClientKey,
+            MockClock::default(),
+            vec![vec![(MANUFACTURER_ID, hop1.as_bytes().to_vec(), -40)]],
+        );
+        let hop2 = aired(&mut second);
+
+        // Signed once, by the first repeater, and carried unchanged after.
+        assert!(!sent.has_client_tag());
+        assert!(hop1.verify_client_with(StaticKeys.client_key()));
+        assert_eq!({ hop2.hmac_tag_client }, { hop1.hmac_tag_client });
+        assert!(!hop2.verify_client_with(OtherClientKey.client_key()));
+
+        // The broadcaster's infra tag and signed payload never change.
+        for hop in [hop1, hop2] {
+            assert_eq!({ hop.hmac_tag_infra }, { sent.hmac_tag_infra });
+            assert_eq!(hop.base_payload(), sent.base_payload());
+        }
+        assert_eq!(hop2.hops_remaining, DEFAULT_HOPS - 2);
+
+        // The first repeater hearing the second hop's copy within the
+        // replay window doesn't take it back.
+        first
+            .scanner
+            .0
+            .push_back(vec![(MANUFACTURER_ID, hop2.as_bytes().to_vec(), -30)]);
+        first.run_cycle();
+        assert_eq!((first.metrics().added, first.metrics().updated), (1, 0));
+    }
+
+    #[test]
+    fn every_notification_packed_in_an_advertisement_is_relayed() {
+        let packed = [notification(1).as_bytes(), notification(2).as_bytes()].concat();
+        let mut r = repeater(vec![vec![(MANUFACTURER_ID, packed, -40)]]);
+
+        r.run_cycle();
+        assert_eq!(ids(&r), [1, 2]);
+        let m = r.metrics();
+        assert_eq!((m.seen, m.matched, m.added), (1, 1, 2));
+    }
+
+    #[test]
+    fn notifications_aired_together_by_one_broadcaster_are_all_relayed() {
+        // The broadcaster stamps every airing from one counter; the second
+        // notification's airing is heard before the first's.
+        let stamped = |id: u8, seq: u32| {
+            let notif = TransportNotificationBuilder::new()
+                .source_id([0xB0; 4])
+                .notification_id([id; 4])
+                .transport(TransportType::Bus)
+                .status(TransportStatus::Coming)
+                .duration_secs(30)
+                .seq(seq)
+                .build_signed(INFRA_KEY_CURRENT)
+                .unwrap();
+            (MANUFACTURER_ID, notif.as_bytes().to_vec(), -40)
+        };
+        let mut r = repeater(vec![vec![stamped(2, 6), stamped(1, 5)]]);
+
+        r.run_cycle();
+        assert_eq!(ids(&r), [2, 1]);
+        assert_eq!(r.metrics().rejected(), 0);
+    }
+
+    #[test]
+    fn v1_notifications_are_relayed_as_v1() {
+        let sent = TransportNotificationV1::downgrade(&notification(1), INFRA_KEY_CURRENT);
+        let scan = || vec![(MANUFACTURER_ID, sent.as_bytes().to_vec(), -40)];
+        let mut r = repeater(vec![scan(), scan()]);
+
+        let saved = r.run_cycle().unwrap();
+        let aired = &saved[0].raw_mfg_payload()[2..];
+        assert_eq!(aired.len(), TransportNotificationV1::SIZE);
+        let relayed = TransportNotificationV1::from_payload_with(aired, INFRA_KEYRING).unwrap();
+        assert!(relayed.verify_client_with(StaticKeys.client_key()));
+        assert_eq!(relayed.base_payload(), sent.base_payload());
+        assert_eq!(saved[0].notification.version, PROTOCOL_VERSION_V1);
+
+        // Heard again, it has no seq to be taken for a replay by.
+        r.run_cycle();
+        assert_eq!(ids(&r), [1]);
+        assert_eq!(r.metrics().rejected(), 0);
+
+        let mut r = repeater(vec![scan()]);
+        r.intake.cfg.relay_v1 = false;
+        r.run_cycle();
+        assert!(ids(&r).is_empty());
+    }
+
+    #[test]
+    fn future_dated_notifications_are_held_to_the_configured_skew() {
+        let ahead_ms = 60_000;
+        let scan = || {
+            let notif = TransportNotificationBuilder::new()
+                .source_id([1; 4])
+                .notification_id([1; 4])
+                .transport(TransportType::Bus)
+                .status(TransportStatus::Coming)
+                .duration_secs(30)
+                .seq(1)
+                .timestamp_ms(unix_now_ms() + ahead_ms)
+                .build_signed(INFRA_KEY_CURRENT)
+                .unwrap();
+            vec![(MANUFACTURER_ID, notif.as_bytes().to_vec(), -40)]
+        };
+
+        let mut r = repeater(vec![scan()]);
+        r.intake.cfg.has_clock = true;
+        r.run_cycle();
+        assert!(ids(&r).is_empty(), "beyond the default 30 s");
+        assert_eq!(r.metrics().stale, 1);
+
+        let mut r = repeater(vec![scan()]);
+        r.intake.cfg.has_clock = true;
+        r.intake.cfg.max_future_skew_ms = 2 * ahead_ms;
+        r.run_cycle();
+        assert_eq!(ids(&r), [1]);
+
+        // Without a clock there is nothing to judge it by.
+        let mut r = repeater(vec![scan()]);
+        r.run_cycle();
+        assert_eq!(ids(&r), [1]);
+    }
+
+    #[test]
+    fn v1_cancellations_are_ignored() {
+        let v1 = |dur| {
+            let notif = TransportNotificationBuilder::new()
+                .source_id([1; 4])
+                .notification_id([1; 4])
+                .transport(TransportType::Bus)
+                .status(TransportStatus::Coming)
+                .duration_secs(dur)
+                .seq(1)
+                .build_signed(INFRA_KEY_CURRENT)
+                .unwrap();
+            let sent = TransportNotificationV1::downgrade(&notif, INFRA_KEY_CURRENT);
+            vec![(MANUFACTURER_ID, sent.as_bytes().to_vec(), -40)]
+        };
+        let mut r = repeater(vec![v1(30), v1(0)]);
+
+        r.run_cycle();
+        r.run_cycle();
+        assert_eq!(ids(&r), [1]);
+    }
+
+    #[test]
+    fn zero_duration_cancels_an_active_notification() {
+        let copy = |id: u8, dur: u16, seq: u32, key| {
+            let notif = TransportNotificationBuilder::new()
+                .source_id([id; 4])
+                .notification_id([id; 4])
+                .transport(TransportType::Bus)
+                .status(TransportStatus::Coming)
+                .duration_secs(dur)
+                .seq(seq)
+                .build_signed(key)
+                .unwrap();
+            (MANUFACTURER_ID, notif.as_bytes().to_vec(), -40)
+        };
+        let key = INFRA_KEY_CURRENT;
+        let forged_key = (key.0, &b"not the infra key"[..]);
+        let mut r = repeater(vec![
+            vec![copy(1, 30, 1, key), copy(2, 30, 1, key)],
+            // Forged, and replayed from before the notification: ignored.
+            vec![copy(1, 0, 2, forged_key), copy(2, 0, 1, key)],
+            vec![copy(1, 0, 2, key)],
+            // The cancellation again, e.g. from a neighbour.
+            vec![copy(1, 0, 2, key)],
+            // Re-issued after the cancellation: relayed again.
+            vec![copy(1, 30, 3, key)],
+        ]);
+
+        r.run_cycle();
+        r.run_cycle();
+        assert_eq!(ids(&r), [1, 2]);
+        // Notification 1 gives way to its cancellation, relayed like it:
+        // one hop fewer, and a client tag of this repeater's.
+        r.run_cycle();
+        assert_eq!(ids(&r), [2, 1]);
+        let cancelled = r.active().lock().unwrap()[1].clone();
+        assert!(cancelled.is_cancellation());
+        assert_eq!(cancelled.notification.seq(), 2);
+        assert_eq!(cancelled.notification.hops_remaining, DEFAULT_HOPS - 1);
+        assert!(cancelled
+            .notification
+            .verify_client_with(StaticKeys.client_key()));
+        assert_eq!(r.metrics().cancelled, 1);
+        r.run_cycle();
+        assert_eq!(ids(&r), [2, 1]);
+        assert_eq!(r.metrics().cancelled, 1);
+        r.run_cycle();
+        assert_eq!(ids(&r), [2, 1]);
+        assert!(!r.active().lock().unwrap()[1].is_cancellation());
+    }
+
+    /// A copy of notification `[1; 4]` lasting `dur`, stamped with `seq`.
+    fn copy(dur: u16, seq: u32) -> (u16, Vec<u8>, i8) {
+        let notif = TransportNotificationBuilder::new()
+            .source_id([1; 4])
+            .notification_id([1; 4])
+            .transport(TransportType::Bus)
+            .status(TransportStatus::Coming)
+            .duration_secs(dur)
+            .seq(seq)
+            .timestamp_ms(1_767_225_600_000 + u64::from(seq))
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap();
+        (MANUFACTURER_ID, notif.as_bytes().to_vec(), -40)
+    }
+
+    #[test]
+    fn replay_cache_restored_after_a_reboot_still_rejects_replays() {
+        // Relayed, then cancelled: the active list saved holds only the
+        // cancellation.
+        let mut before = repeater(vec![vec![copy(30, 5)], vec![copy(0, 6)]]);
+        before.run_cycle();
+        before.run_cycle();
+        assert!(before.active().lock().unwrap()[0].is_cancellation());
+        let saved: Vec<_> = before.replay_cache().collect();
+        assert_eq!(saved, [(([1; 4], [1; 4]), 6, 1_767_225_600_006)]);
+
+        // The notification captured before the cancellation, replayed after
+        // the reboot.
+        let mut forgetful = repeater(vec![vec![copy(30, 5)]]);
+        forgetful.run_cycle();
+        assert_eq!(ids(&forgetful), [1]);
+
+        let mut restored = repeater(vec![vec![copy(30, 5)]]).with_replay_cache(saved);
+        restored.run_cycle();
+        assert!(ids(&restored).is_empty());
+    }
+
+    #[test]
+    fn the_replay_cache_changes_only_when_it_takes_a_seq() {
+        let mut r = repeater(vec![
+            vec![copy(30, 5)],
+            vec![copy(30, 5)],
+            vec![copy(30, 5)],
+            vec![copy(0, 6)],
+        ]);
+        r.run_cycle();
+        assert!(r.take_replay_changed());
+        assert!(!r.take_replay_changed());
+        // Re-heard copies, and a replay of an older one, take nothing.
+        r.run_cycle();
+        r.run_cycle();
+        assert!(!r.take_replay_changed());
+        // A cancellation takes its seq too.
+        r.run_cycle();
+        assert!(r.take_replay_changed());
+
+        // Restoring the cache isn't a change to save back.
+        let saved: Vec<_> = r.replay_cache().collect();
+        let mut restored = repeater(vec![]).with_replay_cache(saved);
+        assert!(!restored.take_replay_changed());
+    }
+
+    /// `sent` as heard at `rssi`, `relays` repeaters after the broadcaster.
+    fn heard_copy(sent: &TransportNotification, relays: u8, rssi: i8) -> (u16, Vec<u8>, i8) {
+        let mut copy = *sent;
+        copy.hops_remaining -= relays;
+        (MANUFACTURER_ID, copy.as_bytes().to_vec(), rssi)
+    }
+
+    fn held(r: &MockRepeater) -> (i8, u8, i64) {
+        let active = r.active.lock().unwrap();
+        let a = &active[0];
+        (a.rssi, a.notification.hops_remaining, a.expires_at_us)
+    }
+
+    #[test]
+    fn stronger_copies_in_later_scans_replace_the_held_one() {
+        let sent = notification(1);
+        let clock = MockClock::default();
+        let mut r = repeater_keyed(
+            &StaticKeys,
+            clock.clone(),
+            vec![
+                vec![heard_copy(&sent, 0, -80)],
+                vec![heard_copy(&sent, 0, -50)],
+                // A neighbour's relay, however strong, changes nothing.
+                vec![heard_copy(&sent, 1, -30)],
+                // A weaker copy from the broadcaster only refreshes expiry.
+                vec![heard_copy(&sent, 0, -90)],
+            ],
+        );
+
+        r.run_cycle();
+        assert_eq!(held(&r), (-80, DEFAULT_HOPS - 1, 30_000_000));
+        r.run_cycle();
+        assert_eq!(held(&r), (-50, DEFAULT_HOPS - 1, 30_000_000));
+        clock.set_us(1_000_000);
+        r.run_cycle();
+        assert_eq!(held(&r), (-50, DEFAULT_HOPS - 1, 30_000_000));
+        clock.set_us(2_000_000);
+        r.run_cycle();
+        assert_eq!(held(&r), (-50, DEFAULT_HOPS - 1, 32_000_000));
+
+        let m = r.metrics();
+        assert_eq!((m.added, m.updated, m.rejected()), (1, 1, 0));
+    }
+
+    #[test]
+    fn scan_queues_the_strongest_copy_heard_in_one_scan() {
+        let sent = notification(1);
+        let mut r = repeater(vec![vec![
+            heard_copy(&sent, 0, -80),
+            heard_copy(&sent, 0, -50),
+            heard_copy(&sent, 1, -30),
+            heard_copy(&sent, 0, -60),
+        ]]);
+
+        r.run_cycle();
+        assert_eq!(held(&r), (-50, DEFAULT_HOPS - 1, 30_000_000));
+        let m = r.metrics();
+        assert_eq!((m.added, m.updated, m.rejected()), (1, 0, 0));
+    }
+
+    #[test]
+    fn quiet_cycles_back_the_scan_off_until_something_is_heard() {
+        let mut scans = vec![Vec::new(); IDLE_CYCLES_BEFORE_BACKOFF as usize + 1];
+        scans.push(vec![(
+            MANUFACTURER_ID,
+            notification(1).as_bytes().to_vec(),
+            -40,
+        )]);
+        let mut r = repeater(scans);
+
+        for _ in 1..IDLE_CYCLES_BEFORE_BACKOFF {
+            r.run_cycle();
+            assert_eq!(r.scan_duration_ms(), r.intake.cfg.scan_duration_ms);
+            assert_eq!(r.next_action(), Action::Scan);
+        }
+        r.run_cycle();
+        assert!(r.backed_off());
+        assert_eq!(r.scan_duration_ms(), IDLE_SCAN_DURATION_MS);
+        assert_eq!(
+            r.next_action(),
+            Action::Sleep {
+                ms: IDLE_SCAN_PERIOD_MS - IDLE_SCAN_DURATION_MS as u32
+            }
+        );
+
+        r.run_cycle();
+        assert!(r.backed_off());
+        r.run_cycle();
+        assert_eq!(r.state(), RepeaterState::Scanning { idle_cycles: 0 });
+        assert_eq!(r.next_action(), Action::Scan);
+    }
+
+    #[test]
+    fn client_tag_is_signed_with_the_providers_key() {
+        struct OwnClientKey;
+        impl KeyProvider for OwnClientKey {
+            fn infra_keyring(&self) -> &[InfraKey] {
+                INFRA_KEYRING
+            }
+            fn current_infra_key(&self) -> InfraKey {
+                INFRA_KEY_CURRENT
+            }
+            fn client_key(&self) -> &'static [u8] {
+                b"deployment-client-key"
+            }
+            fn client_keyring(&self) -> &[ClientKey] {
+                &[(0, b"deployment-client-key")]
+            }
+        }
+
+        let cfg = RepeaterConfig::default();
+        let keyring = cfg.infra_keyring(&OwnClientKey).unwrap();
+        let scanner = MockScanner(
+            vec![vec![(
+                MANUFACTURER_ID,
+                notification(1).as_bytes().to_vec(),
+                -40,
+            )]]
+            .into(),
+        );
+        let mut r = Repeater::new(
+            cfg,
+            keyring,
+            &OwnClientKey,
+            scanner,
+            Vec::new(),
+            MockClock::default(),
+        );
+
+        let relayed = r.run_cycle().unwrap()[0].notification;
+        assert!(relayed.verify_client_with(b"deployment-client-key"));
+        assert!(!relayed.verify_client_with(StaticKeys.client_key()));
+    }
+
+    #[test]
+    fn rolling_client_keys_sign_a_tag_per_generation() {
+        const NEXT: &[u8] = b"next-generation-client!!";
+        struct TwoGenerations;
+        impl KeyProvider for TwoGenerations {
+            fn infra_keyring(&self) -> &[InfraKey] {
+                INFRA_KEYRING
+            }
+            fn current_infra_key(&self) -> InfraKey {
+                INFRA_KEY_CURRENT
+            }
+            fn client_key(&self) -> &'static [u8] {
+                StaticKeys.client_key()
+            }
+            fn client_keyring(&self) -> &[ClientKey] {
+                &[(0, HMAC_KEY_CLIENT), (1, NEXT)]
+            }
+        }
+        let first_hop = |client_key_ids: Vec<u8>| {
+            let cfg = RepeaterConfig {
+                client_key_ids,
+                ..RepeaterConfig::default()
+            };
+            let keyring = cfg.infra_keyring(&TwoGenerations).unwrap();
+            let scanner = MockScanner(
+                vec![vec![(
+                    MANUFACTURER_ID,
+                    notification(1).as_bytes().to_vec(),
+                    -40,
+                )]]
+                .into(),
+            );
+            let mut r = Repeater::new(
+                cfg,
+                keyring,
+                &TwoGenerations,
+                scanner,
+                Vec::new(),
+                MockClock::default(),
+            );
+            r.run_cycle().unwrap()[0].raw_mfg_payload()[2..].to_vec()
+        };
+        let verifies = |payload: &[u8], key: ClientKey| {
+            let notif = TransportNotification::parse_unverified(payload).unwrap();
+            let record = ClientTags::find(payload).unwrap();
+            client_tags::verify(&notif, record.as_ref(), key).is_ok()
+        };
+
+        // During the roll both generations verify, each under its own id.
+        let overlap = first_hop(vec![0, 1]);
+        assert_eq!(
+            overlap.len(),
+            TransportNotification::SIZE + ClientTags::size_with(1)
+        );
+        assert!(verifies(&overlap, (0, HMAC_KEY_CLIENT)));
+        assert!(verifies(&overlap, (1, NEXT)));
+        assert!(!verifies(&overlap, (1, HMAC_KEY_CLIENT)));
+
+        // After it, only the new one.
+        let rolled = first_hop(vec![1]);
+        assert!(verifies(&rolled, (1, NEXT)));
+        assert!(!verifies(&rolled, (0, HMAC_KEY_CLIENT)));
+
+        // A further hop, with only the old key, carries the record on.
+        let mut second = repeater(vec![vec![(MANUFACTURER_ID, overlap.clone(), -40)]]);
+        let hop2 = second.run_cycle().unwrap()[0].raw_mfg_payload()[2..].to_vec();
+        assert_eq!(hop2.len(), overlap.len());
+        assert!(verifies(&hop2, (1, NEXT)));
+    }
+
+    /// What the `id`th repeater of a chain airs after hearing `payload`.
+    #[cfg(feature = "relay-path")]
+    fn relay_through(id: u8, payload: Vec<u8>) -> Vec<u8> {
+        let mut r = repeater(vec![vec![(MANUFACTURER_ID, payload, -40)]]).with_repeater_id([id; 4]);
+        r.run_cycle().unwrap()[0].raw_mfg_payload()[2..].to_vec()
+    }
+
+    #[cfg(feature = "relay-path")]
+    #[test]
+    fn the_relay_path_lists_each_repeater_in_turn() {
+        use ble_protocol_core::relay_path::{RelayPath, MAX_RELAY_PATH};
+
+        let sent = TransportNotificationBuilder::new()
+            .source_id([1; 4])
+            .notification_id([1; 4])
+            .transport(TransportType::Bus)
+            .status(TransportStatus::Coming)
+            .duration_secs(30)
+            .seq(1)
+            .hops_remaining(MAX_RELAY_PATH as u8 + 2)
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap();
+        let mut payload = sent.as_bytes().to_vec();
+        let mut paths = Vec::new();
+        for id in 1..=MAX_RELAY_PATH as u8 + 2 {
+            payload = relay_through(id, payload);
+            paths.push(RelayPath::find(&payload).unwrap().unwrap());
+            // The first hop's client tag still verifies.
+            assert!(TransportNotification::from_payload(&payload)
+                .unwrap()
+                .verify_client_with(StaticKeys.client_key()));
+        }
+
+        let hops = |path: &RelayPath| path.hops().map(|id| id[0]).collect::<Vec<_>>();
+        assert_eq!(hops(&paths[0]), [1]);
+        assert_eq!(hops(&paths[2]), [1, 2, 3]);
+        assert!(!paths[MAX_RELAY_PATH - 1].is_truncated());
+        // Past the bound, later hops are left out and the path says so.
+        let last = paths.last().unwrap();
+        assert_eq!(hops(last), (1..=MAX_RELAY_PATH as u8).collect::<Vec<_>>());
+        assert!(last.is_truncated());
+        assert_eq!(payload.len(), TransportNotification::SIZE + RelayPath::SIZE);
+    }
+
+    #[cfg(not(feature = "relay-path"))]
+    #[test]
+    fn a_production_repeater_drops_the_relay_path() {
+        use ble_protocol_core::relay_path::RelayPath;
+
+        let mut path = RelayPath::new();
+        path.push([9; 4]);
+        let heard = [notification(1).as_bytes(), path.as_bytes()].concat();
+        let mut r = repeater(vec![vec![(MANUFACTURER_ID, heard, -40)]]);
+        let aired = r.run_cycle().unwrap()[0].raw_mfg_payload()[2..].to_vec();
+        assert_eq!(aired.len(), TransportNotification::SIZE);
+        assert_eq!(RelayPath::find(&aired), Ok(None));
+    }
+
+    #[test]
+    fn unlisted_sources_are_dropped_after_verifying() {
+        let heard =
+            [1, 2, 3].map(|id| (MANUFACTURER_ID, notification(id).as_bytes().to_vec(), -40));
+        let cfg = RepeaterConfig {
+            allowed_sources: vec![[1; 4], [2; 4]],
+            denied_sources: vec![[2; 4]],
+            ..RepeaterConfig::default()
+        };
+        let scanner = MockScanner(vec![heard.to_vec()].into());
+        let keyring = cfg.infra_keyring(&StaticKeys).unwrap();
+        let mut r = Repeater::new(
+            cfg,
+            keyring,
+            &StaticKeys,
+            scanner,
+            Vec::new(),
+            MockClock::default(),
+        );
+
+        r.run_cycle();
+        assert_eq!(ids(&r), [1]);
+        let m = r.metrics();
+        assert_eq!((m.infra_fail, m.source_rejected), (0, 2));
+    }
+
+    #[test]
+    fn other_destinations_are_not_relayed() {
+        let to = |id: u8, destination: u8| {
+            let notif = TransportNotificationBuilder::new()
+                .source_id([id; 4])
+                .notification_id([id; 4])
+                .destination(destination)
+                .transport(TransportType::Bus)
+                .status(TransportStatus::Coming)
+                .duration_secs(30)
+                .seq(1)
+                .build_signed(INFRA_KEY_CURRENT)
+                .unwrap();
+            (MANUFACTURER_ID, notif.as_bytes().to_vec(), -40)
+        };
+        let cfg = RepeaterConfig {
+            destinations: vec![3, 4],
+            ..RepeaterConfig::default()
+        };
+        let scanner = MockScanner(vec![vec![to(1, 3), to(2, 7), to(3, 4)]].into());
+        let keyring = cfg.infra_keyring(&StaticKeys).unwrap();
+        let mut r = Repeater::new(
+            cfg,
+            keyring,
+            &StaticKeys,
+            scanner,
+            Vec::new(),
+            MockClock::default(),
+        );
+
+        r.run_cycle();
+        assert_eq!(ids(&r), [1, 3]);
+        assert_eq!(r.metrics().rejected(), 0);
+    }
+
+    #[test]
+    fn acks_from_other_repeaters_are_ignored() {
+        let ack = AckBeacon::new(&notification(1), [9; 4], INFRA_KEY_CURRENT);
+        let mut r = repeater(vec![vec![(MANUFACTURER_ID, ack.as_bytes().to_vec(), -40)]]);
+
+        assert!(r.run_cycle().is_none());
+        let m = r.metrics();
+        assert_eq!((m.matched, m.rejected()), (1, 0));
+    }
+
+    #[test]
+    fn neighbor_aware_relay_skips_what_no_neighbor_relays() {
+        let beacon = |versions| {
+            let beacon = CapabilityBeacon::new([9; 4], versions, 0, INFRA_KEY_CURRENT);
+            (MANUFACTURER_ID, beacon.as_bytes().to_vec(), -40)
+        };
+        let current = |id| (MANUFACTURER_ID, notification(id).as_bytes().to_vec(), -40);
+        let v1 = |id| {
+            let sent = TransportNotificationV1::downgrade(&notification(id), INFRA_KEY_CURRENT);
+            (MANUFACTURER_ID, sent.as_bytes().to_vec(), -40)
+        };
+        let scans = || {
+            vec![
+                vec![beacon(1 << PROTOCOL_VERSION_V1), current(1), v1(2)],
+                // The neighbour upgraded.
+                vec![beacon(SUPPORTED_VERSIONS), current(3)],
+            ]
+        };
+
+        let mut r = repeater(scans());
+        r.intake.cfg.neighbor_aware_relay = true;
+        r.run_cycle();
+        assert_eq!(ids(&r), [2], "the only neighbour relays v1 alone");
+        r.run_cycle();
+        assert_eq!(ids(&r), [2, 3]);
+
+        // Off, beacons are ignored and everything is relayed.
+        let mut r = repeater(scans());
+        r.run_cycle();
+        assert_eq!(ids(&r), [1, 2]);
+        assert_eq!(r.metrics().rejected(), 0);
+    }
+
+    #[test]
+    fn copies_heard_in_later_cycles_are_skipped() {
+        let payload = notification(1).as_bytes().to_vec();
+        let clock = MockClock::default();
+        let mut r = repeater_keyed(
+            &StaticKeys,
+            clock.clone(),
+            vec![
+                vec![(MANUFACTURER_ID, payload.clone(), -40)],
+                vec![(MANUFACTURER_ID, payload, -40)],
+            ],
+        );
+        assert!(r.run_cycle().is_some());
+
+        // Past its expiry the entry is pruned, and the dedup cache still
+        // keeps the copy heard again from coming back.
+        clock.set_us(31_000_000);
+        r.run_cycle();
+        assert!(ids(&r).is_empty());
+        assert_eq!((r.metrics().added, r.metrics().updated), (1, 0));
+        // Empty, and saved as such: nothing to write next time.
+        assert!(r.run_cycle().is_none());
+    }
+
+    #[test]
+    fn the_list_is_saved_on_changes_and_refreshed_now_and_then() {
+        let heard = |ids: &[u8]| {
+            ids.iter()
+                .map(|&id| (MANUFACTURER_ID, notification(id).as_bytes().to_vec(), -40))
+                .collect::<Vec<_>>()
+        };
+        let clock = MockClock::default();
+        let mut r = repeater_keyed(
+            &StaticKeys,
+            clock.clone(),
+            vec![heard(&[1]), heard(&[1]), heard(&[1]), heard(&[1, 2])],
+        );
+        // Shorter than the notifications' 30 s, so they stay active.
+        r.intake.cfg.persist_refresh_secs = 10;
+        let refresh_us = 10_000_000;
+
+        assert!(r.run_cycle().is_some());
+        // Heard again: only the expiry moved.
+        clock.advance_us(refresh_us / 2);
+        assert!(r.run_cycle().is_none());
+        clock.advance_us(refresh_us / 2);
+        assert_eq!(r.run_cycle().map(|saved| saved.len()), Some(1));
+        // A new entry is saved straight away.
+        clock.advance_us(1);
+        assert_eq!(r.run_cycle().map(|saved| saved.len()), Some(2));
+        assert!(r.run_cycle().is_none());
+    }
+
+    #[test]
+    fn own_output_is_recognised_by_address_and_bytes() {
+        const OWN: [u8; 6] = [0xC0, 0xFF, 0xEE, 0, 0, 2];
+        let airing = [entry(1, 0)];
+        let aired = &airing[0].raw_mfg_payload()[2..];
+        let other = notification(2);
+
+        assert!(is_own_echo(Some(OWN), OWN, aired, &airing));
+        // A peer relaying identical bytes is not an echo.
+        assert!(!is_own_echo(Some(OWN), PEER_ADDR, aired, &airing));
+        // Nor is something from our address we aren't airing.
+        assert!(!is_own_echo(Some(OWN), OWN, other.as_bytes(), &airing));
+        assert!(!is_own_echo(None, OWN, aired, &airing));
+    }
+
+    #[test]
+    fn own_echo_is_ignored_and_a_peers_identical_relay_is_not() {
+        const OWN: [u8; 6] = [0xC0, 0xFF, 0xEE, 0, 0, 2];
+        let sent = notification(1);
+        let mut r = repeater(vec![vec![(MANUFACTURER_ID, sent.as_bytes().to_vec(), -40)]])
+            .with_own_addr(OWN);
+        r.run_cycle();
+        let aired = r.active.lock().unwrap()[0].raw_mfg_payload().to_vec();
+
+        let queued = |r: &mut MockRepeater, addr| {
+            let mut found = ScanQueue::with_capacity(4);
+            let heard = Heard {
+                addr,
+                rssi: -20,
+                manufacturer_data: Some((MANUFACTURER_ID, &aired[2..])),
+            };
+            r.intake.consider(heard, &r.active, &mut found);
+            found.into_parts().0.len()
+        };
+        assert_eq!(queued(&mut r, OWN), 0);
+        assert_eq!(queued(&mut r, PEER_ADDR), 1);
+
+        r.intake.cfg.ignore_own_echo = false;
+        assert_eq!(queued(&mut r, OWN), 1);
+    }
+}
@@ -0,0 +1,303 @@
+//! Choosing which active notifications go on air in a re-broadcast cycle.
+//!
+//! Each aired notification costs a `set_data` + `start` on the NimBLE stack.
+//! `select_for_cycle` caps that per cycle and rotates through the active list
+//! so that entries left over by the cap are aired first next cycle.
+//!
+//! Within a cycle, each selected entry's airtime is split into short dwells
+//! aired round-robin (`rotation`), so that no entry is off air for the
+//! whole of everyone else's airtime. A client that listens for a few
+//! hundred milliseconds hears every entry, not just whichever one happens to
+//! be on air.
+//!
+//! A notification of high enough priority (`escalate_min_priority`) is
+//! escalated while fresh: `escalation` gives it `ESCALATION_BOOST` extra
+//! dwells per round when it joins the active list, one fewer for each third
+//! of `escalate_for_ms` that passes, and none after. `round_order` fits the
+//! extra dwells in between everyone else's, so an alert goes out several
+//! times as often for its first few cycles without holding the others off
+//! air, then settles to the same share as the rest.
+//!
+//! Repeaters in range of each other would otherwise keep the same interval
+//! and dwells, and once their advertisements collide they keep colliding.
+//! `Jitter` draws a random extra for each dwell and its advertising
+//! interval, so their schedules drift apart instead.
+
+/// How long one entry stays on air before the next one takes over, when
+/// several are sharing the cycle.
+pub const ENTRY_DWELL_MS: u32 = 200;
+
+/// The notifications to air this cycle, as indices into the active list.
+#[derive(Debug, PartialEq)]
+pub struct CycleSelection {
+    pub indices: Vec<usize>,
+    /// Index to start from next cycle.
+    pub next_cursor: usize,
+    /// Number of notifications left over for a later cycle.
+    pub deferred: usize,
+}
+
+/// Select at most `cap` of `len` active notifications, starting at `cursor`
+/// and wrapping around. The cursor is an index, so entries pruned or added
+/// between cycles shift the rotation by a few places; each entry is still
+/// reached within `ceil(len / cap)` cycles of a stable list.
+pub fn select_for_cycle(len: usize, cap: usize, cursor: usize) -> CycleSelection {
+    if len <= cap {
+        return CycleSelection {
+            indices: (0..len).collect(),
+            next_cursor: 0,
+            deferred: 0,
+        };
+    }
+    let start = cursor % len;
+    CycleSelection {
+        indices: (0..cap).map(|k| (start + k) % len).collect(),
+        next_cursor: (start + cap) % len,
+        deferred: len - cap,
+    }
+}
+
+/// How a cycle's airtime is split: `rounds` passes over the selected
+/// entries, each entry on air for `dwell_ms` per pass.
+#[derive(Debug, PartialEq)]
+pub struct Rotation {
+    pub rounds: u32,
+    pub dwell_ms: u32,
+}
+
+/// Split `airtime_ms` per entry into dwells of at most `dwell_ms` for
+/// `entries` entries. Every entry gets the same `rounds × dwell_ms`, which
+/// is `airtime_ms` rounded up to a whole dwell. A lone entry is aired in one
+/// go, since there is nothing to rotate with.
+pub fn rotation(entries: usize, airtime_ms: u32, dwell_ms: u32) -> Rotation {
+    if entries <= 1 || airtime_ms <= dwell_ms {
+        return Rotation {
+            rounds: 1,
+            dwell_ms: airtime_ms,
+        };
+    }
+    Rotation {
+        rounds: airtime_ms.div_ceil(dwell_ms),
+        dwell_ms,
+    }
+}
+
+/// Extra dwells per round a freshly escalated notification gets.
+pub const ESCALATION_BOOST: u32 = 3;
+
+/// Extra dwells per round for an entry of `priority` accepted at
+/// `accepted_at_us` (see `ActiveNotification::accepted_at_us`), at `now_us`.
+/// Escalation covers priorities from `min_priority` up (0 = never) for
+/// `window_ms` after acceptance: `ESCALATION_BOOST` at first, decaying in
+/// equal steps to none at the end of the window.
+pub fn escalation(
+    priority: u8,
+    accepted_at_us: Option<i64>,
+    now_us: i64,
+    min_priority: u8,
+    window_ms: u32,
+) -> u32 {
+    let Some(accepted_at_us) = accepted_at_us else {
+        return 0;
+    };
+    if min_priority == 0 || priority < min_priority || window_ms == 0 {
+        return 0;
+    }
+    let window_us = i64::from(window_ms) * 1000;
+    let left_us = window_us - (now_us - accepted_at_us).max(0);
+    if left_us <= 0 {
+        return 0;
+    }
+    // Rounded up, so the boost only reaches zero once the window is over.
+    let steps = i64::from(ESCALATION_BOOST);
+    ((left_us * steps + window_us - 1) / window_us) as u32
+}
+
+/// The order of one round's dwells, as indices into the selected entries,
+/// given each one's `escalation`: every entry once, in order, then a pass
+/// over those with at least one extra dwell, then over those with at least
+/// two, and so on. An entry's dwells are thereby spread across the round.
+pub fn round_order(boosts: &[u32]) -> Vec<usize> {
+    let passes = boosts.iter().copied().max().unwrap_or(0);
+    (0..=passes)
+        .flat_map(|pass| (0..boosts.len()).filter(move |&i| boosts[i] >= pass))
+        .collect()
+}
+
+/// Per-repeater randomness for the re-broadcast schedule: a xorshift32
+/// generator. Not cryptographic; it only has to differ between repeaters.
+pub struct Jitter(u32);
+
+impl Jitter {
+    /// `seed` must differ between repeaters; the device takes it from the
+    /// hardware RNG. Zero, which xorshift would never leave, is replaced.
+    pub fn new(seed: u32) -> Self {
+        Self(if seed == 0 { 0x9E37_79B9 } else { seed })
+    }
+
+    fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    /// `base` plus a random extra in `0..=spread`.
+    pub fn add(&mut self, base: u32, spread: u32) -> u32 {
+        let extra = u64::from(self.next()) % (u64::from(spread) + 1);
+        base.saturating_add(extra as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_list_is_aired_in_full() {
+        let cycle = select_for_cycle(3, 8, 5);
+        assert_eq!(cycle.indices, vec![0, 1, 2]);
+        assert_eq!((cycle.next_cursor, cycle.deferred), (0, 0));
+    }
+
+    #[test]
+    fn oversized_list_is_capped() {
+        let cycle = select_for_cycle(10, 4, 0);
+        assert_eq!(cycle.indices, vec![0, 1, 2, 3]);
+        assert_eq!((cycle.next_cursor, cycle.deferred), (4, 6));
+    }
+
+    #[test]
+    fn deferred_entries_are_aired_first_next_cycle() {
+        let first = select_for_cycle(10, 4, 0);
+        let second = select_for_cycle(10, 4, first.next_cursor);
+        let third = select_for_cycle(10, 4, second.next_cursor);
+        assert_eq!(second.indices, vec![4, 5, 6, 7]);
+        assert_eq!(third.indices, vec![8, 9, 0, 1]);
+    }
+
+    #[test]
+    fn every_entry_is_aired_equally_over_full_rotations() {
+        let (len, cap) = (7, 3);
+        let mut aired = vec![0u32; len];
+        let mut cursor = 0;
+        // lcm(7, 3) / 3 = 7 cycles covers every entry exactly 3 times.
+        for _ in 0..7 {
+            let cycle = select_for_cycle(len, cap, cursor);
+            assert_eq!(cycle.indices.len(), cap);
+            for i in cycle.indices {
+                aired[i] += 1;
+            }
+            cursor = cycle.next_cursor;
+        }
+        assert_eq!(aired, vec![3; len]);
+    }
+
+    #[test]
+    fn airtime_is_split_into_dwells() {
+        assert_eq!(
+            rotation(16, 2000, 200),
+            Rotation {
+                rounds: 10,
+                dwell_ms: 200
+            }
+        );
+        // Rounded up to a whole dwell, the same for every entry.
+        assert_eq!(rotation(3, 500, 200).rounds, 3);
+    }
+
+    #[test]
+    fn lone_entry_or_short_airtime_is_aired_in_one_go() {
+        let whole = Rotation {
+            rounds: 1,
+            dwell_ms: 2000,
+        };
+        assert_eq!(rotation(1, 2000, 200), whole);
+        assert_eq!(rotation(0, 2000, 200), whole);
+        assert_eq!(
+            rotation(4, 150, 200),
+            Rotation {
+                rounds: 1,
+                dwell_ms: 150
+            }
+        );
+    }
+
+    #[test]
+    fn cursor_past_a_shrunken_list_wraps() {
+        let cycle = select_for_cycle(5, 2, 9);
+        assert_eq!(cycle.indices, vec![4, 0]);
+        assert_eq!(cycle.next_cursor, 1);
+    }
+
+    #[test]
+    fn fresh_alert_is_boosted_and_decays_to_baseline() {
+        let window_ms = 6000;
+        let boost_at =
+            |age_ms: i64| escalation(9, Some(1_000_000), 1_000_000 + age_ms * 1000, 5, window_ms);
+        let boosts: Vec<u32> = [0, 1999, 2000, 3999, 4000, 5999, 6000, 60_000]
+            .into_iter()
+            .map(boost_at)
+            .collect();
+        assert_eq!(boosts, [3, 3, 2, 2, 1, 1, 0, 0]);
+
+        // An alert and a routine entry: four dwells to one at first, one
+        // each once the window is over.
+        let airings = |boosts: &[u32]| {
+            let mut count = vec![0; boosts.len()];
+            for i in round_order(boosts) {
+                count[i] += 1;
+            }
+            count
+        };
+        assert_eq!(airings(&[boost_at(0), 0]), [4, 1]);
+        assert_eq!(airings(&[boost_at(4500), 0]), [2, 1]);
+        assert_eq!(airings(&[boost_at(6000), 0]), [1, 1]);
+    }
+
+    #[test]
+    fn only_accepted_high_priority_entries_are_escalated() {
+        assert_eq!(escalation(9, Some(0), 0, 5, 6000), ESCALATION_BOOST);
+        assert_eq!(escalation(4, Some(0), 0, 5, 6000), 0, "below the threshold");
+        assert_eq!(escalation(9, None, 0, 5, 6000), 0, "restored, not accepted");
+        assert_eq!(escalation(0, Some(0), 0, 0, 6000), 0, "escalation off");
+    }
+
+    #[test]
+    fn extra_dwells_are_spread_across_the_round() {
+        assert_eq!(round_order(&[0, 2, 0, 1]), [0, 1, 2, 3, 1, 3, 1]);
+        assert_eq!(round_order(&[0, 0]), [0, 1]);
+        assert!(round_order(&[]).is_empty());
+    }
+
+    #[test]
+    fn jitter_stays_within_its_spread() {
+        let mut jitter = Jitter::new(1);
+        for _ in 0..1000 {
+            let v = jitter.add(200, 50);
+            assert!((200..=250).contains(&v), "{v}");
+        }
+        assert_eq!(jitter.add(200, 0), 200);
+        assert_eq!(Jitter::new(0).add(7, 0), 7);
+        assert!(Jitter::new(0).add(0, u32::MAX) > 0);
+    }
+
+    #[test]
+    fn repeaters_started_together_drift_apart() {
+        // Two repeaters start their first dwell at the same instant. Without
+        // jitter they would stay in phase; with it, their dwell boundaries
+        // spread out rather than keep a fixed offset.
+        let (mut a, mut b) = (Jitter::new(0x1234_5678), Jitter::new(0x0BAD_CAFE));
+        let (mut at_a, mut at_b) = (0u32, 0u32);
+        let mut offsets = Vec::new();
+        for _ in 0..100 {
+            at_a += a.add(ENTRY_DWELL_MS, 50);
+            at_b += b.add(ENTRY_DWELL_MS, 50);
+            offsets.push(at_a.abs_diff(at_b));
+        }
+        assert!(offsets.windows(2).any(|w| w[0] != w[1]));
+        assert!(offsets.iter().skip(10).any(|&d| d > 50));
+    }
+}
@@ -0,0 +1,191 @@
+//! The scan task's operating states, and the one function that moves
+//! between them.
+//!
+//! ```text
+//!            active list empty for
+//!            idle_cycles_before_backoff cycles
+//!   Scanning ─────────────────────────────────▶ BackedOff
+//!      ▲                                            │
+//!      └──────── anything active after a cycle ─────┘
+//!
+//!   Scanning / BackedOff ── a cycle done with `once` ──▶ Stopped
+//! ```
+//!
+//! `Scanning` scans in `scan_duration_ms` windows back to back. `BackedOff`
+//! scans for `idle_scan_duration_ms` and keeps the radio off in between, so
+//! scans start `idle_scan_period_ms` apart. With `once`, the first cycle is
+//! also the last: the task airs the active list itself and stops.
+//!
+//! `Repeater::run_cycle` runs a cycle in the current state, then feeds what
+//! it ended with to `step`; the firmware's main loop only carries out the
+//! `Action` that comes back. Re-broadcasting otherwise runs on a task of
+//! its own, airing whatever the active list holds, and has no states.
+
+use crate::config::RepeaterConfig;
+
+/// Where the scan task is between cycles; see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepeaterState {
+    /// Full scan windows, back to back. `idle_cycles` counts the cycles in
+    /// a row that ended with nothing active.
+    Scanning { idle_cycles: u32 },
+    /// Short scans with the radio off in between, until something is
+    /// heard.
+    BackedOff,
+    /// The single cycle of `once` is done.
+    Stopped,
+}
+
+/// What the scan task does after a cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Start the next scan straight away.
+    Scan,
+    /// Keep the radio off for `ms`, then scan.
+    Sleep { ms: u32 },
+    /// Air the active list once and stop (`once`).
+    RebroadcastAndStop,
+}
+
+impl Default for RepeaterState {
+    fn default() -> Self {
+        Self::Scanning { idle_cycles: 0 }
+    }
+}
+
+impl RepeaterState {
+    /// How long a scan lasts in this state.
+    pub fn scan_duration_ms(self, cfg: &RepeaterConfig) -> i32 {
+        match self {
+            Self::BackedOff => cfg.idle_scan_duration_ms,
+            Self::Scanning { .. } | Self::Stopped => cfg.scan_duration_ms,
+        }
+    }
+
+    /// The state after a cycle that ended with `active_empty`, and what to
+    /// do next. `idle_cycles_before_backoff` 0 never backs off.
+    pub fn step(self, active_empty: bool, cfg: &RepeaterConfig) -> (Self, Action) {
+        if cfg.once || self == Self::Stopped {
+            return (Self::Stopped, Action::RebroadcastAndStop);
+        }
+        let next = match self {
+            _ if !active_empty => Self::Scanning { idle_cycles: 0 },
+            Self::Scanning { idle_cycles } => {
+                let idle_cycles = idle_cycles.saturating_add(1);
+                let after = cfg.idle_cycles_before_backoff;
+                if after > 0 && idle_cycles >= after {
+                    Self::BackedOff
+                } else {
+                    Self::Scanning { idle_cycles }
+                }
+            }
+            backed_off => backed_off,
+        };
+        let action = match next {
+            Self::BackedOff => Action::Sleep {
+                ms: cfg.idle_scan_period_ms - cfg.idle_scan_duration_ms as u32,
+            },
+            _ => Action::Scan,
+        };
+        (next, action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{IDLE_CYCLES_BEFORE_BACKOFF, IDLE_SCAN_DURATION_MS, IDLE_SCAN_PERIOD_MS};
+
+    const SLEEP: Action = Action::Sleep {
+        ms: IDLE_SCAN_PERIOD_MS - IDLE_SCAN_DURATION_MS as u32,
+    };
+
+    /// Step from `state` through cycles ending with `empties`, returning
+    /// each state and action.
+    fn run(
+        mut state: RepeaterState,
+        empties: &[bool],
+        cfg: &RepeaterConfig,
+    ) -> Vec<(RepeaterState, Action)> {
+        empties
+            .iter()
+            .map(|&empty| {
+                let (next, action) = state.step(empty, cfg);
+                state = next;
+                (next, action)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quiet_cycles_back_off_and_activity_resumes_scanning() {
+        let cfg = RepeaterConfig::default();
+        let mut empties = vec![true; IDLE_CYCLES_BEFORE_BACKOFF as usize + 1];
+        empties.push(false);
+        let steps = run(RepeaterState::default(), &empties, &cfg);
+
+        let n = IDLE_CYCLES_BEFORE_BACKOFF as usize;
+        assert_eq!(
+            steps[n - 2],
+            (
+                RepeaterState::Scanning {
+                    idle_cycles: IDLE_CYCLES_BEFORE_BACKOFF - 1
+                },
+                Action::Scan
+            )
+        );
+        assert_eq!(steps[n - 1], (RepeaterState::BackedOff, SLEEP));
+        assert_eq!(steps[n], (RepeaterState::BackedOff, SLEEP));
+        assert_eq!(
+            steps[n + 1],
+            (RepeaterState::Scanning { idle_cycles: 0 }, Action::Scan)
+        );
+
+        assert_eq!(
+            RepeaterState::BackedOff.scan_duration_ms(&cfg),
+            IDLE_SCAN_DURATION_MS
+        );
+        assert_eq!(
+            RepeaterState::default().scan_duration_ms(&cfg),
+            cfg.scan_duration_ms
+        );
+    }
+
+    #[test]
+    fn activity_restarts_the_idle_count() {
+        let cfg = RepeaterConfig::default();
+        let steps = run(RepeaterState::default(), &[true, true, false, true], &cfg);
+        assert_eq!(
+            steps.last(),
+            Some(&(RepeaterState::Scanning { idle_cycles: 1 }, Action::Scan))
+        );
+    }
+
+    #[test]
+    fn zero_idle_cycles_never_backs_off() {
+        let cfg = RepeaterConfig {
+            idle_cycles_before_backoff: 0,
+            ..RepeaterConfig::default()
+        };
+        let steps = run(RepeaterState::default(), &[true; 50], &cfg);
+        assert!(steps.iter().all(|(state, action)| {
+            matches!(state, RepeaterState::Scanning { .. }) && *action == Action::Scan
+        }));
+    }
+
+    #[test]
+    fn once_stops_after_the_first_cycle_from_any_state() {
+        let cfg = RepeaterConfig {
+            once: true,
+            ..RepeaterConfig::default()
+        };
+        for state in [RepeaterState::default(), RepeaterState::BackedOff] {
+            for empty in [true, false] {
+                assert_eq!(
+                    state.step(empty, &cfg),
+                    (RepeaterState::Stopped, Action::RebroadcastAndStop)
+                );
+            }
+        }
+    }
+}
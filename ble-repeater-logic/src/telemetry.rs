@@ -0,0 +1,171 @@
+//! Machine-readable event stream (`telemetry` feature).
+//!
+//! Every significant event is written to stdout, the UART console, as one
+//! JSON object per line, next to the human log lines. A gateway picks the
+//! events out as the lines that start with `{`:
+//!
+//! ```text
+//! {"event":"received","notification_id":"05060708","source_id":"01020304","rssi":-61,"notification":{…}}
+//! {"event":"added","notification_id":"05060708","source_id":"01020304","rssi":-61}
+//! {"event":"rejected","rssi":-70,"reason":"infra HMAC mismatch"}
+//! ```
+//!
+//! `notification` (the serde form of `TransportNotification`) is carried by
+//! `received` only; the other events name the notification by its ids.
+//! Fields that don't apply to an event are left out. Without the feature,
+//! the functions here are empty and compile out.
+
+use core::fmt;
+
+use ble_protocol_core::TransportNotification;
+
+use crate::active::ActiveNotification;
+
+/// What happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "telemetry", derive(serde::Serialize))]
+#[cfg_attr(feature = "telemetry", serde(rename_all = "snake_case"))]
+pub enum Event {
+    /// A notification verified.
+    Received,
+    /// Heard but not relayed: unparseable, forged, blocked, replayed, …
+    Rejected,
+    /// Added to the active list.
+    Added,
+    /// An active entry refreshed by a newer copy.
+    Updated,
+    /// An active entry removed by a cancellation.
+    Cancelled,
+    /// An active entry removed on expiry.
+    Pruned,
+    /// An active entry went on air.
+    Rebroadcast,
+}
+
+#[cfg(feature = "telemetry")]
+#[derive(serde::Serialize)]
+struct Record<'a> {
+    event: Event,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notification_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rssi: Option<i8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notification: Option<&'a TransportNotification>,
+}
+
+/// The line for one event.
+#[cfg(feature = "telemetry")]
+fn line(
+    event: Event,
+    notif: Option<&TransportNotification>,
+    rssi: Option<i8>,
+    reason: Option<&dyn fmt::Display>,
+) -> String {
+    let record = Record {
+        event,
+        notification_id: notif.map(TransportNotification::id_hex),
+        source_id: notif.map(TransportNotification::source_hex),
+        rssi,
+        reason: reason.map(ToString::to_string),
+        notification: notif.filter(|_| event == Event::Received),
+    };
+    // A notification with an invalid nibble doesn't serialize; it can only
+    // have come from a bug, so report it rather than drop the event.
+    serde_json::to_string(&record).unwrap_or_else(|e| {
+        format!(
+            r#"{{"event":"telemetry_error","reason":{:?}}}"#,
+            e.to_string()
+        )
+    })
+}
+
+#[cfg(feature = "telemetry")]
+fn emit(
+    event: Event,
+    notif: Option<&TransportNotification>,
+    rssi: Option<i8>,
+    reason: Option<&dyn fmt::Display>,
+) {
+    use std::io::Write;
+
+    // One write per line, so lines from the two tasks don't interleave.
+    let mut line = line(event, notif, rssi, reason);
+    line.push('\n');
+    let _ = std::io::stdout().lock().write_all(line.as_bytes());
+}
+
+#[cfg(not(feature = "telemetry"))]
+#[inline(always)]
+fn emit(
+    _event: Event,
+    _notif: Option<&TransportNotification>,
+    _rssi: Option<i8>,
+    _reason: Option<&dyn fmt::Display>,
+) {
+}
+
+/// A notification verified, heard at `rssi`.
+pub fn received(notif: &TransportNotification, rssi: i8) {
+    emit(Event::Received, Some(notif), Some(rssi), None);
+}
+
+/// An advertisement not relayed. `notif` is `None` if it didn't verify.
+pub fn rejected(notif: Option<&TransportNotification>, rssi: i8, reason: &dyn fmt::Display) {
+    emit(Event::Rejected, notif, Some(rssi), Some(reason));
+}
+
+/// Something happened to an active entry.
+pub fn entry(event: Event, entry: &ActiveNotification) {
+    emit(event, Some(&entry.notification), Some(entry.rssi), None);
+}
+
+#[cfg(all(test, feature = "telemetry"))]
+mod tests {
+    use super::*;
+    use ble_protocol_core::{
+        TransportNotificationBuilder, TransportStatus, TransportType, INFRA_KEY_CURRENT,
+    };
+
+    fn notification() -> TransportNotification {
+        TransportNotificationBuilder::new()
+            .source_id([1, 2, 3, 4])
+            .notification_id([5, 6, 7, 8])
+            .transport(TransportType::Bus)
+            .status(TransportStatus::Late)
+            .duration_secs(30)
+            .build_signed(INFRA_KEY_CURRENT)
+            .unwrap()
+    }
+
+    #[test]
+    fn events_are_one_json_object_per_line() {
+        let notif = notification();
+        let added = line(Event::Added, Some(&notif), Some(-61), None);
+        assert_eq!(
+            added,
+            r#"{"event":"added","notification_id":"05060708","source_id":"01020304","rssi":-61}"#
+        );
+
+        let received = line(Event::Received, Some(&notif), Some(-61), None);
+        let value: serde_json::Value = serde_json::from_str(&received).unwrap();
+        assert_eq!(value["event"], "received");
+        assert_eq!(value["notification"]["transport_status"], "Late");
+        assert!(!received.contains('\n'));
+
+        let rejected = line(
+            Event::Rejected,
+            None,
+            Some(-70),
+            Some(&"infra HMAC mismatch"),
+        );
+        assert_eq!(
+            rejected,
+            r#"{"event":"rejected","rssi":-70,"reason":"infra HMAC mismatch"}"#
+        );
+    }
+}
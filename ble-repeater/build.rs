@@ -1,3 +1,58 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
 fn main() {
     embuild::espidf::sysenv::output();
+    generate_config_overrides();
+}
+
+/// Turn `repeater.toml` (if present) into `apply_overrides`, which assigns
+/// each listed key onto `RepeaterConfig`. Unknown keys or mismatched types
+/// surface as compile errors in the generated code; range checks happen in
+/// `RepeaterConfig::validate` at startup.
+fn generate_config_overrides() {
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let path = std::env::var("REPEATER_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| manifest_dir.join("repeater.toml"));
+    println!("cargo:rerun-if-env-changed=REPEATER_CONFIG");
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let mut body = String::new();
+    if path.exists() {
+        let text = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("reading {}: {e}", path.display()));
+        let table: toml::Table = text
+            .parse()
+            .unwrap_or_else(|e| panic!("parsing {}: {e}", path.display()));
+        for (key, value) in &table {
+            let expr = rust_literal(value, true)
+                .unwrap_or_else(|| panic!("{}: unsupported value for `{key}`", path.display()));
+            writeln!(body, "    cfg.{key} = {expr};").unwrap();
+        }
+    }
+
+    let code = format!(
+        "#[allow(unused_variables)]\nfn apply_overrides(cfg: &mut RepeaterConfig) {{\n{body}}}\n"
+    );
+    let out = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("config_overrides.rs");
+    std::fs::write(out, code).unwrap();
+}
+
+/// Render a TOML value as a Rust expression. A top-level array becomes a
+/// `Vec`; arrays nested inside it become fixed-size arrays, so
+/// `[[0xa1, 0xb2, 0xc3, 0xd4]]` fills a `Vec<[u8; 4]>`.
+fn rust_literal(value: &toml::Value, top_level: bool) -> Option<String> {
+    match value {
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        toml::Value::String(s) => Some(format!("{s:?}.into()")),
+        toml::Value::Array(items) => {
+            let items: Option<Vec<String>> =
+                items.iter().map(|v| rust_literal(v, false)).collect();
+            let items = items?.join(", ");
+            Some(if top_level { format!("vec![{items}]") } else { format!("[{items}]") })
+        }
+        _ => None,
+    }
 }
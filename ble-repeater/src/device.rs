@@ -0,0 +1,52 @@
+//! One-time acquisition of the NimBLE device handle.
+//!
+//! `BLEDevice::take()` hands out a `&'static mut` to a global singleton, so a
+//! second call would alias the first handle. Acquire it once through
+//! `take_ble_device` at startup and pass the handle to anything that needs
+//! it (including any future recovery path) instead of taking it again.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use esp32_nimble::BLEDevice;
+
+/// The resource behind a `TakeOnce` has already been handed out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlreadyTaken;
+
+impl fmt::Display for AlreadyTaken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BLE device already acquired; reuse the existing handle")
+    }
+}
+
+impl std::error::Error for AlreadyTaken {}
+
+/// Calls `take` at most once; later attempts return `AlreadyTaken`.
+pub struct TakeOnce<F> {
+    taken: AtomicBool,
+    take: F,
+}
+
+impl<T, F: Fn() -> T> TakeOnce<F> {
+    pub const fn new(take: F) -> Self {
+        Self {
+            taken: AtomicBool::new(false),
+            take,
+        }
+    }
+
+    pub fn acquire(&self) -> Result<T, AlreadyTaken> {
+        if self.taken.swap(true, Ordering::AcqRel) {
+            return Err(AlreadyTaken);
+        }
+        Ok((self.take)())
+    }
+}
+
+static BLE_DEVICE: TakeOnce<fn() -> &'static mut BLEDevice> = TakeOnce::new(BLEDevice::take);
+
+/// Acquire the NimBLE device. Only the first call succeeds.
+pub fn take_ble_device() -> Result<&'static mut BLEDevice, AlreadyTaken> {
+    BLE_DEVICE.acquire()
+}
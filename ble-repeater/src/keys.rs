@@ -0,0 +1,125 @@
+//! Keys burned into the ESP32's eFuse.
+//!
+//! Each key sits in its own 256-bit eFuse block, laid out as a
+//! `ble_protocol_core::keys` key block: `key_id`, length, key, CRC. The
+//! blocks used, BLK1 and BLK2, are the flash encryption and secure boot key
+//! blocks, so this scheme rules both features out. Burn a block with
+//! `espefuse.py burn_block_data`, then write-protect it
+//! (`espefuse.py write_protect_efuse`). Don't read-protect it: the firmware
+//! reads the key in software.
+//!
+//! A block holds one key, so rolling the infrastructure key means burning a
+//! fresh board; `infra_key_ids` can't add a key that isn't in eFuse.
+//!
+//! BLK3, the user data block, may hold a second client key under its own
+//! `client_key_id`: the next generation while the client key is rolled, or
+//! a second app's key on a mesh shared by two. It stays blank on a board
+//! without one. With both burned, `client_key_ids` picks which of them
+//! sign (see `ble_protocol_core::client_tags`).
+
+use core::fmt;
+
+use ble_protocol_core::keys::{decode_key_block, KeyBlockError, KEY_BLOCK_LEN};
+use ble_protocol_core::{ClientKey, InfraKey, KeyProvider};
+use esp_idf_svc::sys::{
+    esp, esp_efuse_block_t, esp_efuse_block_t_EFUSE_BLK1, esp_efuse_block_t_EFUSE_BLK2,
+    esp_efuse_block_t_EFUSE_BLK3, esp_efuse_read_block, EspError,
+};
+
+/// eFuse block holding the infrastructure key.
+const INFRA_KEY_BLOCK: esp_efuse_block_t = esp_efuse_block_t_EFUSE_BLK1;
+
+/// eFuse block holding the client key.
+const CLIENT_KEY_BLOCK: esp_efuse_block_t = esp_efuse_block_t_EFUSE_BLK2;
+
+/// eFuse block that may hold a second client key; blank if there is none.
+const SECOND_CLIENT_KEY_BLOCK: esp_efuse_block_t = esp_efuse_block_t_EFUSE_BLK3;
+
+/// Why the keys couldn't be read from eFuse.
+#[derive(Debug)]
+pub enum EfuseKeyError {
+    /// The eFuse driver failed to read a block.
+    Read(EspError),
+    /// A block was read but doesn't hold a valid key.
+    Block {
+        name: &'static str,
+        error: KeyBlockError,
+    },
+}
+
+impl EfuseKeyError {
+    /// Whether no key has been burned yet, as opposed to one that is
+    /// damaged or unreadable.
+    pub fn is_blank(&self) -> bool {
+        matches!(
+            self,
+            Self::Block {
+                error: KeyBlockError::Blank,
+                ..
+            }
+        )
+    }
+}
+
+impl fmt::Display for EfuseKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(e) => write!(f, "reading eFuse failed: {}", e),
+            Self::Block { name, error } => write!(f, "{} key in eFuse: {}", name, error),
+        }
+    }
+}
+
+impl std::error::Error for EfuseKeyError {}
+
+/// The infrastructure and client keys, read from eFuse once at startup.
+pub struct EfuseKeys {
+    infra: [InfraKey; 1],
+    /// The client key, then the second one if burned.
+    client: Vec<ClientKey>,
+}
+
+impl EfuseKeys {
+    pub fn read() -> Result<Self, EfuseKeyError> {
+        let infra = read_key(INFRA_KEY_BLOCK, "infrastructure")?;
+        let mut client = vec![read_key(CLIENT_KEY_BLOCK, "client")?];
+        match read_key(SECOND_CLIENT_KEY_BLOCK, "second client") {
+            Ok(next) => client.push(next),
+            Err(e) if e.is_blank() => {}
+            Err(e) => return Err(e),
+        }
+        Ok(Self {
+            infra: [infra],
+            client,
+        })
+    }
+}
+
+impl KeyProvider for EfuseKeys {
+    fn infra_keyring(&self) -> &[InfraKey] {
+        &self.infra
+    }
+
+    fn current_infra_key(&self) -> InfraKey {
+        self.infra[0]
+    }
+
+    fn client_key(&self) -> &'static [u8] {
+        self.client[0].1
+    }
+
+    fn client_keyring(&self) -> &[ClientKey] {
+        &self.client
+    }
+}
+
+/// Read and check the key block in `block`. The key is leaked: it is read
+/// once and needed for as long as the firmware runs.
+fn read_key(block: esp_efuse_block_t, name: &'static str) -> Result<InfraKey, EfuseKeyError> {
+    let mut raw = [0u8; KEY_BLOCK_LEN];
+    esp!(unsafe { esp_efuse_read_block(block, raw.as_mut_ptr().cast(), 0, KEY_BLOCK_LEN * 8) })
+        .map_err(EfuseKeyError::Read)?;
+    let (key_id, key) =
+        decode_key_block(&raw).map_err(|error| EfuseKeyError::Block { name, error })?;
+    Ok((key_id, Box::leak(key.into())))
+}
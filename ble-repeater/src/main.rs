@@ -1,248 +1,480 @@
-use esp32_nimble::enums::*;
-use esp32_nimble::{BLEAdvertisementData, BLEDevice, BLEScan};
+use ble_protocol_core::{InfraKey, KeyProvider, StaticKeys, MANUFACTURER_ID, PROTOCOL_VERSION};
+use esp32_nimble::utilities::mutex::Mutex as NimbleMutex;
+use esp32_nimble::{BLEDevice, BLEExtAdvertising, BLEScan};
 use esp_idf_svc::hal::delay::FreeRtos;
 use esp_idf_svc::hal::task::block_on;
-use esp_idf_svc::sys::esp_timer_get_time;
-use hmac::{Hmac, Mac};
-use log::{error, info};
-use sha2::Sha256;
+use esp_idf_svc::sys::{esp_mac_type_t_ESP_MAC_BT, esp_random, esp_read_mac, esp_timer_get_time};
+use log::{debug, error, info, warn};
+use std::sync::Mutex;
+
+mod device;
+mod keys;
+mod overrides;
+mod radio;
+mod store;
+
+#[cfg(feature = "health")]
+use ble_repeater_logic::health;
+use ble_repeater_logic::{advertise, eventlog, persist, schedule, telemetry};
+
+use ble_repeater_logic::ack::Acker;
+use ble_repeater_logic::active::ActiveNotification;
+use ble_repeater_logic::advertise::StartOutcome;
+use ble_repeater_logic::capability::Announcer;
+use ble_repeater_logic::clock::Clock;
+use ble_repeater_logic::config::{RepeaterConfig, MAX_LISTED_SOURCES};
+use ble_repeater_logic::persist::{SavedEntry, SeenEntry};
+use ble_repeater_logic::repeater::{
+    unix_now_ms, Heard, Repeater, Scanner, SEQ_TRACKED_NOTIFICATIONS,
+};
+use ble_repeater_logic::state::Action;
+use ble_repeater_logic::verbose;
+use keys::EfuseKeys;
+use radio::Radio;
+use store::ActiveStore;
+
+/// Stack of the re-broadcast task. The ESP-IDF pthread default (3 KiB) is
+/// too small for Rust, as for the main task (see `sdkconfig.defaults`).
+const REBROADCAST_TASK_STACK_SIZE: usize = 8 * 1024;
 
-// ── Protocol definitions ────────────────────────────────────────────────
-
-/// Custom manufacturer ID used by our protocol.
-const MANUFACTURER_ID: u16 = 0xFFFF;
+// ── Helpers ─────────────────────────────────────────────────────────────
 
-/// Current protocol version.
-const PROTOCOL_VERSION: u8 = 1;
+/// `Clock` over the ESP timer: microseconds since boot.
+struct EspClock;
 
-/// Infrastructure key: shared between broadcaster and repeater.
-/// Used by the broadcaster to sign, and by the repeater to verify.
-/// In production, store in eFuse — assumed impossible to extract.
-const HMAC_KEY_INFRA: &[u8] = b"infra-secret-key-efuse!!";
+impl Clock for EspClock {
+    fn now_us(&self) -> i64 {
+        unsafe { esp_timer_get_time() }
+    }
+}
 
-/// Client-facing key: used by the repeater to re-sign before broadcasting.
-/// Clients use this key to verify notifications.
-/// In production, store in eFuse on repeater; distribute to app securely.
-const HMAC_KEY_CLIENT: &[u8] = b"client-secret-key-app!!!";
+/// The chip's Bluetooth MAC address, which it advertises from, most
+/// significant byte first.
+fn bt_mac() -> [u8; 6] {
+    let mut mac = [0u8; 6];
+    unsafe { esp_read_mac(mac.as_mut_ptr(), esp_mac_type_t_ESP_MAC_BT) };
+    mac
+}
 
-/// Number of bytes of the truncated HMAC-SHA256 infrastructure tag.
-/// 8 bytes = 64-bit tag (strong enough for repeater-chain verification).
-const HMAC_TAG_INFRA_LEN: usize = 8;
+/// This repeater's id in ack beacons: the low four bytes of its Bluetooth
+/// MAC address, which is unique per chip.
+fn repeater_id() -> [u8; 4] {
+    let mac = bt_mac();
+    [mac[2], mac[3], mac[4], mac[5]]
+}
 
-/// Number of bytes of the truncated HMAC-SHA256 client tag.
-/// 4 bytes = 32-bit tag (sufficient for client-side verification,
-/// saves BLE advertisement space).
-const HMAC_TAG_CLIENT_LEN: usize = 4;
+/// `Scanner` over NimBLE: an active scan on the BLE device.
+struct NimbleScanner(&'static BLEDevice);
 
-type HmacSha256 = Hmac<Sha256>;
+impl Scanner for NimbleScanner {
+    fn scan(&mut self, duration_ms: i32, on_heard: &mut dyn FnMut(Heard<'_>)) {
+        block_on(async {
+            let mut scanner = BLEScan::new();
+            scanner
+                .active_scan(true)
+                .interval(100)
+                .window(99);
 
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum TransportType {
-    Bus = 1,
-    Train = 2,
+            let _ = scanner
+                .start(self.0, duration_ms, |device, data| {
+                    on_heard(Heard {
+                        addr: device.addr().as_be_bytes(),
+                        rssi: device.rssi(),
+                        manufacturer_data: data
+                            .manufacture_data()
+                            .map(|mfg| (mfg.company_identifier, mfg.payload)),
+                    });
+                    None::<()> // keep scanning
+                })
+                .await;
+        });
+    }
 }
 
-impl TransportType {
-    fn from_u8(v: u8) -> Option<Self> {
-        match v {
-            1 => Some(Self::Bus),
-            2 => Some(Self::Train),
-            _ => None,
-        }
+/// Save the active list to NVS with each entry's remaining time. Sealed
+/// entries are left out (see `ActiveNotification::sealed`), and so are v1
+/// ones: what is saved is the current layout, which their tags don't cover.
+fn save_active(store: &mut ActiveStore, active: &[ActiveNotification], cfg: &RepeaterConfig) {
+    let now = EspClock.now_us();
+    let entries: Vec<SavedEntry> = active
+        .iter()
+        .filter(|a| a.sealed.is_none() && a.notification.version == PROTOCOL_VERSION)
+        .map(|a| SavedEntry {
+            notification: a.notification,
+            remaining_ms: (a.remaining_us(now) / 1000) as u32,
+        })
+        .collect();
+    let blob = persist::encode(&entries, cfg.has_clock.then(unix_now_ms));
+    if let Err(e) = store.save(&blob) {
+        error!("failed to save active list to NVS: {:?}", e);
     }
 }
 
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum TransportStatus {
-    Passing = 1,
-    Coming = 2,
-    Late = 3,
+/// Load the active list saved before the last reboot, dropping entries that
+/// have since expired or no longer verify.
+fn restore_active(
+    store: &ActiveStore,
+    cfg: &RepeaterConfig,
+    keyring: &[InfraKey],
+) -> Vec<ActiveNotification> {
+    let blob = match store.load() {
+        Ok(Some(blob)) => blob,
+        Ok(None) => return Vec::new(),
+        Err(e) => {
+            error!("failed to read saved active list from NVS: {:?}", e);
+            return Vec::new();
+        }
+    };
+    let Some(snapshot) = persist::decode(&blob, keyring) else {
+        error!("saved active list is unreadable — starting empty");
+        return Vec::new();
+    };
+
+    // Time spent rebooting is only known with a clock on both sides.
+    let elapsed_ms = match (cfg.has_clock, snapshot.saved_at_ms) {
+        (true, Some(saved_at)) => unix_now_ms().saturating_sub(saved_at),
+        _ => 0,
+    };
+    let now = EspClock.now_us();
+    let restored: Vec<ActiveNotification> = snapshot
+        .remaining_after(elapsed_ms)
+        .take(cfg.max_active_notifications)
+        .map(|e| {
+            ActiveNotification::new(
+                e.notification,
+                cfg.manufacturer_id,
+                now + i64::from(e.remaining_ms) * 1000,
+            )
+        })
+        .collect();
+    info!("Restored {} active notification(s) from NVS", restored.len());
+    restored
 }
 
-impl TransportStatus {
-    fn from_u8(v: u8) -> Option<Self> {
-        match v {
-            1 => Some(Self::Passing),
-            2 => Some(Self::Coming),
-            3 => Some(Self::Late),
-            _ => None,
-        }
+/// Save the replay cache to NVS (see `RepeaterConfig::persist_replay_cache`).
+fn save_replay<S, C: Clock>(store: &mut ActiveStore, repeater: &Repeater<S, C>) {
+    let entries: Vec<SeenEntry> = repeater
+        .replay_cache()
+        .map(|(key, seq, timestamp_ms)| SeenEntry {
+            key,
+            seq,
+            timestamp_ms,
+        })
+        .collect();
+    if let Err(e) = store.save_replay(&persist::encode_replay(&entries)) {
+        error!("failed to save replay cache to NVS: {:?}", e);
     }
 }
 
-#[repr(C, packed)]
-#[derive(Debug, Clone, Copy)]
-struct TransportNotification {
-    version: u8,
-    source_id: [u8; 4],
-    notification_id: [u8; 4],
-    /// High nibble = event_id (0–15), low nibble = destination_id (0–15).
-    event_dest: u8,
-    /// High nibble = transport_type, low nibble = transport_status.
-    type_status: u8,
-    /// How long (in seconds) this notification should be re-broadcast.
-    duration_secs: u16,
-    /// HMAC tag signed by the broadcaster (infrastructure key).
-    /// Verified by every repeater in the chain — never modified.
-    hmac_tag_infra: [u8; HMAC_TAG_INFRA_LEN],
-    /// HMAC tag signed by the first repeater (client key).
-    /// Verified by the client app. Set to zeroes by the broadcaster.
-    hmac_tag_client: [u8; HMAC_TAG_CLIENT_LEN],
+/// Load the replay cache saved before the last reboot, dropping entries
+/// whose packets are too old to replay by now.
+fn restore_replay(store: &ActiveStore) -> Vec<SeenEntry> {
+    let blob = match store.load_replay() {
+        Ok(Some(blob)) => blob,
+        Ok(None) => return Vec::new(),
+        Err(e) => {
+            error!("failed to read saved replay cache from NVS: {:?}", e);
+            return Vec::new();
+        }
+    };
+    let Some(entries) = persist::decode_replay(&blob, unix_now_ms(), SEQ_TRACKED_NOTIFICATIONS)
+    else {
+        error!("saved replay cache is unreadable — starting empty");
+        return Vec::new();
+    };
+    info!("Restored {} replay cache entries from NVS", entries.len());
+    entries
 }
 
-impl TransportNotification {
-    /// Size of the full struct in bytes (including both HMAC tags).
-    const SIZE: usize = core::mem::size_of::<Self>();
-
-    /// Byte size of the base payload (everything before the two HMAC tags).
-    /// This is what both HMAC tags authenticate.
-    const BASE_PAYLOAD_SIZE: usize = Self::SIZE - HMAC_TAG_INFRA_LEN - HMAC_TAG_CLIENT_LEN;
-
-    // ── Nibble accessors ────────────────────────────────────────────
-
-    fn event_id(&self) -> u8 {
-        ({ self.event_dest } >> 4) & 0x0F
+/// The keys burned into eFuse. A debug build on a board with none burned
+/// falls back to the built-in development keys; a release build never does.
+fn load_keys() -> Box<dyn KeyProvider> {
+    match EfuseKeys::read() {
+        Ok(keys) => {
+            info!("Using keys from eFuse");
+            Box::new(keys)
+        }
+        Err(e) if e.is_blank() && cfg!(debug_assertions) => {
+            warn!("{}; using the built-in development keys", e);
+            Box::new(StaticKeys)
+        }
+        Err(e) => panic!("repeater keys unavailable: {}", e),
     }
+}
 
-    fn destination_id(&self) -> u8 {
-        ({ self.event_dest }) & 0x0Fu8
+/// Replace `allowed_sources` and `denied_sources` with the lists an operator
+/// wrote to NVS, where there are any. A list that doesn't decode or is too
+/// long is ignored, keeping the one from `repeater.toml`.
+fn load_source_lists(store: &ActiveStore, cfg: &mut RepeaterConfig) {
+    for (key, list) in [
+        (store::NVS_ALLOWED_SOURCES_KEY, &mut cfg.allowed_sources),
+        (store::NVS_DENIED_SOURCES_KEY, &mut cfg.denied_sources),
+    ] {
+        let blob = match store.load_sources(key) {
+            Ok(Some(blob)) => blob,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("failed to read `{}` from NVS: {:?}", key, e);
+                continue;
+            }
+        };
+        match persist::decode_sources(&blob) {
+            Some(ids) if ids.len() <= MAX_LISTED_SOURCES => {
+                info!("Loaded {} source id(s) from NVS `{}`", ids.len(), key);
+                *list = ids;
+            }
+            _ => error!(
+                "ignoring NVS `{}`: {} bytes is not a list of at most {} source ids",
+                key,
+                blob.len(),
+                MAX_LISTED_SOURCES
+            ),
+        }
     }
+}
 
-    fn transport_type(&self) -> Option<TransportType> {
-        TransportType::from_u8(({ self.type_status } >> 4) & 0x0F)
-    }
+/// Register the health GATT service, answering reads from `active` and
+/// `stats` (see `health`). Must run before anything starts advertising,
+/// since NimBLE fixes its GATT table then.
+#[cfg(feature = "health")]
+fn serve_health(
+    ble_device: &'static BLEDevice,
+    active: std::sync::Arc<Mutex<Vec<ActiveNotification>>>,
+    stats: std::sync::Arc<Mutex<health::CycleStats>>,
+) {
+    use esp32_nimble::utilities::BleUuid;
+    use esp32_nimble::NimbleProperties;
+
+    let uuid = |s| BleUuid::from_uuid128_string(s).expect("health UUIDs are valid");
+    let server = ble_device.get_server();
+    // The re-broadcast task restarts advertising on its next dwell.
+    server.advertise_on_disconnect(false);
+    server.on_connect(|_, desc| info!("health: {:?} connected", desc.address()));
+    let service = server.create_service(uuid(health::HEALTH_SERVICE_UUID));
+    let status = service
+        .lock()
+        .create_characteristic(uuid(health::HEALTH_STATUS_UUID), NimbleProperties::READ);
+    status.lock().on_read(move |value, _| {
+        let record = health::HealthStatus {
+            active: active.lock().unwrap().len(),
+            uptime_us: EspClock.now_us(),
+            stats: *stats.lock().unwrap(),
+        };
+        value.set_value(&record.encode());
+    });
+    info!("Health service up: {}", health::HEALTH_SERVICE_UUID);
+}
 
-    fn transport_status(&self) -> Option<TransportStatus> {
-        TransportStatus::from_u8({ self.type_status } & 0x0F)
+/// Air the active list forever, one cycle at a time, on its own task.
+///
+/// This task is the only user of `advertiser`, so the lock on it is never
+/// contended; NimBLE itself serialises GAP calls from the two tasks.
+fn rebroadcast_loop(
+    advertiser: &NimbleMutex<BLEExtAdvertising>,
+    active: &Mutex<Vec<ActiveNotification>>,
+    cfg: &RepeaterConfig,
+    mut acker: Acker,
+    mut announcer: Announcer,
+) -> ! {
+    // Where the next cycle starts when the op cap truncates one.
+    let mut air_cursor = 0;
+    // Seeded from the hardware RNG, so every repeater draws differently.
+    let mut jitter = schedule::Jitter::new(unsafe { esp_random() });
+    loop {
+        if !rebroadcast_cycle(
+            advertiser,
+            active,
+            cfg,
+            &mut air_cursor,
+            &mut jitter,
+            &mut acker,
+            &mut announcer,
+        ) {
+            FreeRtos::delay_ms(cfg.idle_delay_ms);
+        }
     }
+}
 
-    /// Return the full struct as a byte slice (for re-broadcast).
-    fn as_bytes(&self) -> &[u8] {
-        unsafe {
-            core::slice::from_raw_parts(
-                (self as *const Self) as *const u8,
-                Self::SIZE,
-            )
+/// Air one cycle of the active list, starting from `air_cursor` and moving
+/// it on, then any acks `acker` has due and any capability beacon
+/// `announcer` has. Returns false if there was nothing to air.
+///
+/// The cycle copies its entries out of `active` and releases the lock
+/// before going on air, so the scan task can merge new notifications while
+/// these are advertised. Changes made meanwhile are picked up next cycle.
+fn rebroadcast_cycle(
+    advertiser: &NimbleMutex<BLEExtAdvertising>,
+    active: &Mutex<Vec<ActiveNotification>>,
+    cfg: &RepeaterConfig,
+    air_cursor: &mut usize,
+    jitter: &mut schedule::Jitter,
+    acker: &mut Acker,
+    announcer: &mut Announcer,
+) -> bool {
+    let (entries, total) = {
+        let active = active.lock().unwrap();
+        let cycle =
+            schedule::select_for_cycle(active.len(), cfg.max_advertise_ops_per_cycle, *air_cursor);
+        *air_cursor = cycle.next_cursor;
+        if cycle.deferred > 0 {
+            verbose!(
+                "  op cap of {} reached — deferring {} notification(s) to the next cycle",
+                cfg.max_advertise_ops_per_cycle,
+                cycle.deferred
+            );
         }
+        let entries: Vec<(usize, ActiveNotification)> = cycle
+            .indices
+            .iter()
+            .map(|&i| (i, active[i].clone()))
+            .collect();
+        (entries, active.len())
+    };
+
+    if entries.is_empty() {
+        debug!("No active notifications to broadcast.");
+        return false;
     }
 
-    /// Return only the base payload (everything before both HMAC tags).
-    fn base_payload(&self) -> &[u8] {
-        unsafe {
-            core::slice::from_raw_parts(
-                (self as *const Self) as *const u8,
-                Self::BASE_PAYLOAD_SIZE,
+    debug!("── Re-broadcasting {} active notification(s) ──", total);
+
+    // Rotate through the selected entries in short dwells rather than
+    // airing each for its whole airtime in one block, fresh urgent ones
+    // more than once per round.
+    let rot = schedule::rotation(
+        entries.len(),
+        cfg.rebroadcast_duration_ms,
+        schedule::ENTRY_DWELL_MS,
+    );
+    let now = EspClock.now_us();
+    let boosts: Vec<u32> = entries
+        .iter()
+        .map(|(_, e)| {
+            schedule::escalation(
+                e.notification.priority,
+                e.accepted_at_us,
+                now,
+                cfg.escalate_min_priority,
+                cfg.escalate_for_ms,
             )
+        })
+        .collect();
+    let order = schedule::round_order(&boosts);
+    for round in 0..rot.rounds {
+        for (k, &j) in order.iter().enumerate() {
+            let (i, ref entry) = entries[j];
+            air_dwell(
+                advertiser,
+                cfg,
+                jitter,
+                entry.raw_mfg_payload(),
+                rot.dwell_ms,
+                &i,
+                || {
+                    // Described once per cycle, not on every dwell.
+                    if round == 0 && k < entries.len() {
+                        telemetry::entry(telemetry::Event::Rebroadcast, entry);
+                        let now = EspClock.now_us();
+                        eventlog::aired(entry, now);
+                        verbose!(
+                            "  [{}] {} event {:?} — expires in {}s",
+                            i,
+                            entry.notification,
+                            entry.notification.event(),
+                            entry.remaining_us(now) / 1_000_000
+                        );
+                    }
+                },
+            );
         }
     }
 
-    /// Compute a truncated HMAC-SHA256 tag for the infrastructure key.
-    fn compute_infra_tag(data: &[u8]) -> [u8; HMAC_TAG_INFRA_LEN] {
-        let mut mac =
-            HmacSha256::new_from_slice(HMAC_KEY_INFRA).expect("HMAC accepts any key length");
-        mac.update(data);
-        let result = mac.finalize().into_bytes();
-        let mut tag = [0u8; HMAC_TAG_INFRA_LEN];
-        tag.copy_from_slice(&result[..HMAC_TAG_INFRA_LEN]);
-        tag
-    }
-
-    /// Compute a truncated HMAC-SHA256 tag for the client key.
-    fn compute_client_tag(data: &[u8]) -> [u8; HMAC_TAG_CLIENT_LEN] {
-        let mut mac =
-            HmacSha256::new_from_slice(HMAC_KEY_CLIENT).expect("HMAC accepts any key length");
-        mac.update(data);
-        let result = mac.finalize().into_bytes();
-        let mut tag = [0u8; HMAC_TAG_CLIENT_LEN];
-        tag.copy_from_slice(&result[..HMAC_TAG_CLIENT_LEN]);
-        tag
+    let acks = acker.after_cycle(entries.iter().map(|(_, e)| e), cfg.manufacturer_id);
+    if !acks.is_empty() {
+        verbose!("  acking {} notification(s)", acks.len());
     }
-
-    /// Verify the infrastructure HMAC tag (broadcaster → repeater chain).
-    fn verify_infra(&self) -> bool {
-        let expected = Self::compute_infra_tag(self.base_payload());
-        expected == ({ self.hmac_tag_infra })
+    for ack in &acks {
+        air_dwell(
+            advertiser,
+            cfg,
+            jitter,
+            ack,
+            schedule::ENTRY_DWELL_MS,
+            &"ack",
+            || {},
+        );
     }
-
-    /// Verify the client HMAC tag (repeater → client).
-    fn verify_client(&self) -> bool {
-        let expected = Self::compute_client_tag(self.base_payload());
-        expected == ({ self.hmac_tag_client })
+    if let Some(beacon) = announcer.after_cycle() {
+        air_dwell(
+            advertiser,
+            cfg,
+            jitter,
+            beacon,
+            schedule::ENTRY_DWELL_MS,
+            &"capabilities",
+            || {},
+        );
     }
 
-    /// Sign the client tag in-place (called by the first repeater).
-    fn sign_client(&mut self) {
-        let tag = Self::compute_client_tag(self.base_payload());
-        self.hmac_tag_client = tag;
-    }
+    debug!("── Cycle complete ──");
+    true
+}
 
-    /// Returns true if the client tag has been set (non-zero).
-    fn has_client_tag(&self) -> bool {
-        ({ self.hmac_tag_client }) != [0u8; HMAC_TAG_CLIENT_LEN]
+/// Air `mfg_payload` as a beacon for one dwell of about `dwell_ms`, at the
+/// jittered advertising interval. `label` names it in the log; `on_air`
+/// runs once it is confirmed on air, before the dwell.
+fn air_dwell(
+    advertiser: &NimbleMutex<BLEExtAdvertising>,
+    cfg: &RepeaterConfig,
+    jitter: &mut schedule::Jitter,
+    mfg_payload: &[u8],
+    dwell_ms: u32,
+    label: &dyn core::fmt::Display,
+    on_air: impl FnOnce(),
+) {
+    let mut guard = advertiser.lock();
+    let mut radio = Radio::new(&mut guard);
+    let mut adv = advertise::StopOnDrop::new(&mut radio);
+
+    // Stop any previous advertising
+    adv.stop();
+
+    // Non-scannable beacon repeat (connectable for `health`), at a
+    // fast advertising interval (~20 ms by default). Within the
+    // legal range: `validate` bounds adv_interval + jitter.
+    let interval = jitter.add(
+        u32::from(cfg.adv_interval),
+        u32::from(cfg.adv_interval_jitter),
+    ) as u16;
+    if let Err(e) = adv.load_beacon(mfg_payload, interval) {
+        error!("  [{}] failed to set adv data: {:?}", label, e);
+        return;
     }
 
-    /// Parse and verify a notification from the manufacturer-data payload.
-    /// Verifies the infrastructure HMAC tag. Returns `None` if invalid.
-    fn from_payload(payload: &[u8]) -> Option<Self> {
-        info!("    › parsing payload ({} bytes)", payload.len());
-        if payload.len() < Self::SIZE {
-            return None;
+    match advertise::start_confirmed(&mut *adv, cfg.adv_start_retries) {
+        StartOutcome::Active { attempts: 1 } => {}
+        StartOutcome::Active { attempts } => {
+            info!(
+                "  [{}] advertising confirmed after {} attempts",
+                label, attempts
+            );
         }
-
-        let notif: Self = unsafe {
-            let ptr = payload.as_ptr() as *const Self;
-            core::ptr::read_unaligned(ptr)
-        };
-
-        // Validate protocol version
-        if { notif.version } != PROTOCOL_VERSION {
-            return None;
+        StartOutcome::Silent { attempts } => {
+            error!(
+                "  [{}] radio not advertising after {} successful start(s) — skipping",
+                label, attempts
+            );
+            return;
         }
-        // Validate packed enum nibbles
-        notif.transport_type()?;
-        notif.transport_status()?;
-
-        // Verify infrastructure HMAC tag (set by broadcaster, never changes)
-        if !notif.verify_infra() {
-            error!("    ✗ infra HMAC mismatch — rejecting forged notification");
-            return None;
+        StartOutcome::Failed(e) => {
+            error!("  [{}] failed to start advertising: {:?}", label, e);
+            return;
         }
-
-        Some(notif)
     }
-}
+    on_air();
 
-// ── Active notification with expiry tracking ────────────────────────────
-
-/// A notification we are actively re-broadcasting, with an expiry timestamp.
-#[derive(Clone)]
-struct ActiveNotification {
-    notification: TransportNotification,
-    /// Raw manufacturer-data payload (including the 2-byte company ID) for
-    /// direct re-broadcast.
-    raw_mfg_payload: Vec<u8>,
-    /// Monotonic timestamp (in microseconds) at which this entry expires.
-    expires_at_us: i64,
-}
-
-// ── Configuration ───────────────────────────────────────────────────────
-
-/// Duration to scan for advertisements (ms).
-const SCAN_DURATION_MS: i32 = 3000;
-
-/// Duration to re-broadcast each active notification (ms).
-const REBROADCAST_DURATION_MS: u32 = 2000;
-
-/// Maximum number of notifications kept in the active list.
-const MAX_ACTIVE_NOTIFICATIONS: usize = 16;
-
-// ── Helpers ─────────────────────────────────────────────────────────────
-
-/// Return the current monotonic time in microseconds.
-fn now_us() -> i64 {
-    unsafe { esp_timer_get_time() }
+    // Keep this advertisement on air for one dwell
+    FreeRtos::delay_ms(jitter.add(dwell_ms, cfg.dwell_jitter_ms));
 }
 
 fn main() {
@@ -254,185 +486,172 @@ fn main() {
     esp_idf_svc::log::EspLogger::initialize_default();
 
     info!("Starting BLE Station Repeater...");
+
+    let mut cfg = match overrides::load() {
+        Ok(cfg) => cfg,
+        Err(e) => panic!("repeater configuration rejected: {}", e),
+    };
+    info!(
+        "Scanning in {}ms windows while re-broadcasting each for {}ms ({}ms dwells)",
+        cfg.scan_duration_ms, cfg.rebroadcast_duration_ms, schedule::ENTRY_DWELL_MS
+    );
+    if cfg.uses_test_manufacturer_id() {
+        warn!(
+            "using manufacturer ID 0x{:04X}, reserved for testing; set manufacturer_id \
+             before deploying",
+            MANUFACTURER_ID
+        );
+    } else {
+        info!("Manufacturer ID 0x{:04X}", cfg.manufacturer_id);
+    }
+    let keys = load_keys();
+    let keyring = match cfg.infra_keyring(&*keys) {
+        Ok(keyring) => keyring,
+        Err(e) => panic!("repeater configuration rejected: {}", e),
+    };
     info!(
-        "Scan {}ms → re-broadcast each for {}ms → repeat",
-        SCAN_DURATION_MS, REBROADCAST_DURATION_MS
+        "Accepting infra key id(s) {:?}",
+        keyring.iter().map(|(id, _)| *id).collect::<Vec<_>>()
     );
+    match cfg.client_keys(&*keys) {
+        Ok(client_keys) => info!(
+            "Signing client tags with client key id(s) {:?}",
+            client_keys.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+        ),
+        Err(e) => panic!("repeater configuration rejected: {}", e),
+    }
 
-    let ble_device = BLEDevice::take();
+    let ble_device: &'static BLEDevice = match device::take_ble_device() {
+        Ok(device) => device,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
     let advertiser = ble_device.get_advertising();
 
-    // Persistent list of notifications we are currently re-broadcasting.
-    let mut active: Vec<ActiveNotification> = Vec::new();
-
-    loop {
-        // ── Prune expired notifications ─────────────────────────────────
-        let now = now_us();
-        let before = active.len();
-        active.retain(|n| n.expires_at_us > now);
-        let pruned = before - active.len();
-        if pruned > 0 {
-            info!("Pruned {} expired notification(s)", pruned);
+    // Where the active list is saved across reboots; without it the
+    // repeater still runs, just starting empty after a reset.
+    let mut store = match ActiveStore::open() {
+        Ok(store) => Some(store),
+        Err(e) => {
+            error!("NVS unavailable, active list won't survive a reboot: {:?}", e);
+            None
         }
+    };
+
+    if let Some(store) = &store {
+        load_source_lists(store, &mut cfg);
+    }
 
-        // ── Phase 1: Scan ───────────────────────────────────────────────
+    // Persistent list of notifications we are currently re-broadcasting.
+    let active = store
+        .as_ref()
+        .map(|store| restore_active(store, &cfg, &keyring))
+        .unwrap_or_default();
+    let acker = || Acker::new(repeater_id(), keyring.clone(), cfg.ack_every_cycles);
+    if cfg.ack_every_cycles > 0 {
         info!(
-            "── Scanning for {} ms (active list: {}) ──",
-            SCAN_DURATION_MS,
-            active.len()
+            "Acking relayed notifications every {} cycle(s) as repeater {:02x?}",
+            cfg.ack_every_cycles,
+            repeater_id()
         );
-
-        let new_notifications: Vec<ActiveNotification> = block_on(async {
-            let mut scanner = BLEScan::new();
-            scanner
-                .active_scan(true)
-                .interval(100)
-                .window(99);
-
-            let mut found: Vec<ActiveNotification> = Vec::new();
-
-            let _ = scanner
-                .start(ble_device, SCAN_DURATION_MS, |device, data| {
-                    // Only look at advertisements with our manufacturer ID
-                    if let Some(mfg) = data.manufacture_data() {
-                        if mfg.company_identifier == MANUFACTURER_ID {
-                            if let Some(notif) =
-                                TransportNotification::from_payload(mfg.payload)
-                            {
-                                let sid = { notif.source_id };
-                                let nid = { notif.notification_id };
-                                let dur = { notif.duration_secs };
-
-                                info!(
-                                    "  ✓ verified notification {:02X}{:02X}{:02X}{:02X} from station {:02X}{:02X}{:02X}{:02X} \
-                                     ({:?} {:?} → dest {}) duration {}s via {:?} (RSSI {})",
-                                    nid[0], nid[1], nid[2], nid[3],
-                                    sid[0], sid[1], sid[2], sid[3],
-                                    notif.transport_type().unwrap(),
-                                    notif.transport_status().unwrap(),
-                                    notif.destination_id(),
-                                    dur,
-                                    device.addr(),
-                                    device.rssi(),
-                                );
-
-                                // Relay all valid notifications with a non-zero duration
-                                if dur > 0 {
-                                    let mut notif = notif;
-
-                                    // First repeater signs the client tag;
-                                    // subsequent repeaters pass it through unchanged.
-                                    if !notif.has_client_tag() {
-                                        notif.sign_client();
-                                        info!("    → signed client HMAC tag");
-                                    }
-
-                                    // Re-broadcast: company ID + full struct (both tags)
-                                    let mut raw = Vec::new();
-                                    raw.extend_from_slice(
-                                        &MANUFACTURER_ID.to_le_bytes(),
-                                    );
-                                    raw.extend_from_slice(notif.as_bytes());
-
-                                    let expires =
-                                        now_us() + (dur as i64) * 1_000_000;
-
-                                    found.push(ActiveNotification {
-                                        notification: notif,
-                                        raw_mfg_payload: raw,
-                                        expires_at_us: expires,
-                                    });
-                                }
-                            }
-                        }
-                    }
-                    None::<()> // keep scanning
-                })
-                .await;
-
-            found
-        });
-
-        // ── Merge new notifications into active list ────────────────────
-        for new in new_notifications {
-            // If we already have this notification_id, update its expiry
-            let new_nid = { new.notification.notification_id };
-            if let Some(existing) = active
-                .iter_mut()
-                .find(|a| { a.notification.notification_id } == new_nid)
-            {
-                existing.expires_at_us = new.expires_at_us;
-                existing.notification = new.notification;
-                existing.raw_mfg_payload = new.raw_mfg_payload;
-                info!("  updated notification {:02X}{:02X}{:02X}{:02X} expiry", new_nid[0], new_nid[1], new_nid[2], new_nid[3]);
-            } else if active.len() < MAX_ACTIVE_NOTIFICATIONS {
-                info!("  added notification {:02X}{:02X}{:02X}{:02X} to active list", new_nid[0], new_nid[1], new_nid[2], new_nid[3]);
-                active.push(new);
-            } else {
-                error!("  active list full, dropping notification");
-            }
-        }
-
-        if active.is_empty() {
-            info!("No active notifications to broadcast.");
-            FreeRtos::delay_ms(500);
-            continue;
-        }
-
-        // ── Phase 2: Re-broadcast all active notifications ──────────────
+    }
+    let announcer = || Announcer::new(repeater_id(), &cfg, keys.current_infra_key());
+    if cfg.capability_every_cycles > 0 {
         info!(
-            "── Re-broadcasting {} active notification(s) ──",
-            active.len()
+            "Beaconing capabilities every {} cycle(s)",
+            cfg.capability_every_cycles
         );
+    }
+    if cfg.neighbor_aware_relay {
+        info!(
+            "Skipping relays no neighbour would take; neighbours go stale after {}s",
+            cfg.neighbor_stale_secs
+        );
+    }
+    let mut repeater = Repeater::new(
+        cfg.clone(),
+        keyring.clone(),
+        &*keys,
+        NimbleScanner(ble_device),
+        active,
+        EspClock,
+    )
+    .with_own_addr(bt_mac());
+    #[cfg(feature = "relay-path")]
+    {
+        warn!(
+            "relay-path build: adding {:02x?} to relay paths; not for production",
+            repeater_id()
+        );
+        repeater = repeater.with_repeater_id(repeater_id());
+    }
+    if let Some(store) = store.as_ref().filter(|_| cfg.persist_replay_cache) {
+        let restored = restore_replay(store).into_iter();
+        repeater = repeater.with_replay_cache(restored.map(|e| (e.key, e.seq, e.timestamp_ms)));
+    }
 
-        for (i, entry) in active.iter().enumerate() {
-            let mut adv = advertiser.lock();
-
-            // Stop any previous advertising
-            let _ = adv.stop();
-
-            // Non-connectable, non-scannable — pure beacon repeat
-            adv.advertisement_type(ConnMode::Non);
-            adv.scan_response(false);
-
-            // Fast advertising interval (~20 ms)
-            const INTERVAL: u16 = 32; // 32 × 0.625 ms = 20 ms
-            adv.min_interval(INTERVAL);
-            adv.max_interval(INTERVAL);
-
-            let mut adv_data = BLEAdvertisementData::new();
-            adv_data.manufacturer_data(&entry.raw_mfg_payload);
+    // Shared with the re-broadcast task, which airs it while this one keeps
+    // scanning. With `once` there is no such task: this one airs a single
+    // cycle itself after its scan.
+    let shared_active = repeater.active();
+    #[cfg(feature = "health")]
+    let stats = std::sync::Arc::new(Mutex::new(health::CycleStats::default()));
+    #[cfg(feature = "health")]
+    serve_health(ble_device, repeater.active(), stats.clone());
+    if !cfg.once {
+        let active = repeater.active();
+        let acker = acker();
+        let announcer = announcer();
+        let cfg = cfg.clone();
+        let spawned = std::thread::Builder::new()
+            .stack_size(REBROADCAST_TASK_STACK_SIZE)
+            .spawn(move || rebroadcast_loop(advertiser, &active, &cfg, acker, announcer));
+        if let Err(e) = spawned {
+            error!("failed to start the re-broadcast task: {}", e);
+            return;
+        }
+    }
 
-            if let Err(e) = adv.set_data(&mut adv_data) {
-                error!("  [{}] failed to set adv data: {:?}", i, e);
-                continue;
+    loop {
+        #[cfg(feature = "health")]
+        let before = *repeater.metrics();
+        let snapshot = repeater.run_cycle();
+        #[cfg(feature = "health")]
+        stats
+            .lock()
+            .unwrap()
+            .record(repeater.metrics().since(&before));
+        // Written outside the lock: a flash write can take a while. Each
+        // is written only when it changed.
+        if let Some(store) = &mut store {
+            if let Some(snapshot) = snapshot {
+                save_active(store, &snapshot, &cfg);
             }
-
-            if let Err(e) = adv.start() {
-                error!("  [{}] failed to start advertising: {:?}", i, e);
-                continue;
+            if cfg.persist_replay_cache && repeater.take_replay_changed() {
+                save_replay(store, &repeater);
             }
-
-            let remaining_secs =
-                (entry.expires_at_us - now_us()).max(0) / 1_000_000;
-            let esid = { entry.notification.source_id };
-            let enid = { entry.notification.notification_id };
-            info!(
-                "  [{}] notification {:02X}{:02X}{:02X}{:02X} from station {:02X}{:02X}{:02X}{:02X} ({:?} {:?}) — expires in {}s",
-                i,
-                enid[0], enid[1], enid[2], enid[3],
-                esid[0], esid[1], esid[2], esid[3],
-                entry.notification.transport_type().unwrap_or(TransportType::Bus),
-                entry.notification.transport_status().unwrap_or(TransportStatus::Passing),
-                remaining_secs
-            );
-
-            // Keep this advertisement active for a short burst
-            FreeRtos::delay_ms(REBROADCAST_DURATION_MS);
-
-            let _ = adv.stop();
         }
 
-        info!("── Cycle complete ──\n");
+        match repeater.next_action() {
+            Action::Scan => {}
+            // Backed off while idle: radio off until the next scan is due.
+            Action::Sleep { ms } => FreeRtos::delay_ms(ms),
+            Action::RebroadcastAndStop => {
+                let mut jitter = schedule::Jitter::new(unsafe { esp_random() });
+                rebroadcast_cycle(
+                    advertiser,
+                    &shared_active,
+                    &cfg,
+                    &mut 0,
+                    &mut jitter,
+                    &mut acker(),
+                    &mut announcer(),
+                );
+                info!("Single cycle done (once) — exiting");
+                return;
+            }
+        }
     }
 }
@@ -0,0 +1,16 @@
+//! `RepeaterConfig` as built into this firmware: the defaults, overlaid
+//! with `repeater.toml` by `apply_overrides`, which `build.rs` generates.
+
+use ble_repeater_logic::config::{ConfigError, RepeaterConfig};
+
+// Generated by build.rs from `repeater.toml`.
+include!(concat!(env!("OUT_DIR"), "/config_overrides.rs"));
+
+/// Build the configuration: defaults overlaid with `repeater.toml`, then
+/// validated.
+pub fn load() -> Result<RepeaterConfig, ConfigError> {
+    let mut cfg = RepeaterConfig::default();
+    apply_overrides(&mut cfg);
+    cfg.validate()?;
+    Ok(cfg)
+}
@@ -0,0 +1,82 @@
+//! The NimBLE advertiser as an `Advertiser`, for the re-broadcast path.
+//!
+//! A notification no longer fits a legacy (31-byte) advertisement, so the
+//! repeater needs a BLE 5 chip: an ESP32-S3 (the default target) or an
+//! ESP32-C3, built with `CONFIG_BT_NIMBLE_EXT_ADV=y` (see
+//! `sdkconfig.ext-adv`). The classic ESP32 isn't supported. NimBLE then
+//! only offers extended advertising, and `Radio` drives one extended
+//! instance, still sending legacy PDUs for any payload that fits them.
+
+use ble_repeater_logic::active::MAX_MFG_LEN;
+use ble_repeater_logic::advertise::{fits_legacy, Advertiser};
+use esp32_nimble::{
+    enums::{PrimPhy, SecPhy},
+    BLEError, BLEExtAdvertisement, BLEExtAdvertising,
+};
+
+#[cfg(not(esp_idf_bt_nimble_ext_adv))]
+compile_error!(
+    "the repeater needs BLE 5 extended advertising: build for an ESP32-S3 or ESP32-C3 with sdkconfig.ext-adv"
+);
+
+/// The extended advertising instance the repeater uses.
+const EXT_INSTANCE: u8 = 0;
+
+// The largest payload the active list holds, with its AD header, has to fit
+// the instance's advertising data (see `sdkconfig.ext-adv`).
+const _: () = assert!(
+    2 + MAX_MFG_LEN <= esp_idf_svc::sys::CONFIG_BT_NIMBLE_EXT_ADV_MAX_SIZE as usize,
+    "CONFIG_BT_NIMBLE_EXT_ADV_MAX_SIZE is too small for a notification; build with sdkconfig.ext-adv"
+);
+
+/// Whether beacons are connectable, so a technician can connect and read
+/// the health characteristic (`health` feature; see `health`). Otherwise
+/// they are pure beacons nobody can connect to.
+const CONNECTABLE: bool = cfg!(feature = "health");
+
+/// The NimBLE advertiser, as a beacon.
+pub struct Radio<'a>(&'a mut BLEExtAdvertising);
+
+impl<'a> Radio<'a> {
+    pub fn new(adv: &'a mut BLEExtAdvertising) -> Self {
+        Self(adv)
+    }
+
+    /// Load `mfg_payload` (company ID + notification) as a beacon
+    /// advertised every `interval` (0.625 ms units), connectable only if
+    /// `CONNECTABLE`. Uses legacy PDUs when the payload fits them, since
+    /// every scanner hears those; extended PDUs otherwise. Call while
+    /// stopped.
+    pub fn load_beacon(&mut self, mfg_payload: &[u8], interval: u16) -> Result<(), BLEError> {
+        let legacy = fits_legacy(mfg_payload.len());
+        let mut adv = BLEExtAdvertisement::new(PrimPhy::Phy1M, SecPhy::Phy1M);
+        adv.legacy_advertising(legacy);
+        adv.connectable(CONNECTABLE);
+        // A connectable legacy PDU (ADV_IND) is always scannable; a
+        // connectable extended one never is.
+        adv.scannable(CONNECTABLE && legacy);
+        adv.min_interval(interval.into());
+        adv.max_interval(interval.into());
+        adv.manufacturer_data(mfg_payload);
+        self.0.set_instance_data(EXT_INSTANCE, &mut adv)
+    }
+}
+
+impl Advertiser for Radio<'_> {
+    type Error = BLEError;
+
+    fn start(&mut self) -> Result<(), BLEError> {
+        self.0.start(EXT_INSTANCE)
+    }
+
+    fn stop(&mut self) {
+        // esp32-nimble has no stop for extended instances.
+        unsafe {
+            esp_idf_svc::sys::ble_gap_ext_adv_stop(EXT_INSTANCE);
+        }
+    }
+
+    fn is_advertising(&self) -> bool {
+        unsafe { esp_idf_svc::sys::ble_gap_ext_adv_active(EXT_INSTANCE) }
+    }
+}
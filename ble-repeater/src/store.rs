@@ -0,0 +1,61 @@
+//! The repeater's NVS namespace, where the blobs `persist` encodes are kept
+//! (see `ble_repeater_logic::persist` for their layout).
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::EspError;
+
+const NVS_NAMESPACE: &str = "repeater";
+const NVS_KEY: &str = "active";
+const NVS_REPLAY_KEY: &str = "replay";
+
+/// NVS key overriding `RepeaterConfig::allowed_sources`.
+pub const NVS_ALLOWED_SOURCES_KEY: &str = "allow_src";
+/// NVS key overriding `RepeaterConfig::denied_sources`.
+pub const NVS_DENIED_SOURCES_KEY: &str = "deny_src";
+
+/// The repeater's NVS namespace: the saved active list and replay cache, and
+/// any source lists an operator wrote there.
+pub struct ActiveStore {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl ActiveStore {
+    pub fn open() -> Result<Self, EspError> {
+        let partition = EspDefaultNvsPartition::take()?;
+        Ok(Self {
+            nvs: EspNvs::new(partition, NVS_NAMESPACE, true)?,
+        })
+    }
+
+    /// The saved blob, if one has been written.
+    pub fn load(&self) -> Result<Option<Vec<u8>>, EspError> {
+        self.load_blob(NVS_KEY)
+    }
+
+    /// The source list blob under `key` (`NVS_ALLOWED_SOURCES_KEY` or
+    /// `NVS_DENIED_SOURCES_KEY`), if an operator wrote one.
+    pub fn load_sources(&self, key: &str) -> Result<Option<Vec<u8>>, EspError> {
+        self.load_blob(key)
+    }
+
+    fn load_blob(&self, key: &str) -> Result<Option<Vec<u8>>, EspError> {
+        let Some(len) = self.nvs.blob_len(key)? else {
+            return Ok(None);
+        };
+        let mut buf = vec![0; len];
+        Ok(self.nvs.get_blob(key, &mut buf)?.map(<[u8]>::to_vec))
+    }
+
+    pub fn save(&mut self, blob: &[u8]) -> Result<(), EspError> {
+        self.nvs.set_blob(NVS_KEY, blob)
+    }
+
+    /// The saved replay cache blob, if one has been written.
+    pub fn load_replay(&self) -> Result<Option<Vec<u8>>, EspError> {
+        self.load_blob(NVS_REPLAY_KEY)
+    }
+
+    pub fn save_replay(&mut self, blob: &[u8]) -> Result<(), EspError> {
+        self.nvs.set_blob(NVS_REPLAY_KEY, blob)
+    }
+}